@@ -1,6 +1,9 @@
-use std::fmt::{Debug, Display};
+use std::{
+    fmt::{Debug, Display},
+    num::TryFromIntError,
+};
 
-use utils::{bytes_stringify, Digestible, Digest};
+use utils::{bytes_stringify, Deserialize, Digest, Digestible, Serialize};
 use xxhash_rust::xxh3::xxh3_128;
 
 #[derive(Default, Clone, Hash, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -45,12 +48,74 @@ impl Debug for HashChunk {
     }
 }
 
-pub fn chunks(data: &[u8]) -> HashChunks {
-    let chunker = fastcdc::v2020::FastCDC::new(
+/// A content-defined chunk of a file, the same bytes give the same chunk
+/// wherever they are in the file.
+#[derive(Default, Clone, Hash, PartialEq, Eq)]
+pub struct ChunkRef {
+    pub offset: u64,
+    pub len: u32,
+    pub hash: [u8; 16],
+}
+
+impl Debug for ChunkRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkRef")
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .field("hash", &bytes_stringify(&self.hash))
+            .finish()
+    }
+}
+
+impl Serialize for ChunkRef {
+    fn serialize(&self, serializer: utils::Serializer) -> utils::Serializer {
+        (self.offset, self.len, self.hash).serialize(serializer)
+    }
+
+    fn byte_size(&self) -> Option<usize> {
+        (self.offset, self.len, self.hash).byte_size()
+    }
+}
+
+impl Deserialize for ChunkRef {
+    fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
+        let ((offset, len, hash), bytes) = <(u64, u32, [u8; 16])>::deserialize(bytes)?;
+        Ok((Self { offset, len, hash }, bytes))
+    }
+}
+
+impl Digestible for ChunkRef {
+    fn digest(&self, d: &mut impl Digest) {
+        d.update(self.offset.to_be_bytes());
+        d.update(self.len.to_be_bytes());
+        d.update(self.hash)
+    }
+}
+
+fn chunker(data: &[u8]) -> fastcdc::v2020::FastCDC<'_> {
+    fastcdc::v2020::FastCDC::new(
         data, 65536,  /* 64 KiB */
         131072, /* 128 KiB */
         262144, /* 256 KiB */
-    );
+    )
+}
+
+/// Split `data` into content-defined chunks, see [`ChunkRef`]. Fails if a
+/// chunk is too long for [`ChunkRef::len`].
+pub fn chunk_refs(data: &[u8]) -> Result<Vec<ChunkRef>, TryFromIntError> {
+    chunker(data)
+        .map(|chunk| {
+            Ok(ChunkRef {
+                offset: chunk.offset as u64,
+                len: chunk.length.try_into()?,
+                hash: xxh3_128(&data[chunk.offset..chunk.offset + chunk.length]).to_be_bytes(),
+            })
+        })
+        .collect()
+}
+
+pub fn chunks(data: &[u8]) -> HashChunks {
+    let chunker = chunker(data);
     let mut chunks = if let Some(max_size) = chunker.size_hint().1 {
         Vec::with_capacity(max_size)
     } else {
@@ -77,10 +142,23 @@ pub fn chunks(data: &[u8]) -> HashChunks {
 
 #[cfg(test)]
 mod tests {
-    use super::chunks;
+    use super::{chunk_refs, chunks};
 
     #[test]
     fn test() {
         dbg!(chunks(include_bytes!("test.jpg")));
     }
+
+    #[test]
+    fn chunk_refs_test() {
+        let data = include_bytes!("test.jpg");
+        let refs = chunk_refs(data).unwrap();
+
+        let mut offset = 0;
+        for chunk in refs.iter() {
+            assert_eq!(chunk.offset, offset);
+            offset += chunk.len as u64;
+        }
+        assert_eq!(offset, data.len() as u64);
+    }
 }
@@ -1,18 +1,28 @@
 use std::{
     alloc::{Allocator, Global},
+    cell::RefCell,
+    collections::BTreeMap,
     marker::PhantomData,
+    ops::Bound,
     sync::Arc,
+    time::Duration,
 };
 
-use parking_lot::RwLock;
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::{DBLock, DBRead, DBTransaction, DBWrite, Result, DB};
+use crate::{
+    merge::merge_operands, DBLock, DBRead, DBTransaction, DBWrite, Error, ReadOnly, Result, DB,
+};
 
 type KeyBytes<A> = Box<[u8], A>;
 type ValueBytes = Arc<[u8]>;
 
-type MapType<A> = std::collections::BTreeMap<KeyBytes<A>, ValueBytes, A>;
+type MapType<A> = BTreeMap<KeyBytes<A>, ValueBytes, A>;
+type WritesType<A> = BTreeMap<KeyBytes<A>, Option<ValueBytes>, A>;
+type MergesType<A> = BTreeMap<KeyBytes<A>, Vec<ValueBytes>, A>;
 
+/// In-memory database, keys are ordered by `Ord` of `[u8]`, which is the same
+/// bytewise order as rocksdb.
 #[derive(Debug)]
 pub struct MemoryDB<A: Allocator + Clone = Global> {
     map: Arc<RwLock<MapType<A>>>,
@@ -38,12 +48,45 @@ impl<A: Allocator + Clone> Clone for MemoryDB<A> {
 }
 
 impl<A: Allocator + Clone> MemoryDB<A> {
+    /// Database allocating its map, staged writes and scans in `alloc`, e.g.
+    /// an arena in tests and benchmarks.
     pub fn new_in(alloc: A) -> Self {
         Self {
             map: Arc::new(RwLock::new(MapType::new_in(alloc.clone()))),
             alloc,
         }
     }
+
+    /// Read-only handle sharing the map of this database, so it sees the
+    /// later commits, unlike [`Clone`] which copies the map.
+    pub fn read_only(&self) -> ReadOnly<MemoryDB<A>> {
+        ReadOnly::new(Self {
+            map: self.map.clone(),
+            alloc: self.alloc.clone(),
+        })
+    }
+
+    /// Hold the lock of the map until the guard is dropped, for the tests
+    /// waiting on the lock.
+    #[cfg(test)]
+    pub(crate) fn lock_map(&self) -> impl Sized + '_ {
+        self.map.write()
+    }
+
+    fn scan(&self, from: &[u8], to: Bound<&[u8]>) -> MemoryDBRangeIter<'_, A> {
+        let mut collection = Vec::new_in(self.alloc.clone());
+        collection.extend(
+            self.map
+                .read()
+                .range::<[u8], _>((Bound::Included(from), to))
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        MemoryDBRangeIter {
+            iter: collection.into_iter(),
+            error: None,
+            l: PhantomData,
+        }
+    }
 }
 
 impl<A: Allocator + Clone> DBRead for MemoryDB<A> {
@@ -66,25 +109,26 @@ impl<A: Allocator + Clone> DBRead for MemoryDB<A> {
         Self: 'a;
 
     fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
-        let mut collection = Vec::new_in(self.alloc.clone());
-        collection.extend(
-            self.map
-                .read()
-                .range::<[u8], _>((
-                    std::ops::Bound::Included(from.as_ref()),
-                    std::ops::Bound::Excluded(to.as_ref()),
-                ))
-                .map(|(k, v)| (k.clone(), v.clone())),
-        );
-        MemoryDBRangeIter {
-            iter: collection.into_iter(),
-            l: PhantomData,
-        }
+        self.scan(from.as_ref(), Bound::Excluded(to.as_ref()))
+    }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        self.scan(from.as_ref(), Bound::Unbounded)
+    }
+
+    type ReadView<'a> = ReadOnly<Self>
+    where A: 'a;
+
+    /// A snapshot, see [`DB::snapshot`].
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        self.snapshot()
     }
 }
 
 pub struct MemoryDBRangeIter<'a, A: Allocator + Clone> {
     iter: std::vec::IntoIter<(KeyBytes<A>, ValueBytes), A>,
+    /// yielded instead of the items if the scan failed, e.g. timed out.
+    error: Option<Error>,
     l: PhantomData<&'a u8>,
 }
 
@@ -92,14 +136,87 @@ impl<'a, A: Allocator + Clone> Iterator for MemoryDBRangeIter<'a, A> {
     type Item = Result<(KeyBytes<A>, ValueBytes)>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
         self.iter.next().map(|(k, v)| Ok((k, v)))
     }
 }
 
+/// A transaction over [`MemoryDB`], behaves like the optimistic transaction of
+/// rocksdb: writes are staged until commit, and the commit fails with
+/// [`Error::Conflict`] if a key written or read by `get_for_update` was
+/// modified by another transaction in the meantime.
+///
+/// Merges to keys without staged writes are not tracked, they are resolved on
+/// commit against the latest committed value.
 pub struct MemoryDBTransaction<'a, A: Allocator + Clone = Global> {
-    write: parking_lot::RwLockWriteGuard<'a, MapType<A>>,
+    map: &'a RwLock<MapType<A>>,
     alloc: A,
-    rollback: Vec<(KeyBytes<A>, Option<ValueBytes>), A>,
+    /// staged writes, `None` means deleted.
+    writes: WritesType<A>,
+    /// staged merge operands of keys without staged writes.
+    merges: MergesType<A>,
+    /// tracked keys with the committed value when they were first tracked.
+    tracked: RefCell<BTreeMap<KeyBytes<A>, Option<ValueBytes>, A>>,
+    /// staged writes and merges at each savepoint.
+    savepoints: Vec<(WritesType<A>, MergesType<A>)>,
+    /// max time every read and the commit wait for the map lock.
+    lock_timeout: Option<Duration>,
+}
+
+impl<A: Allocator + Clone> MemoryDBTransaction<'_, A> {
+    fn track(&self, key: &[u8]) -> Result<()> {
+        let mut tracked = self.tracked.borrow_mut();
+        if !tracked.contains_key(key) {
+            let committed = read_lock(self.map, self.lock_timeout)?.get(key).cloned();
+            tracked.insert(key.to_vec_in(self.alloc.clone()).into(), committed);
+        }
+        Ok(())
+    }
+
+    fn scan(&self, from: &[u8], to: Bound<&[u8]>) -> MemoryDBRangeIter<'_, A> {
+        let range = (Bound::Included(from), to);
+        let map = match read_lock(self.map, self.lock_timeout) {
+            Ok(map) => map,
+            Err(err) => {
+                return MemoryDBRangeIter {
+                    iter: Vec::new_in(self.alloc.clone()).into_iter(),
+                    error: Some(err),
+                    l: PhantomData,
+                }
+            }
+        };
+        let mut merged = MapType::new_in(self.alloc.clone());
+        merged.extend(
+            map.range::<[u8], _>(range)
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        drop(map);
+        for (k, v) in self.writes.range::<[u8], _>(range) {
+            if let Some(v) = v {
+                merged.insert(k.clone(), v.clone());
+            } else {
+                merged.remove(k);
+            }
+        }
+        for (k, operands) in self.merges.range::<[u8], _>(range) {
+            if let Some(v) = merge_operands(
+                merged.get(k).map(|v| v.as_ref()),
+                operands.iter().map(|operand| operand.as_ref()),
+            ) {
+                merged.insert(k.clone(), Arc::from(v));
+            }
+        }
+
+        let mut collection = Vec::new_in(self.alloc.clone());
+        collection.extend(merged);
+        MemoryDBRangeIter {
+            iter: collection.into_iter(),
+            error: None,
+            l: PhantomData,
+        }
+    }
 }
 
 impl<A: Allocator + Clone> DBRead for MemoryDBTransaction<'_, A> {
@@ -111,11 +228,27 @@ impl<A: Allocator + Clone> DBRead for MemoryDBTransaction<'_, A> {
         Self: 'a;
 
     fn get(&self, key: impl AsRef<[u8]>) -> crate::Result<Option<Self::ValueBytes<'_>>> {
-        Ok(self.write.get(key.as_ref()).cloned())
+        if let Some(staged) = self.writes.get(key.as_ref()) {
+            Ok(staged.clone())
+        } else {
+            let committed = read_lock(self.map, self.lock_timeout)?
+                .get(key.as_ref())
+                .cloned();
+            if let Some(operands) = self.merges.get(key.as_ref()) {
+                Ok(merge_operands(
+                    committed.as_deref(),
+                    operands.iter().map(|operand| operand.as_ref()),
+                )
+                .map(Arc::from)
+                .or(committed))
+            } else {
+                Ok(committed)
+            }
+        }
     }
 
     fn has(&self, key: impl AsRef<[u8]>) -> crate::Result<bool> {
-        Ok(self.write.get(key.as_ref()).is_some())
+        Ok(self.get(key)?.is_some())
     }
 
     type IterRange<'a> = MemoryDBRangeIter<'a, A>
@@ -123,34 +256,55 @@ impl<A: Allocator + Clone> DBRead for MemoryDBTransaction<'_, A> {
         Self: 'a;
 
     fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
-        let mut collection = Vec::new_in(self.alloc.clone());
-        collection.extend(
-            self.write
-                .range::<[u8], _>((
-                    std::ops::Bound::Included(from.as_ref()),
-                    std::ops::Bound::Excluded(to.as_ref()),
-                ))
-                .map(|(k, v)| (k.clone(), v.clone())),
-        );
-        MemoryDBRangeIter {
-            iter: collection.into_iter(),
-            l: PhantomData,
-        }
+        self.scan(from.as_ref(), Bound::Excluded(to.as_ref()))
+    }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        self.scan(from.as_ref(), Bound::Unbounded)
+    }
+
+    type ReadView<'a> = &'a Self
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        Ok(self)
     }
 }
 
 impl<A: Allocator + Clone> DBWrite for MemoryDBTransaction<'_, A> {
     fn set(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()> {
-        let key: Box<[u8], A> = key.as_ref().to_vec_in(self.alloc.clone()).into();
-        let old = self.write.insert(key.clone(), Arc::from(value.as_ref()));
-        self.rollback.push((key, old));
+        self.track(key.as_ref())?;
+        self.merges.remove(key.as_ref());
+        self.writes.insert(
+            key.as_ref().to_vec_in(self.alloc.clone()).into(),
+            Some(Arc::from(value.as_ref())),
+        );
         Ok(())
     }
 
     fn delete(&mut self, key: impl AsRef<[u8]>) -> Result<()> {
-        let old = self.write.remove(key.as_ref());
-        self.rollback
-            .push((key.as_ref().to_vec_in(self.alloc.clone()).into(), old));
+        self.track(key.as_ref())?;
+        self.merges.remove(key.as_ref());
+        self.writes
+            .insert(key.as_ref().to_vec_in(self.alloc.clone()).into(), None);
+        Ok(())
+    }
+
+    fn merge(&mut self, key: impl AsRef<[u8]>, operand: impl AsRef<[u8]>) -> Result<()> {
+        if let Some(staged) = self.writes.get_mut(key.as_ref()) {
+            // the value is already decided in this transaction, merge it now.
+            if let Some(merged) = merge_operands(staged.as_deref(), [operand.as_ref()]) {
+                *staged = Some(Arc::from(merged));
+            }
+        } else if let Some(operands) = self.merges.get_mut(key.as_ref()) {
+            operands.push(Arc::from(operand.as_ref()));
+        } else {
+            self.merges.insert(
+                key.as_ref().to_vec_in(self.alloc.clone()).into(),
+                vec![Arc::from(operand.as_ref())],
+            );
+        }
         Ok(())
     }
 }
@@ -161,41 +315,135 @@ impl<A: Allocator + Clone> DBLock for MemoryDBTransaction<'_, A> {
         Self: 'a;
 
     fn get_for_update(&self, key: impl AsRef<[u8]>) -> Result<Option<Self::ValueBytes<'_>>> {
-        Ok(self.write.get(key.as_ref()).cloned())
+        self.track(key.as_ref())?;
+        self.get(key)
     }
 }
 
 impl<A: Allocator + Clone> DBTransaction for MemoryDBTransaction<'_, A> {
-    fn rollback(mut self) -> Result<()> {
-        for (key, value) in self.rollback.into_iter().rev() {
+    fn rollback(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn commit(self) -> Result<()> {
+        let mut map = write_lock(self.map, self.lock_timeout)?;
+
+        for (key, value) in self.tracked.into_inner() {
+            let unchanged = match (map.get(&key), &value) {
+                (None, None) => true,
+                (Some(current), Some(value)) => Arc::ptr_eq(current, value),
+                _ => false,
+            };
+            if !unchanged {
+                return Err(Error::Conflict);
+            }
+        }
+
+        for (key, value) in self.writes {
             if let Some(value) = value {
-                self.write.insert(key, value);
+                map.insert(key, value);
             } else {
-                self.write.remove(&key);
+                map.remove(&key);
+            }
+        }
+        for (key, operands) in self.merges {
+            if let Some(value) = merge_operands(
+                map.get(&key).map(|v| v.as_ref()),
+                operands.iter().map(|operand| operand.as_ref()),
+            ) {
+                map.insert(key, Arc::from(value));
             }
         }
         Ok(())
     }
 
-    fn commit(self) -> Result<()> {
+    fn savepoint(&mut self) -> Result<()> {
+        self.savepoints
+            .push((self.writes.clone(), self.merges.clone()));
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        (self.writes, self.merges) = self.savepoints.pop().ok_or(Error::NoSavepoint)?;
+        Ok(())
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        self.savepoints.pop().ok_or(Error::NoSavepoint)?;
         Ok(())
     }
+
+    fn pending_changes(&self) -> usize {
+        self.writes.len() + self.merges.len()
+    }
+
+    fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        self.lock_timeout = timeout;
+    }
+}
+
+/// Lock `lock` for reading, waiting at most `timeout`.
+pub(super) fn read_lock<T>(
+    lock: &RwLock<T>,
+    timeout: Option<Duration>,
+) -> Result<RwLockReadGuard<'_, T>> {
+    match timeout {
+        Some(timeout) => lock.try_read_for(timeout).ok_or(Error::LockTimeout),
+        None => Ok(lock.read()),
+    }
+}
+
+/// Lock `lock` for writing, waiting at most `timeout`.
+fn write_lock<T>(lock: &RwLock<T>, timeout: Option<Duration>) -> Result<RwLockWriteGuard<'_, T>> {
+    match timeout {
+        Some(timeout) => lock.try_write_for(timeout).ok_or(Error::LockTimeout),
+        None => Ok(lock.write()),
+    }
 }
 
 impl<A: Allocator + Clone> DB for MemoryDB<A> {
     type Transaction<'a> = MemoryDBTransaction<'a, A>
     where A: 'a;
 
+    type Snapshot<'a> = ReadOnly<MemoryDB<A>>
+    where A: 'a;
+
     fn start_transaction(&self) -> crate::Result<Self::Transaction<'_>> {
         Ok(MemoryDBTransaction {
-            write: self.map.write(),
+            map: &self.map,
             alloc: self.alloc.clone(),
-            rollback: Vec::with_capacity_in(8, self.alloc.clone()),
+            writes: BTreeMap::new_in(self.alloc.clone()),
+            merges: BTreeMap::new_in(self.alloc.clone()),
+            tracked: RefCell::new(BTreeMap::new_in(self.alloc.clone())),
+            savepoints: Default::default(),
+            lock_timeout: None,
         })
     }
 
+    /// A copy of the map, see [`Clone`].
+    fn snapshot(&self) -> Result<Self::Snapshot<'_>> {
+        Ok(ReadOnly::new(self.clone()))
+    }
+
     fn clear(&mut self) -> Result<()> {
         self.map.write().clear();
         Ok(())
     }
+
+    fn approximate_size(&self) -> Result<u64> {
+        Ok(self
+            .map
+            .read()
+            .iter()
+            .map(|(k, v)| (k.len() + v.len()) as u64)
+            .sum())
+    }
+
+    fn key_count(&self) -> Result<usize> {
+        Ok(self.map.read().len())
+    }
+
+    fn compact_range(&self, _from: Option<&[u8]>, _to: Option<&[u8]>) -> Result<()> {
+        Ok(())
+    }
 }
@@ -1,2 +1,4 @@
 pub mod memory;
-pub mod rocks;
\ No newline at end of file
+pub mod rocks;
+#[cfg(feature = "sled")]
+pub mod sled;
@@ -1,6 +1,6 @@
 use rocksdb::OptimisticTransactionDB;
 
-use crate::{DBLock, DBRead, DBTransaction, DBWrite, Error, Result, DB};
+use crate::{merge, DBLock, DBRead, DBTransaction, DBWrite, DurabilityMode, Error, Result, DB};
 
 #[derive(Debug)]
 pub struct RocksDB {
@@ -11,10 +11,33 @@ impl RocksDB {
     pub fn open_or_create_database(path: impl AsRef<std::path::Path>) -> Result<Self> {
         let mut opts = rocksdb::Options::default();
         opts.create_if_missing(true);
+        opts.set_merge_operator_associative(
+            merge::MERGE_OPERATOR_NAME,
+            |_key, existing, operands| merge::merge_operands(existing, operands.iter()),
+        );
 
         let db = OptimisticTransactionDB::open(&opts, path)?;
         Ok(Self { db })
     }
+
+    /// Open an existing database read-only, e.g. to inspect the state of a
+    /// running tracker. The handle only implements [`DBRead`], and sees the
+    /// database as of the time it is opened.
+    pub fn open_read_only(path: impl AsRef<std::path::Path>) -> Result<RocksDBReadOnly> {
+        let mut opts = rocksdb::Options::default();
+        opts.set_merge_operator_associative(
+            merge::MERGE_OPERATOR_NAME,
+            |_key, existing, operands| merge::merge_operands(existing, operands.iter()),
+        );
+
+        let db = rocksdb::DB::open_for_read_only(&opts, path, false)?;
+        Ok(RocksDBReadOnly { db })
+    }
+}
+
+#[derive(Debug)]
+pub struct RocksDBReadOnly {
+    db: rocksdb::DB,
 }
 
 pub enum RocksDBBytes<'a> {
@@ -72,6 +95,149 @@ impl DBRead for RocksDB {
             check_upper_bound: None,
         }
     }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(
+            from.as_ref(),
+            rocksdb::Direction::Forward,
+        ));
+
+        Self::IterRange {
+            iter,
+            check_upper_bound: None,
+        }
+    }
+
+    type ReadView<'a> = RocksDBSnapshot<'a>
+    where
+        Self: 'a;
+
+    /// A snapshot, see [`DB::snapshot`].
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        self.snapshot()
+    }
+}
+
+impl DBRead for RocksDBReadOnly {
+    type KeyBytes<'a> = Box<[u8]>
+    where
+        Self: 'a;
+
+    type ValueBytes<'a> = RocksDBBytes<'a>
+    where
+        Self: 'a;
+
+    fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Self::ValueBytes<'_>>> {
+        Ok(self.db.get_pinned(key)?.map(|b| b.into()))
+    }
+
+    fn has(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        Ok(self.db.get_pinned(key)?.is_some())
+    }
+
+    type IterRange<'a> = RocksDBRangeIter<'a, rocksdb::DB>
+    where
+        Self: 'a;
+
+    fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        let upper_bound = to.as_ref().to_vec();
+        let mut read_opt = rocksdb::ReadOptions::default();
+        read_opt.set_iterate_upper_bound(upper_bound);
+        let iter = self.db.iterator_opt(
+            rocksdb::IteratorMode::From(from.as_ref(), rocksdb::Direction::Forward),
+            read_opt,
+        );
+
+        Self::IterRange {
+            iter,
+            check_upper_bound: None,
+        }
+    }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        let iter = self.db.iterator(rocksdb::IteratorMode::From(
+            from.as_ref(),
+            rocksdb::Direction::Forward,
+        ));
+
+        Self::IterRange {
+            iter,
+            check_upper_bound: None,
+        }
+    }
+
+    type ReadView<'a> = &'a Self
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        Ok(self)
+    }
+}
+
+/// A snapshot of [`RocksDB`], see [`DB::snapshot`].
+pub struct RocksDBSnapshot<'db> {
+    snapshot: rocksdb::SnapshotWithThreadMode<'db, OptimisticTransactionDB>,
+}
+
+impl<'db> DBRead for RocksDBSnapshot<'db> {
+    type KeyBytes<'a> = Box<[u8]>
+    where
+        Self: 'a;
+
+    type ValueBytes<'a> = RocksDBBytes<'a>
+    where
+        Self: 'a;
+
+    fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Self::ValueBytes<'_>>> {
+        Ok(self
+            .snapshot
+            .get(key)?
+            .map(|b| RocksDBBytes::Owned(b.into_boxed_slice())))
+    }
+
+    fn has(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        Ok(self.snapshot.get(key)?.is_some())
+    }
+
+    type IterRange<'a> = RocksDBRangeIter<'a, OptimisticTransactionDB>
+    where
+        Self: 'a;
+
+    fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        let upper_bound = to.as_ref().to_vec();
+        let mut read_opt = rocksdb::ReadOptions::default();
+        read_opt.set_iterate_upper_bound(upper_bound);
+        let iter = self.snapshot.iterator_opt(
+            rocksdb::IteratorMode::From(from.as_ref(), rocksdb::Direction::Forward),
+            read_opt,
+        );
+
+        Self::IterRange {
+            iter,
+            check_upper_bound: None,
+        }
+    }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        let iter = self.snapshot.iterator(rocksdb::IteratorMode::From(
+            from.as_ref(),
+            rocksdb::Direction::Forward,
+        ));
+
+        Self::IterRange {
+            iter,
+            check_upper_bound: None,
+        }
+    }
+
+    type ReadView<'a> = &'a Self
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        Ok(self)
+    }
 }
 
 impl DB for RocksDB {
@@ -79,9 +245,38 @@ impl DB for RocksDB {
     where
         Self: 'a;
 
+    type Snapshot<'a> = RocksDBSnapshot<'a>
+    where
+        Self: 'a;
+
     fn start_transaction(&self) -> Result<Self::Transaction<'_>> {
         Ok(RocksDBTransaction {
             transaction: self.db.transaction(),
+            savepoints: Default::default(),
+            pending_changes: 0,
+        })
+    }
+
+    fn snapshot(&self) -> Result<Self::Snapshot<'_>> {
+        Ok(RocksDBSnapshot {
+            snapshot: self.db.snapshot(),
+        })
+    }
+
+    fn start_transaction_with(&self, durability: DurabilityMode) -> Result<Self::Transaction<'_>> {
+        let mut write_opt = rocksdb::WriteOptions::default();
+        match durability {
+            DurabilityMode::Safe => write_opt.set_sync(true),
+            DurabilityMode::Fast => {}
+            DurabilityMode::NoSync => write_opt.disable_wal(true),
+        }
+        Ok(RocksDBTransaction {
+            transaction: self.db.transaction_opt(
+                &write_opt,
+                &rocksdb::OptimisticTransactionOptions::default(),
+            ),
+            savepoints: Default::default(),
+            pending_changes: 0,
         })
     }
 
@@ -91,6 +286,27 @@ impl DB for RocksDB {
         }
         Ok(())
     }
+
+    fn approximate_size(&self) -> Result<u64> {
+        Ok(self
+            .db
+            .property_int_value("rocksdb.estimate-live-data-size")?
+            .unwrap_or_default())
+    }
+
+    fn key_count(&self) -> Result<usize> {
+        let mut count = 0;
+        for item in self.db.iterator(rocksdb::IteratorMode::Start) {
+            item?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn compact_range(&self, from: Option<&[u8]>, to: Option<&[u8]>) -> Result<()> {
+        self.db.compact_range(from, to);
+        Ok(())
+    }
 }
 
 pub struct RocksDBRangeIter<'a, D: rocksdb::DBAccess> {
@@ -121,6 +337,12 @@ impl<'a, D: rocksdb::DBAccess> Iterator for RocksDBRangeIter<'a, D> {
 
 pub struct RocksDBTransaction<'db> {
     transaction: rocksdb::Transaction<'db, OptimisticTransactionDB>,
+    /// number of rocksdb savepoints in each savepoint, rocksdb can't remove a
+    /// savepoint without rolling back to it, so released savepoints are merged
+    /// into the previous one. Also keeps the pending changes at the savepoint.
+    savepoints: Vec<(usize, usize)>,
+    /// writes issued, rocksdb doesn't expose the size of the write batch.
+    pending_changes: usize,
 }
 
 impl<'db> DBRead for RocksDBTransaction<'db> {
@@ -158,16 +380,44 @@ impl<'db> DBRead for RocksDBTransaction<'db> {
             check_upper_bound: Some(upper_bound),
         }
     }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        let iter = self.transaction.iterator(rocksdb::IteratorMode::From(
+            from.as_ref(),
+            rocksdb::Direction::Forward,
+        ));
+
+        Self::IterRange {
+            iter,
+            check_upper_bound: None,
+        }
+    }
+
+    type ReadView<'a> = &'a Self
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        Ok(self)
+    }
 }
 
 impl DBWrite for RocksDBTransaction<'_> {
     fn set(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()> {
         self.transaction.put(key, value)?;
+        self.pending_changes += 1;
         Ok(())
     }
 
     fn delete(&mut self, key: impl AsRef<[u8]>) -> Result<()> {
         self.transaction.delete(key)?;
+        self.pending_changes += 1;
+        Ok(())
+    }
+
+    fn merge(&mut self, key: impl AsRef<[u8]>, operand: impl AsRef<[u8]>) -> Result<()> {
+        self.transaction.merge(key, operand)?;
+        self.pending_changes += 1;
         Ok(())
     }
 }
@@ -195,4 +445,31 @@ impl DBTransaction for RocksDBTransaction<'_> {
         self.transaction.commit()?;
         Ok(())
     }
+
+    fn savepoint(&mut self) -> Result<()> {
+        self.transaction.set_savepoint();
+        self.savepoints.push((1, self.pending_changes));
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        let (count, pending_changes) = self.savepoints.pop().ok_or(Error::NoSavepoint)?;
+        for _ in 0..count {
+            self.transaction.rollback_to_savepoint()?;
+        }
+        self.pending_changes = pending_changes;
+        Ok(())
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        let (count, _) = self.savepoints.pop().ok_or(Error::NoSavepoint)?;
+        if let Some((last, _)) = self.savepoints.last_mut() {
+            *last += count;
+        }
+        Ok(())
+    }
+
+    fn pending_changes(&self) -> usize {
+        self.pending_changes
+    }
 }
@@ -0,0 +1,371 @@
+use std::{cell::RefCell, collections::BTreeMap, ops::Bound, time::Duration};
+
+use parking_lot::RwLock;
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    IVec,
+};
+
+use crate::{
+    merge::merge_operands, DBLock, DBRead, DBTransaction, DBWrite, Error, ReadOnly, Result, DB,
+};
+
+use super::memory::{read_lock, MemoryDB};
+
+type WritesType = BTreeMap<IVec, Option<IVec>>;
+type MergesType = BTreeMap<IVec, Vec<IVec>>;
+
+#[derive(Debug)]
+pub struct SledDB {
+    db: sled::Db,
+    /// Held shared by the commits and exclusively by [`DB::snapshot`], so a
+    /// snapshot never sees half of a commit.
+    commits: RwLock<()>,
+}
+
+impl SledDB {
+    pub fn open_or_create_database(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            db,
+            commits: Default::default(),
+        })
+    }
+}
+
+impl DBRead for SledDB {
+    type KeyBytes<'a> = IVec
+    where
+        Self: 'a;
+
+    type ValueBytes<'a> = IVec
+    where
+        Self: 'a;
+
+    fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Self::ValueBytes<'_>>> {
+        Ok(self.db.get(key)?)
+    }
+
+    fn has(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        Ok(self.db.contains_key(key)?)
+    }
+
+    type IterRange<'a> = SledDBRangeIter
+    where
+        Self: 'a;
+
+    fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        SledDBRangeIter {
+            iter: self.db.range(from.as_ref()..to.as_ref()),
+        }
+    }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        SledDBRangeIter {
+            iter: self.db.range(from.as_ref()..),
+        }
+    }
+
+    type ReadView<'a> = <Self as DB>::Snapshot<'a>
+    where
+        Self: 'a;
+
+    /// A snapshot, see [`DB::snapshot`].
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        self.snapshot()
+    }
+}
+
+impl DB for SledDB {
+    type Transaction<'a> = SledDBTransaction<'a>
+    where
+        Self: 'a;
+
+    type Snapshot<'a> = ReadOnly<MemoryDB>
+    where
+        Self: 'a;
+
+    fn start_transaction(&self) -> Result<Self::Transaction<'_>> {
+        Ok(SledDBTransaction {
+            db: &self.db,
+            commits: &self.commits,
+            writes: Default::default(),
+            merges: Default::default(),
+            tracked: Default::default(),
+            savepoints: Default::default(),
+            lock_timeout: None,
+        })
+    }
+
+    /// sled has no point-in-time reads, the snapshot is a copy of the database
+    /// read by one scan while the commits are held off, so it is consistent
+    /// but costs a full scan.
+    fn snapshot(&self) -> Result<Self::Snapshot<'_>> {
+        let _commits = self.commits.write();
+        let copy = MemoryDB::default();
+        let mut transaction = copy.start_transaction()?;
+        for item in self.db.iter() {
+            let (key, value) = item?;
+            transaction.set(key, value)?;
+        }
+        transaction.commit()?;
+        Ok(copy.read_only())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.db.clear()?;
+        Ok(())
+    }
+
+    fn approximate_size(&self) -> Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+
+    fn key_count(&self) -> Result<usize> {
+        Ok(self.db.len())
+    }
+
+    /// sled has no manual compaction, its pages are rewritten in the
+    /// background.
+    fn compact_range(&self, _from: Option<&[u8]>, _to: Option<&[u8]>) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct SledDBRangeIter {
+    iter: sled::Iter,
+}
+
+impl Iterator for SledDBRangeIter {
+    type Item = Result<(IVec, IVec)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|item| item.map_err(Error::from))
+    }
+}
+
+/// A transaction over [`SledDB`], sled only offers closure based transactions,
+/// so writes are staged here and replayed in a sled transaction on commit. The
+/// commit fails with [`Error::Conflict`] if a key written or read by
+/// `get_for_update` was modified by another transaction in the meantime, same
+/// as the optimistic transaction of rocksdb.
+///
+/// Merges to keys without staged writes are not tracked, they are resolved in
+/// the sled transaction on commit.
+pub struct SledDBTransaction<'db> {
+    db: &'db sled::Db,
+    commits: &'db RwLock<()>,
+    /// staged writes, `None` means deleted.
+    writes: WritesType,
+    /// staged merge operands of keys without staged writes.
+    merges: MergesType,
+    /// tracked keys with the committed value when they were first tracked.
+    tracked: RefCell<BTreeMap<IVec, Option<IVec>>>,
+    /// staged writes and merges at each savepoint.
+    savepoints: Vec<(WritesType, MergesType)>,
+    /// max time the commit waits for a running snapshot.
+    lock_timeout: Option<Duration>,
+}
+
+impl SledDBTransaction<'_> {
+    fn track(&self, key: &[u8]) -> Result<()> {
+        let mut tracked = self.tracked.borrow_mut();
+        if !tracked.contains_key(key) {
+            tracked.insert(key.into(), self.db.get(key)?);
+        }
+        Ok(())
+    }
+
+    fn scan(&self, from: &[u8], to: Bound<&[u8]>) -> std::vec::IntoIter<Result<(IVec, IVec)>> {
+        let range = (Bound::Included(from), to);
+        let mut merged = BTreeMap::new();
+        for item in self.db.range::<&[u8], _>(range) {
+            match item {
+                Ok((k, v)) => {
+                    merged.insert(k, v);
+                }
+                Err(err) => return vec![Err(err.into())].into_iter(),
+            }
+        }
+        for (k, v) in self.writes.range::<[u8], _>(range) {
+            if let Some(v) = v {
+                merged.insert(k.clone(), v.clone());
+            } else {
+                merged.remove(k);
+            }
+        }
+        for (k, operands) in self.merges.range::<[u8], _>(range) {
+            if let Some(v) = merge_operands(
+                merged.get(k).map(|v| v.as_ref()),
+                operands.iter().map(|operand| operand.as_ref()),
+            ) {
+                merged.insert(k.clone(), v.into());
+            }
+        }
+
+        merged.into_iter().map(Ok).collect::<Vec<_>>().into_iter()
+    }
+}
+
+impl<'db> DBRead for SledDBTransaction<'db> {
+    type KeyBytes<'a> = IVec
+    where
+        Self: 'a;
+
+    type ValueBytes<'a> = IVec
+    where
+        Self: 'a;
+
+    fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Self::ValueBytes<'_>>> {
+        if let Some(staged) = self.writes.get(key.as_ref()) {
+            Ok(staged.clone())
+        } else if let Some(operands) = self.merges.get(key.as_ref()) {
+            let committed = self.db.get(key)?;
+            Ok(merge_operands(
+                committed.as_deref(),
+                operands.iter().map(|operand| operand.as_ref()),
+            )
+            .map(IVec::from)
+            .or(committed))
+        } else {
+            Ok(self.db.get(key)?)
+        }
+    }
+
+    fn has(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    type IterRange<'a> = std::vec::IntoIter<Result<(IVec, IVec)>>
+    where
+        Self: 'a;
+
+    fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        self.scan(from.as_ref(), Bound::Excluded(to.as_ref()))
+    }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        self.scan(from.as_ref(), Bound::Unbounded)
+    }
+
+    type ReadView<'a> = &'a Self
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        Ok(self)
+    }
+}
+
+impl DBWrite for SledDBTransaction<'_> {
+    fn set(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()> {
+        self.track(key.as_ref())?;
+        self.merges.remove(key.as_ref());
+        self.writes
+            .insert(key.as_ref().into(), Some(value.as_ref().into()));
+        Ok(())
+    }
+
+    fn delete(&mut self, key: impl AsRef<[u8]>) -> Result<()> {
+        self.track(key.as_ref())?;
+        self.merges.remove(key.as_ref());
+        self.writes.insert(key.as_ref().into(), None);
+        Ok(())
+    }
+
+    fn merge(&mut self, key: impl AsRef<[u8]>, operand: impl AsRef<[u8]>) -> Result<()> {
+        if let Some(staged) = self.writes.get_mut(key.as_ref()) {
+            // the value is already decided in this transaction, merge it now.
+            if let Some(merged) = merge_operands(staged.as_deref(), [operand.as_ref()]) {
+                *staged = Some(merged.into());
+            }
+        } else {
+            self.merges
+                .entry(key.as_ref().into())
+                .or_default()
+                .push(operand.as_ref().into());
+        }
+        Ok(())
+    }
+}
+
+impl DBLock for SledDBTransaction<'_> {
+    type ValueBytes<'a> = IVec
+    where
+        Self: 'a;
+
+    fn get_for_update(&self, key: impl AsRef<[u8]>) -> Result<Option<Self::ValueBytes<'_>>> {
+        self.track(key.as_ref())?;
+        self.get(key)
+    }
+}
+
+impl DBTransaction for SledDBTransaction<'_> {
+    fn rollback(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn commit(self) -> Result<()> {
+        let tracked = self.tracked.into_inner();
+        let writes = self.writes;
+        let merges = self.merges;
+
+        let _commit = read_lock(self.commits, self.lock_timeout)?;
+        let result = self.db.transaction(|tx| {
+            for (key, value) in tracked.iter() {
+                if &tx.get(key)? != value {
+                    return Err(ConflictableTransactionError::Abort(()));
+                }
+            }
+
+            for (key, value) in writes.iter() {
+                if let Some(value) = value {
+                    tx.insert(key, value)?;
+                } else {
+                    tx.remove(key)?;
+                }
+            }
+
+            for (key, operands) in merges.iter() {
+                let existing = tx.get(key)?;
+                if let Some(value) = merge_operands(
+                    existing.as_deref(),
+                    operands.iter().map(|operand| operand.as_ref()),
+                ) {
+                    tx.insert(key, value)?;
+                }
+            }
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(TransactionError::Abort(())) => Err(Error::Conflict),
+            Err(TransactionError::Storage(err)) => Err(err.into()),
+        }
+    }
+
+    fn savepoint(&mut self) -> Result<()> {
+        self.savepoints
+            .push((self.writes.clone(), self.merges.clone()));
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        (self.writes, self.merges) = self.savepoints.pop().ok_or(Error::NoSavepoint)?;
+        Ok(())
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        self.savepoints.pop().ok_or(Error::NoSavepoint)?;
+        Ok(())
+    }
+
+    fn pending_changes(&self) -> usize {
+        self.writes.len() + self.merges.len()
+    }
+
+    fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        self.lock_timeout = timeout;
+    }
+}
@@ -3,9 +3,14 @@
 #![feature(macro_metavar_expr)] // for the macro in tests.rs
 
 pub mod backend;
+pub mod merge;
 pub mod prefix;
 
-use std::alloc::Allocator;
+use std::{
+    alloc::Allocator,
+    ops::{Bound, RangeBounds},
+    time::Duration,
+};
 
 use prefix::Prefix;
 use thiserror::Error;
@@ -13,7 +18,32 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("rocksdb error")]
-    RocksdbError(#[from] rocksdb::Error),
+    RocksdbError(rocksdb::Error),
+    #[cfg(feature = "sled")]
+    #[error("sled error")]
+    SledError(#[from] sled::Error),
+    #[error("transaction conflict")]
+    Conflict,
+    #[error("no savepoint in the transaction")]
+    NoSavepoint,
+    /// Waited longer than the lock timeout of the transaction, see
+    /// [`DBTransaction::set_lock_timeout`]. Unlike other errors the
+    /// transaction can be retried after backing off.
+    #[error("lock wait timed out")]
+    LockTimeout,
+}
+
+impl From<rocksdb::Error> for Error {
+    fn from(err: rocksdb::Error) -> Self {
+        match err.kind() {
+            rocksdb::ErrorKind::TimedOut => Error::LockTimeout,
+            // an optimistic transaction fails its commit with `Busy` if a key
+            // it read for update or wrote was changed since, and `TryAgain` if
+            // the memtable no longer holds the history to tell
+            rocksdb::ErrorKind::Busy | rocksdb::ErrorKind::TryAgain => Error::Conflict,
+            _ => Error::RocksdbError(err),
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -31,10 +61,65 @@ pub trait DBRead {
 
     fn has(&self, key: impl AsRef<[u8]>) -> Result<bool>;
 
+    /// View to read many keys consistently, e.g. to render a whole tree while
+    /// it is written. A database reads from a snapshot, see [`DB::snapshot`],
+    /// views that are already consistent, like a snapshot or a transaction,
+    /// read from themselves.
+    type ReadView<'a>: DBRead
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>>;
+
     type IterRange<'a>: Iterator<Item = Result<(Self::KeyBytes<'a>, Self::ValueBytes<'a>)>>
     where
         Self: 'a;
+    /// Iterate the keys in `[from, to)`. Every backend orders keys by
+    /// comparing the bytes as unsigned, a key sorts right after its prefixes,
+    /// the same as rocksdb's default bytewise comparator. The store relies on
+    /// this for log and children scans.
     fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_>;
+
+    /// Iterate the keys not less than `from`.
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_>;
+
+    /// Iterate the keys in `range`, each end can be inclusive, exclusive or
+    /// unbounded, e.g. `(Bound::Excluded(from), Bound::Included(to))`. The
+    /// range is empty if the end is before the start.
+    fn get_range_bounded(&self, range: impl RangeBounds<[u8]>) -> Self::IterRange<'_> {
+        let from = match range.start_bound() {
+            Bound::Included(from) => from.to_vec(),
+            // the smallest key greater than `from`
+            Bound::Excluded(from) => [from, &[0]].concat(),
+            Bound::Unbounded => Vec::new(),
+        };
+        let to = match range.end_bound() {
+            Bound::Included(to) => [to, &[0]].concat(),
+            Bound::Excluded(to) => to.to_vec(),
+            Bound::Unbounded => return self.get_range_from(from),
+        };
+
+        if to < from {
+            // `[from, from)` is empty on every backend
+            self.get_range(&from, &from)
+        } else {
+            self.get_range(from, to)
+        }
+    }
+
+    /// Iterate the keys starting with `prefix`, see
+    /// [`prefix::prefix_upper_bound`].
+    fn prefix_scan(&self, prefix: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        let prefix = prefix.as_ref();
+        let upper_bound = prefix::prefix_upper_bound(prefix);
+        // without upper bound every key not less than the prefix starts with it
+        self.get_range_bounded((
+            Bound::Included(prefix),
+            upper_bound
+                .as_deref()
+                .map_or(Bound::Unbounded, Bound::Excluded),
+        ))
+    }
 }
 
 impl<T: DBRead> DBRead for &T {
@@ -58,6 +143,18 @@ impl<T: DBRead> DBRead for &T {
     fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
         T::get_range(self, from, to)
     }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        T::get_range_from(self, from)
+    }
+
+    type ReadView<'a> = T::ReadView<'a>
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        T::read_view(self)
+    }
 }
 
 impl<T: DBRead> DBRead for &mut T {
@@ -81,6 +178,64 @@ impl<T: DBRead> DBRead for &mut T {
     fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
         T::get_range(self, from, to)
     }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        T::get_range_from(self, from)
+    }
+
+    type ReadView<'a> = T::ReadView<'a>
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        T::read_view(self)
+    }
+}
+
+/// Read-only view of a database, only implements [`DBRead`], so it can be
+/// handed to code that must not write, e.g. to render the state of a trie.
+#[derive(Debug, Clone)]
+pub struct ReadOnly<T>(T);
+
+impl<T: DBRead> ReadOnly<T> {
+    pub fn new(db: T) -> Self {
+        Self(db)
+    }
+}
+
+impl<T: DBRead> DBRead for ReadOnly<T> {
+    type KeyBytes<'a> = T::KeyBytes<'a>
+    where
+        Self: 'a;
+    type ValueBytes<'a> = T::ValueBytes<'a>
+    where
+        Self: 'a;
+    fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Self::ValueBytes<'_>>> {
+        self.0.get(key)
+    }
+
+    fn has(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        self.0.has(key)
+    }
+
+    type IterRange<'a> = T::IterRange<'a>
+    where
+        Self: 'a;
+    fn get_range(&self, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        self.0.get_range(from, to)
+    }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        self.0.get_range_from(from)
+    }
+
+    type ReadView<'a> = T::ReadView<'a>
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        self.0.read_view()
+    }
 }
 
 pub trait DBReadDyn {
@@ -89,6 +244,8 @@ pub trait DBReadDyn {
     fn has(&self, key: &[u8]) -> Result<bool>;
 
     fn get_range(&self, from: &[u8], to: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    fn prefix_scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
 }
 
 impl<T: DBRead> DBReadDyn for T {
@@ -108,12 +265,30 @@ impl<T: DBRead> DBReadDyn for T {
         }
         Ok(vec)
     }
+
+    fn prefix_scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut vec = vec![];
+        for item in DBRead::prefix_scan(self, prefix) {
+            let (key, value) = item?;
+            vec.push((key.as_ref().to_vec(), value.as_ref().to_vec()))
+        }
+        Ok(vec)
+    }
 }
 
 pub trait DBWrite {
     fn set(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()>;
 
     fn delete(&mut self, key: impl AsRef<[u8]>) -> Result<()>;
+
+    /// Merge `operand` into the value of `key` without reading it, the operand
+    /// is a [`merge::MergeValue`] in bytes.
+    ///
+    /// Merges are resolved on commit against the latest committed value, so
+    /// concurrent merges to the same key don't conflict in the memory and sled
+    /// backends. The optimistic transaction of rocksdb still checks conflicts
+    /// on merged keys.
+    fn merge(&mut self, key: impl AsRef<[u8]>, operand: impl AsRef<[u8]>) -> Result<()>;
 }
 
 impl<T: DBWrite> DBWrite for &mut T {
@@ -124,12 +299,18 @@ impl<T: DBWrite> DBWrite for &mut T {
     fn delete(&mut self, key: impl AsRef<[u8]>) -> Result<()> {
         T::delete(self, key)
     }
+
+    fn merge(&mut self, key: impl AsRef<[u8]>, operand: impl AsRef<[u8]>) -> Result<()> {
+        T::merge(self, key, operand)
+    }
 }
 
 pub trait DBWriteDyn {
     fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()>;
 
     fn delete(&mut self, key: &[u8]) -> Result<()>;
+
+    fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<()>;
 }
 
 impl<T: DBWrite> DBWriteDyn for T {
@@ -140,6 +321,10 @@ impl<T: DBWrite> DBWriteDyn for T {
     fn delete(&mut self, key: &[u8]) -> Result<()> {
         DBWrite::delete(self, key)
     }
+
+    fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<()> {
+        DBWrite::merge(self, key, operand)
+    }
 }
 
 pub trait DBLock {
@@ -185,6 +370,41 @@ pub trait DBTransaction: DBWrite + DBRead + DBLock {
 
     fn commit(self) -> Result<()>;
 
+    /// Mark the current state of the transaction, savepoints can be nested.
+    fn savepoint(&mut self) -> Result<()>;
+
+    /// Discard the writes after the last savepoint and remove it, returns
+    /// [`Error::NoSavepoint`] if there is no savepoint.
+    fn rollback_to_savepoint(&mut self) -> Result<()>;
+
+    /// Remove the last savepoint and keep the writes after it, returns
+    /// [`Error::NoSavepoint`] if there is no savepoint.
+    fn release_savepoint(&mut self) -> Result<()>;
+
+    /// Number of writes (sets, deletes and merges) staged by the transaction,
+    /// writes rolled back to a savepoint are not counted. Backends staging
+    /// the writes by key count a key written twice once.
+    fn pending_changes(&self) -> usize;
+
+    /// Max time to wait for the locks of the database, e.g. while another
+    /// transaction commits, before failing with [`Error::LockTimeout`]. `None`
+    /// waits forever, the default.
+    ///
+    /// Supported per backend:
+    /// - memory: every read of the transaction and its commit wait for the
+    ///   lock of the map at most this long.
+    /// - sled: the commit waits at most this long for a running snapshot.
+    /// - rocksdb: ignored, its optimistic transactions take no locks, a
+    ///   concurrent write fails the commit with [`Error::Conflict`] instead.
+    fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        let _ = timeout;
+    }
+
+    /// Whether the transaction has no staged writes.
+    fn is_empty(&self) -> bool {
+        self.pending_changes() == 0
+    }
+
     fn prefix(self, prefix: impl AsRef<[u8]>) -> Prefix<Self>
     where
         Self: std::marker::Sized,
@@ -208,6 +428,16 @@ pub trait DBTransactionDyn: DBWriteDyn + DBReadDyn + DBLockDyn {
     fn rollback(self) -> Result<()>;
 
     fn commit(self) -> Result<()>;
+
+    fn savepoint(&mut self) -> Result<()>;
+
+    fn rollback_to_savepoint(&mut self) -> Result<()>;
+
+    fn release_savepoint(&mut self) -> Result<()>;
+
+    fn pending_changes(&self) -> usize;
+
+    fn set_lock_timeout(&mut self, timeout: Option<Duration>);
 }
 
 impl<T: DBTransaction> DBTransactionDyn for T {
@@ -218,6 +448,41 @@ impl<T: DBTransaction> DBTransactionDyn for T {
     fn commit(self) -> Result<()> {
         self.commit()
     }
+
+    fn savepoint(&mut self) -> Result<()> {
+        DBTransaction::savepoint(self)
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        DBTransaction::rollback_to_savepoint(self)
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        DBTransaction::release_savepoint(self)
+    }
+
+    fn pending_changes(&self) -> usize {
+        DBTransaction::pending_changes(self)
+    }
+
+    fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        DBTransaction::set_lock_timeout(self, timeout)
+    }
+}
+
+/// How hard a commit tries to make the writes survive a crash.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DurabilityMode {
+    /// Write the log and sync it to the disk before the commit returns, the
+    /// writes survive a power loss.
+    Safe,
+    /// Write the log without syncing it, the writes survive a process crash
+    /// but the last commits may be lost on a power loss.
+    #[default]
+    Fast,
+    /// Skip the log, the writes may be lost on any crash. Meant for bulk
+    /// writes that can be redone, like the initial index.
+    NoSync,
 }
 
 pub trait DB: DBRead {
@@ -225,11 +490,38 @@ pub trait DB: DBRead {
     where
         Self: 'a;
 
+    /// Read-only view of the database at the time of the call, the commits
+    /// after it are not seen. Used to read many keys consistently, e.g. to
+    /// render a whole tree while it is written.
+    type Snapshot<'a>: DBRead
+    where
+        Self: 'a;
+
     fn start_transaction(&self) -> Result<Self::Transaction<'_>>;
 
+    fn snapshot(&self) -> Result<Self::Snapshot<'_>>;
+
+    /// Start a transaction which commits with `durability`, backends without a
+    /// log ignore it.
+    fn start_transaction_with(&self, durability: DurabilityMode) -> Result<Self::Transaction<'_>> {
+        let _ = durability;
+        self.start_transaction()
+    }
+
     /// for debug purpose
     fn clear(&mut self) -> Result<()>;
 
+    /// Approximate size in bytes of the data stored in the database.
+    fn approximate_size(&self) -> Result<u64>;
+
+    /// Number of keys stored in the database.
+    fn key_count(&self) -> Result<usize>;
+
+    /// Compact the keys from `from` (included) to `to` (excluded), `None` is
+    /// unbounded. The space of the deleted keys is reclaimed on the disk,
+    /// backends without files on the disk do nothing.
+    fn compact_range(&self, from: Option<&[u8]>, to: Option<&[u8]>) -> Result<()>;
+
     fn prefix(self, prefix: impl AsRef<[u8]>) -> Prefix<Self>
     where
         Self: std::marker::Sized,
@@ -250,19 +542,56 @@ impl<T: DB> DB for &T {
     where
         Self: 'a;
 
+    type Snapshot<'a> = T::Snapshot<'a>
+    where
+        Self: 'a;
+
     fn start_transaction(&self) -> Result<Self::Transaction<'_>> {
         T::start_transaction(self)
     }
 
+    fn snapshot(&self) -> Result<Self::Snapshot<'_>> {
+        T::snapshot(self)
+    }
+
+    fn start_transaction_with(&self, durability: DurabilityMode) -> Result<Self::Transaction<'_>> {
+        T::start_transaction_with(self, durability)
+    }
+
     fn clear(&mut self) -> Result<()> {
         unreachable!()
     }
+
+    fn approximate_size(&self) -> Result<u64> {
+        T::approximate_size(self)
+    }
+
+    fn key_count(&self) -> Result<usize> {
+        T::key_count(self)
+    }
+
+    fn compact_range(&self, from: Option<&[u8]>, to: Option<&[u8]>) -> Result<()> {
+        T::compact_range(self, from, to)
+    }
 }
 
 pub trait DBDyn: DBReadDyn {
     fn start_transaction(&self) -> Result<Box<dyn DBTransactionDyn + '_>>;
 
+    fn snapshot(&self) -> Result<Box<dyn DBReadDyn + '_>>;
+
+    fn start_transaction_with(
+        &self,
+        durability: DurabilityMode,
+    ) -> Result<Box<dyn DBTransactionDyn + '_>>;
+
     fn clear(&mut self) -> Result<()>;
+
+    fn approximate_size(&self) -> Result<u64>;
+
+    fn key_count(&self) -> Result<usize>;
+
+    fn compact_range(&self, from: Option<&[u8]>, to: Option<&[u8]>) -> Result<()>;
 }
 
 impl<T: DB> DBDyn for T {
@@ -270,9 +599,32 @@ impl<T: DB> DBDyn for T {
         Ok(Box::new(T::start_transaction(self)?))
     }
 
+    fn snapshot(&self) -> Result<Box<dyn DBReadDyn + '_>> {
+        Ok(Box::new(T::snapshot(self)?))
+    }
+
+    fn start_transaction_with(
+        &self,
+        durability: DurabilityMode,
+    ) -> Result<Box<dyn DBTransactionDyn + '_>> {
+        Ok(Box::new(T::start_transaction_with(self, durability)?))
+    }
+
     fn clear(&mut self) -> Result<()> {
         T::clear(self)
     }
+
+    fn approximate_size(&self) -> Result<u64> {
+        T::approximate_size(self)
+    }
+
+    fn key_count(&self) -> Result<usize> {
+        T::key_count(self)
+    }
+
+    fn compact_range(&self, from: Option<&[u8]>, to: Option<&[u8]>) -> Result<()> {
+        T::compact_range(self, from, to)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,98 @@
+//! Values for [`DBWrite::merge`](crate::DBWrite::merge).
+//!
+//! A merge value is tagged with its kind, counters are merged by adding and
+//! sets are merged by union. Both are associative, so rocksdb is free to merge
+//! the operands in any grouping.
+
+use std::collections::BTreeSet;
+
+const COUNTER_TAG: u8 = b'c';
+const SET_TAG: u8 = b's';
+
+/// Name of the merge operator registered in rocksdb.
+pub const MERGE_OPERATOR_NAME: &str = "atomic_drive_merge";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeValue {
+    Counter(u128),
+    Set(BTreeSet<Vec<u8>>),
+}
+
+impl MergeValue {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            MergeValue::Counter(value) => {
+                let mut bytes = vec![COUNTER_TAG];
+                bytes.extend_from_slice(&value.to_be_bytes());
+                bytes
+            }
+            MergeValue::Set(items) => {
+                let mut bytes = vec![SET_TAG];
+                for item in items {
+                    bytes.extend_from_slice(&(item.len() as u32).to_be_bytes());
+                    bytes.extend_from_slice(item);
+                }
+                bytes
+            }
+        }
+    }
+
+    /// Decode a merge value, `None` if the bytes are not a merge value.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (tag, mut rest) = bytes.split_first()?;
+        match *tag {
+            COUNTER_TAG => Some(MergeValue::Counter(u128::from_be_bytes(
+                rest.try_into().ok()?,
+            ))),
+            SET_TAG => {
+                let mut items = BTreeSet::new();
+                while !rest.is_empty() {
+                    let (len, tail) = rest.split_at_checked(4)?;
+                    let len = u32::from_be_bytes(len.try_into().ok()?) as usize;
+                    let (item, tail) = tail.split_at_checked(len)?;
+                    items.insert(item.to_vec());
+                    rest = tail;
+                }
+                Some(MergeValue::Set(items))
+            }
+            _ => None,
+        }
+    }
+
+    /// Merge `other` into this value, if the kinds are different `other`
+    /// replaces this value.
+    pub fn merge(self, other: MergeValue) -> MergeValue {
+        match (self, other) {
+            (MergeValue::Counter(a), MergeValue::Counter(b)) => {
+                MergeValue::Counter(a.wrapping_add(b))
+            }
+            (MergeValue::Set(mut a), MergeValue::Set(b)) => {
+                a.extend(b);
+                MergeValue::Set(a)
+            }
+            (_, other) => other,
+        }
+    }
+}
+
+/// Merge `operands` in order into `existing`, operands and existing value that
+/// are not merge values are ignored. Returns `None` if there is nothing to
+/// merge.
+pub fn merge_operands<'a>(
+    existing: Option<&[u8]>,
+    operands: impl IntoIterator<Item = &'a [u8]>,
+) -> Option<Vec<u8>> {
+    operands
+        .into_iter()
+        .filter_map(MergeValue::from_bytes)
+        .fold(
+            existing.and_then(MergeValue::from_bytes),
+            |value, operand| {
+                Some(match value {
+                    Some(value) => value.merge(operand),
+                    None => operand,
+                })
+            },
+        )
+        .map(|value| value.to_bytes())
+}
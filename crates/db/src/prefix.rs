@@ -1,6 +1,9 @@
-use std::alloc::{Allocator, Global};
+use std::{
+    alloc::{Allocator, Global},
+    ops::Bound,
+};
 
-use crate::{DBLock, DBRead, DBTransaction, DBWrite, Result, DB};
+use crate::{DBLock, DBRead, DBTransaction, DBWrite, DurabilityMode, Result, DB};
 
 fn concat_prefix<A: Allocator>(prefix: &[u8], key: &[u8], alloc: A) -> Vec<u8, A> {
     let mut vec = Vec::with_capacity_in(prefix.len() + key.len(), alloc);
@@ -9,6 +12,23 @@ fn concat_prefix<A: Allocator>(prefix: &[u8], key: &[u8], alloc: A) -> Vec<u8, A
     vec
 }
 
+/// The smallest key greater than all keys starting with `prefix`, trailing
+/// 0xFF bytes are dropped and the last remaining byte is incremented, same as
+/// the prefix successor in rocksdb.
+///
+/// Returns `None` if there is no such key (empty prefix or all bytes are
+/// 0xFF), in that case every key not less than `prefix` starts with it.
+pub fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper_bound = prefix.to_vec();
+    while let Some(last) = upper_bound.pop() {
+        if last < u8::MAX {
+            upper_bound.push(last + 1);
+            return Some(upper_bound);
+        }
+    }
+    None
+}
+
 pub struct PrefixKey<'a, DBKey: AsRef<[u8]>> {
     key: DBKey,
     prefix: &'a [u8],
@@ -118,6 +138,33 @@ impl<DBImpl: DBRead, A: Allocator + Clone> DBRead for Prefix<DBImpl, A> {
             prefix: &self.prefix,
         }
     }
+
+    fn get_range_from(&self, from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+        let from = concat_prefix(&self.prefix, from.as_ref(), self.alloc.clone());
+        let upper_bound = prefix_upper_bound(&self.prefix);
+
+        PrefixRangeIter {
+            iter: self.db.get_range_bounded((
+                Bound::Included(from.as_slice()),
+                upper_bound
+                    .as_deref()
+                    .map_or(Bound::Unbounded, Bound::Excluded),
+            )),
+            prefix: &self.prefix,
+        }
+    }
+
+    type ReadView<'a> = Prefix<DBImpl::ReadView<'a>, A>
+    where
+        Self: 'a;
+
+    fn read_view(&self) -> Result<Self::ReadView<'_>> {
+        Ok(Prefix {
+            db: self.db.read_view()?,
+            prefix: self.prefix.clone(),
+            alloc: self.alloc.clone(),
+        })
+    }
 }
 
 impl<DBImpl: DB, A: Allocator + Clone> DB for Prefix<DBImpl, A> {
@@ -125,6 +172,10 @@ impl<DBImpl: DB, A: Allocator + Clone> DB for Prefix<DBImpl, A> {
     where
         Self: 'a;
 
+    type Snapshot<'a> = Prefix<DBImpl::Snapshot<'a>, A>
+    where
+        Self: 'a;
+
     fn start_transaction(&self) -> crate::Result<Self::Transaction<'_>> {
         Ok(Prefix {
             db: self.db.start_transaction()?,
@@ -133,9 +184,67 @@ impl<DBImpl: DB, A: Allocator + Clone> DB for Prefix<DBImpl, A> {
         })
     }
 
+    fn snapshot(&self) -> crate::Result<Self::Snapshot<'_>> {
+        Ok(Prefix {
+            db: self.db.snapshot()?,
+            prefix: self.prefix.clone(),
+            alloc: self.alloc.clone(),
+        })
+    }
+
+    fn start_transaction_with(
+        &self,
+        durability: DurabilityMode,
+    ) -> crate::Result<Self::Transaction<'_>> {
+        Ok(Prefix {
+            db: self.db.start_transaction_with(durability)?,
+            prefix: self.prefix.clone(),
+            alloc: self.alloc.clone(),
+        })
+    }
+
     fn clear(&mut self) -> Result<()> {
         self.db.clear()
     }
+
+    fn approximate_size(&self) -> Result<u64> {
+        let range_size = |from: &[u8], to: &[u8]| -> Result<u64> {
+            let mut size = 0;
+            for item in self.db.get_range(from, to) {
+                let (key, value) = item?;
+                size += (key.as_ref().len() + value.as_ref().len()) as u64;
+            }
+            Ok(size)
+        };
+
+        if let Some(upper_bound) = prefix_upper_bound(&self.prefix) {
+            range_size(&self.prefix, &upper_bound)
+        } else {
+            // no upper bound, the prefix covers every key from itself.
+            Ok(self
+                .db
+                .approximate_size()?
+                .saturating_sub(range_size(&[], &self.prefix)?))
+        }
+    }
+
+    fn key_count(&self) -> Result<usize> {
+        let mut count = 0;
+        for item in self.db.prefix_scan(&self.prefix) {
+            item?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn compact_range(&self, from: Option<&[u8]>, to: Option<&[u8]>) -> Result<()> {
+        let from = concat_prefix(&self.prefix, from.unwrap_or_default(), self.alloc.clone());
+        let to = match to {
+            Some(to) => Some(concat_prefix(&self.prefix, to, self.alloc.clone()).to_vec()),
+            None => prefix_upper_bound(&self.prefix),
+        };
+        self.db.compact_range(Some(&from), to.as_deref())
+    }
 }
 
 impl<DBImpl: DBLock, A: Allocator + Clone> DBLock for Prefix<DBImpl, A> {
@@ -167,6 +276,13 @@ impl<DBImpl: DBWrite, A: Allocator + Clone> DBWrite for Prefix<DBImpl, A> {
             self.alloc.clone(),
         ))
     }
+
+    fn merge(&mut self, key: impl AsRef<[u8]>, operand: impl AsRef<[u8]>) -> crate::Result<()> {
+        self.db.merge(
+            concat_prefix(&self.prefix, key.as_ref(), self.alloc.clone()),
+            operand,
+        )
+    }
 }
 
 impl<DBImpl: DBTransaction, A: Allocator + Clone> DBTransaction for Prefix<DBImpl, A> {
@@ -177,4 +293,24 @@ impl<DBImpl: DBTransaction, A: Allocator + Clone> DBTransaction for Prefix<DBImp
     fn commit(self) -> crate::Result<()> {
         self.db.commit()
     }
+
+    fn savepoint(&mut self) -> crate::Result<()> {
+        self.db.savepoint()
+    }
+
+    fn rollback_to_savepoint(&mut self) -> crate::Result<()> {
+        self.db.rollback_to_savepoint()
+    }
+
+    fn release_savepoint(&mut self) -> crate::Result<()> {
+        self.db.release_savepoint()
+    }
+
+    fn pending_changes(&self) -> usize {
+        self.db.pending_changes()
+    }
+
+    fn set_lock_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.db.set_lock_timeout(timeout)
+    }
 }
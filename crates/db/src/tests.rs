@@ -1,4 +1,9 @@
-use crate::{backend, DBTransaction, DBWrite, Result, DB};
+use std::{collections::BTreeSet, ops::Bound, time::Duration};
+
+use crate::{
+    backend, merge::MergeValue, DBLock, DBRead, DBTransaction, DBWrite, DurabilityMode, Error,
+    Result, DB,
+};
 
 macro_rules! testing {
     (@db: $($db:ident)* ,@tests: $($test:ident)*) => {
@@ -22,12 +27,30 @@ macro_rules! testing {
 fn test_db() -> Result<()> {
     let mut memory_db = backend::memory::MemoryDB::default();
     let mut memory_db_with_prefix = memory_db.clone().prefix("iii");
+    let bump = bumpalo::Bump::new();
+    let mut memory_db_in_bump = backend::memory::MemoryDB::new_in(&bump);
+    let mut memory_db_in_bump_with_prefix =
+        backend::memory::MemoryDB::new_in(&bump).prefix_in("iii", &bump);
     let mut rocks_db =
         backend::rocks::RocksDB::open_or_create_database(test_results::save_dir!("rocks"))?;
 
     testing!(
-        @db: rocks_db memory_db memory_db_with_prefix,
-        @tests: basic_write get_range rollback
+        @db: rocks_db memory_db memory_db_with_prefix memory_db_in_bump memory_db_in_bump_with_prefix,
+        @tests: basic_write get_range range_order rollback key_count isolation savepoint merge durability prefix_scan pending_changes range_bounds compact_range
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn test_sled_db() -> Result<()> {
+    let mut sled_db =
+        backend::sled::SledDB::open_or_create_database(test_results::save_dir!("sled"))?;
+
+    testing!(
+        @db: sled_db,
+        @tests: basic_write get_range range_order rollback key_count isolation savepoint merge durability prefix_scan pending_changes range_bounds compact_range
     );
 
     Ok(())
@@ -77,6 +100,110 @@ fn get_range<D: DB>(db: &mut D) -> Result<()> {
     Ok(())
 }
 
+fn range_keys<R: DBRead>(db: &R, from: impl AsRef<[u8]>, to: impl AsRef<[u8]>) -> Vec<Vec<u8>> {
+    db.get_range(from, to)
+        .map(|d| d.unwrap().0.as_ref().to_vec())
+        .collect()
+}
+
+fn range_bounds<D: DB>(db: &mut D) -> Result<()> {
+    let mut t = db.start_transaction()?;
+    for key in [&b"a"[..], b"b", b"b\0", b"ba", b"c", &[0xFF]] {
+        t.set(key, *b"v")?;
+    }
+    t.commit()?;
+
+    let keys = |range: (Bound<&[u8]>, Bound<&[u8]>)| {
+        db.get_range_bounded(range)
+            .map(|d| d.unwrap().0.as_ref().to_vec())
+            .collect::<Vec<_>>()
+    };
+    let b: &[u8] = b"b";
+    let c: &[u8] = b"c";
+
+    assert_eq!(
+        keys((Bound::Included(b), Bound::Included(c))),
+        vec![
+            b"b".to_vec(),
+            b"b\0".to_vec(),
+            b"ba".to_vec(),
+            b"c".to_vec()
+        ]
+    );
+    assert_eq!(
+        keys((Bound::Included(b), Bound::Excluded(c))),
+        vec![b"b".to_vec(), b"b\0".to_vec(), b"ba".to_vec()]
+    );
+    // excluding `b` still includes the keys it prefixes
+    assert_eq!(
+        keys((Bound::Excluded(b), Bound::Excluded(c))),
+        vec![b"b\0".to_vec(), b"ba".to_vec()]
+    );
+    assert_eq!(
+        keys((Bound::Excluded(b), Bound::Unbounded)),
+        vec![b"b\0".to_vec(), b"ba".to_vec(), b"c".to_vec(), vec![0xFF]]
+    );
+    assert_eq!(
+        keys((Bound::Unbounded, Bound::Included(b))),
+        vec![b"a".to_vec(), b"b".to_vec()]
+    );
+    assert_eq!(keys((Bound::Unbounded, Bound::Unbounded)).len(), 6);
+    // end before start
+    assert!(keys((Bound::Included(c), Bound::Excluded(b))).is_empty());
+    assert!(keys((Bound::Excluded(b), Bound::Included(b))).is_empty());
+
+    Ok(())
+}
+
+/// Keys must be iterated in bytewise order on every backend, including the
+/// empty key and `0xFF` bytes.
+fn range_order<D: DB>(db: &mut D) -> Result<()> {
+    let keys: [&[u8]; 11] = [
+        b"b",
+        &[0xFF, 0xFF],
+        b"",
+        b"a\0",
+        &[0x80],
+        &[0xFF],
+        b"ab",
+        &[0x00],
+        b"a",
+        &[0x7F],
+        &[0xFF, 0x00],
+    ];
+    // `Ord` of `[u8]` is the bytewise comparator
+    let mut sorted = keys.iter().map(|key| key.to_vec()).collect::<Vec<_>>();
+    sorted.sort();
+
+    let mut t = db.start_transaction()?;
+    for key in &keys[..6] {
+        t.set(key, *b"v")?;
+    }
+    t.commit()?;
+
+    // committed keys mixed with uncommitted writes
+    let mut t = db.start_transaction()?;
+    for key in &keys[6..] {
+        t.set(key, *b"v")?;
+    }
+    assert_eq!(range_keys(&t, b"", [0xFF, 0xFF, 0xFF]), sorted);
+    t.commit()?;
+
+    assert_eq!(range_keys(db, b"", [0xFF, 0xFF, 0xFF]), sorted);
+
+    // `to` is excluded, a key sorts after its prefixes
+    assert_eq!(
+        range_keys(db, [0xFF], [0xFF, 0xFF]),
+        vec![vec![0xFF], vec![0xFF, 0x00]]
+    );
+    assert_eq!(
+        range_keys(db, b"a", b"b"),
+        vec![b"a".to_vec(), b"a\0".to_vec(), b"ab".to_vec()]
+    );
+
+    Ok(())
+}
+
 fn rollback<D: DB>(db: &mut D) -> Result<()> {
     let mut t = db.start_transaction()?;
     t.set(*b"100", *b"0")?;
@@ -98,3 +225,541 @@ fn rollback<D: DB>(db: &mut D) -> Result<()> {
 
     Ok(())
 }
+
+fn savepoint<D: DB>(db: &mut D) -> Result<()> {
+    let mut t = db.start_transaction()?;
+    t.set(*b"100", *b"0")?;
+    t.savepoint()?;
+    t.set(*b"100", *b"hello")?;
+    t.set(*b"101", *b"1")?;
+    t.savepoint()?;
+    t.set(*b"102", *b"2")?;
+    t.release_savepoint()?;
+    t.set(*b"103", *b"3")?;
+
+    assert_eq!(t.get(*b"102")?.unwrap().as_ref(), b"2");
+
+    // roll back to the first savepoint, the released one is discarded too.
+    t.rollback_to_savepoint()?;
+    assert!(matches!(t.rollback_to_savepoint(), Err(Error::NoSavepoint)));
+    assert!(matches!(t.release_savepoint(), Err(Error::NoSavepoint)));
+
+    assert_eq!(t.get(*b"100")?.unwrap().as_ref(), b"0");
+    assert!(t.get(*b"101")?.is_none());
+    assert!(t.get(*b"102")?.is_none());
+    assert!(t.get(*b"103")?.is_none());
+
+    t.set(*b"104", *b"4")?;
+    t.commit()?;
+
+    assert_eq!(db.get(*b"100")?.unwrap().as_ref(), b"0");
+    assert!(db.get(*b"101")?.is_none());
+    assert_eq!(db.get(*b"104")?.unwrap().as_ref(), b"4");
+
+    Ok(())
+}
+
+fn merge<D: DB>(db: &mut D) -> Result<()> {
+    let counter = |value: u128| MergeValue::Counter(value).to_bytes();
+    let set = |items: &[&[u8]]| {
+        MergeValue::Set(
+            items
+                .iter()
+                .map(|item| item.to_vec())
+                .collect::<BTreeSet<_>>(),
+        )
+        .to_bytes()
+    };
+
+    let mut t = db.start_transaction()?;
+    t.merge(*b"counter", counter(1))?;
+    t.merge(*b"counter", counter(2))?;
+    t.merge(*b"set", set(&[b"a", b"b"]))?;
+    assert_eq!(t.get(*b"counter")?.unwrap().as_ref(), counter(3));
+    t.commit()?;
+
+    let mut t = db.start_transaction()?;
+    t.merge(*b"counter", counter(10))?;
+    t.merge(*b"set", set(&[b"b", b"c"]))?;
+    t.set(*b"reset", counter(100))?;
+    t.merge(*b"reset", counter(1))?;
+    t.commit()?;
+
+    assert_eq!(db.get(*b"counter")?.unwrap().as_ref(), counter(13));
+    assert_eq!(db.get(*b"set")?.unwrap().as_ref(), set(&[b"a", b"b", b"c"]));
+    assert_eq!(db.get(*b"reset")?.unwrap().as_ref(), counter(101));
+
+    let kvs = db
+        .get_range(b"counter", b"set")
+        .map(|d| d.unwrap())
+        .map(|(k, v)| (k.as_ref().to_vec(), v.as_ref().to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kvs,
+        vec![
+            (b"counter".to_vec(), counter(13)),
+            (b"reset".to_vec(), counter(101))
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn concurrent_merge() -> Result<()> {
+    fn increment<D: DB + Sync>(db: &D) -> Result<()> {
+        let counter = |value: u128| MergeValue::Counter(value).to_bytes();
+
+        // interleaved transactions both commit
+        let mut t1 = db.start_transaction()?;
+        let mut t2 = db.start_transaction()?;
+        t1.merge(*b"counter", counter(1))?;
+        t2.merge(*b"counter", counter(1))?;
+        t1.commit()?;
+        t2.commit()?;
+        assert_eq!(db.get(*b"counter")?.unwrap().as_ref(), counter(2));
+
+        std::thread::scope(|scope| {
+            let handles = (0..4)
+                .map(|_| {
+                    scope.spawn(|| -> Result<()> {
+                        for _ in 0..100 {
+                            let mut t = db.start_transaction()?;
+                            t.merge(*b"counter", counter(1))?;
+                            t.commit()?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                handle.join().unwrap()?;
+            }
+            Result::Ok(())
+        })?;
+        assert_eq!(db.get(*b"counter")?.unwrap().as_ref(), counter(402));
+
+        Ok(())
+    }
+
+    increment(&backend::memory::MemoryDB::default())?;
+    #[cfg(feature = "sled")]
+    {
+        let mut sled_db =
+            backend::sled::SledDB::open_or_create_database(test_results::save_dir!("sled_merge"))?;
+        sled_db.clear()?;
+        increment(&sled_db)?;
+    }
+
+    Ok(())
+}
+
+fn key_count<D: DB>(db: &mut D) -> Result<()> {
+    assert_eq!(db.key_count()?, 0);
+
+    let mut t = db.start_transaction()?;
+    for i in 0..100u32 {
+        t.set(i.to_be_bytes(), *b"value")?;
+    }
+    t.commit()?;
+
+    assert_eq!(db.key_count()?, 100);
+
+    Ok(())
+}
+
+fn compact_range<D: DB>(db: &mut D) -> Result<()> {
+    let mut t = db.start_transaction()?;
+    for i in 0..100u32 {
+        t.set(i.to_be_bytes(), *b"value")?;
+    }
+    t.commit()?;
+
+    let mut t = db.start_transaction()?;
+    for i in (0..100u32).step_by(2) {
+        t.delete(i.to_be_bytes())?;
+    }
+    t.commit()?;
+
+    db.compact_range(Some(&10u32.to_be_bytes()), Some(&20u32.to_be_bytes()))?;
+    db.compact_range(None, None)?;
+
+    assert_eq!(db.key_count()?, 50);
+    assert!(db.get(10u32.to_be_bytes())?.is_none());
+    assert_eq!(db.get(11u32.to_be_bytes())?.unwrap().as_ref(), b"value");
+    assert_eq!(db.get(99u32.to_be_bytes())?.unwrap().as_ref(), b"value");
+
+    Ok(())
+}
+
+#[test]
+fn memory_db_size() -> Result<()> {
+    let db = backend::memory::MemoryDB::default();
+    assert_eq!(db.approximate_size()?, 0);
+
+    let mut t = db.start_transaction()?;
+    t.set(*b"key", *b"value")?;
+    t.commit()?;
+
+    assert_eq!(db.key_count()?, 1);
+    assert_eq!(db.approximate_size()?, 8);
+
+    let prefixed = (&db).prefix("ke");
+    assert_eq!(prefixed.key_count()?, 1);
+    assert_eq!((&db).prefix("x").key_count()?, 0);
+
+    Ok(())
+}
+
+fn prefix_scan<D: DB>(db: &mut D) -> Result<()> {
+    let keys: [&[u8]; 8] = [
+        &[1],
+        &[1, 0],
+        &[1, 0xFF],
+        &[1, 0xFF, 0xFF, 3],
+        &[2],
+        &[0xFF],
+        &[0xFF, 0xFF],
+        &[0xFF, 0xFF, 1],
+    ];
+    let scan = |db: &dyn crate::DBReadDyn, prefix: &[u8]| -> Result<Vec<Vec<u8>>> {
+        Ok(db
+            .prefix_scan(prefix)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect())
+    };
+    let check = |db: &dyn crate::DBReadDyn| -> Result<()> {
+        assert_eq!(scan(db, &[])?.len(), keys.len());
+        assert_eq!(
+            scan(db, &[1])?,
+            vec![vec![1], vec![1, 0], vec![1, 0xFF], vec![1, 0xFF, 0xFF, 3]]
+        );
+        assert_eq!(
+            scan(db, &[1, 0xFF])?,
+            vec![vec![1, 0xFF], vec![1, 0xFF, 0xFF, 3]]
+        );
+        assert_eq!(scan(db, &[1, 0xFF, 0xFF, 3, 0])?, Vec::<Vec<u8>>::new());
+        assert_eq!(
+            scan(db, &[0xFF])?,
+            vec![vec![0xFF], vec![0xFF, 0xFF], vec![0xFF, 0xFF, 1]]
+        );
+        assert_eq!(
+            scan(db, &[0xFF, 0xFF])?,
+            vec![vec![0xFF, 0xFF], vec![0xFF, 0xFF, 1]]
+        );
+        assert_eq!(scan(db, &[3])?, Vec::<Vec<u8>>::new());
+        Ok(())
+    };
+
+    let mut t = db.start_transaction()?;
+    for key in keys {
+        t.set(key, *b"v")?;
+    }
+    // staged writes
+    check(&t)?;
+    t.commit()?;
+    check(db)?;
+
+    Ok(())
+}
+
+#[test]
+fn prefix_upper_bound() -> Result<()> {
+    use crate::prefix::prefix_upper_bound;
+
+    assert_eq!(prefix_upper_bound(b"ab"), Some(b"ac".to_vec()));
+    assert_eq!(prefix_upper_bound(&[1, 0xFF]), Some(vec![2]));
+    assert_eq!(prefix_upper_bound(&[1, 0xFF, 0xFF]), Some(vec![2]));
+    assert_eq!(prefix_upper_bound(&[0xFF, 0xFF]), None);
+    assert_eq!(prefix_upper_bound(&[]), None);
+
+    let db = backend::memory::MemoryDB::default();
+    let mut t = db.start_transaction()?;
+    t.set([1, 0xFE, 0], *b"0")?;
+    t.set([1, 0xFF], *b"1")?;
+    t.set([1, 0xFF, 0xFF, 1], *b"2")?;
+    t.set([2], *b"3")?;
+    t.set([0xFF], *b"4")?;
+    t.set([0xFF, 0xFF, 0], *b"5")?;
+    t.commit()?;
+
+    assert_eq!((&db).prefix([1, 0xFF]).key_count()?, 2);
+    assert_eq!((&db).prefix([1, 0xFF, 0xFF]).key_count()?, 1);
+    assert_eq!((&db).prefix([0xFF]).key_count()?, 2);
+    assert_eq!((&db).prefix([0xFF, 0xFF]).key_count()?, 1);
+    assert_eq!((&db).prefix([0xFF, 0xFF, 0xFF]).key_count()?, 0);
+    assert_eq!((&db).prefix([0xFF]).approximate_size()?, 6);
+
+    Ok(())
+}
+
+fn isolation<D: DB>(db: &mut D) -> Result<()> {
+    let mut t = db.start_transaction()?;
+    t.set(*b"100", *b"0")?;
+    t.set(*b"101", *b"1")?;
+    t.commit()?;
+
+    let mut t = db.start_transaction()?;
+    t.set(*b"100", *b"hello")?;
+    t.delete(*b"101")?;
+    t.set(*b"102", *b"2")?;
+
+    // the transaction sees its own writes
+    assert_eq!(t.get(*b"100")?.unwrap().as_ref(), b"hello");
+    assert!(t.get(*b"101")?.is_none());
+    let kvs = t
+        .get_range(b"100", b"200")
+        .map(|d| d.unwrap())
+        .map(|(k, v)| (k.as_ref().to_vec(), v.as_ref().to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kvs,
+        vec![
+            (b"100".to_vec(), b"hello".to_vec()),
+            (b"102".to_vec(), b"2".to_vec())
+        ]
+    );
+
+    // others don't see uncommitted writes
+    assert_eq!(db.get(*b"100")?.unwrap().as_ref(), b"0");
+    assert_eq!(db.get(*b"101")?.unwrap().as_ref(), b"1");
+    assert!(db.get(*b"102")?.is_none());
+
+    t.commit()?;
+
+    assert_eq!(db.get(*b"100")?.unwrap().as_ref(), b"hello");
+    assert!(db.get(*b"101")?.is_none());
+    assert_eq!(db.get(*b"102")?.unwrap().as_ref(), b"2");
+
+    Ok(())
+}
+
+#[test]
+fn memory_db_in_bump() -> Result<()> {
+    let bump = bumpalo::Bump::new();
+    let db = backend::memory::MemoryDB::new_in(&bump);
+    let allocated = bump.allocated_bytes();
+
+    let mut t = db.start_transaction()?;
+    for i in 0..100u8 {
+        t.set([b'k', i], [i])?;
+    }
+    t.commit()?;
+
+    // the map lives in the bump allocator
+    assert!(bump.allocated_bytes() > allocated);
+    assert_eq!(db.get([b'k', 42])?.unwrap().as_ref(), [42]);
+    let range = db
+        .get_range([b'k', 10], [b'k', 13])
+        .map(|item| item.map(|(key, _)| key.to_vec()))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(range, vec![vec![b'k', 10], vec![b'k', 11], vec![b'k', 12]]);
+
+    Ok(())
+}
+
+#[test]
+fn memory_db_conflict() -> Result<()> {
+    let db = backend::memory::MemoryDB::default();
+
+    let t1 = db.start_transaction()?;
+    assert!(t1.get_for_update(*b"counter")?.is_none());
+
+    let mut t2 = db.start_transaction()?;
+    t2.set(*b"counter", *b"1")?;
+    t2.commit()?;
+
+    let mut t1 = t1;
+    t1.set(*b"counter", *b"1")?;
+    assert!(matches!(t1.commit(), Err(Error::Conflict)));
+    assert_eq!(db.get(*b"counter")?.unwrap().as_ref(), b"1");
+
+    Ok(())
+}
+
+#[test]
+fn rocks_db_conflict() -> Result<()> {
+    let path = test_results::save_dir!("rocks_conflict");
+    let mut db = backend::rocks::RocksDB::open_or_create_database(path)?;
+    db.clear()?;
+
+    let t1 = db.start_transaction()?;
+    assert!(t1.get_for_update(*b"counter")?.is_none());
+
+    let mut t2 = db.start_transaction()?;
+    t2.set(*b"counter", *b"1")?;
+    t2.commit()?;
+
+    let mut t1 = t1;
+    t1.set(*b"counter", *b"2")?;
+    assert!(matches!(t1.commit(), Err(Error::Conflict)));
+    assert_eq!(db.get(*b"counter")?.unwrap().as_ref(), b"1");
+
+    Ok(())
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_db_snapshot() -> Result<()> {
+    let mut db =
+        backend::sled::SledDB::open_or_create_database(test_results::save_dir!("sled_snapshot"))?;
+    db.clear()?;
+    // keys between the two written ones, so the scan of a snapshot takes a while
+    let mut t = db.start_transaction()?;
+    for i in 0..1000u32 {
+        t.set(format!("m{i}"), *b"")?;
+    }
+    t.commit()?;
+
+    std::thread::scope(|scope| {
+        let writer = scope.spawn(|| {
+            for i in 0..200u32 {
+                let mut t = db.start_transaction()?;
+                t.set(*b"a", i.to_be_bytes())?;
+                t.set(*b"z", i.to_be_bytes())?;
+                t.commit()?;
+            }
+            Result::Ok(())
+        });
+
+        // the keys written by one commit are seen together or not at all
+        while !writer.is_finished() {
+            let snapshot = db.snapshot()?;
+            assert_eq!(
+                snapshot.get(*b"a")?.map(|v| v.as_ref().to_vec()),
+                snapshot.get(*b"z")?.map(|v| v.as_ref().to_vec())
+            );
+        }
+        writer.join().unwrap()
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn read_only() -> Result<()> {
+    let path = test_results::save_dir!("rocks_read_only");
+    {
+        let mut db = backend::rocks::RocksDB::open_or_create_database(&path)?;
+        db.clear()?;
+        let mut t = db.start_transaction()?;
+        for i in 0..10 {
+            t.set(format!("key{i}"), format!("value{i}"))?;
+        }
+        t.commit()?;
+    }
+
+    let db = backend::rocks::RocksDB::open_read_only(&path)?;
+    assert_eq!(db.get(*b"key3")?.unwrap().as_ref(), b"value3");
+    let items = db
+        .prefix_scan(*b"key")
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .map(|(k, v)| (k.to_vec(), v.as_ref().to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(items.len(), 10);
+    assert_eq!(items[0], (b"key0".to_vec(), b"value0".to_vec()));
+    assert_eq!(db.get_range(*b"key2", *b"key5").count(), 3);
+
+    let memory_db = backend::memory::MemoryDB::default();
+    let read_only = memory_db.read_only();
+    let mut t = memory_db.start_transaction()?;
+    t.set(*b"key", *b"value")?;
+    t.commit()?;
+    // shares the map, sees the commits after it is opened
+    assert_eq!(read_only.get(*b"key")?.unwrap().as_ref(), b"value");
+
+    Ok(())
+}
+
+#[test]
+fn memory_db_lock_timeout() -> Result<()> {
+    let db = backend::memory::MemoryDB::default();
+    let (locked, unlock) = (std::sync::Barrier::new(2), std::sync::Barrier::new(2));
+
+    let mut timeout = db.start_transaction()?;
+    timeout.set_lock_timeout(Some(Duration::from_millis(10)));
+    timeout.set(*b"key", *b"value")?;
+    let mut wait = db.start_transaction()?;
+    wait.set(*b"key", *b"value")?;
+
+    let (get, range, for_update, commit, waited) = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let guard = db.lock_map();
+            locked.wait();
+            unlock.wait();
+            drop(guard);
+        });
+        locked.wait();
+
+        let get = timeout.get(*b"other").map(|v| v.is_some());
+        let range = timeout
+            .get_range(b"a", b"z")
+            .map(|item| item.map(|_| ()))
+            .collect::<Result<Vec<_>>>();
+        let for_update = timeout.get_for_update(*b"other").map(|v| v.is_some());
+        let commit = timeout.commit();
+
+        // without a timeout the commit waits for the lock
+        unlock.wait();
+        (get, range, for_update, commit, wait.commit())
+    });
+
+    assert!(matches!(get, Err(Error::LockTimeout)));
+    assert!(matches!(range, Err(Error::LockTimeout)));
+    assert!(matches!(for_update, Err(Error::LockTimeout)));
+    assert!(matches!(commit, Err(Error::LockTimeout)));
+    waited?;
+    assert_eq!(db.get(*b"key")?.unwrap().as_ref(), b"value");
+    Ok(())
+}
+
+fn durability<D: DB>(db: &mut D) -> Result<()> {
+    for (i, durability) in [
+        DurabilityMode::Safe,
+        DurabilityMode::Fast,
+        DurabilityMode::NoSync,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let mut t = db.start_transaction_with(durability)?;
+        t.set(format!("key{i}"), format!("value{i}"))?;
+        t.commit()?;
+    }
+
+    for i in 0..3 {
+        assert_eq!(
+            db.get(format!("key{i}"))?.unwrap().as_ref(),
+            format!("value{i}").as_bytes()
+        );
+    }
+
+    Ok(())
+}
+
+fn pending_changes<D: DB>(db: &mut D) -> Result<()> {
+    let mut t = db.start_transaction()?;
+    assert!(t.is_empty());
+
+    for i in 0..10 {
+        t.set(format!("key{i}"), *b"value")?;
+    }
+    t.delete(*b"key10")?;
+    t.merge(*b"counter", MergeValue::Counter(1).to_bytes())?;
+    assert_eq!(t.pending_changes(), 12);
+
+    t.savepoint()?;
+    t.set(*b"key11", *b"value")?;
+    t.delete(*b"key12")?;
+    assert_eq!(t.pending_changes(), 14);
+    t.rollback_to_savepoint()?;
+    assert_eq!(t.pending_changes(), 12);
+    assert!(!t.is_empty());
+
+    t.commit()?;
+    assert!(db.start_transaction()?.is_empty());
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
-use crate::FileFullPath;
+use crate::{FileFullPath, FileType};
 
-#[derive(Debug, Copy, Clone, Hash, PartialEq)]
+#[derive(Debug, Clone, Hash, PartialEq)]
 pub enum FileEventType {
     /// Event when file is created.
     Created,
@@ -10,12 +10,23 @@ pub enum FileEventType {
 
     /// Event when file is changed.
     Changed,
+
+    /// Event when file is moved to the event path.
+    Moved { from: FileFullPath },
+
+    /// Event when file lost the name `original` to a file of another writer,
+    /// and is kept at the event path.
+    Conflicted { original: FileFullPath },
 }
 
 #[derive(Debug, Clone, Hash, PartialEq)]
 pub struct FileEvent {
     pub event_type: FileEventType,
     pub path: FileFullPath,
+    /// The previous path if the file is moved to `path`, the same as in
+    /// [`FileEventType::Moved`].
+    pub old_path: Option<FileFullPath>,
+    pub file_type: Option<FileType>,
 }
 
 pub type FileEventCallback = Box<dyn Fn(Vec<FileEvent>) + Sync + Send + 'static>;
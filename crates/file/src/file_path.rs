@@ -1,7 +1,41 @@
-use std::{fmt::Display, string::FromUtf8Error};
+use std::{borrow::Cow, cmp::Ordering, fmt::Display};
 
+use thiserror::Error;
 use utils::PathTools;
 
+use crate::FileType;
+
+/// Limits on the paths accepted by the tracker, long paths make long keys in
+/// the store and backends like rocksdb have practical key size limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathLimits {
+    /// Max length of the full path in bytes.
+    pub max_path_len: usize,
+    /// Max number of segments, `/a/b` has a depth of 2.
+    pub max_depth: usize,
+}
+
+impl Default for PathLimits {
+    fn default() -> Self {
+        Self {
+            max_path_len: 4096,
+            max_depth: 256,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    #[error("Path too long, {len} bytes exceeds the limit of {max}")]
+    TooLong { len: usize, max: usize },
+    #[error("Path too deep, {depth} levels exceeds the limit of {max}")]
+    TooDeep { depth: usize, max: usize },
+    #[error("Invalid path key: {0}")]
+    InvalidKey(String),
+    #[error("Invalid path segment: {0:?}")]
+    InvalidSegment(String),
+}
+
 #[derive(
     Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord,
 )]
@@ -16,10 +50,74 @@ impl FileFullPath {
         }
     }
 
-    pub fn join(&self, path: &str) -> FileFullPath {
-        FileFullPath {
-            value: PathTools::join(&self.value, path).to_string(),
+    /// Same as [`FileFullPath::parse`], fails if the resolved path exceeds
+    /// `limits`.
+    pub fn parse_with_limits(path: &str, limits: &PathLimits) -> Result<FileFullPath, PathError> {
+        let path = Self::parse(path);
+        path.check_limits(limits)?;
+        Ok(path)
+    }
+
+    pub fn check_limits(&self, limits: &PathLimits) -> Result<(), PathError> {
+        if self.value.len() > limits.max_path_len {
+            return Err(PathError::TooLong {
+                len: self.value.len(),
+                max: limits.max_path_len,
+            });
+        }
+        let depth = self.depth();
+        if depth > limits.max_depth {
+            return Err(PathError::TooDeep {
+                depth,
+                max: limits.max_depth,
+            });
         }
+        Ok(())
+    }
+
+    /// Number of segments in the path, `0` for the root.
+    pub fn depth(&self) -> usize {
+        self.segments().count()
+    }
+
+    /// Append a single file name to this path.
+    ///
+    /// # Panics
+    /// Panics if `segment` is not a valid file name, see [`FileFullPath::is_valid_segment`].
+    /// Use [`FileFullPath::try_join`] for the names read from the filesystem or
+    /// from the ops.
+    pub fn join(&self, segment: &str) -> FileFullPath {
+        self.try_join(segment).unwrap()
+    }
+
+    /// Append a single file name to this path, fails with
+    /// [`PathError::InvalidSegment`] if `segment` is not a valid file name.
+    pub fn try_join(&self, segment: &str) -> Result<FileFullPath, PathError> {
+        if !Self::is_valid_segment(segment) {
+            return Err(PathError::InvalidSegment(segment.to_string()));
+        }
+        Ok(FileFullPath {
+            value: PathTools::join(&self.value, segment).to_string(),
+        })
+    }
+
+    /// Whether `segment` can be used as a single file name, it must not be empty,
+    /// contain a directory separator, or be `.` / `..`.
+    pub fn is_valid_segment(segment: &str) -> bool {
+        !segment.is_empty()
+            && segment != "."
+            && segment != ".."
+            && !segment.contains(PathTools::DIRECTORY_SEPARATOR_CHAR)
+    }
+
+    /// Returns an iterator over the ancestors of this path, from the root to the
+    /// parent directory. e.g. `/a/b/c` yields `/`, `/a`, `/a/b`.
+    pub fn ancestors(&self) -> impl Iterator<Item = FileFullPath> + '_ {
+        let mut dive = PathTools::dive(&self.value).collect::<Vec<_>>();
+        dive.pop();
+        dive.into_iter().map(|value| FileFullPath {
+            value: value.to_string(),
+        })
     }
 
     pub fn dirname(&self) -> FileFullPath {
@@ -28,8 +126,89 @@ impl FileFullPath {
         }
     }
 
-    pub fn as_bytes(&self) -> &[u8] {
-        self.value.as_bytes()
+    /// Whether `other` is this path or a path below it, e.g. `/a` contains
+    /// `/a` and `/a/b`, but not `/ab`.
+    pub fn contains(&self, other: &FileFullPath) -> bool {
+        let mut segments = other.segments();
+        self.segments()
+            .all(|segment| segments.next() == Some(segment))
+    }
+
+    /// Compare segment by segment, so a parent sorts right before its children,
+    /// e.g. `/a/b` sorts before `/a.txt` while the default ordering puts it after.
+    pub fn cmp_hierarchical(&self, other: &FileFullPath) -> Ordering {
+        self.cmp_segments(None, other, None)
+    }
+
+    /// Same as [`FileFullPath::cmp_hierarchical`], but directories sort before
+    /// files within the same folder. `self_type` and `other_type` are the file
+    /// types of the two paths.
+    pub fn cmp_directories_first(
+        &self,
+        self_type: FileType,
+        other: &FileFullPath,
+        other_type: FileType,
+    ) -> Ordering {
+        self.cmp_segments(Some(self_type), other, Some(other_type))
+    }
+
+    fn cmp_segments(
+        &self,
+        self_type: Option<FileType>,
+        other: &FileFullPath,
+        other_type: Option<FileType>,
+    ) -> Ordering {
+        let a = self.segments().collect::<Vec<_>>();
+        let b = other.segments().collect::<Vec<_>>();
+
+        for (i, (a_segment, b_segment)) in a.iter().zip(b.iter()).enumerate() {
+            if a_segment == b_segment {
+                continue;
+            }
+
+            if let (Some(self_type), Some(other_type)) = (self_type, other_type) {
+                // the segment is a directory if it is an ancestor of the path
+                let a_is_dir = i + 1 < a.len() || self_type == FileType::Directory;
+                let b_is_dir = i + 1 < b.len() || other_type == FileType::Directory;
+                if a_is_dir != b_is_dir {
+                    return b_is_dir.cmp(&a_is_dir);
+                }
+            }
+
+            return a_segment.cmp(b_segment);
+        }
+
+        a.len().cmp(&b.len())
+    }
+
+    fn segments(&self) -> impl Iterator<Item = &str> {
+        self.value
+            .split(PathTools::DIRECTORY_SEPARATOR_CHAR)
+            .filter(|segment| !segment.is_empty())
+    }
+
+    /// Key encoding of the path, used in the store. Unlike [`Display`] it is
+    /// unambiguous, `%` in segments is escaped as `%25`, and `/` within a
+    /// segment would be escaped as `%2F` if it is ever allowed, so `/a/b` and a
+    /// segment literally named `a/b` never share a key. Paths without `%`
+    /// encode to the display string, borrowed.
+    pub fn as_bytes(&self) -> Cow<'_, [u8]> {
+        if !self.value.contains(KEY_ESCAPE_CHAR) {
+            return Cow::Borrowed(self.value.as_bytes());
+        }
+
+        let mut key = String::with_capacity(self.value.len() + 2);
+        for segment in self.segments() {
+            key.push(PathTools::DIRECTORY_SEPARATOR_CHAR);
+            for c in segment.chars() {
+                match c {
+                    KEY_ESCAPE_CHAR => key.push_str("%25"),
+                    PathTools::DIRECTORY_SEPARATOR_CHAR => key.push_str("%2F"),
+                    c => key.push(c),
+                }
+            }
+        }
+        Cow::Owned(key.into_bytes())
     }
 
     #[allow(clippy::len_without_is_empty)]
@@ -37,12 +216,58 @@ impl FileFullPath {
         self.value.len()
     }
 
-    pub fn from_bytes(bytes: Vec<u8>) -> Result<FileFullPath, FromUtf8Error> {
-        // TODO: check path
-        Ok(FileFullPath {
-            value: String::from_utf8(bytes)?,
-        })
+    /// Decode the key encoding of [`FileFullPath::as_bytes`]. Only the
+    /// canonical encoding is accepted, e.g. `/a/`, `/./a` or `/%2f` are
+    /// rejected, so every path has exactly one key.
+    pub fn from_bytes(bytes: &[u8]) -> Result<FileFullPath, PathError> {
+        let invalid = || PathError::InvalidKey(String::from_utf8_lossy(bytes).into_owned());
+        let key = std::str::from_utf8(bytes).map_err(|_| invalid())?;
+        let rest = key
+            .strip_prefix(PathTools::DIRECTORY_SEPARATOR_CHAR)
+            .ok_or_else(invalid)?;
+        if rest.is_empty() {
+            return Ok(FileFullPath {
+                value: PathTools::DIRECTORY_SEPARATOR_CHAR.to_string(),
+            });
+        }
+
+        let mut value = String::with_capacity(key.len());
+        for segment in rest.split(PathTools::DIRECTORY_SEPARATOR_CHAR) {
+            let segment = unescape_key_segment(segment).ok_or_else(invalid)?;
+            if !Self::is_valid_segment(&segment) {
+                return Err(invalid());
+            }
+            value.push(PathTools::DIRECTORY_SEPARATOR_CHAR);
+            value.push_str(&segment);
+        }
+        Ok(FileFullPath { value })
+    }
+}
+
+const KEY_ESCAPE_CHAR: char = '%';
+
+fn unescape_key_segment(segment: &str) -> Option<Cow<'_, str>> {
+    if !segment.contains(KEY_ESCAPE_CHAR) {
+        return Some(Cow::Borrowed(segment));
+    }
+
+    let mut result = String::with_capacity(segment.len());
+    let mut rest = segment;
+    while let Some(index) = rest.find(KEY_ESCAPE_CHAR) {
+        result.push_str(&rest[..index]);
+        rest = &rest[index..];
+        let (c, tail) = match rest.strip_prefix("%25") {
+            Some(tail) => (KEY_ESCAPE_CHAR, tail),
+            None => (
+                PathTools::DIRECTORY_SEPARATOR_CHAR,
+                rest.strip_prefix("%2F")?,
+            ),
+        };
+        result.push(c);
+        rest = tail;
     }
+    result.push_str(rest);
+    Some(Cow::Owned(result))
 }
 
 impl From<FileFullPath> for String {
@@ -62,3 +287,178 @@ impl Display for FileFullPath {
         write!(f, "{}", self.value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::FileType;
+
+    use super::{FileFullPath, PathError, PathLimits};
+
+    #[test]
+    fn ancestors_test() {
+        let ancestors = |path: &str| {
+            FileFullPath::parse(path)
+                .ancestors()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(ancestors("/").is_empty());
+        assert_eq!(ancestors("/a"), vec!["/"]);
+        assert_eq!(ancestors("/a/b/c"), vec!["/", "/a", "/a/b"]);
+        assert_eq!(ancestors("/a/b/c/"), vec!["/", "/a", "/a/b"]);
+    }
+
+    #[test]
+    fn join_test() {
+        assert_eq!(FileFullPath::parse("/").join("a").as_ref(), "/a");
+        assert_eq!(FileFullPath::parse("/a/b").join("c.txt").as_ref(), "/a/b/c.txt");
+        assert_eq!(
+            FileFullPath::parse("/a").join("b").join("c"),
+            FileFullPath::parse("/a/b/c")
+        );
+    }
+
+    #[test]
+    fn try_join_test() {
+        let path = FileFullPath::parse("/a");
+        assert_eq!(path.try_join("b"), Ok(FileFullPath::parse("/a/b")));
+        for segment in ["", ".", "..", "../b", "b/c"] {
+            assert_eq!(
+                path.try_join(segment),
+                Err(PathError::InvalidSegment(segment.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn join_invalid_test() {
+        FileFullPath::parse("/a").join("../b");
+    }
+
+    #[test]
+    fn limits_test() {
+        let limits = PathLimits {
+            max_path_len: 8,
+            max_depth: 3,
+        };
+
+        assert_eq!(
+            FileFullPath::parse_with_limits("/a/b/c", &limits),
+            Ok(FileFullPath::parse("/a/b/c"))
+        );
+        assert_eq!(FileFullPath::parse("/").depth(), 0);
+        assert_eq!(
+            FileFullPath::parse_with_limits("/a/b/c/d", &limits),
+            Err(PathError::TooDeep { depth: 4, max: 3 })
+        );
+        assert_eq!(
+            FileFullPath::parse_with_limits("/abcdefgh", &limits),
+            Err(PathError::TooLong { len: 9, max: 8 })
+        );
+        // checked after resolving
+        assert!(FileFullPath::parse_with_limits("/a/b/c/../../d", &limits).is_ok());
+    }
+
+    #[test]
+    fn is_valid_segment_test() {
+        assert!(FileFullPath::is_valid_segment("a"));
+        assert!(FileFullPath::is_valid_segment(".a"));
+        assert!(FileFullPath::is_valid_segment("..."));
+        assert!(!FileFullPath::is_valid_segment(""));
+        assert!(!FileFullPath::is_valid_segment("."));
+        assert!(!FileFullPath::is_valid_segment(".."));
+        assert!(!FileFullPath::is_valid_segment("a/b"));
+    }
+
+    #[test]
+    fn key_bytes_test() {
+        for path in ["/", "/a/b", "/100%", "/%25/%2F", "/a%b/%%", "/.a/.../b c"] {
+            let path = FileFullPath::parse(path);
+            assert_eq!(FileFullPath::from_bytes(&path.as_bytes()), Ok(path));
+        }
+
+        // display stays human readable, the key escapes
+        let path = FileFullPath::parse("/100%/a");
+        assert_eq!(path.to_string(), "/100%/a");
+        assert_eq!(&path.as_bytes()[..], b"/100%25/a");
+        assert!(matches!(path.as_bytes(), std::borrow::Cow::Owned(_)));
+        assert!(matches!(
+            FileFullPath::parse("/a/b").as_bytes(),
+            std::borrow::Cow::Borrowed(b"/a/b")
+        ));
+
+        // a segment literally named `a/b` is not a valid path, never `/a/b`
+        assert!(FileFullPath::from_bytes(b"/a%2Fb").is_err());
+        for key in [
+            &b""[..],
+            b"a",
+            b"//a",
+            b"/a/",
+            b"/./a",
+            b"/a/..",
+            b"/%",
+            b"/%2",
+            b"/%2f",
+            b"/%41",
+            b"/\xff",
+        ] {
+            assert!(
+                matches!(FileFullPath::from_bytes(key), Err(PathError::InvalidKey(_))),
+                "{key:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cmp_hierarchical_test() {
+        let sorted = |paths: &[&str]| {
+            let mut paths = paths
+                .iter()
+                .map(|path| FileFullPath::parse(path))
+                .collect::<Vec<_>>();
+            paths.sort_by(|a, b| a.cmp_hierarchical(b));
+            paths.into_iter().map(String::from).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sorted(&["/a.txt", "/a/b", "/a", "/"]),
+            vec!["/", "/a", "/a/b", "/a.txt"]
+        );
+        assert_eq!(
+            sorted(&["/b", "/a/z/z", "/a/b", "/.a", "/a/.b"]),
+            vec!["/.a", "/a/.b", "/a/b", "/a/z/z", "/b"]
+        );
+        assert_eq!(
+            FileFullPath::parse("/a/b").cmp_hierarchical(&FileFullPath::parse("/a/b/")),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn cmp_directories_first_test() {
+        let mut paths = vec![
+            ("/b.txt", FileType::File),
+            ("/c", FileType::Directory),
+            ("/a.txt", FileType::File),
+            ("/c/.d", FileType::File),
+            ("/d/e", FileType::File),
+            ("/c/b", FileType::Directory),
+        ]
+        .into_iter()
+        .map(|(path, file_type)| (FileFullPath::parse(path), file_type))
+        .collect::<Vec<_>>();
+        paths.sort_by(|(a, a_type), (b, b_type)| a.cmp_directories_first(*a_type, b, *b_type));
+
+        assert_eq!(
+            paths
+                .into_iter()
+                .map(|(path, _)| String::from(path))
+                .collect::<Vec<_>>(),
+            vec!["/c", "/c/b", "/c/.d", "/d/e", "/a.txt", "/b.txt"]
+        );
+    }
+}
@@ -7,4 +7,8 @@ pub struct FileStats {
     /// the size of the file, in bytes.
     pub size: u64,
     pub file_type: FileType,
+    /// permission bits and file type, `st_mode` in unix.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
 }
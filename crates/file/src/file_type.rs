@@ -1,5 +1,7 @@
-use utils::Serialize;
+use utils::{Deserialize, Serialize};
 
+/// The discriminant is stored in the type marker of every tracked file, so it
+/// must never change.
 #[repr(u8)]
 #[derive(
     Debug, Copy, Clone, Hash, PartialEq, Eq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
@@ -8,10 +10,41 @@ pub enum FileType {
     File = b'f',
     Directory = b'd',
     SymbolicLink = b's',
+    #[cfg(unix)]
+    BlockDevice = b'b',
+    #[cfg(unix)]
+    CharDevice = b'c',
+    #[cfg(unix)]
+    Fifo = b'p',
+    #[cfg(unix)]
+    Socket = b'k',
+}
+
+impl FileType {
+    /// Device, fifo or socket. They are tracked but their content is never
+    /// read, reading a fifo blocks until a writer shows up.
+    pub fn is_special(&self) -> bool {
+        !matches!(self, Self::File | Self::Directory | Self::SymbolicLink)
+    }
 }
 
 impl From<std::fs::FileType> for FileType {
     fn from(value: std::fs::FileType) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            if value.is_block_device() {
+                return Self::BlockDevice;
+            } else if value.is_char_device() {
+                return Self::CharDevice;
+            } else if value.is_fifo() {
+                return Self::Fifo;
+            } else if value.is_socket() {
+                return Self::Socket;
+            }
+        }
+
         if value.is_symlink() {
             Self::SymbolicLink
         } else if value.is_dir() {
@@ -37,3 +70,48 @@ impl Serialize for FileType {
         Some(1)
     }
 }
+
+impl Deserialize for FileType {
+    fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
+        let (discriminant, rest) = bytes
+            .split_first()
+            .ok_or_else(|| "Failed to decode file type: empty bytes".to_string())?;
+        let file_type = Self::try_from(*discriminant)
+            .map_err(|_| format!("Unknown file type discriminant: {discriminant:#04x}"))?;
+
+        Ok((file_type, rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::{Deserialize, Serialize};
+
+    use super::FileType;
+
+    #[test]
+    fn deserialize_test() {
+        for file_type in [
+            FileType::File,
+            FileType::Directory,
+            FileType::SymbolicLink,
+            FileType::BlockDevice,
+            FileType::CharDevice,
+            FileType::Fifo,
+            FileType::Socket,
+        ] {
+            assert_eq!(
+                FileType::from_bytes(&file_type.to_bytes()).unwrap(),
+                file_type
+            );
+        }
+
+        assert!(FileType::from_bytes(&[]).is_err());
+        assert_eq!(
+            FileType::from_bytes(&[0xff]).unwrap_err(),
+            "Unknown file type discriminant: 0xff"
+        );
+        assert!(FileType::from_bytes(b"x").is_err());
+        assert!(FileType::try_from(0u8).is_err());
+    }
+}
@@ -1,7 +1,66 @@
 use std::path::PathBuf;
 
+use crate::FileFullPath;
+
 #[derive(Debug, Clone)]
 pub struct Configuration {
     pub root: PathBuf,
-    pub use_inode: bool,
+    pub marker: MarkerStrategy,
+    pub update_marker: UpdateMarkerStrategy,
+    /// Where `root` is placed in the tracker, several roots can share one
+    /// tracker with distinct mount points. Mount points must not be nested.
+    pub mount_point: FileFullPath,
+}
+
+#[cfg(test)]
+impl Configuration {
+    /// The setup of the tests, `root` mounted at `/` and the files told apart
+    /// by their inode.
+    pub(crate) fn for_test(root: &std::path::Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            marker: MarkerStrategy::Inode,
+            update_marker: Default::default(),
+            mount_point: FileFullPath::parse("/"),
+        }
+    }
+}
+
+/// How the marker of a file is derived, the tracker reports a move when the
+/// same marker shows up at another path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarkerStrategy {
+    /// No marker, a rename is a delete and a create.
+    #[default]
+    None,
+    /// The inode number, cheap and kept across renames. Not stable on some
+    /// network file systems, and reused by new files after a delete.
+    Inode,
+    /// Hash of the path, stable where the inode numbers are not, but a rename
+    /// is a delete and a create.
+    PathHash,
+    /// Hash of the file content, directories have no marker. Kept across
+    /// file systems, but every file is read when indexed, and a copy is taken
+    /// as a move.
+    ContentHash,
+}
+
+/// How the update marker of a file is derived, the tracker updates the entity
+/// when it changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpdateMarkerStrategy {
+    /// The times and the size of the file.
+    #[default]
+    Metadata,
+    /// Same as `Metadata`, and when the modification time has no nanoseconds
+    /// also the first and the last bytes of the file. Catches same-size edits
+    /// within a second on file systems with second granularity, at the cost
+    /// of a small read.
+    SampleContent,
+    /// The modification time and the size only, as a
+    /// [`TimeSizeMarker`](crate::tracker::TimeSizeMarker). The tracker can
+    /// compare it with a tolerance on the time, so files restored with rounded
+    /// times are not updated, see
+    /// [`Tracker::set_mtime_tolerance`](crate::tracker::Tracker::set_mtime_tolerance).
+    TimeSize,
 }
@@ -1,49 +1,644 @@
-use super::{Configuration, Result, Walker, WalkerItem};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+};
 
-pub struct Discoverer {
+use chunk::ChunkRef;
+use db::{DBTransaction, DB};
+use trie::{store::TrieStoreRead, Op};
+use utils::Serialize;
+
+use crate::{
+    tracker::{
+        update_markers_match, Discovery, Entity, Error as TrackerError, Tracker, TrackerEvent,
+        TrackerTransaction,
+    },
+    FileEvent, FileEventType, FileFullPath, FileStats,
+};
+
+use super::{
+    Configuration, Error, Helper, OverflowPolicy, Result, Walker, WalkerItem, WatchEvent,
+    WatchQueue,
+};
+
+/// Difference between the tracker and the disk, see
+/// [`Discoverer::diff_against_filesystem`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    /// On the disk, not in the tracker.
+    Added(FileFullPath),
+    /// In the tracker, not on the disk.
+    Removed(FileFullPath),
+    /// The type or the update marker changed.
+    Modified(FileFullPath),
+    /// The marker in the tracker is found at another path on the disk.
+    Moved {
+        from: FileFullPath,
+        to: FileFullPath,
+    },
+}
+
+/// When [`Discoverer::index_batched`] commits the transaction, whichever
+/// limit is reached first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchLimits {
+    /// Max number of ops in one transaction.
+    pub max_ops: usize,
+    /// Max size in bytes of the ops in one transaction, as encoded in the op
+    /// log.
+    pub max_bytes: usize,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        Self {
+            max_ops: 10_000,
+            max_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+pub struct Discoverer<DBImpl> {
     configuration: Configuration,
+    tracker: Tracker<DBImpl>,
     current_walker: Option<Walker>,
+    /// whether the initial full walk is finished.
+    indexed: bool,
+    /// folders that need to be re-indexed, reported by the watcher.
+    changed_folders: BTreeSet<PathBuf>,
 }
 
-impl Discoverer {
-    pub fn new(configuration: Configuration) -> Self {
+impl<DBImpl: DB> Discoverer<DBImpl> {
+    pub fn new(configuration: Configuration, tracker: Tracker<DBImpl>) -> Self {
         Self {
-            configuration: configuration.clone(),
+            configuration,
+            tracker,
             current_walker: None,
+            indexed: false,
+            changed_folders: Default::default(),
         }
     }
 
-    // pub fn poll_ops(&mut self) -> Result<()> {
-    //     if let WalkerItem::Reached {
-    //         folder,
-    //         metadata: _,
-    //         children,
-    //     } = self.poll_walker()?
-    //     {
-    //         let mut transaction = self.tracker.start_transaction()?;
-    //         let ops = transaction.apply(Discovery {
-    //             entities: children
-    //                 .into_iter()
-    //                 .map(|(name, metadata)| DiscoveryEntity {
-    //                     name: self.convert_name(&name),
-    //                     marker: self.make_marker(&metadata),
-    //                     type_marker: self.make_type_marker(&metadata),
-    //                     update_marker: self.make_update_marker(&metadata),
-    //                 })
-    //                 .collect(),
-    //             location: (self.convert_path(&folder).unwrap(), Default::default()),
-    //         })?;
-    //         transaction.commit()?;
-    //         if !ops.is_empty() {
-    //             dbg!(ops);
-    //         }
-    //     }
-
-    //     Ok(())
-    // }
+    pub fn tracker(&self) -> &Tracker<DBImpl> {
+        &self.tracker
+    }
+
+    /// Start a [`WatchQueue`] for the changes of the root folder, the events
+    /// are delivered to `callback` on a worker thread. Fails if the root can
+    /// not be read, e.g. it is missing or the permission is denied, or if the
+    /// worker can not be started.
+    pub fn watch(
+        &self,
+        capacity: usize,
+        overflow: OverflowPolicy,
+        callback: impl FnMut(WatchEvent) + Send + 'static,
+    ) -> Result<WatchQueue> {
+        std::fs::read_dir(&self.configuration.root)?;
+        Ok(WatchQueue::new(capacity, overflow, callback)?)
+    }
+
+    /// Report a changed path, only the folder containing the path will be
+    /// re-indexed on the next [`Discoverer::poll_ops`].
+    pub fn notify_changed(&mut self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            if parent.starts_with(&self.configuration.root) {
+                self.changed_folders.insert(parent.to_owned());
+                return;
+            }
+        }
+
+        self.changed_folders.insert(self.configuration.root.clone());
+    }
+
+    /// Walk the whole tree again on the following polls.
+    pub fn rescan(&mut self) {
+        self.indexed = false;
+        self.current_walker = None;
+    }
+
+    /// Index one folder, returns `None` if there is nothing to do.
+    ///
+    /// Before the initial full walk is finished, folders come from the walker.
+    /// After that, only the folders reported by [`Discoverer::notify_changed`]
+    /// are read.
+    pub fn poll_ops(&mut self) -> Result<Option<Vec<Op<u128, Entity>>>> {
+        Ok(self.poll(false)?.map(|event| event.ops))
+    }
+
+    /// Same as [`Discoverer::poll_ops`], but the transaction is rolled back,
+    /// returns the changes that would have been committed.
+    ///
+    /// The walker still advances, so successive dry runs cover the whole tree.
+    /// Call [`Discoverer::rescan`] before indexing for real.
+    pub fn poll_ops_dry_run(&mut self) -> Result<Option<TrackerEvent>> {
+        self.poll(true)
+    }
+
+    /// Finish the initial full walk, the folders are applied to one
+    /// transaction which is committed every time it reaches `limits`, so the
+    /// memory stays bounded and the progress is durable. Returns the number of
+    /// ops.
+    ///
+    /// The clock and the trie ids are read from the database by every
+    /// transaction, so the ops continue across the commits as if they were
+    /// committed at once. On error the committed batches are kept and the
+    /// next poll walks the tree from the root again.
+    ///
+    /// Does nothing if the initial walk is finished, call
+    /// [`Discoverer::rescan`] to walk the tree again.
+    pub fn index_batched(&mut self, limits: BatchLimits) -> Result<usize> {
+        if self.indexed {
+            return Ok(0);
+        }
+
+        let mut walker = self
+            .current_walker
+            .take()
+            .unwrap_or_else(|| Walker::new(&self.configuration.root));
+        let helper = Helper::new(&self.configuration);
+        let mut ops = 0;
+        let mut transaction = self.tracker.start_transaction()?;
+        let mut batch_ops = 0;
+        let mut batch_bytes = 0;
+        for item in walker.iter() {
+            let discovery = match item? {
+                WalkerItem::Reached {
+                    folder,
+                    metadata: _,
+                    children,
+                } => helper.make_discovery(&folder, children),
+                // the children of an unreadable folder are kept as they are.
+                WalkerItem::Pending | WalkerItem::Error { .. } => None,
+            };
+            let Some(discovery) = discovery else {
+                continue;
+            };
+
+            self.create_mount_point(&mut transaction, &discovery)?;
+            transaction.apply(discovery)?;
+            let published = &transaction.published().ops;
+            for op in &published[batch_ops..] {
+                batch_bytes += op.byte_size().unwrap_or_default();
+            }
+            batch_ops = published.len();
+
+            if batch_ops >= limits.max_ops || batch_bytes >= limits.max_bytes {
+                ops += batch_ops;
+                transaction.commit()?;
+                transaction = self.tracker.start_transaction()?;
+                batch_ops = 0;
+                batch_bytes = 0;
+            }
+        }
+        ops += batch_ops;
+        transaction.commit()?;
+
+        // the full walk is finished, changes are already covered by it.
+        self.indexed = true;
+        self.changed_folders.clear();
+        Ok(ops)
+    }
+
+    fn poll(&mut self, dry_run: bool) -> Result<Option<TrackerEvent>> {
+        let item = self.poll_changes()?;
+        if let WalkerItem::Error { .. } = item {
+            // the tracked children of an unreadable folder are kept as they
+            // are, it is read again on the next walk.
+            return Ok(Some(Default::default()));
+        }
+
+        if let WalkerItem::Reached {
+            folder,
+            metadata: _,
+            children,
+        } = item
+        {
+            let helper = Helper::new(&self.configuration);
+
+            if self.indexed {
+                // new folders are not known by the tracker, index them as well.
+                let trie = self.tracker.trie();
+                for (name, metadata) in children.iter() {
+                    if metadata.is_dir() {
+                        let child_folder = folder.join(name);
+                        let known = helper
+                            .convert_path(&child_folder)
+                            .map(|path| trie.get_id_by_path(&path))
+                            .transpose()
+                            .map_err(TrackerError::from)?
+                            .flatten()
+                            .is_some();
+                        if !known {
+                            self.changed_folders.insert(child_folder);
+                        }
+                    }
+                }
+            }
+
+            let discovery = if let Some(discovery) = helper.make_discovery(&folder, children) {
+                discovery
+            } else {
+                return Ok(Some(Default::default()));
+            };
+
+            let mut transaction = self.tracker.start_transaction()?;
+            self.create_mount_point(&mut transaction, &discovery)?;
+            let skipped = if dry_run {
+                // earlier dry runs are rolled back, the parent folders are missing.
+                transaction.create_folders(discovery.location_full_path())?;
+                transaction.published().ops.len()
+            } else {
+                0
+            };
+            transaction.apply(discovery)?;
+            let mut event = transaction.published().clone();
+            event.ops.drain(..skipped);
+            if dry_run {
+                transaction.rollback()?;
+            } else {
+                transaction.commit()?;
+            }
+
+            Ok(Some(event))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Rename a file on the disk and record the move in the tracker, so the
+    /// entity keeps the same identity.
+    ///
+    /// If `to` already exists it is replaced.
+    pub fn rename(&self, from: FileFullPath, to: FileFullPath) -> Result<Vec<FileEvent>> {
+        let mount_point = &self.configuration.mount_point;
+        if &from == mount_point || &to == mount_point {
+            return Err(Error::InvalidOp("Can't rename the root".to_string()));
+        }
+
+        let helper = Helper::new(&self.configuration);
+        let outside = || Error::InvalidOp("Path is outside the mount point".to_string());
+        let from_fspath = helper.convert_fspath(from.as_ref()).ok_or_else(outside)?;
+        let to_fspath = helper.convert_fspath(to.as_ref()).ok_or_else(outside)?;
+
+        // the parents are re-read below, keep re-indexes out until committed.
+        let _guard = self
+            .tracker
+            .lock_paths([from.dirname().as_ref(), to.dirname().as_ref()]);
+        let overwritten = std::fs::symlink_metadata(&to_fspath).is_ok();
+
+        std::fs::rename(&from_fspath, &to_fspath)?;
+
+        let mut transaction = self.tracker.start_transaction()?;
+        transaction.rename(from.as_ref(), to.as_ref())?;
+        let moved = transaction.take_events();
+
+        // refresh the update markers changed by the rename.
+        let mut folders = vec![to.dirname()];
+        if from.dirname() != to.dirname() {
+            folders.push(from.dirname());
+        }
+        for folder in folders {
+            if let WalkerItem::Reached {
+                folder,
+                metadata: _,
+                children,
+            } = Walker::read_folder(helper.convert_fspath(folder.as_ref()).ok_or_else(outside)?)?
+            {
+                if let Some(discovery) = helper.make_discovery(&folder, children) {
+                    transaction.apply(discovery)?;
+                }
+            }
+        }
+        transaction.commit()?;
+
+        let mut events = vec![];
+        if overwritten {
+            events.push(FileEvent {
+                event_type: FileEventType::Deleted,
+                path: to,
+                old_path: None,
+                file_type: None,
+            });
+        }
+        events.extend(moved);
+
+        Ok(events)
+    }
+
+    /// Read the file at `path` and split it into content-defined chunks, the
+    /// chunks are stored in the entity of the file, so files sharing chunks
+    /// can be deduplicated and synced incrementally.
+    ///
+    /// Fails if the file changed since it was indexed.
+    pub fn chunk_file(&self, path: FileFullPath) -> Result<Vec<ChunkRef>> {
+        let helper = Helper::new(&self.configuration);
+        let fspath = helper
+            .convert_fspath(path.as_ref())
+            .ok_or_else(|| Error::InvalidOp("Path is outside the mount point".to_string()))?;
+
+        let _guard = self.tracker.lock_paths([path.as_ref()]);
+        let metadata = std::fs::symlink_metadata(&fspath)?;
+        if !metadata.is_file() {
+            return Err(Error::InvalidOp("Only files can be chunked".to_string()));
+        }
+        let chunks = chunk::chunk_refs(&std::fs::read(&fspath)?)
+            .map_err(|err| Error::InvalidOp(format!("Chunk is too long, {err}")))?;
+
+        let mut transaction = self.tracker.start_transaction()?;
+        transaction.set_chunks(
+            path.as_ref(),
+            &helper.make_update_marker(&fspath, &metadata),
+            chunks.clone(),
+        )?;
+        transaction.commit()?;
+
+        Ok(chunks)
+    }
+
+    /// Stats of the file at `path` on the disk.
+    pub fn stat_file(&self, path: FileFullPath) -> Result<FileStats> {
+        let helper = Helper::new(&self.configuration);
+        let fspath = helper
+            .convert_fspath(path.as_ref())
+            .ok_or_else(|| Error::InvalidOp("Path is outside the mount point".to_string()))?;
+
+        Ok(helper.convert_stats(&std::fs::symlink_metadata(fspath)?))
+    }
+
+    /// Whether the file at `path` changed since `prev` was taken by
+    /// [`Discoverer::stat_file`], a removed file is changed. Only stats the
+    /// file, no marker is computed, so it is cheap to poll.
+    ///
+    /// The times have second granularity, an edit keeping the size within the
+    /// same second is only caught by the update marker.
+    pub fn stat_changed(&self, path: FileFullPath, prev: &FileStats) -> Result<bool> {
+        match self.stat_file(path) {
+            Ok(stats) => Ok(&stats != prev),
+            Err(Error::IOError(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(true),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Index the whole tree with `threads` workers reading the folders, the
+    /// discoveries are applied to the tracker one by one on the current
+    /// thread.
+    ///
+    /// A folder is only handed to the workers after its parent is applied, so
+    /// the tracker always knows the location of a discovery. Returns the move
+    /// events inferred by the tracker.
+    pub fn index_parallel(&self, threads: usize) -> Result<Vec<FileEvent>> {
+        let (job_sender, job_receiver) = mpsc::channel::<PathBuf>();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let helper = Helper::new(&self.configuration);
+        let _guard = self
+            .tracker
+            .lock_paths([self.configuration.mount_point.as_ref()]);
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                let job_receiver = job_receiver.clone();
+                let result_sender = result_sender.clone();
+                let helper = &helper;
+                scope.spawn(move || loop {
+                    let folder = match job_receiver.lock().unwrap().recv() {
+                        Ok(folder) => folder,
+                        Err(_) => break,
+                    };
+                    let result = match Walker::read_folder(&folder) {
+                        Ok(WalkerItem::Reached {
+                            folder,
+                            metadata: _,
+                            children,
+                        }) => {
+                            let child_folders = children
+                                .iter()
+                                .filter(|(_, metadata)| metadata.is_dir())
+                                .map(|(name, _)| folder.join(name))
+                                .collect::<Vec<_>>();
+                            (helper.make_discovery(&folder, children), child_folders)
+                        }
+                        // the tracked children of an unreadable folder are
+                        // kept as they are, same as the serial walk.
+                        Ok(WalkerItem::Pending | WalkerItem::Error { .. }) | Err(_) => {
+                            (None, vec![])
+                        }
+                    };
+                    if result_sender.send(result).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(result_sender);
+
+            let mut events = vec![];
+            let mut pending = 1;
+            job_sender
+                .send(self.configuration.root.clone())
+                .expect("workers alive");
+
+            while pending > 0 {
+                let (discovery, child_folders) = result_receiver.recv().expect("workers alive");
+                pending -= 1;
+
+                if let Some(discovery) = discovery {
+                    let mut transaction = self.tracker.start_transaction()?;
+                    self.create_mount_point(&mut transaction, &discovery)?;
+                    transaction.apply(discovery)?;
+                    events.extend(transaction.take_events());
+                    transaction.commit()?;
+                }
+
+                for child_folder in child_folders {
+                    job_sender.send(child_folder).expect("workers alive");
+                    pending += 1;
+                }
+            }
+
+            // stop the workers
+            drop(job_sender);
+
+            Ok(events)
+        })
+    }
+
+    /// Index the folder at `path` again, same as a folder reported by
+    /// [`Discoverer::notify_changed`], but it takes a shared reference, so it
+    /// can run beside [`Discoverer::rename`] on other threads. Returns the
+    /// move events inferred by the tracker.
+    pub fn reindex(&self, path: FileFullPath) -> Result<Vec<FileEvent>> {
+        let helper = Helper::new(&self.configuration);
+        let fspath = helper
+            .convert_fspath(path.as_ref())
+            .ok_or_else(|| Error::InvalidOp("Path is outside the mount point".to_string()))?;
+
+        // a rename committed between the read and the apply would be undone.
+        let _guard = self.tracker.lock_paths([path.as_ref()]);
+        let mut events = vec![];
+        if let WalkerItem::Reached {
+            folder,
+            metadata: _,
+            children,
+        } = Walker::read_folder(fspath)?
+        {
+            if let Some(discovery) = helper.make_discovery(&folder, children) {
+                let mut transaction = self.tracker.start_transaction()?;
+                self.create_mount_point(&mut transaction, &discovery)?;
+                transaction.apply(discovery)?;
+                events = transaction.take_events();
+                transaction.commit()?;
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Compare the files under `root` on the disk with the tracker, nothing
+    /// is written. Useful to find out why the tracker is out of sync before
+    /// indexing again.
+    pub fn diff_against_filesystem(&self, root: &Path) -> Result<Vec<Drift>> {
+        let helper = Helper::new(&self.configuration);
+        let location = helper
+            .convert_path(root)
+            .ok_or_else(|| Error::InvalidOp("Path is outside the root".to_string()))?;
+
+        let mut on_disk = BTreeMap::new();
+        let mut unreadable = vec![];
+        let mut walker = Walker::new(root);
+        for item in walker.iter() {
+            match item? {
+                WalkerItem::Reached {
+                    folder,
+                    metadata: _,
+                    children,
+                } => {
+                    if let Some(discovery) = helper.make_discovery(&folder, children) {
+                        let folder = FileFullPath::parse(discovery.location_full_path());
+                        for entity in discovery.entities {
+                            let path = folder.try_join(&entity.name).map_err(TrackerError::from)?;
+                            on_disk.insert(path.to_string(), entity);
+                        }
+                    }
+                }
+                WalkerItem::Error { path, error: _ } => unreadable.extend(
+                    helper
+                        .convert_path(&path)
+                        .map(|path| FileFullPath::parse(&path)),
+                ),
+                WalkerItem::Pending => {}
+            }
+        }
+
+        let trie = self.tracker.trie();
+        let mut in_tracker = BTreeMap::new();
+        if let Some(id) = trie.get_id_by_path(&location).map_err(TrackerError::from)? {
+            let mut stack = vec![(location, id)];
+            while let Some((path, id)) = stack.pop() {
+                for (key, child) in trie.get_children(id).map_err(TrackerError::from)? {
+                    let child_path = FileFullPath::parse(&path)
+                        .try_join(key.as_str())
+                        .map_err(TrackerError::from)?
+                        .to_string();
+                    let entity = trie.get_ensure(child).map_err(TrackerError::from)?.content;
+                    in_tracker.insert(child_path.clone(), entity);
+                    stack.push((child_path, child));
+                }
+            }
+        }
+
+        // nothing is known of the children of the unreadable folders
+        in_tracker.retain(|path, _| {
+            let path = FileFullPath::parse(path);
+            !unreadable
+                .iter()
+                .any(|folder| folder != &path && folder.contains(&path))
+        });
+
+        let mut drifts = vec![];
+        let mut added = vec![];
+        for (path, entity) in on_disk.iter() {
+            match in_tracker.remove(path) {
+                Some(tracked) => {
+                    if tracked.type_marker != entity.type_marker
+                        || !update_markers_match(
+                            &tracked.update_marker,
+                            &entity.update_marker,
+                            self.tracker.mtime_tolerance(),
+                        )
+                    {
+                        drifts.push(Drift::Modified(FileFullPath::parse(path)));
+                    }
+                }
+                None => added.push((path, entity)),
+            }
+        }
+
+        // what is left in the tracker is gone from its path, it is moved if
+        // its marker shows up at a new path.
+        let mut markers = in_tracker
+            .iter()
+            .filter(|(_, tracked)| !tracked.marker.is_empty())
+            .map(|(path, tracked)| (tracked.marker.clone(), path.clone()))
+            .collect::<BTreeMap<_, _>>();
+        for (path, entity) in added {
+            let from = if entity.marker.is_empty() {
+                None
+            } else {
+                markers.remove(&entity.marker)
+            };
+            drifts.push(match from {
+                Some(from) => {
+                    in_tracker.remove(&from);
+                    Drift::Moved {
+                        from: FileFullPath::parse(&from),
+                        to: FileFullPath::parse(path),
+                    }
+                }
+                None => Drift::Added(FileFullPath::parse(path)),
+            });
+        }
+        drifts.extend(
+            in_tracker
+                .into_keys()
+                .map(|path| Drift::Removed(FileFullPath::parse(&path))),
+        );
+
+        Ok(drifts)
+    }
+
+    /// Create the folders of the mount point before the root is applied.
+    fn create_mount_point<T: DBTransaction>(
+        &self,
+        transaction: &mut TrackerTransaction<T>,
+        discovery: &Discovery,
+    ) -> Result<()> {
+        let mount_point = &self.configuration.mount_point;
+        if mount_point.as_ref() != "/" && discovery.location_full_path() == mount_point.as_ref() {
+            transaction.create_folders(mount_point.as_ref())?;
+        }
+        Ok(())
+    }
 
     fn poll_changes(&mut self) -> Result<WalkerItem> {
-        self.poll_walker()
+        if !self.indexed {
+            return self.poll_walker();
+        }
+
+        while let Some(folder) = self.changed_folders.pop_first() {
+            match Walker::read_folder(&folder) {
+                Ok(item @ WalkerItem::Reached { .. }) => return Ok(item),
+                // the folder is gone, re-index its parent.
+                Ok(WalkerItem::Pending) | Err(_) if folder != self.configuration.root => {
+                    self.notify_changed(&folder)
+                }
+                Ok(item) => return Ok(item),
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(WalkerItem::Pending)
     }
 
     fn poll_walker(&mut self) -> Result<WalkerItem> {
@@ -57,7 +652,861 @@ impl Discoverer {
         if let Some(next) = walker.iter().next() {
             Ok(next?)
         } else {
+            // the full walk is finished, changes are already covered by it.
+            self.indexed = true;
+            self.current_walker = None;
+            self.changed_folders.clear();
             Ok(WalkerItem::Pending)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use db::backend::memory::MemoryDB;
+    use trie::store::TrieStoreRead;
+    use utils::Deserialize;
+
+    use crate::tracker::{Tracker, TrackerEvent};
+
+    use crate::{FileEventType, FileFullPath, FileType};
+
+    use super::{
+        super::{MarkerStrategy, UpdateMarkerStrategy},
+        BatchLimits, Configuration, Discoverer, Drift, Error, OverflowPolicy, WatchEvent,
+    };
+
+    /// A discoverer over an empty tracker in memory.
+    fn new_discoverer(configuration: Configuration) -> Discoverer<MemoryDB> {
+        Discoverer::new(configuration, Tracker::init(MemoryDB::default()).unwrap())
+    }
+
+    fn index_all(discoverer: &mut Discoverer<MemoryDB>) -> usize {
+        let mut count = 0;
+        while discoverer.poll_ops().unwrap().is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn incremental_test() {
+        let root = test_results::save_dir!("incremental");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/b/c")).unwrap();
+        std::fs::create_dir_all(root.join("d/e")).unwrap();
+        std::fs::write(root.join("a/b/c/file"), "hello").unwrap();
+        std::fs::write(root.join("d/e/file"), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+
+        assert_eq!(index_all(&mut discoverer), 6);
+        assert_eq!(index_all(&mut discoverer), 0);
+
+        std::fs::write(root.join("a/b/c/file"), "world!").unwrap();
+        discoverer.notify_changed(root.join("a/b/c/file"));
+        assert_eq!(index_all(&mut discoverer), 1);
+
+        std::fs::create_dir_all(root.join("d/f/g")).unwrap();
+        std::fs::write(root.join("d/f/g/file"), "hello").unwrap();
+        discoverer.notify_changed(root.join("d/f"));
+        assert_eq!(index_all(&mut discoverer), 3);
+
+        let trie = discoverer.tracker().trie();
+        assert!(trie.get_id_by_path("/a/b/c/file").unwrap().is_some());
+        assert!(trie.get_id_by_path("/d/f/g/file").unwrap().is_some());
+    }
+
+    #[test]
+    fn size_test() {
+        let root = test_results::save_dir!("size");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::write(root.join("a/file"), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+
+        let size = |discoverer: &Discoverer<MemoryDB>, path: &str| {
+            let trie = discoverer.tracker().trie();
+            let id = trie.get_id_by_path(path).unwrap().unwrap();
+            trie.get_ensure(id).unwrap().content.size
+        };
+        assert_eq!(size(&discoverer, "/a/file"), 5);
+        assert_eq!(size(&discoverer, "/a"), 0);
+
+        std::fs::write(root.join("a/file"), "hello world!").unwrap();
+        discoverer.notify_changed(root.join("a/file"));
+        assert_eq!(index_all(&mut discoverer), 1);
+        assert_eq!(size(&discoverer, "/a/file"), 12);
+    }
+
+    #[test]
+    fn dry_run_test() {
+        let root = test_results::save_dir!("dry_run");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::write(root.join("a/file"), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+
+        let mut created = vec![];
+        while let Some(event) = discoverer.poll_ops_dry_run().unwrap() {
+            created.extend(event.ops.into_iter().map(|op| op.child_key.to_string()));
+        }
+        created.sort();
+        assert_eq!(created, vec!["a", "b", "file"]);
+        let trie = discoverer.tracker().trie();
+        assert!(trie.get_children(trie::ROOT).unwrap().is_empty());
+        assert_eq!(trie.iter_log().unwrap().count(), 0);
+
+        discoverer.rescan();
+        assert_eq!(index_all(&mut discoverer), 3);
+        let trie = discoverer.tracker().trie();
+        assert!(trie.get_id_by_path("/a/b").unwrap().is_some());
+        assert!(trie.get_id_by_path("/a/file").unwrap().is_some());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn non_utf8_name_test() {
+        use std::{ffi::OsStr, os::unix::prelude::OsStrExt};
+
+        let root = test_results::save_dir!("non_utf8_name");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        let name = OsStr::from_bytes(b"bad\xffname");
+        std::fs::write(root.join("dir").join(name), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+        let trie = discoverer.tracker().trie();
+        assert!(trie.get_id_by_path("/dir/bad%FFname").unwrap().is_some());
+
+        discoverer
+            .rename(
+                FileFullPath::parse("/dir/bad%FFname"),
+                FileFullPath::parse("/dir/bad%FEname"),
+            )
+            .unwrap();
+        assert!(!root.join("dir").join(name).exists());
+        assert_eq!(
+            std::fs::read(root.join("dir").join(OsStr::from_bytes(b"bad\xfename"))).unwrap(),
+            b"hello"
+        );
+        let trie = discoverer.tracker().trie();
+        assert!(trie.get_id_by_path("/dir/bad%FFname").unwrap().is_none());
+        assert!(trie.get_id_by_path("/dir/bad%FEname").unwrap().is_some());
+    }
+
+    #[test]
+    fn ref_test() {
+        let root = test_results::save_dir!("ref");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::write(root.join("a/file"), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+
+        let tracker = discoverer.tracker();
+        let r = tracker.get_or_create_ref("/a/file").unwrap();
+        assert_eq!(tracker.get_or_create_ref("/a/file").unwrap(), r);
+        assert!(tracker.get_or_create_ref("/a/missing").is_err());
+        assert_eq!(
+            tracker.resolve_ref(r.clone()).unwrap(),
+            Some(FileFullPath::parse("/a/file"))
+        );
+
+        discoverer
+            .rename(FileFullPath::parse("/a"), FileFullPath::parse("/b"))
+            .unwrap();
+        discoverer
+            .rename(
+                FileFullPath::parse("/b/file"),
+                FileFullPath::parse("/b/renamed"),
+            )
+            .unwrap();
+        assert_eq!(
+            discoverer.tracker().resolve_ref(r.clone()).unwrap(),
+            Some(FileFullPath::parse("/b/renamed"))
+        );
+
+        std::fs::remove_file(root.join("b/renamed")).unwrap();
+        discoverer.notify_changed(root.join("b/renamed"));
+        index_all(&mut discoverer);
+        assert_eq!(discoverer.tracker().resolve_ref(r).unwrap(), None);
+    }
+
+    #[test]
+    fn move_directory_test() {
+        let root = test_results::save_dir!("move_directory");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/dir/sub")).unwrap();
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        std::fs::write(root.join("a/dir/x"), "x").unwrap();
+        std::fs::write(root.join("a/dir/sub/y"), "y").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+
+        let id = |discoverer: &Discoverer<MemoryDB>, path: &str| {
+            discoverer
+                .tracker()
+                .trie()
+                .get_id_by_path(path)
+                .unwrap()
+                .unwrap()
+        };
+        let x = id(&discoverer, "/a/dir/x");
+        let sub = id(&discoverer, "/a/dir/sub");
+        let y = id(&discoverer, "/a/dir/sub/y");
+
+        // move the directory and rename a child directory, a new directory
+        // takes the old name
+        std::fs::rename(root.join("a/dir"), root.join("b/moved")).unwrap();
+        std::fs::rename(root.join("b/moved/sub"), root.join("b/moved/renamed")).unwrap();
+        std::fs::create_dir(root.join("b/moved/sub")).unwrap();
+        let receiver = discoverer.tracker().subscribe();
+        discoverer.notify_changed(root.join("a/dir"));
+        discoverer.notify_changed(root.join("b/moved"));
+        discoverer.notify_changed(root.join("b/moved/sub"));
+        index_all(&mut discoverer);
+
+        assert_eq!(id(&discoverer, "/b/moved/x"), x);
+        assert_eq!(id(&discoverer, "/b/moved/renamed"), sub);
+        assert_eq!(id(&discoverer, "/b/moved/renamed/y"), y);
+        assert_ne!(id(&discoverer, "/b/moved/sub"), sub);
+        // moved directly, not through the recycle bin
+        let moves = receiver
+            .try_iter()
+            .flat_map(|event: TrackerEvent| event.events)
+            .map(|event| (event.old_path.unwrap(), event.path))
+            .collect::<Vec<_>>();
+        assert!(moves.contains(&(
+            FileFullPath::parse("/b/moved/sub"),
+            FileFullPath::parse("/b/moved/renamed")
+        )));
+        assert!(discoverer
+            .tracker()
+            .trie()
+            .get_id_by_path("/a/dir")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn moved_event_test() {
+        let root = test_results::save_dir!("moved_event");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/dir")).unwrap();
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        std::fs::write(root.join("a/dir/file"), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+
+        std::fs::rename(root.join("a/dir"), root.join("b/dir")).unwrap();
+        let receiver = discoverer.tracker().subscribe();
+        discoverer.notify_changed(root.join("a/dir"));
+        discoverer.notify_changed(root.join("b/dir"));
+        index_all(&mut discoverer);
+
+        let events = receiver
+            .try_iter()
+            .flat_map(|event: TrackerEvent| event.events)
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].event_type,
+            FileEventType::Moved {
+                from: FileFullPath::parse("/a/dir")
+            }
+        );
+        assert_eq!(events[0].path, FileFullPath::parse("/b/dir"));
+    }
+
+    #[test]
+    fn marker_strategy_test() {
+        let rename = |marker: MarkerStrategy| {
+            let root = test_results::save_dir!("marker_strategy").join(format!("{marker:?}"));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(root.join("a")).unwrap();
+            std::fs::create_dir_all(root.join("b")).unwrap();
+            std::fs::write(root.join("a/file"), "hello").unwrap();
+
+            let mut discoverer = new_discoverer(Configuration {
+                marker,
+                ..Configuration::for_test(&root)
+            });
+            index_all(&mut discoverer);
+            let id = |discoverer: &Discoverer<MemoryDB>, path: &str| {
+                discoverer.tracker().trie().get_id_by_path(path).unwrap()
+            };
+            let old_id = id(&discoverer, "/a/file");
+            assert!(old_id.is_some());
+
+            std::fs::rename(root.join("a/file"), root.join("b/renamed")).unwrap();
+            let receiver = discoverer.tracker().subscribe();
+            discoverer.notify_changed(root.join("a/file"));
+            discoverer.notify_changed(root.join("b/renamed"));
+            index_all(&mut discoverer);
+
+            let moved = receiver
+                .try_iter()
+                .flat_map(|event: TrackerEvent| event.events)
+                .any(|event| {
+                    event.event_type
+                        == FileEventType::Moved {
+                            from: FileFullPath::parse("/a/file"),
+                        }
+                });
+            assert_eq!(id(&discoverer, "/b/renamed") == old_id, moved);
+            moved
+        };
+
+        assert!(rename(MarkerStrategy::Inode));
+        assert!(rename(MarkerStrategy::ContentHash));
+        assert!(!rename(MarkerStrategy::None));
+        assert!(!rename(MarkerStrategy::PathHash));
+    }
+
+    #[test]
+    fn mount_point_test() {
+        let root = test_results::save_dir!("mount_point");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/dir")).unwrap();
+        std::fs::create_dir_all(root.join("b/dir")).unwrap();
+        std::fs::write(root.join("a/dir/file"), "a").unwrap();
+        std::fs::write(root.join("b/file"), "b").unwrap();
+
+        let db = MemoryDB::default();
+        let mount = |tracker, folder: &str, mount_point: &str| {
+            Discoverer::new(
+                Configuration {
+                    mount_point: FileFullPath::parse(mount_point),
+                    ..Configuration::for_test(&root.join(folder))
+                },
+                tracker,
+            )
+        };
+        let mut a = mount(Tracker::init(&db).unwrap(), "a", "/drives/a");
+        let mut b = mount(Tracker::from_db(&db), "b", "/drives/b");
+        while a.poll_ops().unwrap().is_some() {}
+        while b.poll_ops().unwrap().is_some() {}
+
+        let trie = a.tracker().trie();
+        assert!(trie.get_id_by_path("/drives/a/dir/file").unwrap().is_some());
+        assert!(trie.get_id_by_path("/drives/b/file").unwrap().is_some());
+        assert!(trie.get_id_by_path("/dir").unwrap().is_none());
+
+        // changes in one mount don't touch the other
+        std::fs::remove_file(root.join("b/file")).unwrap();
+        b.notify_changed(root.join("b/file"));
+        while b.poll_ops().unwrap().is_some() {}
+        let trie = a.tracker().trie();
+        assert!(trie.get_id_by_path("/drives/b/file").unwrap().is_none());
+        assert!(trie.get_id_by_path("/drives/b/dir").unwrap().is_some());
+        assert!(trie.get_id_by_path("/drives/a/dir/file").unwrap().is_some());
+
+        a.rename(
+            FileFullPath::parse("/drives/a/dir/file"),
+            FileFullPath::parse("/drives/a/renamed"),
+        )
+        .unwrap();
+        assert!(root.join("a/renamed").exists());
+        assert!(a
+            .rename(
+                FileFullPath::parse("/drives/a/renamed"),
+                FileFullPath::parse("/drives/b/renamed"),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn subscribe_test() {
+        let root = test_results::save_dir!("subscribe");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::write(root.join("a/file"), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        let first = discoverer.tracker().subscribe();
+        let second = discoverer.tracker().subscribe();
+        index_all(&mut discoverer);
+
+        let received = |receiver: &std::sync::mpsc::Receiver<_>| {
+            receiver
+                .try_iter()
+                .map(|event: TrackerEvent| event.ops.len())
+                .sum::<usize>()
+        };
+        let count = received(&first);
+        assert!(count >= 2);
+        assert_eq!(received(&second), count);
+
+        let mut transaction = discoverer.tracker().start_transaction().unwrap();
+        transaction.rename("/a/file", "/a/renamed").unwrap();
+        transaction.rollback().unwrap();
+        assert_eq!(received(&first), 0);
+        assert_eq!(received(&second), 0);
+
+        let mut transaction = discoverer.tracker().start_transaction().unwrap();
+        transaction.rename("/a/file", "/a/renamed").unwrap();
+        transaction.commit().unwrap();
+        let event = first.try_recv().unwrap();
+        assert_eq!(event.events.len(), 1);
+        assert_eq!(
+            event.events[0].old_path,
+            Some(FileFullPath::parse("/a/file"))
+        );
+        assert!(second.try_recv().is_ok());
+    }
+
+    #[test]
+    fn index_parallel_test() {
+        let root = test_results::save_dir!("index_parallel");
+        let _ = std::fs::remove_dir_all(&root);
+        for i in 0..5 {
+            for j in 0..5 {
+                let folder = root.join(format!("{i}/{j}"));
+                std::fs::create_dir_all(&folder).unwrap();
+                std::fs::write(folder.join("file"), format!("{i}{j}")).unwrap();
+            }
+        }
+
+        let configuration = Configuration::for_test(&root);
+        let mut serial = new_discoverer(configuration.clone());
+        index_all(&mut serial);
+
+        let parallel = new_discoverer(configuration);
+        let events = parallel.index_parallel(4).unwrap();
+        assert!(events.is_empty());
+
+        assert_eq!(
+            parallel.tracker().trie().to_string(),
+            serial.tracker().trie().to_string()
+        );
+        assert!(parallel
+            .tracker()
+            .trie()
+            .get_id_by_path("/4/4/file")
+            .unwrap()
+            .is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn index_parallel_unreadable_folder_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = test_results::save_dir!("index_parallel_unreadable_folder");
+        let locked = root.join("a/locked");
+        let _ = std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&locked).unwrap();
+        std::fs::write(locked.join("hidden"), "hidden").unwrap();
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        std::fs::write(root.join("b/file"), "hello").unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        if std::fs::read_dir(&locked).is_ok() {
+            // the permissions are not enforced, e.g. running as root
+            std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+
+        let discoverer = new_discoverer(Configuration::for_test(&root));
+        let result = discoverer.index_parallel(4);
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        // the unreadable folder is skipped, the rest of the tree is indexed
+        result.unwrap();
+        let trie = discoverer.tracker().trie();
+        assert!(trie.get_id_by_path("/a/locked").unwrap().is_some());
+        assert!(trie.get_id_by_path("/a/locked/hidden").unwrap().is_none());
+        assert!(trie.get_id_by_path("/b/file").unwrap().is_some());
+    }
+
+    #[test]
+    fn index_batched_test() {
+        let root = test_results::save_dir!("index_batched");
+        let _ = std::fs::remove_dir_all(&root);
+        for i in 0..8 {
+            for j in 0..8 {
+                let folder = root.join(format!("{i}/{j}"));
+                std::fs::create_dir_all(&folder).unwrap();
+                for k in 0..4 {
+                    std::fs::write(folder.join(format!("file{k}")), format!("{i}{j}{k}")).unwrap();
+                }
+            }
+        }
+
+        let configuration = Configuration {
+            mount_point: FileFullPath::parse("/a"),
+            ..Configuration::for_test(&root)
+        };
+        let mut single = new_discoverer(configuration.clone());
+        let single_events = single.tracker().subscribe();
+        index_all(&mut single);
+        let single_ops = single_events.try_iter().map(|e| e.ops.len()).sum::<usize>();
+
+        let mut batched = new_discoverer(configuration);
+        let batched_events = batched.tracker().subscribe();
+        let ops = batched
+            .index_batched(BatchLimits {
+                max_ops: 10,
+                max_bytes: usize::MAX,
+            })
+            .unwrap();
+        let batched_events = batched_events.try_iter().collect::<Vec<_>>();
+
+        assert_eq!(ops, single_ops);
+        assert!(batched_events.len() > 1);
+        // a batch goes over the limit by the ops of its last folder at most
+        assert!(batched_events.iter().all(|e| e.ops.len() < 10 + 8));
+        // the markers keep increasing across the commits
+        let markers = batched_events
+            .iter()
+            .flat_map(|e| e.ops.iter().map(|op| op.marker))
+            .collect::<Vec<_>>();
+        assert!(markers.windows(2).all(|w| w[0] < w[1]));
+
+        assert_eq!(
+            batched.tracker().trie().to_string(),
+            single.tracker().trie().to_string()
+        );
+
+        // nothing more to index
+        assert_eq!(batched.index_batched(Default::default()).unwrap(), 0);
+        assert!(batched.poll_ops().unwrap().is_none());
+
+        // a small byte limit commits after every folder
+        batched.rescan();
+        let events = batched.tracker().subscribe();
+        batched
+            .index_batched(BatchLimits {
+                max_ops: usize::MAX,
+                max_bytes: 1,
+            })
+            .unwrap();
+        assert!(events.try_iter().all(|e| e.ops.is_empty()));
+        assert_eq!(
+            batched.tracker().trie().to_string(),
+            single.tracker().trie().to_string()
+        );
+    }
+
+    #[test]
+    fn rename_test() {
+        let root = test_results::save_dir!("rename");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        std::fs::write(root.join("a/file"), "hello").unwrap();
+        std::fs::write(root.join("b/other"), "world").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+
+        let trie = discoverer.tracker().trie();
+        let id = trie.get_id_by_path("/a/file").unwrap().unwrap();
+        let entity = trie.get_ensure(id).unwrap().content;
+        drop(trie);
+
+        let events = discoverer
+            .rename(
+                FileFullPath::parse("/a/file"),
+                FileFullPath::parse("/b/renamed"),
+            )
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].event_type,
+            FileEventType::Moved {
+                from: FileFullPath::parse("/a/file")
+            }
+        );
+        assert_eq!(events[0].path, FileFullPath::parse("/b/renamed"));
+        assert_eq!(events[0].old_path, Some(FileFullPath::parse("/a/file")));
+        assert_eq!(events[0].file_type, Some(FileType::File));
+
+        let trie = discoverer.tracker().trie();
+        assert!(trie.get_id_by_path("/a/file").unwrap().is_none());
+        assert_eq!(trie.get_id_by_path("/b/renamed").unwrap(), Some(id));
+        let moved = trie.get_ensure(id).unwrap().content;
+        assert_eq!(moved.marker, entity.marker);
+        assert_eq!(moved.type_marker, entity.type_marker);
+        drop(trie);
+
+        // overwrite a existing file
+        let events = discoverer
+            .rename(
+                FileFullPath::parse("/b/renamed"),
+                FileFullPath::parse("/b/other"),
+            )
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event_type, FileEventType::Deleted);
+        assert_eq!(events[1].old_path, Some(FileFullPath::parse("/b/renamed")));
+
+        let trie = discoverer.tracker().trie();
+        assert!(trie.get_id_by_path("/b/renamed").unwrap().is_none());
+        assert_eq!(trie.get_id_by_path("/b/other").unwrap(), Some(id));
+        assert_eq!(
+            std::fs::read_to_string(root.join("b/other")).unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn rename_while_reindexing_test() {
+        let root = test_results::save_dir!("rename_while_reindexing");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::write(root.join("a/file"), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+        let id = discoverer
+            .tracker()
+            .trie()
+            .get_id_by_path("/a/file")
+            .unwrap()
+            .unwrap();
+
+        // a re-index reading the folder before a rename and applying it after
+        // would move the file back to its old name.
+        let discoverer = &discoverer;
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..100 {
+                    let (from, to) = if i % 2 == 0 {
+                        ("/a/file", "/a/renamed")
+                    } else {
+                        ("/a/renamed", "/a/file")
+                    };
+                    discoverer
+                        .rename(FileFullPath::parse(from), FileFullPath::parse(to))
+                        .unwrap();
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..100 {
+                    discoverer.reindex(FileFullPath::parse("/a")).unwrap();
+                }
+            });
+        });
+
+        assert_eq!(discoverer.diff_against_filesystem(&root).unwrap(), vec![]);
+        assert_eq!(
+            discoverer
+                .tracker()
+                .trie()
+                .get_id_by_path("/a/file")
+                .unwrap(),
+            Some(id)
+        );
+    }
+
+    #[test]
+    fn diff_against_filesystem_test() {
+        let root = test_results::save_dir!("diff_against_filesystem");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("d")).unwrap();
+        std::fs::write(root.join("a"), "hello").unwrap();
+        std::fs::write(root.join("b"), "hello").unwrap();
+        std::fs::write(root.join("d/c"), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+        assert_eq!(discoverer.diff_against_filesystem(&root).unwrap(), vec![]);
+
+        std::fs::write(root.join("new"), "hello").unwrap();
+        std::fs::remove_file(root.join("a")).unwrap();
+        std::fs::rename(root.join("b"), root.join("b2")).unwrap();
+
+        let expected = vec![
+            Drift::Moved {
+                from: FileFullPath::parse("/b"),
+                to: FileFullPath::parse("/b2"),
+            },
+            Drift::Added(FileFullPath::parse("/new")),
+            Drift::Removed(FileFullPath::parse("/a")),
+        ];
+        assert_eq!(discoverer.diff_against_filesystem(&root).unwrap(), expected);
+        // nothing is written
+        assert!(discoverer
+            .tracker()
+            .trie()
+            .get_id_by_path("/a")
+            .unwrap()
+            .is_some());
+        assert_eq!(discoverer.diff_against_filesystem(&root).unwrap(), expected);
+        assert_eq!(
+            discoverer.diff_against_filesystem(&root.join("d")).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn chunk_file_test() {
+        let root = test_results::save_dir!("chunk_file");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        // pseudo random content, large enough for a few chunks
+        let mut state = 1u64;
+        let data = (0..1 << 20)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        std::fs::write(root.join("a"), &data).unwrap();
+        std::fs::write(root.join("b"), &data).unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+
+        let a = discoverer.chunk_file(FileFullPath::parse("/a")).unwrap();
+        let b = discoverer.chunk_file(FileFullPath::parse("/b")).unwrap();
+        assert!(a.len() > 1);
+        assert_eq!(a, b);
+
+        let trie = discoverer.tracker().trie();
+        let id = trie.get_id_by_path("/a").unwrap().unwrap();
+        assert_eq!(trie.get_ensure(id).unwrap().content.chunks, Some(a));
+    }
+
+    #[test]
+    fn stat_changed_test() {
+        let root = test_results::save_dir!("stat_changed");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("file");
+        std::fs::write(&path, "hello").unwrap();
+
+        let discoverer = new_discoverer(Configuration::for_test(&root));
+        let file = FileFullPath::parse("/file");
+        let stats = discoverer.stat_file(file.clone()).unwrap();
+        assert_eq!(stats.size, 5);
+        assert!(!discoverer.stat_changed(file.clone(), &stats).unwrap());
+
+        // touched
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1000))
+            .unwrap();
+        assert!(discoverer.stat_changed(file.clone(), &stats).unwrap());
+
+        let stats = discoverer.stat_file(file.clone()).unwrap();
+        assert!(!discoverer.stat_changed(file.clone(), &stats).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(discoverer.stat_changed(file, &stats).unwrap());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn hard_link_test() {
+        let root = test_results::save_dir!("hard_link");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        std::fs::create_dir_all(root.join("c")).unwrap();
+        std::fs::write(root.join("a/file"), "hello").unwrap();
+
+        let mut discoverer = new_discoverer(Configuration::for_test(&root));
+        index_all(&mut discoverer);
+
+        let id = |discoverer: &Discoverer<MemoryDB>, path: &str| {
+            discoverer.tracker().trie().get_id_by_path(path).unwrap()
+        };
+        let file = id(&discoverer, "/a/file").unwrap();
+
+        // same marker at another path, the first path is still linked
+        std::fs::hard_link(root.join("a/file"), root.join("b/link")).unwrap();
+        std::fs::hard_link(root.join("a/file"), root.join("a/same")).unwrap();
+        discoverer.notify_changed(root.join("b/link"));
+        discoverer.notify_changed(root.join("a/same"));
+        index_all(&mut discoverer);
+
+        assert_eq!(id(&discoverer, "/a/file"), Some(file));
+        let link = id(&discoverer, "/b/link").unwrap();
+        let same = id(&discoverer, "/a/same").unwrap();
+        assert_ne!(link, file);
+        assert_ne!(same, file);
+        assert_ne!(same, link);
+
+        // moving a link is still a move
+        std::fs::rename(root.join("b/link"), root.join("c/moved")).unwrap();
+        discoverer.notify_changed(root.join("b/link"));
+        discoverer.notify_changed(root.join("c/moved"));
+        index_all(&mut discoverer);
+
+        assert_eq!(id(&discoverer, "/b/link"), None);
+        assert_eq!(id(&discoverer, "/a/file"), Some(file));
+        assert_eq!(id(&discoverer, "/a/same"), Some(same));
+        assert!(id(&discoverer, "/c/moved").is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_file_test() {
+        let root = test_results::save_dir!("special_file");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let status = std::process::Command::new("mkfifo")
+            .arg(root.join("fifo"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // both strategies read regular files, a fifo would block them forever
+        let mut discoverer = new_discoverer(Configuration {
+            marker: MarkerStrategy::ContentHash,
+            update_marker: UpdateMarkerStrategy::SampleContent,
+            ..Configuration::for_test(&root)
+        });
+        index_all(&mut discoverer);
+
+        let trie = discoverer.tracker().trie();
+        let id = trie.get_id_by_path("/fifo").unwrap().unwrap();
+        let node = trie.get_ensure(id).unwrap();
+        let file_type = FileType::from_bytes(&node.content.type_marker).unwrap();
+        assert_eq!(file_type, FileType::Fifo);
+        assert!(file_type.is_special());
+        assert!(node.content.marker.is_empty());
+
+        assert!(discoverer.chunk_file(FileFullPath::parse("/fifo")).is_err());
+    }
+
+    #[test]
+    fn watch_test() {
+        let root = test_results::save_dir!("watch");
+        let _ = std::fs::remove_dir_all(&root);
+        let discoverer = new_discoverer(Configuration::for_test(&root));
+        assert!(matches!(
+            discoverer.watch(16, OverflowPolicy::Rescan, |_| {}),
+            Err(Error::IOError(_))
+        ));
+
+        std::fs::create_dir_all(&root).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let queue = discoverer
+            .watch(16, OverflowPolicy::Rescan, move |event| {
+                let _ = sender.send(event);
+            })
+            .unwrap();
+        queue.sender().send(root.join("a"));
+        drop(queue);
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![WatchEvent::Changed(root.join("a"))]
+        );
+    }
+}
@@ -7,6 +7,8 @@ pub enum Error {
     TrackerError(#[from] TrackerError),
     #[error("IO error")]
     IOError(#[from] std::io::Error),
+    #[error("Invalid Operation, {0}")]
+    InvalidOp(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,51 +1,105 @@
 use std::{
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
+    fmt::Write,
     fs::Metadata,
-    os::unix::prelude::MetadataExt,
+    io::{Read, Seek, SeekFrom},
+    os::unix::prelude::{MetadataExt, OsStrExt, OsStringExt},
     path::{Path, PathBuf},
+    time::UNIX_EPOCH,
 };
 
-use utils::{Digestible, PathTools, Serialize, Xxhash};
+use utils::{Digest, Digestible, PathTools, Serialize, Xxhash};
 
 use crate::{
-    tracker::{FileMarker, FileTypeMarker, FileUpdateMarker},
+    tracker::{
+        Discovery, DiscoveryBuilder, DiscoveryEntity, DiscoveryMode, FileMarker,
+        FilePermissionMarker, FileSymlinkTarget, FileTypeMarker, FileUpdateMarker, TimeSizeMarker,
+    },
     FileStats, FileType,
 };
 
-use super::Configuration;
+use super::{Configuration, MarkerStrategy, UpdateMarkerStrategy};
+
+/// Bytes read from each end of a file by [`UpdateMarkerStrategy::SampleContent`].
+const CONTENT_SAMPLE_LEN: u64 = 4 * 1024;
 
 pub struct Helper<'a> {
     configuration: &'a Configuration,
 }
 
-impl Helper<'_> {
+impl<'a> Helper<'a> {
+    pub fn new(configuration: &'a Configuration) -> Self {
+        Self { configuration }
+    }
+
     pub fn convert_path(&self, path: &Path) -> Option<String> {
-        let path = path.to_string_lossy().to_string();
+        let path = escape_file_name(path.as_os_str());
 
-        let relative = PathTools::relative(&self.configuration.root.to_string_lossy(), &path);
+        let relative = PathTools::relative(
+            &escape_file_name(self.configuration.root.as_os_str()),
+            &path,
+        );
 
-        if relative.starts_with("..")
-            || relative
-                .chars()
-                .any(|c| c == std::char::REPLACEMENT_CHARACTER)
-        {
+        if relative.starts_with("..") {
             None
         } else {
-            Some(PathTools::resolve("/", &relative).to_string())
+            Some(PathTools::resolve(self.configuration.mount_point.as_ref(), &relative).to_string())
         }
     }
 
-    pub fn make_marker(&self, metadata: &Metadata) -> FileMarker {
-        if self.configuration.use_inode && metadata.is_dir() {
-            (FileType::from(metadata.file_type()), metadata.ino())
-                .to_bytes()
-                .to_vec()
-        } else {
-            Default::default()
+    /// Marker of the file at `path`, see [`MarkerStrategy`].
+    pub fn make_marker(&self, path: &Path, metadata: &Metadata) -> FileMarker {
+        let file_type = FileType::from(metadata.file_type());
+        match self.configuration.marker {
+            MarkerStrategy::None => Default::default(),
+            MarkerStrategy::Inode => (file_type, metadata.ino()).to_bytes().to_vec(),
+            MarkerStrategy::PathHash => {
+                let mut hash = Xxhash::new();
+                hash.update(path.as_os_str().as_bytes());
+                (file_type, b'p', hash.finish()).to_bytes().to_vec()
+            }
+            // devices, fifos and sockets are never read, a fifo would block
+            MarkerStrategy::ContentHash if file_type == FileType::File => {
+                match Self::hash_content(path) {
+                    Ok(hash) => (file_type, b'c', hash).to_bytes().to_vec(),
+                    // the file is gone or unreadable, it has no marker
+                    Err(_) => Default::default(),
+                }
+            }
+            MarkerStrategy::ContentHash => Default::default(),
         }
     }
 
-    pub fn make_update_marker(&self, metadata: &Metadata) -> FileUpdateMarker {
+    fn hash_content(path: &Path) -> std::io::Result<[u8; 8]> {
+        let mut file = std::fs::File::open(path)?;
+        let mut hash = Xxhash::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match file.read(&mut buf)? {
+                0 => return Ok(hash.finish()),
+                len => hash.update(&buf[..len]),
+            }
+        }
+    }
+
+    /// Update marker of the file at `path`, see [`UpdateMarkerStrategy`].
+    ///
+    /// The times are hashed with the size and the type, so empty files, whose
+    /// size is always zero, still differ by their times. The size is the
+    /// logical size, for a sparse file it counts the holes, the allocated
+    /// blocks are left out because they change when the file system allocates
+    /// lazily, without any write to the file.
+    pub fn make_update_marker(&self, path: &Path, metadata: &Metadata) -> FileUpdateMarker {
+        if self.configuration.update_marker == UpdateMarkerStrategy::TimeSize && !metadata.is_dir()
+        {
+            return TimeSizeMarker {
+                mtime: metadata.mtime(),
+                mtime_nsec: metadata.mtime_nsec() as u32,
+                size: metadata.size(),
+            }
+            .to_marker();
+        }
+
         let mut hash = Xxhash::new();
         if !metadata.is_dir() {
             metadata.ctime().digest(&mut hash);
@@ -53,31 +107,404 @@ impl Helper<'_> {
             metadata.mtime().digest(&mut hash);
             metadata.mtime_nsec().digest(&mut hash);
             metadata.size().digest(&mut hash);
+
+            // creation time is not available on every file system, mark whether
+            // it is present so different layouts never hash the same fields.
+            if let Ok(created) = metadata.created() {
+                true.digest(&mut hash);
+                let created = created
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos();
+                created.digest(&mut hash);
+            } else {
+                false.digest(&mut hash);
+            }
+
+            if self.configuration.update_marker == UpdateMarkerStrategy::SampleContent
+                && metadata.is_file()
+                && metadata.mtime_nsec() == 0
+            {
+                // an unreadable file is hashed without the sample
+                if let Ok(sample) = Self::sample_content(path, metadata.size()) {
+                    true.digest(&mut hash);
+                    hash.update(sample);
+                } else {
+                    false.digest(&mut hash);
+                }
+            }
         }
         self.make_type_marker(metadata).digest(&mut hash);
         hash.finish().to_vec()
     }
 
+    /// Hash of the size and the first and last [`CONTENT_SAMPLE_LEN`] bytes.
+    fn sample_content(path: &Path, size: u64) -> std::io::Result<[u8; 8]> {
+        let mut file = std::fs::File::open(path)?;
+        let mut hash = Xxhash::new();
+        size.digest(&mut hash);
+
+        let mut buf = Vec::with_capacity(CONTENT_SAMPLE_LEN as usize);
+        (&mut file).take(CONTENT_SAMPLE_LEN).read_to_end(&mut buf)?;
+        hash.update(&buf);
+
+        if size > CONTENT_SAMPLE_LEN {
+            file.seek(SeekFrom::Start(
+                (size - CONTENT_SAMPLE_LEN).max(CONTENT_SAMPLE_LEN),
+            ))?;
+            buf.clear();
+            file.take(CONTENT_SAMPLE_LEN).read_to_end(&mut buf)?;
+            hash.update(&buf);
+        }
+
+        Ok(hash.finish())
+    }
+
     pub fn make_type_marker(&self, metadata: &Metadata) -> FileTypeMarker {
         FileType::from(metadata.file_type()).to_bytes().into_vec()
     }
 
+    pub fn make_permission_marker(&self, metadata: &Metadata) -> FilePermissionMarker {
+        (metadata.mode(), metadata.uid(), metadata.gid())
+            .to_bytes()
+            .to_vec()
+    }
+
     pub fn convert_stats(&self, metadata: &Metadata) -> FileStats {
         FileStats {
             creation_time: metadata.ctime() as u64,
             last_write_time: metadata.mtime() as u64,
             size: metadata.size(),
             file_type: metadata.file_type().into(),
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        }
+    }
+
+    /// Convert a tracker path to the path on the disk, returns `None` if the
+    /// path is outside the mount point.
+    pub fn convert_fspath(&self, path: &str) -> Option<PathBuf> {
+        let relative = PathTools::relative(
+            self.configuration.mount_point.as_ref(),
+            &PathTools::resolve("/", path),
+        );
+
+        if relative.starts_with("..") {
+            None
+        } else {
+            Some(
+                self.configuration
+                    .root
+                    .join(unescape_file_name(&format!("./{}", relative))),
+            )
+        }
+    }
+
+    /// The size of a regular file, the length reported for the other types
+    /// depends on the file system.
+    pub fn make_size(&self, metadata: &Metadata) -> u64 {
+        if metadata.is_file() {
+            metadata.len()
+        } else {
+            0
         }
     }
 
-    pub fn convert_fspath(&self, path: &str) -> PathBuf {
-        self.configuration
-            .root
-            .join(PathBuf::from(format!(".{}", path)))
+    /// Target of the symbolic link at `path`, `None` if it is not a link or
+    /// the link is gone.
+    pub fn make_symlink_target(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+    ) -> Option<FileSymlinkTarget> {
+        if metadata.is_symlink() {
+            std::fs::read_link(path)
+                .ok()
+                .map(|target| target.into_os_string().into_vec())
+        } else {
+            None
+        }
     }
 
     pub fn convert_name(&self, file_name: &OsStr) -> String {
-        file_name.to_string_lossy().to_string()
+        escape_file_name(file_name)
+    }
+
+    /// Build the discovery of a folder from its children, returns `None` if
+    /// the folder is outside the root or a child name is not valid.
+    pub fn make_discovery(
+        &self,
+        folder: &Path,
+        children: Vec<(OsString, Metadata)>,
+    ) -> Option<Discovery> {
+        DiscoveryBuilder::new(self.convert_path(folder)?, Default::default())
+            .mode(DiscoveryMode::Full)
+            .entities(children.into_iter().map(|(name, metadata)| {
+                let path = folder.join(&name);
+                DiscoveryEntity {
+                    name: self.convert_name(&name),
+                    marker: self.make_marker(&path, &metadata),
+                    type_marker: self.make_type_marker(&metadata),
+                    update_marker: self.make_update_marker(&path, &metadata),
+                    permission_marker: self.make_permission_marker(&metadata),
+                    symlink_target: self.make_symlink_target(&path, &metadata),
+                    hard_links: metadata.nlink(),
+                    size: self.make_size(&metadata),
+                }
+            }))
+            .build()
+            .ok()
+    }
+}
+
+/// Convert a name on the disk to a tracker name without losing any byte.
+///
+/// Bytes that are not valid UTF-8 are written as `%XX`, and a `%` that would
+/// be read as an escape is written as `%25`. Other names are kept as is.
+pub fn escape_file_name(name: &OsStr) -> String {
+    let mut bytes = name.as_bytes();
+    let mut escaped = String::with_capacity(bytes.len());
+
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                push_escaped_str(&mut escaped, valid);
+                return escaped;
+            }
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                push_escaped_str(&mut escaped, std::str::from_utf8(valid).unwrap_or_default());
+                let (invalid, rest) = rest.split_at(err.error_len().unwrap_or(rest.len()));
+                for byte in invalid {
+                    write!(escaped, "%{:02X}", byte).unwrap();
+                }
+                bytes = rest;
+            }
+        }
+    }
+}
+
+/// The name on the disk of a tracker name, see [`escape_file_name`].
+pub fn unescape_file_name(name: &str) -> OsString {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        match (byte, decode_hex(tail)) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    OsString::from_vec(bytes)
+}
+
+fn push_escaped_str(escaped: &mut String, mut str: &str) {
+    while let Some(index) = str.find('%') {
+        escaped.push_str(&str[..index]);
+        str = &str[index + 1..];
+        if decode_hex(str.as_bytes()).is_some() {
+            escaped.push_str("%25");
+        } else {
+            escaped.push('%');
+        }
+    }
+    escaped.push_str(str);
+}
+
+/// Decode the two hex digits at the start of `bytes`.
+fn decode_hex(bytes: &[u8]) -> Option<u8> {
+    match bytes {
+        [high, low, ..] => {
+            Some(((*high as char).to_digit(16)? << 4 | (*low as char).to_digit(16)?) as u8)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::Metadata,
+        os::unix::prelude::MetadataExt,
+        path::PathBuf,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::{
+        escape_file_name, unescape_file_name, Configuration, Helper, UpdateMarkerStrategy,
+    };
+
+    #[test]
+    fn update_marker_test() {
+        let root = test_results::save_dir!("update_marker");
+        std::fs::create_dir_all(&root).unwrap();
+        let configuration = Configuration::for_test(&root);
+        let helper = Helper::new(&configuration);
+
+        let write = |name: &str, mtime: u64| {
+            let path = root.join(name);
+            std::fs::write(&path, "same size").unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))
+                .unwrap();
+            (path.clone(), std::fs::metadata(&path).unwrap())
+        };
+        let marker =
+            |(path, metadata): &(PathBuf, Metadata)| helper.make_update_marker(path, metadata);
+
+        let a = write("a", 1000);
+        assert_eq!(marker(&a), marker(&a));
+
+        let b = write("b", 2000);
+        assert_eq!(a.1.len(), b.1.len());
+        assert_ne!(marker(&a), marker(&b));
+
+        let a_changed = write("a", 2000);
+        assert_ne!(marker(&a), marker(&a_changed));
+    }
+
+    #[test]
+    fn empty_and_sparse_update_marker_test() {
+        let root = test_results::save_dir!("empty_and_sparse_update_marker");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let create = |name: &str, mtime: u64| {
+            let path = root.join(name);
+            let file = std::fs::File::create(&path).unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))
+                .unwrap();
+            path
+        };
+        let a = create("a", 1000);
+        let b = create("b", 2000);
+
+        for update_marker in [
+            UpdateMarkerStrategy::Metadata,
+            UpdateMarkerStrategy::SampleContent,
+            UpdateMarkerStrategy::TimeSize,
+        ] {
+            let configuration = Configuration {
+                update_marker,
+                ..Configuration::for_test(&root)
+            };
+            let helper = Helper::new(&configuration);
+            let marker =
+                |path: &PathBuf| helper.make_update_marker(path, &std::fs::metadata(path).unwrap());
+
+            assert_eq!(std::fs::metadata(&a).unwrap().len(), 0);
+            assert_eq!(marker(&a), marker(&a));
+            assert_ne!(marker(&a), marker(&b), "{update_marker:?}");
+        }
+
+        // a sparse file, the logical size is hashed
+        let configuration = Configuration::for_test(&root);
+        let helper = Helper::new(&configuration);
+        let path = create("sparse", 1000);
+        let empty = helper.make_update_marker(&path, &std::fs::metadata(&path).unwrap());
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_len(1024 * 1024).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(1000))
+            .unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.size(), 1024 * 1024);
+        let sparse = helper.make_update_marker(&path, &metadata);
+        assert_ne!(sparse, empty);
+        assert_eq!(sparse, helper.make_update_marker(&path, &metadata));
+    }
+
+    #[test]
+    fn sample_content_test() {
+        let root = test_results::save_dir!("sample_content");
+        std::fs::create_dir_all(&root).unwrap();
+        let configuration = Configuration {
+            update_marker: UpdateMarkerStrategy::SampleContent,
+            ..Configuration::for_test(&root)
+        };
+        let helper = Helper::new(&configuration);
+
+        // two same-size edits within the same second
+        let path = root.join("file");
+        let mut samples = vec![];
+        for content in *b"ab" {
+            std::fs::write(&path, vec![content; 10 * 1024]).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(1000))
+                .unwrap();
+            let metadata = std::fs::metadata(&path).unwrap();
+            assert_eq!(metadata.mtime_nsec(), 0);
+            samples.push((
+                Helper::sample_content(&path, metadata.size()).unwrap(),
+                helper.make_update_marker(&path, &metadata),
+            ));
+        }
+        // the content alone tells the edits apart
+        assert_ne!(samples[0].0, samples[1].0);
+        assert_ne!(samples[0].1, samples[1].1);
+
+        // an edit in the middle is not sampled
+        let mut content = vec![b'b'; 10 * 1024];
+        content[5 * 1024] = b'c';
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(
+            Helper::sample_content(&path, 10 * 1024).unwrap(),
+            samples[1].0
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn stats_mode_test() {
+        use std::os::unix::prelude::{MetadataExt, PermissionsExt};
+
+        let root = test_results::save_dir!("stats_mode");
+        std::fs::create_dir_all(&root).unwrap();
+        let configuration = Configuration::for_test(&root);
+        let helper = Helper::new(&configuration);
+
+        let path = root.join("file");
+        std::fs::write(&path, "hello").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        let stats = helper.convert_stats(&metadata);
+        assert_eq!(stats.mode & 0o777, 0o640);
+        assert_eq!(stats.uid, metadata.uid());
+        assert_eq!(stats.gid, metadata.gid());
+        let permission_marker = helper.make_permission_marker(&metadata);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(helper.convert_stats(&metadata).mode & 0o777, 0o600);
+        assert_ne!(helper.make_permission_marker(&metadata), permission_marker);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn escape_file_name_test() {
+        use std::{ffi::OsStr, os::unix::prelude::OsStrExt};
+
+        for (name, escaped) in [
+            (&b"plain"[..], "plain"),
+            (b"caf\xc3\xa9", "caf\u{e9}"),
+            (b"bad\xffname", "bad%FFname"),
+            (b"\xc3", "%C3"),
+            (b"100%", "100%"),
+            (b"%zz", "%zz"),
+            (b"%41", "%2541"),
+            (b"%\xff", "%%FF"),
+            (b"%2525", "%252525"),
+        ] {
+            let name = OsStr::from_bytes(name);
+            assert_eq!(escape_file_name(name), escaped);
+            assert_eq!(unescape_file_name(escaped), name);
+        }
     }
 }
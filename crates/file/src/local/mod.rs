@@ -4,6 +4,8 @@ mod helper;
 pub use helper::*;
 mod walker;
 pub use walker::*;
+mod watcher;
+pub use watcher::*;
 mod discoverer;
 pub use discoverer::*;
 mod tracker;
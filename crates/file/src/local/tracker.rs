@@ -7,8 +7,8 @@ pub struct Tracker<DBImpl> {
 }
 
 impl<DBImpl: DBRead> Tracker<DBImpl> {
-    fn tracker(&self) -> RawTracker<DBImpl> {
-        RawTracker::from_db(self.db)
+    fn tracker(&self) -> RawTracker<&DBImpl> {
+        RawTracker::from_db(&self.db)
     }
 }
 
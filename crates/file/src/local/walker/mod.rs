@@ -1,8 +1,13 @@
 use std::{
     collections::LinkedList,
+    fs::ReadDir,
     path::{Path, PathBuf},
 };
 
+use crate::FileFullPath;
+
+use super::escape_file_name;
+
 #[derive(Debug)]
 pub enum WalkerItem {
     Pending,
@@ -11,6 +16,13 @@ pub enum WalkerItem {
         metadata: std::fs::Metadata,
         children: Vec<(std::ffi::OsString, std::fs::Metadata)>,
     },
+    /// The folder at `path` could not be read, e.g. the permission is denied.
+    /// The walk goes on with the other folders, nothing below `path` is
+    /// visited.
+    Error {
+        path: PathBuf,
+        error: std::io::Error,
+    },
 }
 
 impl WalkerItem {
@@ -22,6 +34,7 @@ impl WalkerItem {
                 metadata: _,
                 children: _,
             } => Some(folder),
+            WalkerItem::Error { path, error: _ } => Some(path),
         }
     }
 }
@@ -62,6 +75,68 @@ impl std::ops::DerefMut for WalkerIter<'_> {
     }
 }
 
+/// Iterator yields every entry under the walker root one at a time, see
+/// [`Walker::iter_flat`].
+pub struct WalkerFlatIter {
+    root: Option<PathBuf>,
+    stack: Vec<(FileFullPath, ReadDir)>,
+    /// The error of the folder yielded last, reported on the next call.
+    error: Option<std::io::Error>,
+}
+
+impl Iterator for WalkerFlatIter {
+    type Item = Result<(FileFullPath, std::fs::Metadata), std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+
+        if let Some(root) = self.root.take() {
+            match std::fs::read_dir(root) {
+                Ok(read_dir) => self.stack.push((FileFullPath::parse("/"), read_dir)),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        while let Some((folder, read_dir)) = self.stack.last_mut() {
+            let entry = match read_dir.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(err)) => return Some(Err(err)),
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(err) => return Some(Err(err)),
+            };
+            let path = match folder.try_join(&escape_file_name(&entry.file_name())) {
+                Ok(path) => path,
+                Err(err) => {
+                    return Some(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        err,
+                    )))
+                }
+            };
+
+            if metadata.is_dir() {
+                match std::fs::read_dir(entry.path()) {
+                    Ok(read_dir) => self.stack.push((path.clone(), read_dir)),
+                    Err(err) => self.error = Some(err),
+                }
+            }
+
+            return Some(Ok((path, metadata)));
+        }
+
+        None
+    }
+}
+
 pub struct Walker {
     root: PathBuf,
     current_stack: LinkedList<PathBuf>,
@@ -88,31 +163,62 @@ impl Walker {
         WalkerIter::new(self)
     }
 
+    /// Iterate every file and folder under the root as a flat stream, folders
+    /// are read lazily and only one entry is held at a time.
+    ///
+    /// Paths are relative to the root. A folder which can't be read is still
+    /// yielded, followed by the error of reading it.
+    pub fn iter_flat(&self) -> WalkerFlatIter {
+        WalkerFlatIter {
+            root: Some(self.root.clone()),
+            stack: vec![],
+            error: None,
+        }
+    }
+
+    /// Read a single folder without descending into it.
+    pub fn read_folder(path: impl AsRef<Path>) -> Result<WalkerItem, std::io::Error> {
+        let folder = path.as_ref().to_owned();
+        let metadata = std::fs::symlink_metadata(&folder)?;
+        if !metadata.is_dir() {
+            return Ok(WalkerItem::Pending);
+        }
+
+        let mut children = vec![];
+        for entry in std::fs::read_dir(&folder)? {
+            let child = entry?;
+            children.push((child.file_name(), child.metadata()?));
+        }
+
+        Ok(WalkerItem::Reached {
+            folder,
+            metadata,
+            children,
+        })
+    }
+
+    /// The folders which can't be read are yielded as [`WalkerItem::Error`],
+    /// so one unreadable folder doesn't abort the walk.
     fn next(&mut self) -> Result<Option<WalkerItem>, std::io::Error> {
         let base = self.current_stack.pop_front();
         if let Some(base_path) = base {
-            let base_metadata = std::fs::symlink_metadata(&base_path)?;
-            if base_metadata.is_dir() {
-                let read_dir = std::fs::read_dir(&base_path)?;
-                let mut children = vec![];
-                for entry in read_dir.into_iter() {
-                    let child = entry?;
-                    let file_type = child.file_type()?;
-                    let file_name = child.file_name();
-                    let file_metadata = child.metadata()?;
-                    if file_type.is_dir() {
-                        self.current_stack.push_back(base_path.join(&file_name))
+            let item = match Self::read_folder(&base_path) {
+                Ok(item) => item,
+                Err(error) => {
+                    return Ok(Some(WalkerItem::Error {
+                        path: base_path,
+                        error,
+                    }))
+                }
+            };
+            if let WalkerItem::Reached { children, .. } = &item {
+                for (file_name, metadata) in children {
+                    if metadata.is_dir() {
+                        self.current_stack.push_back(base_path.join(file_name))
                     }
-                    children.push((file_name, file_metadata));
                 }
-                Ok(Some(WalkerItem::Reached {
-                    folder: base_path,
-                    metadata: base_metadata,
-                    children,
-                }))
-            } else {
-                Ok(Some(WalkerItem::Pending))
             }
+            Ok(Some(item))
         } else {
             self.start_new_walking();
             Ok(None)
@@ -122,6 +228,8 @@ impl Walker {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use super::Walker;
 
     #[test]
@@ -131,4 +239,113 @@ mod tests {
             println!("{:?}", r.unwrap().folder().unwrap());
         });
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_folder_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use super::WalkerItem;
+
+        let root = test_results::save_dir!("unreadable_folder");
+        let locked = root.join("a/locked");
+        let _ = std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(locked.join("hidden")).unwrap();
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        if std::fs::read_dir(&locked).is_ok() {
+            // the permissions are not enforced, e.g. running as root
+            std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+
+        let mut reached = vec![];
+        let mut errors = vec![];
+        for item in Walker::new(&root).iter() {
+            match item.unwrap() {
+                WalkerItem::Reached { folder, .. } => reached.push(folder),
+                WalkerItem::Error { path, error } => {
+                    assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+                    errors.push(path)
+                }
+                WalkerItem::Pending => {}
+            }
+        }
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        reached.sort();
+        assert_eq!(reached, vec![root.clone(), root.join("a"), root.join("b")]);
+        assert_eq!(errors, vec![locked]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn iter_flat_unreadable_folder_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = test_results::save_dir!("iter_flat_unreadable_folder");
+        let locked = root.join("a/locked");
+        let _ = std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(locked.join("hidden")).unwrap();
+        std::fs::write(root.join("a/file"), "1").unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        if std::fs::read_dir(&locked).is_ok() {
+            // the permissions are not enforced, e.g. running as root
+            std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+
+        let mut items = vec![];
+        for item in Walker::new(&root).iter_flat() {
+            items.push(
+                item.map(|(path, _)| path.to_string())
+                    .map_err(|err| err.kind()),
+            );
+        }
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let position = items
+            .iter()
+            .position(|item| item == &Ok("/a/locked".to_string()))
+            .unwrap();
+        assert_eq!(
+            items[position + 1],
+            Err(std::io::ErrorKind::PermissionDenied)
+        );
+        assert!(items.contains(&Ok("/a".to_string())));
+        assert!(items.contains(&Ok("/a/file".to_string())));
+        assert!(!items.contains(&Ok("/a/locked/hidden".to_string())));
+    }
+
+    #[test]
+    fn iter_flat_test() {
+        let root = test_results::save_dir!("iter_flat");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/b/c")).unwrap();
+        std::fs::create_dir_all(root.join("d")).unwrap();
+        std::fs::write(root.join("file"), "1").unwrap();
+        std::fs::write(root.join("a/file"), "2").unwrap();
+        std::fs::write(root.join("a/b/c/file"), "3").unwrap();
+        std::fs::write(root.join("d/file"), "4").unwrap();
+
+        let mut visited = BTreeMap::new();
+        for item in Walker::new(&root).iter_flat() {
+            let (path, metadata) = item.unwrap();
+            if metadata.is_file() {
+                *visited.entry(path.to_string()).or_insert(0) += 1;
+            }
+        }
+
+        assert_eq!(
+            visited.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("/a/b/c/file".to_string(), 1),
+                ("/a/file".to_string(), 1),
+                ("/d/file".to_string(), 1),
+                ("/file".to_string(), 1),
+            ]
+        );
+    }
 }
@@ -0,0 +1,199 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+};
+
+/// Event delivered to the callback of a [`WatchQueue`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// The path changed, see [`super::Discoverer::notify_changed`].
+    Changed(PathBuf),
+    /// Events were lost, the whole tree needs to be walked again, see
+    /// [`super::Discoverer::rescan`].
+    Rescan,
+}
+
+/// What a [`WatchQueue`] does when it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest event and ask for a rescan.
+    DropOldest,
+    /// Drop every queued event and ask for a rescan, which covers them.
+    Rescan,
+}
+
+#[derive(Default)]
+struct State {
+    events: VecDeque<PathBuf>,
+    rescan: bool,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    condvar: Condvar,
+    capacity: usize,
+    overflow: OverflowPolicy,
+}
+
+/// Bounded queue between the watcher thread and a worker thread running the
+/// callback, so a slow callback never blocks the delivery of events.
+///
+/// Dropping the queue delivers the remaining events and waits for the worker.
+pub struct WatchQueue {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl WatchQueue {
+    /// Fails if the worker thread can not be spawned.
+    pub fn new(
+        capacity: usize,
+        overflow: OverflowPolicy,
+        mut callback: impl FnMut(WatchEvent) + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let shared = Arc::new(Shared {
+            state: Default::default(),
+            condvar: Condvar::new(),
+            capacity: capacity.max(1),
+            overflow,
+        });
+
+        let worker_shared = shared.clone();
+        let worker = std::thread::Builder::new()
+            .name("watch-queue".to_string())
+            .spawn(move || {
+                while let Some(event) = worker_shared.next() {
+                    callback(event)
+                }
+            })?;
+
+        Ok(Self {
+            shared,
+            worker: Some(worker),
+        })
+    }
+
+    /// Sender to hand over to the watcher thread.
+    pub fn sender(&self) -> WatchSender {
+        WatchSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for WatchQueue {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.condvar.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Shared {
+    /// Wait for the next event, returns `None` once the queue is closed and
+    /// empty.
+    fn next(&self) -> Option<WatchEvent> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.rescan {
+                state.rescan = false;
+                return Some(WatchEvent::Rescan);
+            }
+            if let Some(path) = state.events.pop_front() {
+                return Some(WatchEvent::Changed(path));
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.condvar.wait(state).unwrap();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WatchSender {
+    shared: Arc<Shared>,
+}
+
+impl WatchSender {
+    /// Queue a changed path, never blocks on the callback.
+    pub fn send(&self, path: PathBuf) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.events.len() >= self.shared.capacity {
+            match self.shared.overflow {
+                OverflowPolicy::DropOldest => {
+                    state.events.pop_front();
+                }
+                OverflowPolicy::Rescan => state.events.clear(),
+            }
+            state.rescan = true;
+        }
+        state.events.push_back(path);
+        drop(state);
+        self.shared.condvar.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::mpsc};
+
+    use super::{OverflowPolicy, WatchEvent, WatchQueue};
+
+    fn run(overflow: OverflowPolicy) -> Vec<WatchEvent> {
+        let (started, wait_started) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        let (delivered, received) = mpsc::channel();
+
+        let queue = WatchQueue::new(2, overflow, move |event| {
+            if delivered.send(event).is_ok() {
+                let _ = started.send(());
+                // slow callback, blocked until the test releases it
+                let _ = wait_release.recv();
+            }
+        })
+        .unwrap();
+        let sender = queue.sender();
+
+        sender.send(PathBuf::from("0"));
+        wait_started.recv().unwrap();
+        // the callback is blocked, sending still returns right away
+        for i in 1..=5 {
+            sender.send(PathBuf::from(i.to_string()));
+        }
+        drop(release);
+        drop(queue);
+
+        received.try_iter().collect()
+    }
+
+    #[test]
+    fn drop_oldest_test() {
+        assert_eq!(
+            run(OverflowPolicy::DropOldest),
+            vec![
+                WatchEvent::Changed(PathBuf::from("0")),
+                WatchEvent::Rescan,
+                WatchEvent::Changed(PathBuf::from("4")),
+                WatchEvent::Changed(PathBuf::from("5")),
+            ]
+        );
+    }
+
+    #[test]
+    fn rescan_test() {
+        assert_eq!(
+            run(OverflowPolicy::Rescan),
+            vec![
+                WatchEvent::Changed(PathBuf::from("0")),
+                WatchEvent::Rescan,
+                WatchEvent::Changed(PathBuf::from("5")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::Clock;
+
+/// Source of the clock stamped as the marker of every op of the tracker. The
+/// clocks are compared as integers, so the ops of every source are totally
+/// ordered.
+pub trait ClockSource: Send + Sync {
+    /// The clock of the next op, must be greater than `last`.
+    fn next(&self, last: Clock) -> Clock;
+}
+
+/// Increment the last clock, enough for a single writer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CounterClock;
+
+impl ClockSource for CounterClock {
+    fn next(&self, last: Clock) -> Clock {
+        last + 1
+    }
+}
+
+/// Hybrid logical clock, the high 64 bits are the wall time in milliseconds
+/// and the low 64 bits a counter for the ops within the same millisecond.
+///
+/// The clock never goes back, if the wall time is behind the last clock, e.g.
+/// after a clock observed from a writer whose time is ahead, the counter keeps
+/// increasing. So ops roughly follow the real time across writers, and an op
+/// is always ordered after the ops it has seen.
+#[derive(Debug, Clone, Copy)]
+pub struct HybridClock {
+    now: fn() -> u64,
+}
+
+impl HybridClock {
+    pub fn new() -> Self {
+        Self::with_time(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64
+        })
+    }
+
+    /// Use `now` as the wall time in milliseconds, e.g. for tests.
+    pub fn with_time(now: fn() -> u64) -> Self {
+        Self { now }
+    }
+
+    /// Wall time in milliseconds of `clock`.
+    pub fn wall_time(clock: Clock) -> u64 {
+        (clock >> 64) as u64
+    }
+}
+
+impl Default for HybridClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClockSource for HybridClock {
+    fn next(&self, last: Clock) -> Clock {
+        (((self.now)() as Clock) << 64).max(last + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClockSource, CounterClock, HybridClock};
+
+    #[test]
+    fn hybrid_clock_test() {
+        assert_eq!(CounterClock.next(1), 2);
+
+        let clock = HybridClock::with_time(|| 10);
+        let first = clock.next(0);
+        assert_eq!(HybridClock::wall_time(first), 10);
+        let second = clock.next(first);
+        assert!(second > first);
+        assert_eq!(HybridClock::wall_time(second), 10);
+
+        // the wall time is behind the last clock
+        let ahead = HybridClock::with_time(|| 20).next(second);
+        assert!(clock.next(ahead) > ahead);
+        assert_eq!(HybridClock::wall_time(clock.next(ahead)), 20);
+    }
+}
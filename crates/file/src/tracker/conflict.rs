@@ -0,0 +1,132 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use utils::PathTools;
+
+/// How a node losing a name conflict is put back into its folder, see
+/// [`Tracker::set_conflict_naming`](super::Tracker::set_conflict_naming).
+///
+/// The placeholders of the template are replaced:
+/// - `{name}` the name the node lost, e.g. `report.txt`
+/// - `{stem}` the name without the extension, e.g. `report`
+/// - `{ext}` the extension with its dot, e.g. `.txt`, empty if there is none
+/// - `{date}` the date of the conflict in UTC, e.g. `2024-01-02`
+/// - `{n}` the number of the copy, from 1 until the name is free
+///
+/// Other text is kept as is. If the template has no `{n}` and the name is
+/// taken, ` 2`, ` 3`, ... are added before the extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictNaming {
+    pub template: String,
+}
+
+impl ConflictNaming {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+        }
+    }
+
+    /// Name of the `n`th copy of `name`, `n` starts at 1.
+    pub fn render(&self, name: &str, now: SystemTime, n: usize) -> String {
+        let ext = PathTools::extname(name);
+        let stem = &name[..name.len() - ext.len()];
+
+        let mut rendered = String::with_capacity(self.template.len() + name.len());
+        let mut has_n = false;
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let placeholder = rest.find('}').map(|end| &rest[..=end]);
+            match placeholder {
+                Some("{name}") => rendered.push_str(name),
+                Some("{stem}") => rendered.push_str(stem),
+                Some("{ext}") => rendered.push_str(ext),
+                Some("{date}") => rendered.push_str(&format_date(now)),
+                Some("{n}") => {
+                    has_n = true;
+                    rendered.push_str(&n.to_string())
+                }
+                _ => {
+                    rendered.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+            }
+            rest = &rest[placeholder.map_or(0, str::len)..];
+        }
+        rendered.push_str(rest);
+
+        if !has_n && n > 1 {
+            let ext = PathTools::extname(&rendered);
+            rendered.insert_str(rendered.len() - ext.len(), &format!(" {n}"));
+        }
+        rendered
+    }
+}
+
+impl Default for ConflictNaming {
+    fn default() -> Self {
+        Self::new("{stem} (conflict {date}){ext}")
+    }
+}
+
+/// `time` as `YYYY-MM-DD` in UTC.
+fn format_date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400;
+
+    // civil date from the days since 1970-01-01, in eras of 400 years
+    // starting on March 1st, so the leap day is the last day of a year.
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{format_date, ConflictNaming};
+
+    #[test]
+    fn conflict_naming_test() {
+        let now = UNIX_EPOCH + Duration::from_secs(1704153600);
+        assert_eq!(format_date(UNIX_EPOCH), "1970-01-01");
+        assert_eq!(format_date(now), "2024-01-02");
+        assert_eq!(
+            format_date(UNIX_EPOCH + Duration::from_secs(951782400)),
+            "2000-02-29"
+        );
+
+        let naming = ConflictNaming::default();
+        assert_eq!(
+            naming.render("report.txt", now, 1),
+            "report (conflict 2024-01-02).txt"
+        );
+        assert_eq!(
+            naming.render("report.txt", now, 2),
+            "report (conflict 2024-01-02) 2.txt"
+        );
+        assert_eq!(naming.render("a", now, 1), "a (conflict 2024-01-02)");
+
+        let naming = ConflictNaming::new("{name}.conflict-{n}");
+        assert_eq!(naming.render("a.txt", now, 3), "a.txt.conflict-3");
+
+        // unknown placeholders and braces are kept
+        let naming = ConflictNaming::new("{stem} {x} {{ext}");
+        assert_eq!(naming.render("{a}.md", now, 1), "{a} {x} {.md");
+    }
+}
@@ -1,4 +1,12 @@
-use super::{FileMarker, FileName, FileUpdateMarker, FileTypeMarker};
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+use crate::FileFullPath;
+
+use super::{
+    FileMarker, FileName, FilePermissionMarker, FileSymlinkTarget, FileTypeMarker, FileUpdateMarker,
+};
 
 #[derive(Debug, Clone)]
 pub struct DiscoveryEntity {
@@ -6,11 +14,33 @@ pub struct DiscoveryEntity {
     pub marker: FileMarker,
     pub type_marker: FileTypeMarker,
     pub update_marker: FileUpdateMarker,
+    pub permission_marker: FilePermissionMarker,
+    /// The target if the entity is a symbolic link.
+    pub symlink_target: Option<FileSymlinkTarget>,
+    /// Number of paths linked to the file, sharing its marker. `1`, or `0`
+    /// when unknown, unless the file has hard links.
+    pub hard_links: u64,
+    /// Size of the file in bytes, `0` for directories and symbolic links.
+    pub size: u64,
+}
+
+/// How the entities of a [`Discovery`] relate to the children already in the
+/// tracker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiscoveryMode {
+    /// The entities are every child of the location, the children not listed
+    /// are moved to recycle.
+    #[default]
+    Full,
+    /// The entities are some children of the location, e.g. when the caller
+    /// only knows about a subset, the children not listed are left untouched.
+    Shallow,
 }
 
 #[derive(Debug)]
 pub struct Discovery {
     pub location: (String, FileMarker),
+    pub mode: DiscoveryMode,
     pub entities: Vec<DiscoveryEntity>,
 }
 
@@ -22,3 +52,131 @@ impl Discovery {
         &self.location.1
     }
 }
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryError {
+    #[error("Invalid file name {0:?}")]
+    InvalidName(String),
+    #[error("Duplicate file name {0:?}")]
+    DuplicateName(String),
+}
+
+/// Builds a [`Discovery`] whose entities are valid file names, see
+/// [`FileFullPath::is_valid_segment`], without duplicates.
+#[derive(Debug)]
+pub struct DiscoveryBuilder {
+    location: (String, FileMarker),
+    mode: DiscoveryMode,
+    lowercase_names: bool,
+    entities: Vec<DiscoveryEntity>,
+}
+
+impl DiscoveryBuilder {
+    pub fn new(location: String, marker: FileMarker) -> Self {
+        Self {
+            location: (location, marker),
+            mode: Default::default(),
+            lowercase_names: false,
+            entities: Vec::new(),
+        }
+    }
+
+    pub fn mode(mut self, mode: DiscoveryMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Lowercase the entity names, for case insensitive file systems where
+    /// `a` and `A` are the same file.
+    pub fn lowercase_names(mut self, lowercase_names: bool) -> Self {
+        self.lowercase_names = lowercase_names;
+        self
+    }
+
+    pub fn entity(mut self, entity: DiscoveryEntity) -> Self {
+        self.entities.push(entity);
+        self
+    }
+
+    pub fn entities(mut self, entities: impl IntoIterator<Item = DiscoveryEntity>) -> Self {
+        self.entities.extend(entities);
+        self
+    }
+
+    pub fn build(mut self) -> Result<Discovery, DiscoveryError> {
+        let mut names = HashSet::with_capacity(self.entities.len());
+        for entity in self.entities.iter_mut() {
+            if self.lowercase_names {
+                entity.name = entity.name.to_lowercase();
+            }
+            if !FileFullPath::is_valid_segment(&entity.name) {
+                return Err(DiscoveryError::InvalidName(entity.name.clone()));
+            }
+            if !names.insert(entity.name.as_str()) {
+                return Err(DiscoveryError::DuplicateName(entity.name.clone()));
+            }
+        }
+
+        Ok(Discovery {
+            location: self.location,
+            mode: self.mode,
+            entities: self.entities,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiscoveryBuilder, DiscoveryEntity, DiscoveryError};
+
+    fn entity(name: &str) -> DiscoveryEntity {
+        DiscoveryEntity {
+            name: name.to_string(),
+            marker: vec![],
+            type_marker: vec![b'f'],
+            update_marker: vec![],
+            permission_marker: vec![],
+            symlink_target: None,
+            hard_links: 1,
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn duplicate_name_test() {
+        let discovery = DiscoveryBuilder::new("/".to_string(), vec![])
+            .entities([entity("a"), entity("A")])
+            .build()
+            .unwrap();
+        assert_eq!(discovery.entities.len(), 2);
+
+        assert_eq!(
+            DiscoveryBuilder::new("/".to_string(), vec![])
+                .entities([entity("a"), entity("b"), entity("a")])
+                .build()
+                .unwrap_err(),
+            DiscoveryError::DuplicateName("a".to_string())
+        );
+        assert_eq!(
+            DiscoveryBuilder::new("/".to_string(), vec![])
+                .lowercase_names(true)
+                .entities([entity("a"), entity("A")])
+                .build()
+                .unwrap_err(),
+            DiscoveryError::DuplicateName("a".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_name_test() {
+        for name in ["", ".", "..", "a/b"] {
+            assert_eq!(
+                DiscoveryBuilder::new("/".to_string(), vec![])
+                    .entity(entity(name))
+                    .build()
+                    .unwrap_err(),
+                DiscoveryError::InvalidName(name.to_string())
+            );
+        }
+    }
+}
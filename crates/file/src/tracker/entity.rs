@@ -1,14 +1,53 @@
 use std::fmt::{Debug, Display};
 
+use chunk::ChunkRef;
 use utils::{bytes_stringify, Deserialize, Digest, Digestible, Serialize};
 
-use super::{FileMarker, FileTypeMarker, FileUpdateMarker};
+use super::{
+    FileMarker, FilePermissionMarker, FileSymlinkTarget, FileTypeMarker, FileUpdateMarker,
+};
 
-#[derive(Clone, Default)]
+/// First byte of a versioned [`Entity`] record. Records written before the
+/// version was added start with the length of the marker, whose first byte is
+/// always `0` for markers shorter than 16 MiB.
+const ENTITY_VERSION_TAG: u8 = 0xFF;
+
+/// Version of the [`Entity`] records written by this build, bump it when a
+/// field is added. Older records are decoded with the default of the fields
+/// they don't have, and written back in the latest version the first time the
+/// node is read or written in a transaction.
+///
+/// - 0: the records without the version tag, the marker, the update marker
+///   and the type marker.
+/// - 1: the permission marker, the symlink target and the chunks.
+/// - 2: the ignored flag.
+/// - 3: the size.
+/// - 4: the raw name.
+pub const ENTITY_VERSION: u8 = 4;
+
+#[derive(Clone, Default, PartialEq, Eq)]
 pub struct Entity {
     pub marker: FileMarker,
     pub update_marker: FileUpdateMarker,
     pub type_marker: FileTypeMarker,
+    /// Empty if unknown, e.g. in the records before version 1, and then not
+    /// compared with the discovered one.
+    pub permission_marker: FilePermissionMarker,
+    pub symlink_target: Option<FileSymlinkTarget>,
+    /// Content-defined chunks of the file, `None` until the file is chunked
+    /// and again after its content changes.
+    pub chunks: Option<Vec<ChunkRef>>,
+    /// Excluded by the user, the discoveries of the folder and below are
+    /// skipped, the node is kept so the flag persists. Added in version 2.
+    pub ignored: bool,
+    /// Size of the file in bytes as last indexed, `0` for directories and
+    /// symbolic links. Added in version 3.
+    pub size: u64,
+    /// Name on the disk when it is not the key of the node, e.g. a decomposed
+    /// name stored under its NFC key, see
+    /// [`Tracker::set_nfc_keys`](super::Tracker::set_nfc_keys). Added in
+    /// version 4.
+    pub raw_name: Option<String>,
 }
 
 impl Display for Entity {
@@ -18,48 +57,104 @@ impl Display for Entity {
             bytes_stringify(&self.marker),
             bytes_stringify(&self.update_marker),
             bytes_stringify(&self.type_marker)
-        ))
+        ))?;
+        if self.ignored {
+            f.write_str("(ignored)")?;
+        }
+        Ok(())
     }
 }
 
 impl Debug for Entity {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!(
-            "`{}`({})({})",
-            bytes_stringify(&self.marker),
-            bytes_stringify(&self.update_marker),
-            bytes_stringify(&self.type_marker)
-        ))
+        Display::fmt(self, f)
     }
 }
 
 impl Serialize for Entity {
     fn serialize(&self, serializer: utils::Serializer) -> utils::Serializer {
+        let serializer = (ENTITY_VERSION_TAG, ENTITY_VERSION).serialize(serializer);
         let serializer = self.marker.serialize(serializer);
         let serializer = self.update_marker.serialize(serializer);
-        self.type_marker.serialize(serializer)
+        let serializer = self.type_marker.serialize(serializer);
+        let serializer = self.permission_marker.serialize(serializer);
+        let serializer = self.symlink_target.serialize(serializer);
+        let serializer = self.chunks.serialize(serializer);
+        let serializer = self.ignored.serialize(serializer);
+        let serializer = self.size.serialize(serializer);
+        self.raw_name.serialize(serializer)
     }
 
     fn byte_size(&self) -> Option<usize> {
         Some(
-            self.marker.byte_size()?
+            2 + self.marker.byte_size()?
                 + self.update_marker.byte_size()?
-                + self.type_marker.byte_size()?,
+                + self.type_marker.byte_size()?
+                + self.permission_marker.byte_size()?
+                + self.symlink_target.byte_size()?
+                + self.chunks.byte_size()?
+                + self.ignored.byte_size()?
+                + self.size.byte_size()?
+                + self.raw_name.byte_size()?,
         )
     }
 }
 
 impl Deserialize for Entity {
+    /// Fields added after the first version are decoded only if
+    /// `version >= <version they were added in>`, and default otherwise.
     fn deserialize(bytes: &[u8]) -> std::result::Result<(Self, &[u8]), String> {
+        let (version, bytes) = match bytes.split_first() {
+            Some((&ENTITY_VERSION_TAG, rest)) => {
+                let (version, rest) = u8::deserialize(rest)?;
+                if version > ENTITY_VERSION {
+                    return Err(format!(
+                        "Entity version {version} is newer than the supported {ENTITY_VERSION}"
+                    ));
+                }
+                (version, rest)
+            }
+            // written before the version was added
+            _ => (0, bytes),
+        };
         let (marker, bytes) = <_>::deserialize(bytes)?;
         let (update_marker, bytes) = <_>::deserialize(bytes)?;
         let (type_marker, bytes) = <_>::deserialize(bytes)?;
+        let (permission_marker, symlink_target, chunks, bytes) = if version >= 1 {
+            let (permission_marker, bytes) = <_>::deserialize(bytes)?;
+            let (symlink_target, bytes) = <_>::deserialize(bytes)?;
+            let (chunks, bytes) = <_>::deserialize(bytes)?;
+            (permission_marker, symlink_target, chunks, bytes)
+        } else {
+            (Default::default(), None, None, bytes)
+        };
+        let (ignored, bytes) = if version >= 2 {
+            <_>::deserialize(bytes)?
+        } else {
+            (false, bytes)
+        };
+        let (size, bytes) = if version >= 3 {
+            <_>::deserialize(bytes)?
+        } else {
+            (0, bytes)
+        };
+        let (raw_name, bytes) = if version >= 4 {
+            <_>::deserialize(bytes)?
+        } else {
+            (None, bytes)
+        };
 
         Ok((
             Self {
                 marker,
                 update_marker,
                 type_marker,
+                permission_marker,
+                symlink_target,
+                chunks,
+                ignored,
+                size,
+                raw_name,
             },
             bytes,
         ))
@@ -71,5 +166,159 @@ impl Digestible for Entity {
         self.marker.digest(data);
         self.update_marker.digest(data);
         self.type_marker.digest(data);
+        self.permission_marker.digest(data);
+        if let Some(symlink_target) = &self.symlink_target {
+            true.digest(data);
+            symlink_target.digest(data);
+        } else {
+            false.digest(data);
+        }
+        if let Some(chunks) = &self.chunks {
+            true.digest(data);
+            for chunk in chunks {
+                chunk.digest(data);
+            }
+        } else {
+            false.digest(data);
+        }
+        self.ignored.digest(data);
+        self.size.digest(data);
+        if let Some(raw_name) = &self.raw_name {
+            true.digest(data);
+            raw_name.digest(data);
+        } else {
+            false.digest(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chunk::ChunkRef;
+    use utils::{Deserialize, Serialize};
+
+    use super::{Entity, ENTITY_VERSION, ENTITY_VERSION_TAG};
+
+    #[test]
+    fn entity_version_test() {
+        let entity = Entity {
+            marker: vec![1, 2],
+            update_marker: vec![3],
+            type_marker: vec![b'f'],
+            permission_marker: vec![4],
+            symlink_target: Some(b"target".to_vec()),
+            chunks: Some(vec![ChunkRef {
+                offset: 0,
+                len: 5,
+                hash: [6; 16],
+            }]),
+            ignored: true,
+            size: 5,
+            raw_name: Some("raw".to_string()),
+        };
+
+        let bytes = entity.to_bytes();
+        assert_eq!(bytes[..2], [ENTITY_VERSION_TAG, ENTITY_VERSION]);
+        assert!(Entity::from_bytes(&bytes).unwrap() == entity);
+
+        // a record of version 3, without the raw name
+        let v3 = (
+            (ENTITY_VERSION_TAG, 3u8),
+            (
+                entity.marker.clone(),
+                entity.update_marker.clone(),
+                entity.type_marker.clone(),
+            ),
+            (
+                entity.permission_marker.clone(),
+                entity.symlink_target.clone(),
+                entity.chunks.clone(),
+            ),
+            (entity.ignored, entity.size),
+        )
+            .to_bytes();
+        assert!(
+            Entity::from_bytes(&v3).unwrap()
+                == Entity {
+                    raw_name: None,
+                    ..entity.clone()
+                }
+        );
+
+        // a record of version 2, without the size
+        let v2 = (
+            (ENTITY_VERSION_TAG, 2u8),
+            (
+                entity.marker.clone(),
+                entity.update_marker.clone(),
+                entity.type_marker.clone(),
+            ),
+            (
+                entity.permission_marker.clone(),
+                entity.symlink_target.clone(),
+                entity.chunks.clone(),
+            ),
+            entity.ignored,
+        )
+            .to_bytes();
+        assert!(
+            Entity::from_bytes(&v2).unwrap()
+                == Entity {
+                    size: 0,
+                    raw_name: None,
+                    ..entity.clone()
+                }
+        );
+
+        // a record of version 1, without the ignored flag
+        let v1 = (
+            (ENTITY_VERSION_TAG, 1u8),
+            (
+                entity.marker.clone(),
+                entity.update_marker.clone(),
+                entity.type_marker.clone(),
+            ),
+            (
+                entity.permission_marker.clone(),
+                entity.symlink_target.clone(),
+                entity.chunks.clone(),
+            ),
+        )
+            .to_bytes();
+        let not_ignored = Entity {
+            ignored: false,
+            size: 0,
+            raw_name: None,
+            ..entity.clone()
+        };
+        assert!(Entity::from_bytes(&v1).unwrap() == not_ignored);
+
+        // a record written before the version, the three fields of the first
+        // layout without the tag, each a 4 bytes big endian length and bytes
+        let legacy = [
+            &[0, 0, 0, 2, 1, 2][..],
+            &[0, 0, 0, 1, 3],
+            &[0, 0, 0, 1, b'f'],
+        ]
+        .concat();
+        let (decoded, rest) = Entity::deserialize(&legacy).unwrap();
+        assert!(rest.is_empty());
+        let baseline = Entity {
+            marker: entity.marker.clone(),
+            update_marker: entity.update_marker.clone(),
+            type_marker: entity.type_marker.clone(),
+            ..Default::default()
+        };
+        assert!(decoded == baseline);
+        // written back in the latest version
+        assert_eq!(
+            decoded.to_bytes()[..2],
+            [ENTITY_VERSION_TAG, ENTITY_VERSION]
+        );
+        assert!(Entity::from_bytes(&decoded.to_bytes()).unwrap() == baseline);
+        // a cut record is an error, not a panic
+        assert!(Entity::deserialize(&legacy[..legacy.len() - 1]).is_err());
+
+        assert!(Entity::from_bytes(&[ENTITY_VERSION_TAG, ENTITY_VERSION + 1]).is_err());
     }
 }
@@ -1,3 +1,7 @@
+use std::time::Duration;
+
+use utils::{Deserialize, Serialize};
+
 /// store file system level file identifier, e.g. inode number in linux, file_id
 /// in windows.
 ///
@@ -29,6 +33,87 @@ pub type FileMarker = Vec<u8>;
 /// as Vec<u8>, allowing it to store any value.
 pub type FileTypeMarker = Vec<u8>;
 
+/// Store the permissions and ownership of the file, e.g. mode, uid and gid in
+/// unix.
+///
+/// # Behavior
+/// If the tracker detects a change in the permission marker at the same
+/// location, the file is deemed updated, same as the update marker.
+///
+/// If the permission marker is supplied as empty, the tracker makes no
+/// judgment about the permissions.
+pub type FilePermissionMarker = Vec<u8>;
+
+/// The target of a symbolic link, as the bytes of the path on the platform.
+///
+/// # Behavior
+/// If the tracker detects a change in the target at the same location, the
+/// link is deemed updated, same as the update marker. A change between a file
+/// and a link is a change in the file type marker.
+pub type FileSymlinkTarget = Vec<u8>;
+
 /// Store information about whether the file is updated.
 /// Usually is a combination of file mtime and size.
 pub type FileUpdateMarker = Vec<u8>;
+
+/// An update marker made of the modification time and the size of the file,
+/// unlike a hash it can be compared with a tolerance on the time, see
+/// [`update_markers_match`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSizeMarker {
+    /// Seconds since the epoch.
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+    pub size: u64,
+}
+
+impl TimeSizeMarker {
+    const TAG: u8 = b't';
+    const LEN: usize = 1 + 8 + 4 + 8;
+
+    pub fn to_marker(&self) -> FileUpdateMarker {
+        (Self::TAG, self.mtime, self.mtime_nsec, self.size)
+            .to_bytes()
+            .to_vec()
+    }
+
+    /// Parse an update marker made by [`TimeSizeMarker::to_marker`], `None`
+    /// for other update markers.
+    pub fn from_marker(marker: &[u8]) -> Option<Self> {
+        // the decoding panics on short bytes
+        if marker.len() != Self::LEN || marker[0] != Self::TAG {
+            return None;
+        }
+        match <(u8, i64, u32, u64)>::deserialize(marker) {
+            Ok(((Self::TAG, mtime, mtime_nsec, size), [])) => Some(Self {
+                mtime,
+                mtime_nsec,
+                size,
+            }),
+            _ => None,
+        }
+    }
+
+    fn nanos(&self) -> i128 {
+        self.mtime as i128 * 1_000_000_000 + self.mtime_nsec as i128
+    }
+}
+
+/// Whether the update markers `a` and `b` are the same. Two
+/// [`TimeSizeMarker`]s with the same size and modification times at most
+/// `tolerance` apart are the same, e.g. a file restored from a backup which
+/// rounded the times. Other update markers are compared as bytes.
+pub fn update_markers_match(a: &[u8], b: &[u8], tolerance: Duration) -> bool {
+    if a == b {
+        return true;
+    }
+    match (
+        TimeSizeMarker::from_marker(a),
+        TimeSizeMarker::from_marker(b),
+    ) {
+        (Some(a), Some(b)) => {
+            a.size == b.size && (a.nanos() - b.nanos()).unsigned_abs() <= tolerance.as_nanos()
+        }
+        _ => false,
+    }
+}
@@ -4,18 +4,41 @@
 //! We use an additional database to track local files and store the data
 //! associated with the files.
 
+mod clock;
+mod conflict;
 mod discovery;
 mod entity;
 mod marker;
+mod op_stream;
+mod path_lock;
 
+pub use clock::*;
+pub use conflict::*;
 pub use discovery::*;
 pub use entity::*;
 pub use marker::*;
+pub use op_stream::*;
+pub use path_lock::*;
 
-use db::{DBLock, DBRead, DBTransaction, DBWrite, DB};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use chunk::ChunkRef;
+use db::{DBLock, DBRead, DBTransaction, DBWrite, DurabilityMode, DB};
 use thiserror::Error;
-use trie::{Error as TrieError, Op, OpTarget, Trie, TrieId, TrieTransaction, store::TrieStoreRead};
-use utils::{Deserialize, Serialize};
+use trie::{
+    store::{TrieStoreRead, TrieVisitor, WalkControl},
+    Error as TrieError, MarkerCollisionPolicy, Op, OpTarget, Trie, TrieId, TrieKeyRef, TrieNode,
+    TrieRef, TrieTransaction, Undo,
+};
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+use utils::{Deserialize, FixedSize, PathTools, Serialize};
+
+use crate::{FileEvent, FileEventType, FileFullPath, FileType, PathError, PathLimits};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -25,27 +48,162 @@ pub enum Error {
     IgnoredOp(String),
     #[error("Decode error, {0}")]
     DecodeError(String),
+    #[error("Invalid path, {0}")]
+    PathError(#[from] PathError),
     #[error("Trie error")]
     TrieError(#[from] TrieError),
     #[error("db error")]
     DBError(#[from] db::Error),
+    #[error("IO error")]
+    IOError(#[from] std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Since we will never conflict, use a simple u128 as the clock
-type Clock = u128;
+/// Limits of a subtree, see [`Tracker::set_quota`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// Number of nodes below the root of the subtree.
+    pub max_nodes: Option<usize>,
+    /// Total length of the chunks of the files below the root of the
+    /// subtree, files not chunked yet count as empty.
+    pub max_bytes: Option<u64>,
+}
+
+/// Nodes and bytes below a node, see [`Quota`].
+#[derive(Default)]
+struct SubtreeStats {
+    root: Option<TrieId>,
+    nodes: usize,
+    bytes: u64,
+}
+
+impl TrieVisitor<Entity> for SubtreeStats {
+    fn enter(&mut self, id: TrieId, node: &TrieNode<Entity>) -> WalkControl {
+        if self.root.is_none() {
+            self.root = Some(id);
+        } else {
+            self.nodes += 1;
+        }
+        self.bytes += node
+            .content
+            .chunks
+            .iter()
+            .flatten()
+            .map(|chunk| chunk.len as u64)
+            .sum::<u64>();
+        WalkControl::Descend
+    }
+}
+
+/// Marker of the ops of the tracker, see [`ClockSource`].
+pub type Clock = u128;
+
+/// Who made an op, e.g. the user or the device, see [`Tracker::set_actor`].
+pub type ActorId = u64;
 
 type FileName = String;
 
+/// Changes published to the subscribers by a committed [`TrackerTransaction`].
+#[derive(Clone, Default)]
+pub struct TrackerEvent {
+    pub ops: Vec<Op<Clock, Entity>>,
+    /// Actor of the local ops of the transaction.
+    pub actor: ActorId,
+    /// Move events inferred by the transaction.
+    pub events: Vec<FileEvent>,
+}
+
+/// Ops generated by [`TrackerTransaction::apply_with_summary`].
+#[derive(Clone, Default)]
+pub struct ChangeSummary {
+    pub ops: Vec<Op<Clock, Entity>>,
+    /// Whether any op moved a node or changed its content, ops can net to no
+    /// visible change.
+    pub changed: bool,
+}
+
+/// Health snapshot of a [`Tracker`], see [`Tracker::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrackerStats {
+    /// Nodes in the tree, including the ones in conflict and recycle but not
+    /// the reserved root, conflict and recycle nodes.
+    pub nodes: usize,
+    /// Nodes moved to conflict.
+    pub conflicts: usize,
+    /// Nodes moved to recycle.
+    pub recycled: usize,
+    /// Ops in the log.
+    pub log_len: u64,
+    /// Clock of the latest local op.
+    pub clock: Clock,
+}
+
+type Subscribers = Arc<Mutex<Vec<mpsc::Sender<TrackerEvent>>>>;
+
+/// `value` in Unicode NFC, borrowed if it is already.
+fn to_nfc(value: &str) -> Cow<'_, str> {
+    if is_nfc_quick(value.chars()) == IsNormalized::Yes {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(value.nfc().collect())
+    }
+}
+
 pub struct Tracker<DBImpl> {
     db: DBImpl,
+    subscribers: Subscribers,
+    durability: DurabilityMode,
+    path_limits: PathLimits,
+    clock_source: Arc<dyn ClockSource>,
+    mtime_tolerance: Duration,
+    nfc_keys: bool,
+    actor: ActorId,
+    conflict_naming: Option<ConflictNaming>,
+    path_locks: Arc<PathLocks>,
 }
 
 const DB_TRIE_PREFIX: &[u8] = b"trie:";
 const MARKERS_PREFIX: &[u8] = b"mk:";
+/// Path of the nodes before they were moved to the recycle bin, so moving
+/// them out is reported as a move.
+const RECYCLED_PREFIX: &[u8] = b"rc:";
+/// Last access clock of the nodes, local state which is not synced.
+const ACCESS_PREFIX: &[u8] = b"la:";
+/// The nodes ordered by last access clock, see [`access_order_key`].
+const ACCESS_ORDER_PREFIX: &[u8] = b"lq:";
+/// Quota of the nodes, see [`Quota`].
+const QUOTA_PREFIX: &[u8] = b"qt:";
+/// Notes of the nodes, see [`TrackerTransaction::set_note`].
+const NOTE_PREFIX: &[u8] = b"nt:";
+/// Actor of the ops by marker, kept beside the log so the markers and the
+/// order of the ops are the same as without it.
+const ACTOR_PREFIX: &[u8] = b"ac:";
 const CLOCK_KEY: &[u8] = b"current_clock";
 
+fn access_key(id: TrieId) -> Vec<u8> {
+    [ACCESS_PREFIX, id.as_bytes()].concat()
+}
+
+fn access_order_key(clock: Clock, id: TrieId) -> Vec<u8> {
+    [ACCESS_ORDER_PREFIX, &clock.to_be_bytes(), id.as_bytes()].concat()
+}
+
+fn quota_key(id: TrieId) -> Vec<u8> {
+    [QUOTA_PREFIX, id.as_bytes()].concat()
+}
+
+fn note_key(id: TrieId) -> Vec<u8> {
+    [NOTE_PREFIX, id.as_bytes()].concat()
+}
+
+fn actor_key(marker: Clock) -> Vec<u8> {
+    [ACTOR_PREFIX, &marker.to_be_bytes()].concat()
+}
+
+/// Max length of a note in bytes, notes are meant for small contents.
+pub const MAX_NOTE_SIZE: usize = 64 * 1024;
+
 impl<DBImpl: DB> Tracker<DBImpl> {
     pub fn init(db: DBImpl) -> Result<Self> {
         Trie::<Clock, Entity, _>::init(db::DB::prefix(&db, DB_TRIE_PREFIX))?;
@@ -54,15 +212,229 @@ impl<DBImpl: DB> Tracker<DBImpl> {
             transaction.set(CLOCK_KEY, 0u128.to_bytes())?;
         }
         transaction.commit()?;
-        Ok(Tracker { db })
+        Ok(Tracker {
+            db,
+            subscribers: Default::default(),
+            durability: Default::default(),
+            path_limits: Default::default(),
+            clock_source: Arc::new(CounterClock),
+            mtime_tolerance: Duration::ZERO,
+            nfc_keys: false,
+            actor: 0,
+            conflict_naming: None,
+            path_locks: Default::default(),
+        })
     }
 
     pub fn start_transaction(&self) -> Result<TrackerTransaction<DBImpl::Transaction<'_>>> {
         Ok(TrackerTransaction {
-            db: self.db.start_transaction()?,
+            db: self.db.start_transaction_with(self.durability)?,
             current_ops: Default::default(),
+            current_changed: false,
+            current_events: Default::default(),
+            published: TrackerEvent {
+                actor: self.actor,
+                ..Default::default()
+            },
+            subscribers: Some(self.subscribers.clone()),
+            path_limits: self.path_limits,
+            clock_source: self.clock_source.clone(),
+            mtime_tolerance: self.mtime_tolerance,
+            nfc_keys: self.nfc_keys,
+            actor: self.actor,
+            conflict_naming: self.conflict_naming.clone(),
+        })
+    }
+
+    /// Receive a [`TrackerEvent`] for every transaction committed after this
+    /// call, nothing is sent for rolled back transactions.
+    pub fn subscribe(&self) -> mpsc::Receiver<TrackerEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Get a stable reference to the file at `path`, the reference keeps
+    /// pointing to the file after it is moved or renamed.
+    pub fn get_or_create_ref(&self, path: &str) -> Result<TrieRef> {
+        let mut transaction = self.start_transaction()?;
+        let r = transaction.get_or_create_ref(path)?;
+        transaction.commit()?;
+        Ok(r)
+    }
+
+    /// Rebuild the marker index from the trie, see
+    /// [`TrackerTransaction::rebuild_marker_index`].
+    pub fn rebuild_marker_index(&self) -> Result<usize> {
+        let mut transaction = self.start_transaction()?;
+        let count = transaction.rebuild_marker_index()?;
+        transaction.commit()?;
+        Ok(count)
+    }
+
+    /// Mark the file at `path` as accessed, see
+    /// [`TrackerTransaction::touch`].
+    pub fn touch(&self, path: &str) -> Result<()> {
+        let mut transaction = self.start_transaction()?;
+        transaction.touch(path)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Limit the nodes and bytes below the folder at `path`, see
+    /// [`TrackerTransaction::set_quota`].
+    pub fn set_quota(
+        &self,
+        path: &str,
+        max_nodes: Option<usize>,
+        max_bytes: Option<u64>,
+    ) -> Result<()> {
+        let mut transaction = self.start_transaction()?;
+        transaction.set_quota(path, max_nodes, max_bytes)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Mark the file or folder at `path` as ignored, or clear the flag, see
+    /// [`TrackerTransaction::set_ignored`].
+    pub fn set_ignored(&self, path: &str, ignored: bool) -> Result<()> {
+        let mut transaction = self.start_transaction()?;
+        transaction.set_ignored(path, ignored)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Store `note` with the file at `path`, see
+    /// [`TrackerTransaction::set_note`].
+    pub fn set_note(&self, path: &str, note: impl AsRef<[u8]>) -> Result<()> {
+        let mut transaction = self.start_transaction()?;
+        transaction.set_note(path, note)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// The note of the file at `path`, `None` if it has none.
+    pub fn get_note(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.key(path);
+        let id = self
+            .trie()
+            .get_id_by_path(&path)?
+            .ok_or_else(|| Error::InvalidOp("File not found".to_string()))?;
+        Ok(self
+            .db
+            .get(note_key(id))?
+            .map(|note| note.as_ref().to_vec()))
+    }
+
+    /// Write the ops of the log newer than `marker` to `w`, oldest first, as
+    /// frames read by [`OpStreamReader`] with their actors, see
+    /// [`Tracker::op_actor`]. Returns the marker of the newest op written, or
+    /// `marker` if there is none, to pass on the next call.
+    ///
+    /// The log is read from a snapshot, the ops committed meanwhile are left
+    /// to the next call. The ops without a recorded actor are written with
+    /// actor 0.
+    pub fn stream_ops_since(&self, marker: Clock, mut w: impl std::io::Write) -> Result<Clock> {
+        let snapshot = self.db.snapshot()?;
+        let trie =
+            Trie::<Clock, Entity, _>::from_db(db::prefix::Prefix::new(&snapshot, DB_TRIE_PREFIX));
+
+        // the log is iterated from the newest op
+        let mut ops = vec![];
+        for log in trie.iter_log()? {
+            let op = log?.op;
+            if op.marker <= marker {
+                break;
+            }
+            ops.push(op);
+        }
+
+        let newest = ops.first().map_or(marker, |op| op.marker);
+        for op in ops.iter().rev() {
+            let actor = snapshot
+                .get(actor_key(op.marker))?
+                .map(|bytes| ActorId::from_bytes(bytes.as_ref()).map_err(Error::DecodeError))
+                .transpose()?
+                .unwrap_or_default();
+            write_op_frame(&mut w, actor, op)?;
+        }
+        w.flush()?;
+
+        Ok(newest)
+    }
+
+    /// Compact the whole database, so the space of the nodes and the log
+    /// entries removed from it is reclaimed on the disk.
+    pub fn compact(&self) -> Result<()> {
+        Ok(self.db.compact_range(None, None)?)
+    }
+
+    /// Counts of the tree without walking it, the nodes are counted by their
+    /// keys and the log length is read from the stored counter.
+    pub fn stats(&self) -> Result<TrackerStats> {
+        let trie = self.trie();
+        let clock = self.db.get(CLOCK_KEY)?.ok_or(Error::InvalidOp(
+            "Tracker Database not initialized.".to_owned(),
+        ))?;
+
+        Ok(TrackerStats {
+            // the root, conflict and recycle nodes are always there
+            nodes: trie.node_count()?.saturating_sub(3),
+            conflicts: trie.children_count(trie::CONFLICT)?,
+            recycled: trie.children_count(trie::RECYCLE)?,
+            log_len: trie.log_len()?,
+            clock: Clock::from_bytes(clock.as_ref()).map_err(Error::DecodeError)?,
+        })
+    }
+
+    /// Paths of the `n` least recently touched files, oldest first. Files
+    /// never touched and deleted files are not returned.
+    pub fn least_recently_accessed(&self, n: usize) -> Result<Vec<FileFullPath>> {
+        let trie = self.trie();
+        let mut paths = vec![];
+
+        for item in self.db.prefix_scan(ACCESS_ORDER_PREFIX) {
+            if paths.len() >= n {
+                break;
+            }
+            let (key, _) = item?;
+            let id = key
+                .as_ref()
+                .get(ACCESS_ORDER_PREFIX.len() + std::mem::size_of::<Clock>()..)
+                .ok_or_else(|| Error::DecodeError("Invalid access order key".to_string()))
+                .and_then(|id| TrieId::from_bytes(id).map_err(Error::DecodeError))?;
+            if let Some(path) = trie.get_path_by_id(id)? {
+                paths.push(FileFullPath::parse(&path));
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// Current path of the file referenced by `r`, `None` if the reference is
+    /// unknown or the file is deleted.
+    pub fn resolve_ref(&self, r: TrieRef) -> Result<Option<FileFullPath>> {
+        let trie = self.trie();
+        Ok(if let Some(id) = trie.get_id(r)? {
+            trie.get_path_by_id(id)?
+                .map(|path| FileFullPath::parse(&path))
+        } else {
+            None
         })
     }
+
+    /// Current path and entity of the node `id`, read from one snapshot so
+    /// they always match, e.g. for the ids of the ops in a [`TrackerEvent`].
+    /// `None` if the node is unknown, deleted or in the conflict folder.
+    pub fn resolve_id(&self, id: TrieId) -> Result<Option<(FileFullPath, Entity)>> {
+        let trie = self.trie();
+        let snapshot = trie.snapshot()?;
+        let Some(path) = snapshot.get_path_by_id(id)? else {
+            return Ok(None);
+        };
+        let node = snapshot.get_ensure(id)?;
+        Ok(Some((FileFullPath::parse(&path), node.content)))
+    }
 }
 
 impl<DBImpl: DBRead> Tracker<DBImpl> {
@@ -71,13 +443,147 @@ impl<DBImpl: DBRead> Tracker<DBImpl> {
     }
 
     pub fn from_db(db: DBImpl) -> Self {
-        Self { db }
+        Self {
+            db,
+            subscribers: Default::default(),
+            durability: Default::default(),
+            path_limits: Default::default(),
+            clock_source: Arc::new(CounterClock),
+            mtime_tolerance: Duration::ZERO,
+            nfc_keys: false,
+            actor: 0,
+            conflict_naming: None,
+            path_locks: Default::default(),
+        }
+    }
+
+    pub fn durability(&self) -> DurabilityMode {
+        self.durability
+    }
+
+    /// Set the durability of the transactions started after this call, e.g.
+    /// [`DurabilityMode::NoSync`] for the initial index and
+    /// [`DurabilityMode::Safe`] once it is done.
+    pub fn set_durability(&mut self, durability: DurabilityMode) {
+        self.durability = durability
+    }
+
+    pub fn path_limits(&self) -> PathLimits {
+        self.path_limits
+    }
+
+    /// Set the limits checked by the transactions started after this call,
+    /// paths over the limits are rejected with [`Error::PathError`].
+    pub fn set_path_limits(&mut self, path_limits: PathLimits) {
+        self.path_limits = path_limits
+    }
+
+    /// Set the source of the op markers of the transactions started after
+    /// this call, [`CounterClock`] by default. Use [`HybridClock`] when the
+    /// ops of several writers are merged.
+    pub fn set_clock_source(&mut self, clock_source: impl ClockSource + 'static) {
+        self.clock_source = Arc::new(clock_source)
+    }
+
+    pub fn mtime_tolerance(&self) -> Duration {
+        self.mtime_tolerance
+    }
+
+    /// Set the tolerance on the modification time of the transactions started
+    /// after this call, zero by default. [`TimeSizeMarker`] update markers
+    /// with the same size and times at most `tolerance` apart are taken as
+    /// unchanged, e.g. for files restored from a backup which rounded the
+    /// times. Other update markers are always compared exactly.
+    pub fn set_mtime_tolerance(&mut self, tolerance: Duration) {
+        self.mtime_tolerance = tolerance
+    }
+
+    pub fn nfc_keys(&self) -> bool {
+        self.nfc_keys
+    }
+
+    /// Normalize the names to Unicode NFC in the transactions started after
+    /// this call, off by default. The same name is written composed on Linux
+    /// and decomposed on macOS, with this on both spellings are the same node.
+    /// The paths passed to the tracker are normalized too, and the name on the
+    /// disk is kept in [`Entity::raw_name`] when it differs from the key.
+    ///
+    /// Turn it on before the first index, the keys already stored are not
+    /// rewritten.
+    pub fn set_nfc_keys(&mut self, nfc_keys: bool) {
+        self.nfc_keys = nfc_keys
+    }
+
+    /// `value` in NFC if [`Tracker::set_nfc_keys`] is on.
+    fn key<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        if self.nfc_keys {
+            to_nfc(value)
+        } else {
+            Cow::Borrowed(value)
+        }
+    }
+
+    /// Lock the subtrees at `paths` until the guard is dropped, waiting for
+    /// the guards of overlapping subtrees, see [`PathLocks`]. The locks are
+    /// advisory, the operations reading the disk take them from the read
+    /// until the commit, e.g. the renames and the re-indexes of the
+    /// [`Discoverer`](crate::local::Discoverer).
+    pub fn lock_paths<'a>(&self, paths: impl IntoIterator<Item = &'a str>) -> PathLockGuard {
+        self.path_locks.lock(paths)
+    }
+
+    pub fn actor(&self) -> ActorId {
+        self.actor
+    }
+
+    /// Set the actor recorded for the ops of the transactions started after
+    /// this call, 0 by default. The actor is not part of the marker, it never
+    /// changes the order of the ops.
+    pub fn set_actor(&mut self, actor: ActorId) {
+        self.actor = actor
+    }
+
+    /// Actor of the op with `marker`, `None` if the op is unknown or was
+    /// written before the actors were recorded.
+    pub fn op_actor(&self, marker: Clock) -> Result<Option<ActorId>> {
+        self.db
+            .get(actor_key(marker))?
+            .map(|bytes| ActorId::from_bytes(bytes.as_ref()).map_err(Error::DecodeError))
+            .transpose()
+    }
+
+    pub fn conflict_naming(&self) -> Option<&ConflictNaming> {
+        self.conflict_naming.as_ref()
+    }
+
+    /// Put the nodes losing a name conflict in
+    /// [`TrackerTransaction::apply_remote_ops`] back into their folder under a
+    /// name from `naming`, in the transactions started after this call. Off by
+    /// default, the losers stay in the internal conflict folder.
+    pub fn set_conflict_naming(&mut self, naming: Option<ConflictNaming>) {
+        self.conflict_naming = naming
     }
 }
 
 pub struct TrackerTransaction<DBImpl: DBRead + DBWrite + DBLock> {
     db: DBImpl,
     current_ops: Vec<Op<Clock, Entity>>,
+    /// Whether [`Self::current_ops`] changed the tree.
+    current_changed: bool,
+    current_events: Vec<FileEvent>,
+    /// All changes of this transaction, sent to the subscribers on commit.
+    published: TrackerEvent,
+    subscribers: Option<Subscribers>,
+    path_limits: PathLimits,
+    clock_source: Arc<dyn ClockSource>,
+    /// See [`Tracker::set_mtime_tolerance`].
+    mtime_tolerance: Duration,
+    /// See [`Tracker::set_nfc_keys`].
+    nfc_keys: bool,
+    /// See [`Tracker::set_actor`].
+    actor: ActorId,
+    /// See [`Tracker::set_conflict_naming`].
+    conflict_naming: Option<ConflictNaming>,
 }
 
 impl<DBImpl: DBRead + DBWrite + DBLock> TrackerTransaction<DBImpl> {
@@ -85,52 +591,349 @@ impl<DBImpl: DBRead + DBWrite + DBLock> TrackerTransaction<DBImpl> {
         Self {
             db,
             current_ops: Default::default(),
+            current_changed: false,
+            current_events: Default::default(),
+            published: Default::default(),
+            subscribers: None,
+            path_limits: Default::default(),
+            clock_source: Arc::new(CounterClock),
+            mtime_tolerance: Duration::ZERO,
+            nfc_keys: false,
+            actor: 0,
+            conflict_naming: None,
+        }
+    }
+
+    pub fn with_path_limits(mut self, path_limits: PathLimits) -> Self {
+        self.path_limits = path_limits;
+        self
+    }
+
+    pub fn with_clock_source(mut self, clock_source: impl ClockSource + 'static) -> Self {
+        self.clock_source = Arc::new(clock_source);
+        self
+    }
+
+    pub fn with_mtime_tolerance(mut self, tolerance: Duration) -> Self {
+        self.mtime_tolerance = tolerance;
+        self
+    }
+
+    pub fn with_nfc_keys(mut self, nfc_keys: bool) -> Self {
+        self.nfc_keys = nfc_keys;
+        self
+    }
+
+    pub fn with_actor(mut self, actor: ActorId) -> Self {
+        self.actor = actor;
+        self.published.actor = actor;
+        self
+    }
+
+    pub fn with_conflict_naming(mut self, naming: Option<ConflictNaming>) -> Self {
+        self.conflict_naming = naming;
+        self
+    }
+
+    /// `value` in NFC if [`Tracker::set_nfc_keys`] is on.
+    fn key<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        if self.nfc_keys {
+            to_nfc(value)
+        } else {
+            Cow::Borrowed(value)
+        }
+    }
+
+    /// Advance the clock past `clock`, e.g. the marker of an op received
+    /// from another writer, so the next local ops are ordered after it.
+    pub fn observe_clock(&mut self, clock: Clock) -> Result<()> {
+        if clock > self.current_clock()? {
+            self.db.set(CLOCK_KEY, clock.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Apply the ops received from another tracker, e.g. read by an
+    /// [`OpStreamReader`], with their actors. The clock is advanced past them
+    /// first, see [`TrackerTransaction::observe_clock`].
+    ///
+    /// With [`Tracker::set_conflict_naming`], the nodes losing a name conflict
+    /// are put back next to the winner, and a
+    /// [`FileEventType::Conflicted`] event is emitted for each.
+    pub fn apply_remote_ops(&mut self, ops: Vec<(ActorId, Op<Clock, Entity>)>) -> Result<()> {
+        let Some(since) = ops.iter().map(|(_, op)| op.marker).min() else {
+            return Ok(());
+        };
+        let conflicts_before = if self.conflict_naming.is_some() {
+            self.conflict_ids()?
+        } else {
+            Default::default()
+        };
+
+        for (actor, op) in ops.iter() {
+            self.observe_clock(op.marker)?;
+            self.db.set(actor_key(op.marker), actor.to_bytes())?;
+        }
+        self.trie()
+            .apply(ops.into_iter().map(|(_, op)| op).collect())?;
+
+        if let Some(naming) = self.conflict_naming.clone() {
+            self.restore_conflicts(&naming, &conflicts_before, since)?;
+        }
+        Ok(())
+    }
+
+    fn conflict_ids(&mut self) -> Result<HashSet<TrieId>> {
+        Ok(self
+            .trie()
+            .get_children(trie::CONFLICT)?
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect())
+    }
+
+    /// Move the nodes which entered the conflict folder since `before` back
+    /// to the folder they lost the name in, under a name from `naming`.
+    /// `since` is the oldest marker of the applied ops, the logs moving the
+    /// nodes into the conflict folder are not older.
+    fn restore_conflicts(
+        &mut self,
+        naming: &ConflictNaming,
+        before: &HashSet<TrieId>,
+        since: Clock,
+    ) -> Result<()> {
+        let mut conflicts = self.conflict_ids()?;
+        conflicts.retain(|id| !before.contains(id));
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+
+        // the latest log moving a node moved it into the conflict folder, its
+        // op targets the lost name.
+        let mut lost = vec![];
+        {
+            let trie = self.trie();
+            for log in trie.iter_log()? {
+                let log = log?;
+                if log.op.marker < since || conflicts.is_empty() {
+                    break;
+                }
+                for undo in log.undos.iter() {
+                    let Undo::Move { id, to: _ } = undo else {
+                        continue;
+                    };
+                    if !conflicts.remove(id) {
+                        continue;
+                    }
+                    let parent = match &log.op.parent_target {
+                        OpTarget::Id(parent) => Some(*parent),
+                        OpTarget::Ref(parent) => trie.get_id(parent.to_owned())?,
+                        OpTarget::Path(parent) => trie.get_id_by_path(parent)?,
+                        OpTarget::NewId => None,
+                    };
+                    if let Some(parent) = parent {
+                        lost.push((*id, parent, log.op.child_key.to_string()));
+                    }
+                }
+            }
         }
+
+        self.lock()?;
+        let now = SystemTime::now();
+        for (id, parent, name) in lost {
+            let Some(parent_path) = self.trie().get_path_by_id(parent)? else {
+                // the folder is gone as well
+                continue;
+            };
+
+            let mut n = 1;
+            let key = loop {
+                let key = naming.render(&name, now, n);
+                if self
+                    .trie()
+                    .get_child_ref(parent, TrieKeyRef(&key))?
+                    .is_none()
+                {
+                    break key;
+                }
+                n += 1;
+            };
+
+            let node = self.get_node(id)?;
+            let event = FileEvent {
+                event_type: FileEventType::Conflicted {
+                    original: FileFullPath::parse(&parent_path).try_join(&name)?,
+                },
+                path: FileFullPath::parse(&parent_path).try_join(&key)?,
+                old_path: None,
+                file_type: FileType::from_bytes(&node.content.type_marker).ok(),
+            };
+            self.published.events.push(event.clone());
+            self.current_events.push(event);
+
+            let new_clock = self.auto_increment_clock()?;
+            self.do_op(Op {
+                marker: new_clock,
+                parent_target: OpTarget::Id(parent),
+                child_key: key.into(),
+                child_target: OpTarget::Id(id),
+                child_content: Some(Entity {
+                    raw_name: None,
+                    ..node.content
+                }),
+            })?;
+        }
+        self.take_summary();
+
+        Ok(())
+    }
+
+    /// Take the move events inferred by this transaction so far.
+    pub fn take_events(&mut self) -> Vec<FileEvent> {
+        core::mem::take(&mut self.current_events)
+    }
+
+    /// Changes made by this transaction so far, sent to the subscribers on
+    /// commit.
+    pub fn published(&self) -> &TrackerEvent {
+        &self.published
     }
 
     fn do_op(&mut self, op: Op<Clock, Entity>) -> Result<()> {
-        self.trie().apply(vec![op.clone()])?;
+        let child_id = match op.child_target {
+            OpTarget::Id(id) => Some(id),
+            _ => None,
+        };
+        let before = child_id
+            .map(|id| self.trie().get(id))
+            .transpose()?
+            .flatten();
+
+        self.trie().apply_one(op.clone())?;
+        self.db.set(actor_key(op.marker), self.actor.to_bytes())?;
+
+        let after = child_id
+            .map(|id| self.trie().get(id))
+            .transpose()?
+            .flatten();
+        if before.is_none() || before != after {
+            self.current_changed = true;
+        }
+
+        self.published.ops.push(op.clone());
         self.current_ops.push(op);
         Ok(())
     }
 
+    fn current_clock(&mut self) -> Result<Clock> {
+        let bytes = self.db.get_for_update(CLOCK_KEY)?.ok_or(Error::InvalidOp(
+            "Tracker Database not initialized.".to_owned(),
+        ))?;
+        Clock::from_bytes(bytes.as_ref()).map_err(Error::DecodeError)
+    }
+
     fn auto_increment_clock(&mut self) -> Result<Clock> {
-        let clock = {
-            let bytes = self.db.get_for_update(CLOCK_KEY)?.ok_or(Error::InvalidOp(
-                "Tracker Database not initialized.".to_owned(),
-            ))?;
-            Clock::from_bytes(bytes.as_ref()).map_err(Error::DecodeError)? + 1
-        };
+        let last = self.current_clock()?;
+        let clock = self.clock_source.next(last);
 
         self.db.set(CLOCK_KEY, &clock.to_bytes())?;
 
         Ok(clock)
     }
 
+    /// The ops of different trackers may have the same clock, they are
+    /// ordered by their child ref so every tracker converges.
     fn trie(&mut self) -> TrieTransaction<Clock, Entity, db::prefix::Prefix<&'_ mut DBImpl>> {
         TrieTransaction::from_db(db::prefix::Prefix::new(&mut self.db, DB_TRIE_PREFIX))
+            .with_collision_policy(MarkerCollisionPolicy::ChildRef)
+    }
+
+    /// Read the node `id`, rewriting its entity in the latest version if it
+    /// was stored by an older one, see [`ENTITY_VERSION`].
+    fn get_node(&mut self, id: TrieId) -> Result<TrieNode<Entity>> {
+        self.trie()
+            .migrate(id)?
+            .ok_or_else(|| TrieError::TreeBroken(format!("Trie id {id} not found")).into())
     }
 
     fn get_marker(&self, file_marker: &FileMarker) -> Result<Option<TrieId>> {
+        Ok(self.get_marker_ids(file_marker)?.first().copied())
+    }
+
+    /// Ids of the nodes with the marker, more than one if the file has hard
+    /// links. The index stores the ids one after another, an index with a
+    /// single id is the same as before hard links were tracked.
+    fn get_marker_ids(&self, file_marker: &FileMarker) -> Result<Vec<TrieId>> {
         let mut key = Vec::with_capacity(MARKERS_PREFIX.len() + file_marker.len());
         key.extend_from_slice(MARKERS_PREFIX);
         key.extend_from_slice(file_marker);
-        self.db
-            .get(key)?
-            .map(|d| TrieId::from_bytes(d.as_ref()))
-            .transpose()
-            .map_err(Error::DecodeError)
+        let Some(bytes) = self.db.get(key)? else {
+            return Ok(vec![]);
+        };
+
+        let bytes = bytes.as_ref();
+        if bytes.len() % TrieId::SIZE != 0 {
+            return Err(Error::DecodeError("Invalid marker index".to_string()));
+        }
+        bytes
+            .chunks_exact(TrieId::SIZE)
+            .map(|id| TrieId::from_bytes(id).map_err(Error::DecodeError))
+            .collect()
     }
 
-    fn set_marker(&mut self, file_marker: &FileMarker, file_id: &TrieId) -> Result<()> {
+    fn set_marker_ids(&mut self, file_marker: &FileMarker, file_ids: &[TrieId]) -> Result<()> {
         let mut key = Vec::with_capacity(MARKERS_PREFIX.len() + file_marker.len());
         key.extend_from_slice(MARKERS_PREFIX);
         key.extend_from_slice(file_marker);
-        self.db.set(key, file_id.as_bytes())?;
+        self.db.set(
+            key,
+            file_ids
+                .iter()
+                .flat_map(|id| id.as_bytes().iter().copied())
+                .collect::<Vec<_>>(),
+        )?;
 
         Ok(())
     }
 
+    /// Add the node to the nodes with the marker. The nodes no longer under
+    /// the root are dropped, only the other links of a file are kept.
+    fn add_marker(&mut self, file_marker: &FileMarker, file_id: &TrieId) -> Result<()> {
+        let mut ids = vec![];
+        for id in self.get_marker_ids(file_marker)? {
+            if id != *file_id && self.trie().get_path_by_id(id)?.is_some() {
+                ids.push(id);
+            }
+        }
+        ids.push(*file_id);
+
+        self.set_marker_ids(file_marker, &ids)
+    }
+
+    /// Of the nodes with the marker of `entity`, the node moved to `entity`.
+    /// A node no longer under the root is moved back first. `None` if the
+    /// entity is a new file, or a new hard link to a file still tracked at
+    /// its other paths.
+    fn find_moved(&mut self, entity: &DiscoveryEntity, ids: &[TrieId]) -> Result<Option<TrieId>> {
+        let mut linked = vec![];
+        for id in ids.iter().copied() {
+            if self.trie().get(id)?.is_none() {
+                continue;
+            }
+            if self.trie().get_path_by_id(id)?.is_some() {
+                linked.push(id);
+            } else {
+                return Ok(Some(id));
+            }
+        }
+
+        // every other path is still linked, this is one more link
+        if (linked.len() as u64) < entity.hard_links {
+            return Ok(None);
+        }
+        Ok(linked.first().copied())
+    }
+
     fn delete_marker(&mut self, file_marker: &FileMarker) -> Result<()> {
         let mut key = Vec::with_capacity(MARKERS_PREFIX.len() + file_marker.len());
         key.extend_from_slice(MARKERS_PREFIX);
@@ -140,7 +943,58 @@ impl<DBImpl: DBRead + DBWrite + DBLock> TrackerTransaction<DBImpl> {
         Ok(())
     }
 
+    fn recycled_key(id: TrieId) -> Vec<u8> {
+        let mut key = Vec::with_capacity(RECYCLED_PREFIX.len() + id.as_bytes().len());
+        key.extend_from_slice(RECYCLED_PREFIX);
+        key.extend_from_slice(id.as_bytes());
+        key
+    }
+
+    /// Record a move event if the node is moving from another location, must
+    /// be called before the node is moved.
+    fn record_move(&mut self, id: TrieId, parent: TrieId, key: &str) -> Result<()> {
+        let recycled_key = Self::recycled_key(id);
+        let recycled_path = self
+            .db
+            .get(&recycled_key)?
+            .map(|path| String::from_utf8_lossy(path.as_ref()).into_owned());
+        if recycled_path.is_some() {
+            self.db.delete(recycled_key)?;
+        }
+
+        let trie = self.trie();
+        let old_path = trie.get_path_by_id(id)?.or(recycled_path);
+        let new_path = trie
+            .get_path_by_id(parent)?
+            .map(|parent| FileFullPath::parse(&parent).try_join(key))
+            .transpose()?;
+        let file_type = trie
+            .get(id)?
+            .and_then(|node| FileType::from_bytes(&node.content.type_marker).ok());
+
+        if let (Some(old_path), Some(new_path)) = (old_path, new_path) {
+            if old_path != new_path.as_ref() {
+                let old_path = FileFullPath::parse(&old_path);
+                let event = FileEvent {
+                    event_type: FileEventType::Moved {
+                        from: old_path.clone(),
+                    },
+                    path: new_path,
+                    old_path: Some(old_path),
+                    file_type,
+                };
+                self.published.events.push(event.clone());
+                self.current_events.push(event);
+            }
+        }
+
+        Ok(())
+    }
+
     fn move_node_to_recycle(&mut self, node: TrieId) -> Result<()> {
+        if let Some(path) = self.trie().get_path_by_id(node)? {
+            self.db.set(Self::recycled_key(node), path.into_bytes())?;
+        }
         let new_clock = self.auto_increment_clock()?;
 
         self.do_op(Op {
@@ -156,8 +1010,10 @@ impl<DBImpl: DBRead + DBWrite + DBLock> TrackerTransaction<DBImpl> {
         &mut self,
         parent: TrieId,
         entity: DiscoveryEntity,
+        raw_name: Option<FileName>,
         exist_id: TrieId,
     ) -> Result<()> {
+        let ignored = self.get_node(exist_id)?.content.ignored;
         let new_clock = self.auto_increment_clock()?;
 
         self.do_op(Op {
@@ -169,13 +1025,24 @@ impl<DBImpl: DBRead + DBWrite + DBLock> TrackerTransaction<DBImpl> {
                 marker: entity.marker,
                 update_marker: entity.update_marker,
                 type_marker: entity.type_marker,
+                permission_marker: entity.permission_marker,
+                symlink_target: entity.symlink_target,
+                chunks: None,
+                ignored,
+                size: entity.size,
+                raw_name,
             }),
         })?;
 
         Ok(())
     }
 
-    fn move_entity_to(&mut self, parent: TrieId, entity: DiscoveryEntity) -> Result<TrieId> {
+    fn move_entity_to(
+        &mut self,
+        parent: TrieId,
+        entity: DiscoveryEntity,
+        raw_name: Option<FileName>,
+    ) -> Result<TrieId> {
         let new_clock = self.auto_increment_clock()?;
 
         let target_id = self.trie().create_id()?;
@@ -189,6 +1056,12 @@ impl<DBImpl: DBRead + DBWrite + DBLock> TrackerTransaction<DBImpl> {
                 marker: entity.marker,
                 update_marker: entity.update_marker,
                 type_marker: entity.type_marker,
+                permission_marker: entity.permission_marker,
+                symlink_target: entity.symlink_target,
+                chunks: None,
+                ignored: false,
+                size: entity.size,
+                raw_name,
             }),
         })?;
 
@@ -202,9 +1075,50 @@ impl<DBImpl: DBRead + DBWrite + DBLock> TrackerTransaction<DBImpl> {
         Ok(())
     }
 
+    fn check_path_limits(&self, path: &str) -> Result<()> {
+        Ok(FileFullPath::parse(path).check_limits(&self.path_limits)?)
+    }
+
+    fn take_summary(&mut self) -> ChangeSummary {
+        ChangeSummary {
+            ops: core::mem::take(&mut self.current_ops),
+            changed: core::mem::take(&mut self.current_changed),
+        }
+    }
+
     pub fn apply(&mut self, input: Discovery) -> Result<Vec<Op<Clock, Entity>>> {
+        Ok(self.apply_with_summary(input)?.ops)
+    }
+
+    /// Same as [`Self::apply`], also reports whether the ops actually changed
+    /// the tree, so an idle rescan can skip the downstream work.
+    ///
+    /// With [`Tracker::set_nfc_keys`] on, the names are normalized first, and
+    /// when two names on the disk have the same key only the first one is
+    /// tracked.
+    pub fn apply_with_summary(&mut self, mut input: Discovery) -> Result<ChangeSummary> {
+        #[cfg(feature = "tracing")]
+        let (_span, start) = (
+            tracing::debug_span!("tracker_apply", entities = input.entities.len()).entered(),
+            std::time::Instant::now(),
+        );
+
         self.lock()?;
 
+        // the names on the disk, by key, when they are not the same
+        let mut raw_names = BTreeMap::new();
+        if self.nfc_keys {
+            input.location.0 = to_nfc(&input.location.0).into_owned();
+            let mut keys = HashSet::with_capacity(input.entities.len());
+            input.entities.retain_mut(|entity| {
+                if let Cow::Owned(key) = to_nfc(&entity.name) {
+                    let raw_name = std::mem::replace(&mut entity.name, key);
+                    raw_names.insert(entity.name.clone(), raw_name);
+                }
+                keys.insert(entity.name.clone())
+            });
+        }
+
         let target: TrieId;
 
         if !input.location_marker().is_empty() {
@@ -222,55 +1136,129 @@ impl<DBImpl: DBRead + DBWrite + DBLock> TrackerTransaction<DBImpl> {
             return Err(Error::InvalidOp("Location not found".to_string()));
         }
 
+        // the children of ignored folders are kept as they are
+        if self.is_ignored(target)? {
+            return Ok(self.take_summary());
+        }
+
+        // reject the whole discovery before touching the store
+        let location = if input.location_marker().is_empty() {
+            Some(input.location_full_path().to_string())
+        } else {
+            self.trie().get_path_by_id(target)?
+        };
+        if let Some(location) = location {
+            for entity in input.entities.iter() {
+                FileFullPath::parse(&location)
+                    .try_join(&entity.name)?
+                    .check_limits(&self.path_limits)?;
+            }
+        }
+
         let mut old_entities = vec![];
         for child in self.trie().get_children(target)? {
             old_entities.push(child);
         }
 
-        let mut entities = vec![];
+        // only the children of the location are counted, the descendants of
+        // the recycled folders and of the folders moved here are not.
+        let children = match input.mode {
+            DiscoveryMode::Full => input.entities.len(),
+            DiscoveryMode::Shallow => {
+                old_entities.len()
+                    + input
+                        .entities
+                        .iter()
+                        .filter(|entity| {
+                            !old_entities
+                                .iter()
+                                .any(|(key, _)| key.as_str() == entity.name)
+                        })
+                        .count()
+            }
+        };
+        self.check_quota(target, children.saturating_sub(old_entities.len()), 0)?;
+
+        let mut entities = vec![];
         for entity in input.entities {
             if !entity.marker.is_empty() {
-                let marker = self.get_marker(&entity.marker)?;
-                entities.push((entity, marker))
+                let ids = self.get_marker_ids(&entity.marker)?;
+                entities.push((entity, ids))
             } else {
-                entities.push((entity, None))
+                entities.push((entity, vec![]))
             }
         }
 
-        for (entity, exist_id) in entities {
-            let old_entity_id = {
+        // the hard links of a file can be in the same folder, the entities
+        // keeping their name are matched first so they keep their own node.
+        let (same_name, other): (Vec<_>, Vec<_>) =
+            entities.into_iter().partition(|(entity, ids)| {
                 old_entities
                     .iter()
-                    .enumerate()
-                    .find_map(|(i, (key, _))| {
-                        if key.as_str() == entity.name {
-                            Some(i)
-                        } else {
-                            None
-                        }
-                    })
-                    .map(|i| old_entities.remove(i).1)
-            };
+                    .any(|(key, id)| key.as_str() == entity.name && ids.contains(id))
+            });
+
+        // match the stored children by marker first, so a child renamed in
+        // this folder keeps its id instead of being recycled and recreated.
+        let mut renamed = vec![];
+        let mut unmatched = vec![];
+        for (entity, ids) in same_name.into_iter().chain(other) {
+            let old_index = old_entities
+                .iter()
+                .position(|(key, id)| key.as_str() == entity.name && ids.contains(id))
+                .or_else(|| old_entities.iter().position(|(_, id)| ids.contains(id)));
+            if let Some(old_index) = old_index {
+                let old_entity = self.get_node(old_entities[old_index].1)?;
+                if old_entity.content.type_marker == entity.type_marker {
+                    let (old_key, old_entity_id) = old_entities.remove(old_index);
+                    if old_key.as_str() == entity.name {
+                        let raw_name = raw_names.remove(&entity.name);
+                        self.update_if_changed(
+                            target,
+                            entity,
+                            raw_name,
+                            old_entity_id,
+                            &old_entity.content,
+                        )?;
+                    } else {
+                        renamed.push((entity, old_entity_id));
+                    }
+                    continue;
+                }
 
-            if let Some(old_entity_id) = old_entity_id {
-                let old_entity = self.trie().get_ensure(old_entity_id)?;
-                let old_marker = old_entity.content.marker;
+                // same marker with another type, it's a new file
+                unmatched.push((entity, vec![]));
+            } else {
+                unmatched.push((entity, ids));
+            }
+        }
+
+        // then by name
+        let mut added = vec![];
+        for (entity, ids) in unmatched {
+            if let Some(old_index) = old_entities
+                .iter()
+                .position(|(key, _)| key.as_str() == entity.name)
+            {
+                let (_, old_entity_id) = old_entities.remove(old_index);
+                let old_entity = self.get_node(old_entity_id)?;
+                let old_marker = &old_entity.content.marker;
+                // if marker is empty, same as old_marker
                 let marker = if entity.marker.is_empty() {
-                    old_marker.clone()
+                    old_marker
                 } else {
-                    entity.marker.clone()
-                }; // if marker is empty, same as old_marker
-                let old_update_marker = &old_entity.content.update_marker;
-                let update_marker = &entity.update_marker;
-                let old_type_marker = &old_entity.content.type_marker;
-                let type_marker = &entity.type_marker;
-
-                if marker == old_marker && type_marker == old_type_marker {
-                    if update_marker != old_update_marker {
-                        // update
-                        self.move_exist_entity_to(target, entity, old_entity_id)?;
-                    }
+                    &entity.marker
+                };
 
+                if marker == old_marker && entity.type_marker == old_entity.content.type_marker {
+                    let raw_name = raw_names.remove(&entity.name);
+                    self.update_if_changed(
+                        target,
+                        entity,
+                        raw_name,
+                        old_entity_id,
+                        &old_entity.content,
+                    )?;
                     continue;
                 } else {
                     // move old to recycle, move new here
@@ -278,43 +1266,1455 @@ impl<DBImpl: DBRead + DBWrite + DBLock> TrackerTransaction<DBImpl> {
                 }
             }
 
-            if let Some(exist_id) = exist_id {
-                self.move_exist_entity_to(target, entity, exist_id)?;
+            added.push((entity, ids));
+        }
 
-                if let Some(i) = old_entities.iter().enumerate().find_map(|(i, (_, id))| {
-                    if id == &exist_id {
-                        Some(i)
-                    } else {
-                        None
-                    }
-                }) {
-                    old_entities.remove(i);
-                }
+        // recycle before the renames, to free the names. A shallow discovery
+        // only lists some children, the others are kept.
+        if input.mode == DiscoveryMode::Full {
+            for (_, old_entity_id) in old_entities {
+                self.move_node_to_recycle(old_entity_id)?;
+            }
+        }
+
+        for (entity, id) in renamed {
+            self.record_move(id, target, &entity.name)?;
+            let raw_name = raw_names.remove(&entity.name);
+            self.move_exist_entity_to(target, entity, raw_name, id)?;
+        }
+
+        for (entity, ids) in added {
+            let raw_name = raw_names.remove(&entity.name);
+            if let Some(exist_id) = self.find_moved(&entity, &ids)? {
+                self.record_move(exist_id, target, &entity.name)?;
+                self.move_exist_entity_to(target, entity, raw_name, exist_id)?;
             } else {
                 let marker = entity.marker.clone();
-                let new_id = self.move_entity_to(target, entity)?;
+                let new_id = self.move_entity_to(target, entity, raw_name)?;
                 if !marker.is_empty() {
-                    self.set_marker(&marker, &new_id)?;
+                    self.add_marker(&marker, &new_id)?;
                 }
             }
         }
 
-        for (_, old_entity_id) in old_entities {
-            self.move_node_to_recycle(old_entity_id)?;
+        let summary = self.take_summary();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            ops = summary.ops.len(),
+            changed = summary.changed,
+            elapsed_us = start.elapsed().as_micros() as u64,
+            "tracker applied"
+        );
+        Ok(summary)
+    }
+
+    /// Update the entity in place if the update marker, the permission marker,
+    /// the symlink target, the size or the raw name changed.
+    fn update_if_changed(
+        &mut self,
+        target: TrieId,
+        entity: DiscoveryEntity,
+        raw_name: Option<FileName>,
+        old_entity_id: TrieId,
+        old_entity: &Entity,
+    ) -> Result<()> {
+        // if either permission marker is empty, make no judgment, the records
+        // before version 1 have none
+        if !update_markers_match(
+            &entity.update_marker,
+            &old_entity.update_marker,
+            self.mtime_tolerance,
+        ) || entity.symlink_target != old_entity.symlink_target
+            || entity.size != old_entity.size
+            || raw_name != old_entity.raw_name
+            || (!entity.permission_marker.is_empty()
+                && !old_entity.permission_marker.is_empty()
+                && entity.permission_marker != old_entity.permission_marker)
+        {
+            // update
+            self.move_exist_entity_to(target, entity, raw_name, old_entity_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Create the missing folders along `path`, returns the id of the last
+    /// one. The folders have no marker, they are only placeholders for the
+    /// discoveries below them.
+    pub fn create_folders(&mut self, path: &str) -> Result<TrieId> {
+        self.lock()?;
+        self.check_path_limits(path)?;
+
+        let mut id = trie::ROOT;
+        for name in PathTools::parts(path).filter(|name| !name.is_empty()) {
+            let key = self.key(name);
+            id = if let Some(child) = self.trie().get_child_ref(id, TrieKeyRef(&key))? {
+                child
+            } else {
+                let raw_name = (key != name).then(|| name.to_string());
+                self.move_entity_to(
+                    id,
+                    DiscoveryEntity {
+                        name: key.into_owned(),
+                        marker: Default::default(),
+                        type_marker: FileType::Directory.to_bytes().to_vec(),
+                        update_marker: Default::default(),
+                        permission_marker: Default::default(),
+                        symlink_target: None,
+                        hard_links: 1,
+                        size: 0,
+                    },
+                    raw_name,
+                )?
+            };
+        }
+
+        Ok(id)
+    }
+
+    /// Get a stable reference to the file at `path`, an existing reference is
+    /// returned if there is one.
+    pub fn get_or_create_ref(&mut self, path: &str) -> Result<TrieRef> {
+        let path = self.key(path);
+        let id = self
+            .trie()
+            .get_id_by_path(&path)?
+            .ok_or_else(|| Error::InvalidOp("File not found".to_string()))?;
+
+        if let Some(r) = self
+            .trie()
+            .get_refs(id)?
+            .and_then(|refs| refs.first().cloned())
+        {
+            return Ok(r);
         }
 
-        Ok(core::mem::take(&mut self.current_ops))
+        self.lock()?;
+        let r = TrieRef::new();
+        self.trie().set_ref(r.clone(), Some(id))?;
+        Ok(r)
+    }
+
+    /// Mark the file at `path` as accessed now. The access time is local
+    /// state, only the access index is written, not the trie.
+    pub fn touch(&mut self, path: &str) -> Result<()> {
+        let path = self.key(path);
+        let id = self
+            .trie()
+            .get_id_by_path(&path)?
+            .ok_or_else(|| Error::InvalidOp("File not found".to_string()))?;
+        let clock = self.auto_increment_clock()?;
+
+        let old_clock = self
+            .db
+            .get(access_key(id))?
+            .map(|bytes| Clock::from_bytes(bytes.as_ref()))
+            .transpose()
+            .map_err(Error::DecodeError)?;
+        if let Some(old_clock) = old_clock {
+            self.db.delete(access_order_key(old_clock, id))?;
+        }
+        self.db.set(access_key(id), clock.to_bytes())?;
+        self.db.set(access_order_key(clock, id), [])?;
+
+        Ok(())
+    }
+
+    /// Store the chunks of the file at `path`. `update_marker` is the update
+    /// marker of the file when it was read, the chunks are rejected if the
+    /// file changed since it was indexed.
+    pub fn set_chunks(
+        &mut self,
+        path: &str,
+        update_marker: &FileUpdateMarker,
+        chunks: Vec<ChunkRef>,
+    ) -> Result<()> {
+        self.lock()?;
+
+        let path = self.key(path);
+        let id = self
+            .trie()
+            .get_id_by_path(&path)?
+            .ok_or_else(|| Error::InvalidOp("File not found".to_string()))?;
+        let node = self.get_node(id)?;
+        if !update_markers_match(
+            &node.content.update_marker,
+            update_marker,
+            self.mtime_tolerance,
+        ) {
+            return Err(Error::InvalidOp(
+                "File changed since it was indexed".to_string(),
+            ));
+        }
+        let bytes = |chunks: &[ChunkRef]| chunks.iter().map(|chunk| chunk.len as u64).sum::<u64>();
+        self.check_quota(
+            node.parent,
+            0,
+            bytes(&chunks)
+                .saturating_sub(bytes(node.content.chunks.as_deref().unwrap_or_default())),
+        )?;
+
+        let new_clock = self.auto_increment_clock()?;
+        self.do_op(Op {
+            marker: new_clock,
+            parent_target: OpTarget::Id(node.parent),
+            child_key: node.key,
+            child_target: OpTarget::Id(id),
+            child_content: Some(Entity {
+                chunks: Some(chunks),
+                ..node.content
+            }),
+        })
+    }
+
+    /// Mark the file or folder at `path` as ignored, or clear the flag. The
+    /// discoveries of an ignored folder and of the folders below it are
+    /// skipped, the stored children are kept. The flag is part of the entity,
+    /// it follows the node when moved or updated.
+    pub fn set_ignored(&mut self, path: &str, ignored: bool) -> Result<()> {
+        self.lock()?;
+
+        let path = self.key(path);
+        let id = self
+            .trie()
+            .get_id_by_path(&path)?
+            .ok_or_else(|| Error::InvalidOp("File not found".to_string()))?;
+        if id == trie::ROOT {
+            return Err(Error::InvalidOp("Could not ignore the root".to_string()));
+        }
+        let node = self.get_node(id)?;
+        if node.content.ignored == ignored {
+            return Ok(());
+        }
+
+        let new_clock = self.auto_increment_clock()?;
+        self.do_op(Op {
+            marker: new_clock,
+            parent_target: OpTarget::Id(node.parent),
+            child_key: node.key,
+            child_target: OpTarget::Id(id),
+            child_content: Some(Entity {
+                ignored,
+                ..node.content
+            }),
+        })
+    }
+
+    /// Whether `id` or one of its ancestors is ignored.
+    fn is_ignored(&mut self, id: TrieId) -> Result<bool> {
+        let mut target = id;
+        loop {
+            let node = self.get_node(target)?;
+            if node.content.ignored {
+                return Ok(true);
+            }
+
+            // the special nodes are their own parent
+            if node.parent == target {
+                return Ok(false);
+            }
+            target = node.parent;
+        }
+    }
+
+    /// Limit the nodes and bytes below the folder at `path`. The quota is
+    /// stored with the node, it follows the folder when moved. A discovery
+    /// adding more children than allowed, or chunks larger than allowed, is
+    /// rejected with [`Error::InvalidOp`]. `None` and `None` remove the
+    /// quota.
+    pub fn set_quota(
+        &mut self,
+        path: &str,
+        max_nodes: Option<usize>,
+        max_bytes: Option<u64>,
+    ) -> Result<()> {
+        self.lock()?;
+
+        let path = self.key(path);
+        let id = self
+            .trie()
+            .get_id_by_path(&path)?
+            .ok_or_else(|| Error::InvalidOp("File not found".to_string()))?;
+        if max_nodes.is_none() && max_bytes.is_none() {
+            self.db.delete(quota_key(id))?;
+        } else {
+            self.db.set(
+                quota_key(id),
+                (max_nodes.map(|max| max as u64), max_bytes).to_bytes(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Store `note` with the file at `path`, an empty note removes it. The
+    /// note is stored inline in the database, separate from the content of
+    /// the file, and follows the node when moved. Notes longer than
+    /// [`MAX_NOTE_SIZE`] are rejected with [`Error::InvalidOp`].
+    pub fn set_note(&mut self, path: &str, note: impl AsRef<[u8]>) -> Result<()> {
+        let note = note.as_ref();
+        if note.len() > MAX_NOTE_SIZE {
+            return Err(Error::InvalidOp(format!(
+                "Note of {} bytes exceeds the limit of {MAX_NOTE_SIZE}",
+                note.len()
+            )));
+        }
+        self.lock()?;
+
+        let path = self.key(path);
+        let id = self
+            .trie()
+            .get_id_by_path(&path)?
+            .ok_or_else(|| Error::InvalidOp("File not found".to_string()))?;
+        if note.is_empty() {
+            self.db.delete(note_key(id))?;
+        } else {
+            self.db.set(note_key(id), note)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_quota(&self, id: TrieId) -> Result<Option<Quota>> {
+        self.db
+            .get(quota_key(id))?
+            .map(|bytes| {
+                <(Option<u64>, Option<u64>)>::from_bytes(bytes.as_ref())
+                    .map(|(max_nodes, max_bytes)| Quota {
+                        max_nodes: max_nodes.map(|max| max as usize),
+                        max_bytes,
+                    })
+                    .map_err(Error::DecodeError)
+            })
+            .transpose()
+    }
+
+    /// Check the quotas of `id` and its ancestors before adding `nodes`
+    /// nodes and `bytes` bytes below `id`.
+    fn check_quota(&mut self, id: TrieId, nodes: usize, bytes: u64) -> Result<()> {
+        if nodes == 0 && bytes == 0 {
+            return Ok(());
+        }
+
+        let mut target = id;
+        loop {
+            if let Some(quota) = self.get_quota(target)? {
+                let mut stats = SubtreeStats::default();
+                self.trie().walk(target, &mut stats)?;
+                if quota.max_nodes.is_some_and(|max| stats.nodes + nodes > max)
+                    || quota.max_bytes.is_some_and(|max| stats.bytes + bytes > max)
+                {
+                    return Err(Error::InvalidOp("quota exceeded".to_string()));
+                }
+            }
+
+            // the special nodes are their own parent
+            let parent = self.get_node(target)?.parent;
+            if parent == target {
+                return Ok(());
+            }
+            target = parent;
+        }
+    }
+
+    /// Rewrite the marker index from the markers of all nodes in the trie,
+    /// stale entries are removed first. Returns the number of indexed markers.
+    pub fn rebuild_marker_index(&mut self) -> Result<usize> {
+        self.lock()?;
+
+        let stale = self
+            .db
+            .prefix_scan(MARKERS_PREFIX)
+            .map(|item| item.map(|(key, _)| key.as_ref().to_vec()))
+            .collect::<db::Result<Vec<_>>>()?;
+        for key in stale {
+            self.db.delete(key)?;
+        }
+
+        // nodes under ROOT are visited last, so they come before the recycled
+        // nodes with the same marker.
+        let mut markers = BTreeMap::<_, Vec<_>>::new();
+        let mut stack = vec![trie::ROOT, trie::CONFLICT, trie::RECYCLE];
+        while let Some(id) = stack.pop() {
+            for (_, child) in self.trie().get_children(id)? {
+                // the special nodes are their own parent
+                if child != id {
+                    stack.push(child);
+                }
+            }
+
+            let marker = self.get_node(id)?.content.marker;
+            if !marker.is_empty() {
+                markers.entry(marker).or_default().insert(0, id);
+            }
+        }
+
+        for (marker, ids) in markers.iter() {
+            self.set_marker_ids(marker, ids)?;
+        }
+
+        Ok(markers.len())
+    }
+
+    /// Move the entity at `from` to `to`, keeping its identity. The entity
+    /// already at `to` is moved to recycle.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<Vec<Op<Clock, Entity>>> {
+        self.lock()?;
+        self.check_path_limits(to)?;
+
+        let from = self.key(from);
+        let id = self
+            .trie()
+            .get_id_by_path(&from)?
+            .ok_or_else(|| Error::InvalidOp("Source not found".to_string()))?;
+        let to_key = self.key(to);
+        let parent = self
+            .trie()
+            .get_id_by_path(PathTools::dirname(&to_key))?
+            .ok_or_else(|| Error::InvalidOp("Location not found".to_string()))?;
+        let key = PathTools::basename(&to_key).to_string();
+        let raw_name = PathTools::basename(to);
+        let raw_name = (raw_name != key).then(|| raw_name.to_string());
+
+        if let Some(exist_id) = self.trie().get_child_ref(parent, TrieKeyRef(&key))? {
+            if exist_id != id {
+                self.move_node_to_recycle(exist_id)?;
+            }
+        }
+
+        self.record_move(id, parent, &key)?;
+
+        let content = Entity {
+            raw_name,
+            ..self.get_node(id)?.content
+        };
+        let new_clock = self.auto_increment_clock()?;
+        self.do_op(Op {
+            marker: new_clock,
+            parent_target: OpTarget::Id(parent),
+            child_key: key.into(),
+            child_target: OpTarget::Id(id),
+            child_content: Some(content),
+        })?;
+
+        Ok(self.take_summary().ops)
     }
 }
 
 impl<DBImpl: DBTransaction> TrackerTransaction<DBImpl> {
     pub fn commit(self) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let (_span, start) = (
+            tracing::debug_span!(
+                "tracker_commit",
+                ops = self.published.ops.len(),
+                pending_changes = self.db.pending_changes()
+            )
+            .entered(),
+            std::time::Instant::now(),
+        );
+
         self.db.commit()?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            elapsed_us = start.elapsed().as_micros() as u64,
+            "tracker committed"
+        );
+
+        if let Some(subscribers) = self.subscribers {
+            if !self.published.ops.is_empty() {
+                // drop the subscribers whose receiver is gone
+                subscribers
+                    .lock()
+                    .unwrap()
+                    .retain(|sender| sender.send(self.published.clone()).is_ok());
+            }
+        }
         Ok(())
     }
 
+    /// Discard the transaction. The clock and the trie id counter are only
+    /// written through the transaction and never cached outside of it, so the
+    /// next transaction continues from the values before this one.
     pub fn rollback(self) -> Result<()> {
         self.db.rollback()?;
         Ok(())
     }
+
+    /// Number of writes staged in the database by this transaction, e.g. to
+    /// log big applies or to commit in smaller batches.
+    pub fn pending_changes(&self) -> usize {
+        self.db.pending_changes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use chunk::ChunkRef;
+    use db::{backend::memory::MemoryDB, DBRead, DBTransaction, DBWrite, DB};
+    use trie::{store::TrieStoreRead, Op, OpTarget, TrieRef};
+    use utils::{Deserialize, Serialize};
+
+    use crate::{FileEvent, FileEventType, FileFullPath, FileType, PathError, PathLimits};
+
+    use super::{
+        ActorId, Clock, ConflictNaming, Discovery, DiscoveryEntity, DiscoveryMode, Entity, Error,
+        HybridClock, OpStreamReader, TimeSizeMarker, Tracker, TrackerStats, CLOCK_KEY,
+        MARKERS_PREFIX, MAX_NOTE_SIZE,
+    };
+
+    /// A discovered entity with the type marker `type_marker`, e.g. `b'f'`,
+    /// and the update marker `[update_marker]`.
+    fn entity(name: &str, type_marker: u8, update_marker: u8) -> DiscoveryEntity {
+        DiscoveryEntity {
+            name: name.to_string(),
+            marker: vec![],
+            type_marker: vec![type_marker],
+            update_marker: vec![update_marker],
+            permission_marker: vec![],
+            symlink_target: None,
+            hard_links: 1,
+            size: 0,
+        }
+    }
+
+    /// Apply a full discovery of `location` in a transaction and commit it.
+    fn apply_full<DBImpl: DB>(
+        tracker: &Tracker<DBImpl>,
+        location: &str,
+        entities: Vec<DiscoveryEntity>,
+    ) -> Vec<Op<Clock, Entity>> {
+        let mut transaction = tracker.start_transaction().unwrap();
+        let ops = transaction
+            .apply(Discovery {
+                location: (location.to_string(), vec![]),
+                mode: DiscoveryMode::Full,
+                entities,
+            })
+            .unwrap();
+        transaction.commit().unwrap();
+        ops
+    }
+
+    #[test]
+    fn rebuild_marker_index_test() {
+        let db = MemoryDB::default();
+        let tracker = Tracker::init(&db).unwrap();
+
+        let with_marker = |name: &str, marker: &[u8]| DiscoveryEntity {
+            marker: marker.to_vec(),
+            ..entity(name, b'f', 1)
+        };
+        apply_full(
+            &tracker,
+            "/",
+            vec![with_marker("a", b"1"), with_marker("b", b"2")],
+        );
+
+        // lose the index and leave a stale entry
+        let mut transaction = db.start_transaction().unwrap();
+        transaction.delete([MARKERS_PREFIX, b"1"].concat()).unwrap();
+        transaction.delete([MARKERS_PREFIX, b"2"].concat()).unwrap();
+        transaction
+            .set([MARKERS_PREFIX, b"stale"].concat(), [0; 8])
+            .unwrap();
+        transaction.commit().unwrap();
+
+        let transaction = tracker.start_transaction().unwrap();
+        assert!(transaction.get_marker(&b"1".to_vec()).unwrap().is_none());
+        transaction.rollback().unwrap();
+
+        assert_eq!(tracker.rebuild_marker_index().unwrap(), 2);
+
+        let mut transaction = tracker.start_transaction().unwrap();
+        let a = transaction.trie().get_id_by_path("/a").unwrap();
+        let b = transaction.trie().get_id_by_path("/b").unwrap();
+        assert!(a.is_some());
+        assert_eq!(transaction.get_marker(&b"1".to_vec()).unwrap(), a);
+        assert_eq!(transaction.get_marker(&b"2".to_vec()).unwrap(), b);
+        assert!(transaction
+            .get_marker(&b"stale".to_vec())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn least_recently_accessed_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let file = |name: &str| entity(name, b'f', 1);
+        apply_full(
+            &tracker,
+            "/",
+            vec![file("a"), file("b"), file("c"), file("d")],
+        );
+        let paths = |paths: &[&str]| {
+            paths
+                .iter()
+                .map(|path| FileFullPath::parse(path))
+                .collect::<Vec<_>>()
+        };
+
+        for path in ["/c", "/a", "/b", "/c"] {
+            tracker.touch(path).unwrap();
+        }
+        assert!(tracker.touch("/missing").is_err());
+        assert_eq!(
+            tracker.least_recently_accessed(10).unwrap(),
+            paths(&["/a", "/b", "/c"])
+        );
+        assert_eq!(
+            tracker.least_recently_accessed(2).unwrap(),
+            paths(&["/a", "/b"])
+        );
+
+        // deleted files are skipped
+        apply_full(&tracker, "/", vec![file("b"), file("c"), file("d")]);
+        assert_eq!(
+            tracker.least_recently_accessed(2).unwrap(),
+            paths(&["/b", "/c"])
+        );
+    }
+
+    #[test]
+    fn symlink_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let index = |type_marker: u8, symlink_target: Option<&str>| {
+            apply_full(
+                &tracker,
+                "/",
+                vec![DiscoveryEntity {
+                    symlink_target: symlink_target.map(|target| target.as_bytes().to_vec()),
+                    ..entity("a", type_marker, 1)
+                }],
+            );
+
+            let trie = tracker.trie();
+            let id = trie.get_id_by_path("/a").unwrap().unwrap();
+            (id, trie.get_ensure(id).unwrap().content)
+        };
+
+        let (file, _) = index(b'f', None);
+        // a file replaced by a link, the file is recycled
+        let (link, entity) = index(b's', Some("/target"));
+        assert_ne!(link, file);
+        assert_eq!(entity.symlink_target, Some(b"/target".to_vec()));
+        assert!(tracker.trie().get_path_by_id(file).unwrap().is_none());
+        assert_eq!(
+            tracker.trie().get_ensure(file).unwrap().parent,
+            trie::RECYCLE
+        );
+
+        // only the target changed, the link is updated
+        let (updated, entity) = index(b's', Some("/other"));
+        assert_eq!(updated, link);
+        assert_eq!(entity.symlink_target, Some(b"/other".to_vec()));
+    }
+
+    #[test]
+    fn change_summary_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let index = |update_marker: u8| {
+            let mut transaction = tracker.start_transaction().unwrap();
+            let summary = transaction
+                .apply_with_summary(Discovery {
+                    location: ("/".to_string(), vec![]),
+                    mode: DiscoveryMode::Full,
+                    entities: vec![DiscoveryEntity {
+                        marker: b"1".to_vec(),
+                        ..entity("a", b'f', update_marker)
+                    }],
+                })
+                .unwrap();
+            transaction.commit().unwrap();
+            summary
+        };
+
+        assert!(index(1).changed);
+        // unchanged
+        let summary = index(1);
+        assert!(summary.ops.is_empty());
+        assert!(!summary.changed);
+        // edited
+        let summary = index(2);
+        assert_eq!(summary.ops.len(), 1);
+        assert!(summary.changed);
+    }
+
+    #[test]
+    fn path_limits_test() {
+        let mut tracker = Tracker::init(MemoryDB::default()).unwrap();
+        tracker.set_path_limits(PathLimits {
+            max_path_len: 16,
+            max_depth: 2,
+        });
+        let folder = |name: &str| entity(name, b'd', 1);
+
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction
+            .apply(Discovery {
+                location: ("/".to_string(), vec![]),
+                mode: DiscoveryMode::Full,
+                entities: vec![folder("a")],
+            })
+            .unwrap();
+        transaction
+            .apply(Discovery {
+                location: ("/a".to_string(), vec![]),
+                mode: DiscoveryMode::Full,
+                entities: vec![folder("b")],
+            })
+            .unwrap();
+
+        assert!(matches!(
+            transaction.apply(Discovery {
+                location: ("/a/b".to_string(), vec![]),
+                mode: DiscoveryMode::Full,
+                entities: vec![folder("c")],
+            }),
+            Err(Error::PathError(PathError::TooDeep { depth: 3, max: 2 }))
+        ));
+        assert!(matches!(
+            transaction.apply(Discovery {
+                location: ("/".to_string(), vec![]),
+                mode: DiscoveryMode::Full,
+                entities: vec![folder("a"), folder("a-very-long-name")],
+            }),
+            Err(Error::PathError(PathError::TooLong { len: 17, max: 16 }))
+        ));
+        assert!(matches!(
+            transaction.apply(Discovery {
+                location: ("/".to_string(), vec![]),
+                mode: DiscoveryMode::Full,
+                entities: vec![folder("x/../y")],
+            }),
+            Err(Error::PathError(PathError::InvalidSegment(_)))
+        ));
+        assert!(matches!(
+            transaction.create_folders("/x/y/z"),
+            Err(Error::PathError(PathError::TooDeep { .. }))
+        ));
+        assert!(matches!(
+            transaction.rename("/a", "/a/b/c"),
+            Err(Error::PathError(PathError::TooDeep { .. }))
+        ));
+        transaction.commit().unwrap();
+
+        // nothing was written for the rejected discoveries
+        assert!(tracker.trie().get_id_by_path("/a/b").unwrap().is_some());
+        assert!(tracker
+            .trie()
+            .get_id_by_path("/a-very-long-name")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn shallow_discovery_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let file = |name: &str, update_marker: u8| entity(name, b'f', update_marker);
+        let update_marker = |path: &str| {
+            tracker
+                .trie()
+                .get_id_by_path(path)
+                .unwrap()
+                .map(|id| tracker.trie().get_ensure(id).unwrap().content.update_marker)
+        };
+
+        apply_full(&tracker, "/", vec![file("a", 1), file("b", 1)]);
+
+        // "a" is not listed, it survives
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction
+            .apply(Discovery {
+                location: ("/".to_string(), vec![]),
+                mode: DiscoveryMode::Shallow,
+                entities: vec![file("b", 2), file("c", 1)],
+            })
+            .unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(update_marker("/a"), Some(vec![1]));
+        assert_eq!(update_marker("/b"), Some(vec![2]));
+        assert_eq!(update_marker("/c"), Some(vec![1]));
+
+        // a full discovery still recycles the children not listed
+        apply_full(&tracker, "/", vec![file("b", 2)]);
+        assert_eq!(update_marker("/a"), None);
+        assert_eq!(update_marker("/c"), None);
+        assert_eq!(update_marker("/b"), Some(vec![2]));
+    }
+
+    #[test]
+    fn ignored_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let apply = |location: &str, entities| apply_full(&tracker, location, entities);
+        let exists = |path: &str| tracker.trie().get_id_by_path(path).unwrap().is_some();
+
+        apply("/", vec![entity("dir", b'd', 1)]);
+        apply("/dir", vec![entity("a", b'f', 1), entity("sub", b'd', 1)]);
+        apply("/dir/sub", vec![entity("b", b'f', 1)]);
+
+        tracker.set_ignored("/dir", true).unwrap();
+        assert!(tracker.trie().to_string().contains("(ignored)"));
+
+        // the discoveries of the folder and below are skipped
+        assert!(apply("/dir", vec![entity("c", b'f', 1)]).is_empty());
+        assert!(apply("/dir/sub", vec![]).is_empty());
+        assert!(exists("/dir/a") && exists("/dir/sub/b"));
+        assert!(!exists("/dir/c"));
+
+        // the flag survives an update of the folder
+        assert!(!apply("/", vec![entity("dir", b'd', 2)]).is_empty());
+        let id = tracker.trie().get_id_by_path("/dir").unwrap().unwrap();
+        assert!(tracker.trie().get_ensure(id).unwrap().content.ignored);
+
+        tracker.set_ignored("/dir", false).unwrap();
+        apply("/dir", vec![entity("c", b'f', 1)]);
+        assert!(!exists("/dir/a"));
+        assert!(exists("/dir/c"));
+        assert!(tracker.set_ignored("/", true).is_err());
+    }
+
+    #[test]
+    fn note_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        apply_full(
+            &tracker,
+            "/",
+            vec![entity("a", b'd', 1), entity("b", b'd', 1)],
+        );
+        apply_full(&tracker, "/a", vec![entity("file", b'f', 1)]);
+        assert_eq!(tracker.get_note("/a/file").unwrap(), None);
+        tracker.set_note("/a/file", b"remember the milk").unwrap();
+        assert!(tracker.set_note("/a/missing", b"note").is_err());
+
+        // the note follows the node
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction.rename("/a/file", "/b/file").unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(
+            tracker.get_note("/b/file").unwrap().as_deref(),
+            Some(&b"remember the milk"[..])
+        );
+        assert!(tracker.get_note("/a/file").is_err());
+
+        assert!(matches!(
+            tracker.set_note("/b/file", vec![0; MAX_NOTE_SIZE + 1]),
+            Err(Error::InvalidOp(_))
+        ));
+        tracker.set_note("/b/file", []).unwrap();
+        assert_eq!(tracker.get_note("/b/file").unwrap(), None);
+    }
+
+    #[test]
+    fn mtime_tolerance_test() {
+        let mut tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let file = |mtime_nsec: u32, size: u64| DiscoveryEntity {
+            update_marker: TimeSizeMarker {
+                mtime: 1000,
+                mtime_nsec,
+                size,
+            }
+            .to_marker(),
+            ..entity("file", b'f', 1)
+        };
+        let apply = |tracker: &Tracker<MemoryDB>, file: DiscoveryEntity| {
+            apply_full(tracker, "/", vec![file])
+        };
+
+        apply(&tracker, file(123_456_789, 10));
+        // restored from a backup which rounded the time to milliseconds
+        let restored = file(123_000_000, 10);
+        assert!(!apply(&tracker, restored.clone()).is_empty());
+
+        apply(&tracker, file(123_456_789, 10));
+        tracker.set_mtime_tolerance(Duration::from_millis(1));
+        assert!(apply(&tracker, restored.clone()).is_empty());
+        // the chunks indexed from the restored file are accepted
+        tracker
+            .start_transaction()
+            .unwrap()
+            .set_chunks("/file", &restored.update_marker, vec![])
+            .unwrap();
+
+        // a change in size is still an update
+        assert!(!apply(&tracker, file(123_000_000, 11)).is_empty());
+        // so is a time out of the tolerance
+        assert!(!apply(&tracker, file(125_000_000, 11)).is_empty());
+    }
+
+    #[test]
+    fn permission_marker_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let file = |permission_marker| DiscoveryEntity {
+            permission_marker,
+            ..entity("a", b'f', 1)
+        };
+
+        apply_full(&tracker, "/", vec![file(vec![1])]);
+        assert!(!apply_full(&tracker, "/", vec![file(vec![2])]).is_empty());
+        // an unknown permission is no change
+        assert!(apply_full(&tracker, "/", vec![file(vec![])]).is_empty());
+
+        // e.g. a record before version 1
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        apply_full(&tracker, "/", vec![file(vec![])]);
+        assert!(apply_full(&tracker, "/", vec![file(vec![2])]).is_empty());
+    }
+
+    #[test]
+    fn quota_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let apply = |mode: DiscoveryMode, names: &[&str]| {
+            let mut transaction = tracker.start_transaction().unwrap();
+            transaction.apply(Discovery {
+                location: ("/dir".to_string(), vec![]),
+                mode,
+                entities: names.iter().map(|name| entity(name, b'f', 1)).collect(),
+            })?;
+            transaction.commit()
+        };
+
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction.create_folders("/dir").unwrap();
+        transaction.commit().unwrap();
+        tracker.set_quota("/dir", Some(2), Some(10)).unwrap();
+
+        let before = tracker.trie().to_string();
+        assert!(matches!(
+            apply(DiscoveryMode::Full, &["a", "b", "c"]),
+            Err(Error::InvalidOp(message)) if message == "quota exceeded"
+        ));
+        assert_eq!(tracker.trie().to_string(), before);
+
+        apply(DiscoveryMode::Full, &["a", "b"]).unwrap();
+        // replacing the children does not add any
+        apply(DiscoveryMode::Full, &["a", "c"]).unwrap();
+        assert!(apply(DiscoveryMode::Shallow, &["d"]).is_err());
+
+        // the quota of an ancestor applies too
+        tracker.set_quota("/dir", None, Some(10)).unwrap();
+        tracker.set_quota("/", Some(3), None).unwrap();
+        assert!(apply(DiscoveryMode::Shallow, &["d"]).is_err());
+        tracker.set_quota("/", None, None).unwrap();
+        apply(DiscoveryMode::Shallow, &["d"]).unwrap();
+
+        let chunks = |len: u32| {
+            vec![ChunkRef {
+                offset: 0,
+                len,
+                hash: [0; 16],
+            }]
+        };
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction
+            .set_chunks("/dir/a", &vec![1], chunks(6))
+            .unwrap();
+        assert!(matches!(
+            transaction.set_chunks("/dir/c", &vec![1], chunks(6)),
+            Err(Error::InvalidOp(_))
+        ));
+        // replacing the chunks only counts the difference
+        transaction
+            .set_chunks("/dir/a", &vec![1], chunks(10))
+            .unwrap();
+    }
+
+    #[test]
+    fn rollback_clock_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let discovery = || Discovery {
+            location: ("/".to_string(), vec![]),
+            mode: DiscoveryMode::Full,
+            entities: vec![
+                entity("a", b'f', 1),
+                entity("b", b'f', 1),
+                entity("c", b'f', 1),
+            ],
+        };
+        let clock = || Clock::from_bytes(&tracker.db.get(CLOCK_KEY).unwrap().unwrap()).unwrap();
+        let before = clock();
+
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction.apply(discovery()).unwrap();
+        let applied_once =
+            Clock::from_bytes(&transaction.db.get(CLOCK_KEY).unwrap().unwrap()).unwrap();
+        transaction.apply(discovery()).unwrap();
+        let rolled_back = transaction.trie().get_id_by_path("/c").unwrap();
+        transaction.rollback().unwrap();
+
+        assert_eq!(clock(), before);
+        assert_eq!(tracker.trie().get_id_by_path("/c").unwrap(), None);
+
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction.apply(discovery()).unwrap();
+        assert_eq!(
+            transaction.trie().get_id_by_path("/c").unwrap(),
+            rolled_back
+        );
+        transaction.commit().unwrap();
+
+        // the clock continues as if the rolled back transaction never ran
+        assert_eq!(clock(), applied_once);
+        let mut markers = tracker
+            .trie()
+            .iter_log()
+            .unwrap()
+            .map(|log| log.unwrap().op.marker)
+            .collect::<Vec<_>>();
+        markers.sort();
+        markers.dedup();
+        assert_eq!(markers.len(), 3);
+        assert!(markers
+            .iter()
+            .all(|marker| *marker > before && *marker <= applied_once));
+    }
+
+    #[test]
+    fn hybrid_clock_sync_test() {
+        let mut a = Tracker::init(MemoryDB::default()).unwrap();
+        a.set_clock_source(HybridClock::with_time(|| 100));
+        // the wall time of b is behind
+        let mut b = Tracker::init(MemoryDB::default()).unwrap();
+        b.set_clock_source(HybridClock::with_time(|| 50));
+
+        let mut transaction = a.start_transaction().unwrap();
+        transaction.create_folders("/a").unwrap();
+        transaction.commit().unwrap();
+
+        // b receives the ops of a, then renames the folder created by a
+        let mut received = a
+            .trie()
+            .iter_log()
+            .unwrap()
+            .map(|log| log.unwrap().op)
+            .collect::<Vec<_>>();
+        received.reverse();
+        let mut transaction = b.start_transaction().unwrap();
+        for op in received.iter() {
+            transaction.observe_clock(op.marker).unwrap();
+        }
+        transaction.trie().apply(received.clone()).unwrap();
+        let renamed = transaction.rename("/a", "/c").unwrap();
+        transaction.commit().unwrap();
+
+        let created = received.last().unwrap().marker;
+        let renamed = renamed.last().unwrap().marker;
+        assert_eq!(HybridClock::wall_time(created), 100);
+        // the rename is ordered after the op it has seen, not by the wall time of b
+        assert!(renamed > created);
+        assert_eq!(HybridClock::wall_time(renamed), 100);
+        assert!(!b.trie().path_exists("/a").unwrap());
+        assert!(b.trie().path_exists("/c").unwrap());
+    }
+
+    #[test]
+    fn stats_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        assert_eq!(tracker.stats().unwrap(), TrackerStats::default());
+
+        let mut transaction = tracker.start_transaction().unwrap();
+        for path in ["/a", "/b", "/c/d"] {
+            transaction.create_folders(path).unwrap();
+        }
+        // the old /c is moved to recycle with its child
+        transaction.rename("/b", "/c").unwrap();
+        // a newer /a from another writer, the old one is moved to conflict
+        transaction
+            .trie()
+            .apply(vec![Op {
+                marker: Clock::MAX,
+                parent_target: OpTarget::Id(trie::ROOT),
+                child_key: "a".to_string().into(),
+                child_target: OpTarget::NewId,
+                child_content: Some(Default::default()),
+            }])
+            .unwrap();
+        transaction.commit().unwrap();
+
+        let stats = tracker.stats().unwrap();
+        assert_eq!(
+            stats,
+            TrackerStats {
+                nodes: 5,
+                conflicts: 1,
+                recycled: 1,
+                log_len: tracker.trie().iter_log().unwrap().count() as u64,
+                clock: stats.clock,
+            }
+        );
+        assert_eq!(stats.log_len, 7);
+        assert!(stats.clock > 0);
+    }
+
+    #[test]
+    fn nfc_keys_test() {
+        const NFC: &str = "caf\u{e9}";
+        const NFD: &str = "cafe\u{301}";
+
+        let apply = |tracker: &Tracker<MemoryDB>, name: &str| {
+            apply_full(tracker, "/", vec![entity(name, b'd', 1)]);
+        };
+        let get =
+            |tracker: &Tracker<MemoryDB>, path: &str| tracker.trie().get_id_by_path(path).unwrap();
+
+        // without normalization the spellings are two names
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        apply(&tracker, NFD);
+        let id = get(&tracker, &format!("/{NFD}"));
+        apply(&tracker, NFC);
+        assert!(get(&tracker, &format!("/{NFC}")).is_some());
+        assert!(get(&tracker, &format!("/{NFC}")) != id);
+
+        let mut tracker = Tracker::init(MemoryDB::default()).unwrap();
+        tracker.set_nfc_keys(true);
+        apply(&tracker, NFD);
+        let id = get(&tracker, &format!("/{NFC}")).unwrap();
+        assert!(get(&tracker, &format!("/{NFD}")).is_none());
+        let node = tracker.trie().get_ensure(id).unwrap();
+        assert_eq!(node.content.raw_name.as_deref(), Some(NFD));
+
+        // the other spelling is the same node, only its raw name changes
+        apply(&tracker, NFC);
+        assert_eq!(get(&tracker, &format!("/{NFC}")), Some(id));
+        assert_eq!(tracker.stats().unwrap().recycled, 0);
+        let node = tracker.trie().get_ensure(id).unwrap();
+        assert_eq!(node.content.raw_name, None);
+
+        // paths are normalized too
+        tracker.set_note(&format!("/{NFD}"), b"note").unwrap();
+        assert_eq!(
+            tracker.get_note(&format!("/{NFC}")).unwrap(),
+            Some(b"note".to_vec())
+        );
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction
+            .rename(&format!("/{NFC}"), &format!("/{NFD}2"))
+            .unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(get(&tracker, &format!("/{NFC}2")), Some(id));
+        let node = tracker.trie().get_ensure(id).unwrap();
+        assert_eq!(node.content.raw_name, Some(format!("{NFD}2")));
+    }
+
+    #[test]
+    fn stream_ops_since_test() {
+        let a = Tracker::init(MemoryDB::default()).unwrap();
+        let b = Tracker::init(MemoryDB::default()).unwrap();
+
+        let replicate = |since: Clock| {
+            let mut stream = vec![];
+            let newest = a.stream_ops_since(since, &mut stream).unwrap();
+            let ops = OpStreamReader::new(stream.as_slice())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            let count = ops.len();
+
+            let mut transaction = b.start_transaction().unwrap();
+            transaction.apply_remote_ops(ops).unwrap();
+            transaction.commit().unwrap();
+            (newest, count)
+        };
+
+        let mut transaction = a.start_transaction().unwrap();
+        for path in ["/a/b", "/c"] {
+            transaction.create_folders(path).unwrap();
+        }
+        transaction.rename("/c", "/a/c").unwrap();
+        transaction.commit().unwrap();
+
+        let (marker, count) = replicate(0);
+        assert_eq!(count, 4);
+        assert_eq!(b.trie().to_string(), a.trie().to_string());
+
+        // nothing new
+        assert_eq!(replicate(marker), (marker, 0));
+
+        let mut transaction = a.start_transaction().unwrap();
+        transaction.create_folders("/d").unwrap();
+        transaction.rename("/a/b", "/d/b").unwrap();
+        transaction.commit().unwrap();
+
+        let (newer, count) = replicate(marker);
+        assert!(newer > marker);
+        assert_eq!(count, 2);
+        assert_eq!(b.trie().to_string(), a.trie().to_string());
+
+        // a stream cut inside the last frame
+        let mut stream = vec![];
+        a.stream_ops_since(marker, &mut stream).unwrap();
+        stream.pop();
+        let mut reader = OpStreamReader::new(stream.as_slice());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(Error::DecodeError(_)))));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn actor_test() {
+        let mut a = Tracker::init(MemoryDB::default()).unwrap();
+        a.set_actor(1);
+        let mut b = Tracker::init(MemoryDB::default()).unwrap();
+        b.set_actor(2);
+        let events = a.subscribe();
+
+        let mut transaction = a.start_transaction().unwrap();
+        transaction.create_folders("/a/b").unwrap();
+        transaction.commit().unwrap();
+        a.set_actor(3);
+        let mut transaction = a.start_transaction().unwrap();
+        let renamed = transaction.rename("/a/b", "/c").unwrap();
+        transaction.commit().unwrap();
+
+        assert_eq!(
+            events.try_iter().map(|e| e.actor).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+
+        // the actors are read back from the log
+        let actors = a
+            .trie()
+            .iter_log()
+            .unwrap()
+            .map(|log| a.op_actor(log.unwrap().op.marker).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(actors, vec![Some(3), Some(1), Some(1)]);
+        assert_eq!(a.op_actor(Clock::MAX).unwrap(), None);
+
+        // and kept by the replica
+        let mut stream = vec![];
+        a.stream_ops_since(0, &mut stream).unwrap();
+        let ops = OpStreamReader::new(stream.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            ops.iter().map(|(actor, _)| *actor).collect::<Vec<_>>(),
+            vec![1, 1, 3]
+        );
+        let mut transaction = b.start_transaction().unwrap();
+        transaction.apply_remote_ops(ops).unwrap();
+        let local = transaction.create_folders("/d").unwrap();
+        transaction.commit().unwrap();
+
+        let marker = renamed.last().unwrap().marker;
+        assert_eq!(b.op_actor(marker).unwrap(), Some(3));
+        let local = b
+            .trie()
+            .iter_log()
+            .unwrap()
+            .map(|log| log.unwrap().op)
+            .find(|op| op.child_target == OpTarget::Id(local))
+            .unwrap();
+        assert_eq!(b.op_actor(local.marker).unwrap(), Some(2));
+        // the actor doesn't change the order of the ops
+        assert!(local.marker > marker);
+        assert!(b.trie().path_exists("/d").unwrap());
+    }
+
+    #[test]
+    fn resolve_id_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let mut transaction = tracker.start_transaction().unwrap();
+        let id = transaction.create_folders("/a/b").unwrap();
+        transaction.rename("/a", "/c").unwrap();
+        transaction.commit().unwrap();
+
+        let (path, entity) = tracker.resolve_id(id).unwrap().unwrap();
+        assert_eq!(path, FileFullPath::parse("/c/b"));
+        assert_eq!(entity, tracker.trie().get_ensure(id).unwrap().content);
+        assert_eq!(
+            tracker.resolve_id(trie::ROOT).unwrap().unwrap().0,
+            FileFullPath::parse("/")
+        );
+        assert!(tracker.resolve_id(trie::CONFLICT).unwrap().is_none());
+
+        // deleted, the node is in the recycle bin
+        apply_full(&tracker, "/", vec![]);
+        assert!(tracker.trie().get(id).unwrap().is_some());
+        assert!(tracker.resolve_id(id).unwrap().is_none());
+    }
+
+    #[test]
+    fn migrate_entity_test() {
+        let tracker = Tracker::init(MemoryDB::default()).unwrap();
+        let mut transaction = tracker.start_transaction().unwrap();
+        let id = transaction.create_folders("/a").unwrap();
+        transaction.commit().unwrap();
+
+        // overwrite the node with an entity of version 0, without the tag
+        let node = tracker.trie().get_ensure(id).unwrap();
+        let (key, _) = tracker
+            .db
+            .prefix_scan(super::DB_TRIE_PREFIX)
+            .map(Result::unwrap)
+            .find(|(_, value)| value.as_ref() == node.to_bytes().as_slice())
+            .unwrap();
+        let key = key.as_ref().to_vec();
+        let content = node.content.to_bytes();
+        let mut record = node.to_bytes();
+        record.truncate(record.len() - content.len());
+        record.extend_from_slice(
+            &(
+                node.content.marker.clone(),
+                node.content.update_marker.clone(),
+                node.content.type_marker.clone(),
+            )
+                .to_bytes(),
+        );
+        let mut db = tracker.db.start_transaction().unwrap();
+        db.set(&key, record).unwrap();
+        db.commit().unwrap();
+
+        // the first read writes it back in the latest version
+        let mut transaction = tracker.start_transaction().unwrap();
+        assert!(transaction.get_node(id).unwrap() == node);
+        assert_eq!(transaction.pending_changes(), 1);
+        transaction.commit().unwrap();
+        assert_eq!(
+            tracker.db.get(&key).unwrap().unwrap().as_ref(),
+            node.to_bytes().as_slice()
+        );
+
+        // the records in the latest version are left alone
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction.get_node(id).unwrap();
+        assert_eq!(transaction.pending_changes(), 0);
+    }
+
+    #[test]
+    fn conflict_naming_test() {
+        let mut tracker = Tracker::init(MemoryDB::default()).unwrap();
+        tracker.set_conflict_naming(Some(ConflictNaming::default()));
+        let file = |update_marker: u8| Entity {
+            type_marker: FileType::File.to_bytes().to_vec(),
+            update_marker: vec![update_marker],
+            ..Default::default()
+        };
+        let remote = |marker: Clock, key: &str, content: Entity| {
+            (
+                1,
+                Op {
+                    marker,
+                    parent_target: OpTarget::Id(trie::ROOT),
+                    child_key: key.to_string().into(),
+                    child_target: OpTarget::NewId,
+                    child_content: Some(content),
+                },
+            )
+        };
+
+        apply_full(
+            &tracker,
+            "/",
+            vec![entity("x.txt", FileType::File.into(), 1)],
+        );
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction.create_folders("/d/e").unwrap();
+        transaction.commit().unwrap();
+        let local = tracker.trie().get_id_by_path("/x.txt").unwrap().unwrap();
+
+        // a newer x.txt from another writer wins the name, the local file is
+        // put back beside it
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction
+            .apply_remote_ops(vec![remote(1 << 64, "x.txt", file(2))])
+            .unwrap();
+        let events = transaction.take_events();
+        transaction.commit().unwrap();
+
+        let date = ConflictNaming::new("{date}").render("", SystemTime::now(), 1);
+        let copy = format!("/x (conflict {date}).txt");
+        assert_eq!(
+            events,
+            vec![FileEvent {
+                event_type: FileEventType::Conflicted {
+                    original: FileFullPath::parse("/x.txt")
+                },
+                path: FileFullPath::parse(&copy),
+                old_path: None,
+                file_type: Some(FileType::File),
+            }]
+        );
+        let trie = tracker.trie();
+        assert_eq!(trie.get_id_by_path(&copy).unwrap(), Some(local));
+        let winner = trie.get_id_by_path("/x.txt").unwrap().unwrap();
+        assert_ne!(winner, local);
+        assert_eq!(trie.get_ensure(winner).unwrap().content, file(2));
+        assert_eq!(trie.children_count(trie::CONFLICT).unwrap(), 0);
+
+        // the next copy of the same name is numbered, an empty node losing to
+        // a folder with children is put back too
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction
+            .apply_remote_ops(vec![
+                remote(2 << 64, "x.txt", file(3)),
+                remote(3 << 64, "d", Default::default()),
+            ])
+            .unwrap();
+        transaction.commit().unwrap();
+
+        let trie = tracker.trie();
+        assert_eq!(
+            trie.get_id_by_path(&format!("/x (conflict {date}) 2.txt"))
+                .unwrap(),
+            Some(winner)
+        );
+        assert!(trie.path_exists("/d/e").unwrap());
+        assert!(trie.path_exists(&format!("/d (conflict {date})")).unwrap());
+        assert_eq!(trie.children_count(trie::CONFLICT).unwrap(), 0);
+        assert_eq!(trie.get_children(trie::ROOT).unwrap().len(), 5);
+
+        // off, the loser stays in the conflict folder
+        tracker.set_conflict_naming(None);
+        let mut transaction = tracker.start_transaction().unwrap();
+        transaction
+            .apply_remote_ops(vec![remote(4 << 64, "x.txt", file(4))])
+            .unwrap();
+        assert!(transaction.take_events().is_empty());
+        transaction.commit().unwrap();
+        assert_eq!(tracker.trie().children_count(trie::CONFLICT).unwrap(), 1);
+    }
+
+    #[test]
+    fn remote_clock_collision_test() {
+        let remote = |child: u128, update_marker: u8| {
+            (
+                1,
+                Op {
+                    marker: 1 << 64,
+                    parent_target: OpTarget::Id(trie::ROOT),
+                    child_key: "x.txt".to_string().into(),
+                    child_target: OpTarget::Ref(TrieRef::from(child)),
+                    child_content: Some(Entity {
+                        type_marker: FileType::File.to_bytes().to_vec(),
+                        update_marker: vec![update_marker],
+                        ..Default::default()
+                    }),
+                },
+            )
+        };
+        let peer = |ops: Vec<(ActorId, Op<Clock, Entity>)>| {
+            let tracker = Tracker::init(MemoryDB::default()).unwrap();
+            for op in ops {
+                let mut transaction = tracker.start_transaction().unwrap();
+                transaction.apply_remote_ops(vec![op]).unwrap();
+                transaction.commit().unwrap();
+            }
+            tracker
+        };
+
+        // two writers with the same clock, applied in either order
+        let a = peer(vec![remote(101, 1), remote(102, 2)]);
+        let b = peer(vec![remote(102, 2), remote(101, 1)]);
+        assert_eq!(a.trie().root_hash().unwrap(), b.trie().root_hash().unwrap());
+        // the larger child ref wins the name
+        let trie = a.trie();
+        assert_eq!(
+            trie.get_id_by_path("/x.txt").unwrap(),
+            trie.get_id(TrieRef::from(102)).unwrap()
+        );
+    }
 }
@@ -0,0 +1,91 @@
+use std::io::{ErrorKind, Read, Write};
+
+use trie::Op;
+use utils::{Deserialize, Serialize};
+
+use super::{ActorId, Clock, Entity, Error, Result};
+
+const ACTOR_SIZE: usize = std::mem::size_of::<ActorId>();
+
+/// Write `op` as one frame of an op stream, the length of the frame in 4 bytes
+/// big endian, the actor of the op in 8 bytes big endian, then the op.
+pub fn write_op_frame(w: &mut impl Write, actor: ActorId, op: &Op<Clock, Entity>) -> Result<()> {
+    let bytes = op.to_bytes();
+    let len = u32::try_from(ACTOR_SIZE + bytes.len())
+        .map_err(|_| Error::InvalidOp("Op too large for a frame".to_string()))?;
+    w.write_all(&len.to_be_bytes())?;
+    w.write_all(&actor.to_be_bytes())?;
+    w.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Reads the ops of a stream written by
+/// [`Tracker::stream_ops_since`](super::Tracker::stream_ops_since) with their
+/// actors, to pass to
+/// [`TrackerTransaction::apply_remote_ops`](super::TrackerTransaction::apply_remote_ops).
+///
+/// The iteration ends at the end of the stream, a stream cut inside a frame
+/// yields an error and nothing after it.
+pub struct OpStreamReader<R> {
+    reader: R,
+    failed: bool,
+}
+
+impl<R: Read> OpStreamReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            failed: false,
+        }
+    }
+
+    /// `None` at the end of the stream, before the first byte of a frame.
+    fn read_frame(&mut self) -> Result<Option<(ActorId, Op<Clock, Entity>)>> {
+        let mut len = [0; 4];
+        let mut filled = 0;
+        while filled < len.len() {
+            match self.reader.read(&mut len[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(truncated()),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+        self.reader.read_exact(&mut bytes).map_err(|err| {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                truncated()
+            } else {
+                err.into()
+            }
+        })?;
+        if bytes.len() < ACTOR_SIZE {
+            return Err(Error::DecodeError("Op frame too short".to_string()));
+        }
+        let (actor, op) = bytes.split_at(ACTOR_SIZE);
+        let actor = ActorId::from_be_bytes(actor.try_into().unwrap());
+        Ok(Some((
+            actor,
+            Op::from_bytes(op).map_err(Error::DecodeError)?,
+        )))
+    }
+}
+
+impl<R: Read> Iterator for OpStreamReader<R> {
+    type Item = Result<(ActorId, Op<Clock, Entity>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let frame = self.read_frame();
+        self.failed = frame.is_err();
+        frame.transpose()
+    }
+}
+
+fn truncated() -> Error {
+    Error::DecodeError("Op stream ends inside a frame".to_string())
+}
@@ -0,0 +1,107 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::FileFullPath;
+
+/// Advisory locks on the subtrees of a tracker, see [`Tracker::lock_paths`].
+///
+/// The transactions of the database don't order the operations reading the
+/// disk, e.g. a folder read before a rename may be applied after the rename
+/// is committed. Operations holding the lock of a subtree from the read to
+/// the commit are serialized with the operations on overlapping subtrees,
+/// those on disjoint subtrees proceed.
+///
+/// [`Tracker::lock_paths`]: super::Tracker::lock_paths
+#[derive(Default)]
+pub struct PathLocks {
+    /// Locked subtrees of every guard.
+    locked: Mutex<Vec<FileFullPath>>,
+    released: Condvar,
+}
+
+impl PathLocks {
+    /// Lock the subtrees at `paths` at once, waiting while one of them
+    /// overlaps a locked subtree, i.e. one contains the other. The locks are
+    /// released when the guard is dropped.
+    ///
+    /// Not reentrant, locking a subtree overlapping one already held by the
+    /// same thread never returns.
+    pub fn lock<'a>(self: &Arc<Self>, paths: impl IntoIterator<Item = &'a str>) -> PathLockGuard {
+        let paths = paths
+            .into_iter()
+            .map(FileFullPath::parse)
+            .collect::<Vec<_>>();
+
+        let mut locked = self.locked.lock().unwrap();
+        while locked.iter().any(|held| {
+            paths
+                .iter()
+                .any(|path| held.contains(path) || path.contains(held))
+        }) {
+            locked = self.released.wait(locked).unwrap();
+        }
+        locked.extend(paths.iter().cloned());
+
+        PathLockGuard {
+            locks: self.clone(),
+            paths,
+        }
+    }
+}
+
+/// Guard of the subtrees locked by [`PathLocks::lock`].
+pub struct PathLockGuard {
+    locks: Arc<PathLocks>,
+    paths: Vec<FileFullPath>,
+}
+
+impl Drop for PathLockGuard {
+    fn drop(&mut self) {
+        let mut locked = self.locks.locked.lock().unwrap();
+        for path in self.paths.iter() {
+            if let Some(index) = locked.iter().position(|held| held == path) {
+                locked.swap_remove(index);
+            }
+        }
+        drop(locked);
+        self.locks.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::PathLocks;
+
+    #[test]
+    fn path_locks_test() {
+        let locks = Arc::new(PathLocks::default());
+
+        let guard = locks.lock(["/a/b"]);
+        // disjoint subtrees, the same folder twice in one guard is fine
+        drop(locks.lock(["/a/c", "/ab", "/a/c"]));
+
+        let released = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            for path in ["/a", "/a/b/c"] {
+                let (locks, released) = (&locks, &released);
+                scope.spawn(move || {
+                    let _guard = locks.lock([path]);
+                    assert!(released.load(Ordering::SeqCst));
+                });
+            }
+            std::thread::sleep(Duration::from_millis(50));
+            released.store(true, Ordering::SeqCst);
+            drop(guard);
+        });
+
+        // every lock is released
+        drop(locks.lock(["/"]));
+    }
+}
@@ -1,12 +1,17 @@
 pub mod store;
 
-use std::{cmp::Ordering, fmt::Display, marker::PhantomData};
+use std::{
+    cmp::Ordering, collections::BTreeSet, fmt::Display, marker::PhantomData, time::Duration,
+};
 
 use db::{DBLock, DBRead, DBTransaction, DBWrite, DB};
 use std::fmt::Debug;
-use store::{TrieStore, TrieStoreRead, TrieStoreTransaction};
+use store::{TrieStore, TrieStoreRead, TrieStoreTransaction, TrieVisitor, WalkControl};
 use thiserror::Error;
-use utils::{tree_stringify, Deserialize, Digestible, Serialize, Serializer};
+use utils::{
+    tree_stringify, tree_stringify_with_options, Blake3, Deserialize, Digest, Digestible,
+    FixedSize, Serialize, Serializer, TreeStringifyOptions,
+};
 use uuid::Uuid;
 
 use std::hash::Hash;
@@ -64,8 +69,11 @@ impl Debug for TrieId {
 }
 
 impl TrieId {
-    pub fn inc(&self) -> Self {
-        TrieId((u64::from_be_bytes(self.0) + 1).to_be_bytes())
+    /// The next id, `None` if the id space is exhausted.
+    pub fn inc(&self) -> Option<Self> {
+        u64::from_be_bytes(self.0)
+            .checked_add(1)
+            .map(|id| TrieId(id.to_be_bytes()))
     }
 
     pub fn id(&self) -> u64 {
@@ -87,6 +95,14 @@ impl Serialize for TrieId {
     }
 }
 
+impl FixedSize for TrieId {
+    const SIZE: usize = <[u8; 8]>::SIZE;
+
+    fn serialize_fixed(&self, buf: &mut [u8]) {
+        self.0.serialize_fixed(buf)
+    }
+}
+
 impl Deserialize for TrieId {
     fn deserialize(bytes: &[u8]) -> std::result::Result<(Self, &[u8]), String> {
         let (id, rest) = <[u8; 8]>::deserialize(bytes)?;
@@ -112,15 +128,19 @@ impl TrieKey {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    pub fn as_key_ref(&self) -> TrieKeyRef<'_> {
+        TrieKeyRef(&self.0)
+    }
 }
 
 impl Serialize for TrieKey {
     fn serialize(&self, serializer: Serializer) -> Serializer {
-        self.0.serialize(serializer)
+        self.as_key_ref().serialize(serializer)
     }
 
     fn byte_size(&self) -> Option<usize> {
-        self.0.byte_size()
+        self.as_key_ref().byte_size()
     }
 }
 
@@ -143,6 +163,57 @@ impl Display for TrieKey {
     }
 }
 
+/// Borrowed [`TrieKey`], for looking up children without allocating a
+/// `String`. Encodes to the same bytes as the owned key.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct TrieKeyRef<'a>(pub &'a str);
+
+impl<'a> TrieKeyRef<'a> {
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0.as_bytes()
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+}
+
+impl Serialize for TrieKeyRef<'_> {
+    fn serialize(&self, mut serializer: Serializer) -> Serializer {
+        serializer.extend_from_slice(&(self.0.len() as u32).to_be_bytes());
+        serializer.extend_from_slice(self.0.as_bytes());
+        serializer
+    }
+
+    fn byte_size(&self) -> Option<usize> {
+        Some(self.0.len() + std::mem::size_of::<u32>())
+    }
+}
+
+impl<'a> From<&'a TrieKey> for TrieKeyRef<'a> {
+    fn from(value: &'a TrieKey) -> Self {
+        value.as_key_ref()
+    }
+}
+
+impl<'a> From<&'a str> for TrieKeyRef<'a> {
+    fn from(value: &'a str) -> Self {
+        Self(value)
+    }
+}
+
+impl From<TrieKeyRef<'_>> for TrieKey {
+    fn from(value: TrieKeyRef<'_>) -> Self {
+        Self(value.0.to_owned())
+    }
+}
+
+impl Display for TrieKeyRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.0, f)
+    }
+}
+
 /// The reference of the node, which is used to determine the node of the operation during the distributed operation
 #[derive(Debug, Default, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub struct TrieRef(pub [u8; 16]);
@@ -167,6 +238,14 @@ impl Serialize for TrieRef {
     }
 }
 
+impl FixedSize for TrieRef {
+    const SIZE: usize = <[u8; 16]>::SIZE;
+
+    fn serialize_fixed(&self, buf: &mut [u8]) {
+        self.0.serialize_fixed(buf)
+    }
+}
+
 impl Deserialize for TrieRef {
     fn deserialize(bytes: &[u8]) -> std::result::Result<(Self, &[u8]), String> {
         let (r, rest) = <_>::deserialize(bytes)?;
@@ -201,6 +280,33 @@ impl TrieHash {
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
+
+    /// Hash of a node from the digest of its content and the hashes of its
+    /// children, in any order. Peers compare these hashes, so the function
+    /// must never change. The children are sorted by the bytes of their key,
+    /// then the hash is the blake3 of:
+    ///
+    /// ```text
+    /// content_digest
+    /// for each child: key length (u64 big endian) | key bytes | child hash
+    /// ```
+    pub fn combine<'a>(
+        content_digest: &[u8; 32],
+        children: impl IntoIterator<Item = (TrieKeyRef<'a>, TrieHash)>,
+    ) -> TrieHash {
+        let mut children = children.into_iter().collect::<Vec<_>>();
+        children.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+
+        let mut hash = Blake3::new();
+        hash.update(content_digest);
+        for (key, child) in children {
+            hash.update((key.as_bytes().len() as u64).to_be_bytes());
+            hash.update(key.as_bytes());
+            hash.update(child);
+        }
+
+        TrieHash(hash.finish())
+    }
 }
 
 impl Serialize for TrieHash {
@@ -213,6 +319,14 @@ impl Serialize for TrieHash {
     }
 }
 
+impl FixedSize for TrieHash {
+    const SIZE: usize = <[u8; 32]>::SIZE;
+
+    fn serialize_fixed(&self, buf: &mut [u8]) {
+        self.0.serialize_fixed(buf)
+    }
+}
+
 impl Deserialize for TrieHash {
     fn deserialize(bytes: &[u8]) -> std::result::Result<(Self, &[u8]), String> {
         let (r, rest) = <_>::deserialize(bytes)?;
@@ -276,6 +390,54 @@ impl<C: TrieContent> Deserialize for TrieNode<C> {
     }
 }
 
+/// A detached copy of a subtree, which can be sent to another trie and attached
+/// with [`TrieTransaction::graft`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SerializedSubtree<C: TrieContent> {
+    pub key: TrieKey,
+    pub content: C,
+    pub refs: Vec<TrieRef>,
+    pub children: Vec<SerializedSubtree<C>>,
+}
+
+impl<C: TrieContent> Serialize for SerializedSubtree<C> {
+    fn serialize(&self, mut serializer: Serializer) -> Serializer {
+        serializer = self.key.serialize(serializer);
+        serializer = self.content.serialize(serializer);
+        serializer = self.refs.serialize(serializer);
+        serializer = self.children.serialize(serializer);
+        serializer
+    }
+
+    fn byte_size(&self) -> Option<usize> {
+        Some(
+            self.key.byte_size()?
+                + self.content.byte_size()?
+                + self.refs.byte_size()?
+                + self.children.byte_size()?,
+        )
+    }
+}
+
+impl<C: TrieContent> Deserialize for SerializedSubtree<C> {
+    fn deserialize(bytes: &[u8]) -> std::result::Result<(Self, &[u8]), String> {
+        let (key, bytes) = TrieKey::deserialize(bytes)?;
+        let (content, bytes) = C::deserialize(bytes)?;
+        let (refs, bytes) = <_>::deserialize(bytes)?;
+        let (children, bytes) = <_>::deserialize(bytes)?;
+
+        Ok((
+            Self {
+                key,
+                content,
+                refs,
+                children,
+            },
+            bytes,
+        ))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Do<C: TrieContent> {
     Ref(TrieRef, Option<TrieId>),
@@ -383,6 +545,9 @@ pub enum OpTarget {
     Ref(TrieRef),
     Id(TrieId),
     NewId,
+    /// The node at the path when the op is done, for ops written by hand. The
+    /// path is resolved again each time the op is redone.
+    Path(String),
 }
 
 impl Debug for OpTarget {
@@ -391,6 +556,7 @@ impl Debug for OpTarget {
             Self::Ref(r) => f.write_fmt(format_args!("&{:?}", r)),
             Self::Id(id) => f.write_fmt(format_args!("#{:?}", id)),
             Self::NewId => write!(f, "*"),
+            Self::Path(path) => f.write_fmt(format_args!("{:?}", path)),
         }
     }
 }
@@ -419,6 +585,10 @@ impl Serialize for OpTarget {
                 serializer = i.serialize(serializer);
             }
             OpTarget::NewId => serializer.push(b'n'),
+            OpTarget::Path(path) => {
+                serializer.push(b'p');
+                serializer = path.serialize(serializer);
+            }
         }
 
         serializer
@@ -429,6 +599,7 @@ impl Serialize for OpTarget {
             OpTarget::Ref(r) => 1 + r.byte_size()?,
             OpTarget::Id(i) => 1 + i.byte_size()?,
             OpTarget::NewId => 1,
+            OpTarget::Path(path) => 1 + path.byte_size()?,
         })
     }
 }
@@ -445,6 +616,10 @@ impl Deserialize for OpTarget {
                 Ok((Self::Id(id), bytes))
             }
             b'n' => Ok((Self::NewId, &bytes[1..])),
+            b'p' => {
+                let (path, bytes) = <_>::deserialize(&bytes[1..])?;
+                Ok((Self::Path(path), bytes))
+            }
             _ => Err("Failed to decode OpTarget".to_string()),
         }
     }
@@ -459,6 +634,25 @@ pub struct Op<M: TrieMarker, C: TrieContent> {
     pub child_content: Option<C>,
 }
 
+impl<M: TrieMarker, C: TrieContent> Op<M, C> {
+    /// Deterministic order of two ops with the same marker, by their child
+    /// ref, ops whose child is not targeted by ref come first. Ops with the
+    /// same child ref are ordered by their serialized bytes, so the order is
+    /// total and every replica orders the ops the same way.
+    pub fn cmp_tiebreak(&self, other: &Self) -> Ordering {
+        self.child_ref()
+            .cmp(&other.child_ref())
+            .then_with(|| self.to_bytes().as_ref().cmp(other.to_bytes().as_ref()))
+    }
+
+    fn child_ref(&self) -> Option<&TrieRef> {
+        match &self.child_target {
+            OpTarget::Ref(child_ref) => Some(child_ref),
+            _ => None,
+        }
+    }
+}
+
 impl<M: TrieMarker, C: TrieContent> Serialize for Op<M, C> {
     fn serialize(&self, mut serializer: Serializer) -> Serializer {
         serializer = self.marker.serialize(serializer);
@@ -520,6 +714,35 @@ impl<M: TrieMarker + Debug, C: TrieContent + Debug> Debug for Op<M, C> {
     }
 }
 
+/// Collapse consecutive ops moving the same node into the last one, keeping
+/// its marker and the content of the last op that sets one.
+///
+/// The compacted ops build the same tree as long as none of the collapsed
+/// moves would be skipped as a cycle, which holds for the ops of a single
+/// tracker. The markers of the collapsed ops are not logged.
+pub fn compact_ops<M: TrieMarker, C: TrieContent>(ops: Vec<Op<M, C>>) -> Vec<Op<M, C>> {
+    let mut compacted: Vec<Op<M, C>> = Vec::with_capacity(ops.len());
+
+    for op in ops {
+        match compacted.last_mut() {
+            // the same path can be another node after the move
+            Some(last)
+                if last.child_target == op.child_target
+                    && !matches!(op.child_target, OpTarget::NewId | OpTarget::Path(_)) =>
+            {
+                let child_content = op.child_content.or_else(|| last.child_content.take());
+                *last = Op {
+                    child_content,
+                    ..op
+                };
+            }
+            _ => compacted.push(op),
+        }
+    }
+
+    compacted
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct LogOp<M: TrieMarker, C: TrieContent> {
     pub op: Op<M, C>,
@@ -554,12 +777,54 @@ pub struct Trie<M: TrieMarker, C: TrieContent, DBImpl> {
     c: PhantomData<C>,
 }
 
-impl<M: TrieMarker, C: TrieContent + Display, DBImpl: DBRead> Display for Trie<M, C, DBImpl> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<M: TrieMarker, C: TrieContent + Display, DBImpl: DBRead> Trie<M, C, DBImpl> {
+    /// Render the tree like [`Display`], with depth / entries limits or ascii
+    /// characters.
+    pub fn to_string_with_options(&self, options: &TreeStringifyOptions) -> String {
+        rendered(self.read_view().and_then(|trie| trie.render(options)))
+    }
+
+    /// Render the whole forest, including the `CONFLICT` and `RECYCLE` roots
+    /// hidden by [`Display`], useful to find out where a node went.
+    pub fn dbg_full(&self) -> String {
+        rendered(self.read_view().and_then(|trie| trie.render_full()))
+    }
+
+    /// Render the whole forest as a Graphviz digraph, one cluster for each of
+    /// `ROOT`, `CONFLICT` and `RECYCLE`. Solid edges go from a parent to its
+    /// children and dashed edges from a ref to its node. Meant for debugging
+    /// small trees, e.g. `dot -Tsvg`.
+    pub fn to_dot(&self) -> String {
+        rendered(self.read_view().and_then(|trie| trie.render_dot()))
+    }
+
+    /// Trie over a consistent view of the database, see
+    /// [`DBRead::read_view`], a snapshot unless the trie already reads one or
+    /// a transaction.
+    fn read_view(&self) -> Result<Trie<M, C, DBImpl::ReadView<'_>>> {
+        Ok(Trie {
+            store: self.store.read_view()?,
+            m: Default::default(),
+            c: Default::default(),
+        })
+    }
+}
+
+/// The rendering, or a placeholder if the database failed to read, so a
+/// broken database still prints.
+fn rendered(rendering: Result<String>) -> String {
+    rendering.unwrap_or_else(|err| format!("<unavailable: {err}>"))
+}
+
+// the renderings read the tree from a consistent view, see `Trie::read_view`,
+// so a concurrent write never shows up half done, e.g. a child whose node is
+// already moved.
+impl<M: TrieMarker, C: TrieContent + Display, DBImpl: DBRead> Trie<M, C, DBImpl> {
+    fn render(&self, options: &TreeStringifyOptions) -> Result<String> {
         let mut items = vec![];
-        self.dbg_itemization(ROOT, "", &mut items);
+        self.dbg_itemization(ROOT, "", &mut items)?;
 
-        f.write_str(&tree_stringify(
+        Ok(tree_stringify_with_options(
             items.iter().map(|(path, _, node)| {
                 (
                     path.as_ref(),
@@ -571,16 +836,80 @@ impl<M: TrieMarker, C: TrieContent + Display, DBImpl: DBRead> Display for Trie<M
                 )
             }),
             "/",
+            options,
         ))
     }
-}
 
-impl<M: TrieMarker, C: TrieContent + Display, DBImpl: DBRead> Debug for Trie<M, C, DBImpl> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn render_full(&self) -> Result<String> {
+        let mut items = vec![];
+        for (name, root) in [("ROOT", ROOT), ("CONFLICT", CONFLICT), ("RECYCLE", RECYCLE)] {
+            items.push((format!("/{}", name), root, self.store.get_ensure(root)?));
+            for (_, id) in self.store.get_children(root)? {
+                self.dbg_itemization(id, &format!("/{}", name), &mut items)?;
+            }
+        }
+
+        Ok(tree_stringify(
+            items.iter().map(|(path, id, node)| {
+                (
+                    path.as_ref(),
+                    if id.id() < 10 {
+                        "".to_string()
+                    } else {
+                        format!("[{}] #{}", node.content, id)
+                    },
+                )
+            }),
+            "/",
+        ))
+    }
+
+    fn render_dot(&self) -> Result<String> {
+        let mut dot = String::from("digraph trie {\n");
+        let mut edges = String::new();
+        for (name, root) in [("ROOT", ROOT), ("CONFLICT", CONFLICT), ("RECYCLE", RECYCLE)] {
+            let mut items = vec![(format!("/{}", name), root, self.store.get_ensure(root)?)];
+            for (_, id) in self.store.get_children(root)? {
+                self.dbg_itemization(id, "", &mut items)?;
+            }
+
+            dot.push_str(&format!(
+                "  subgraph cluster_{} {{\n    label=\"{}\";\n",
+                name, name
+            ));
+            for (_, id, node) in items.iter() {
+                let label = if *id == root {
+                    name.to_string()
+                } else if node.content.to_string().is_empty() {
+                    format!("#{} {}", id, node.key)
+                } else {
+                    format!("#{} {}\n[{}]", id, node.key, node.content)
+                };
+                dot.push_str(&format!(
+                    "    \"{}\" [label=\"{}\"];\n",
+                    id,
+                    dot_escape(&label)
+                ));
+                if *id != root {
+                    edges.push_str(&format!("  \"{}\" -> \"{}\";\n", node.parent, id));
+                }
+                for r in self.store.get_refs(*id)?.unwrap_or_default() {
+                    dot.push_str(&format!("    \"{}\" [shape=box];\n", r));
+                    edges.push_str(&format!("  \"{}\" -> \"{}\" [style=dashed];\n", r, id));
+                }
+            }
+            dot.push_str("  }\n");
+        }
+        dot.push_str(&edges);
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    fn render_debug(&self) -> Result<String> {
         let mut items = vec![];
-        self.dbg_itemization(ROOT, "", &mut items);
+        self.dbg_itemization(ROOT, "", &mut items)?;
 
-        f.write_str(&tree_stringify(
+        Ok(tree_stringify(
             items
                 .iter()
                 .map(|(path, id, node)| (path.as_ref(), format!("[{}] #{}", node.content, id))),
@@ -589,6 +918,31 @@ impl<M: TrieMarker, C: TrieContent + Display, DBImpl: DBRead> Debug for Trie<M,
     }
 }
 
+/// Escape `label` for a quoted string of the dot language.
+fn dot_escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl<M: TrieMarker, C: TrieContent + Display, DBImpl: DBRead> Display for Trie<M, C, DBImpl> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&rendered(
+            self.read_view()
+                .and_then(|trie| trie.render(&Default::default())),
+        ))
+    }
+}
+
+impl<M: TrieMarker, C: TrieContent + Display, DBImpl: DBRead> Debug for Trie<M, C, DBImpl> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&rendered(
+            self.read_view().and_then(|trie| trie.render_debug()),
+        ))
+    }
+}
+
 impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead> Trie<M, C, DBImpl> {
     pub fn from_db(db: DBImpl) -> Self {
         Trie {
@@ -598,23 +952,134 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead> Trie<M, C, DBImpl> {
         }
     }
 
+    /// Hash of the tree under `ROOT`, two peers with identical trees have the
+    /// same root hash. The hash is computed on each call, in `O(n)`.
+    pub fn root_hash(&self) -> Result<TrieHash> {
+        self.store.get_subtree_hash(ROOT)
+    }
+
+    /// Same as [`Trie::root_hash`], including the `CONFLICT` and `RECYCLE`
+    /// roots, which are local to each peer.
+    pub fn root_hash_with_local(&self) -> Result<TrieHash> {
+        let mut hash = Blake3::new();
+        for root in [ROOT, CONFLICT, RECYCLE] {
+            hash.update(self.store.get_subtree_hash(root)?);
+        }
+        Ok(TrieHash(hash.finish()))
+    }
+
+    /// Whether the tree under `ROOT` has the root hash of another peer, the
+    /// trees only need to be compared further if not. Failing to hash the
+    /// tree counts as a difference.
+    pub fn compare_roots(&self, other_hash: &TrieHash) -> bool {
+        self.root_hash()
+            .map(|hash| &hash == other_hash)
+            .unwrap_or(false)
+    }
+
+    /// Build the tree again in `db` from the log alone, by applying the logged
+    /// ops in marker order on an empty trie. The ops are deterministic, so the
+    /// trees under `ROOT` and `RECYCLE` must come out the same, otherwise
+    /// [`Error::TreeBroken`] is returned with both hashes.
+    ///
+    /// Ops with [`OpTarget::Id`] are replayed as is and only match when the
+    /// ids were minted in the same order, and nodes added without an op, like
+    /// the descendants of [`TrieTransaction::graft`], are not in the log, the
+    /// log can't reproduce such trees.
+    pub fn rebuild_from_log<T: DB>(&self, db: T) -> Result<Trie<M, C, T>> {
+        // the log is iterated from the newest op
+        let logs = self.store.iter_log()?.collect::<Result<Vec<_>>>()?;
+
+        let mut rebuilt = Trie::init(db)?;
+        let mut writer = rebuilt.write()?;
+        for log in logs.into_iter().rev() {
+            writer.apply_one(log.op)?;
+        }
+        writer.commit()?;
+
+        for root in [ROOT, RECYCLE] {
+            let rebuilt_hash = rebuilt.store.get_subtree_hash(root)?;
+            let stored_hash = self.store.get_subtree_hash(root)?;
+            if rebuilt_hash != stored_hash {
+                return Err(Error::TreeBroken(format!(
+                    "the tree rebuilt from the log differs at {root}, {rebuilt_hash:?} != {stored_hash:?}"
+                )));
+            }
+        }
+
+        Ok(rebuilt)
+    }
+
+    /// Whether there is a node at `path` under `ROOT`, nodes moved to
+    /// `CONFLICT` or `RECYCLE` don't exist.
+    pub fn path_exists(&self, path: &str) -> Result<bool> {
+        Ok(self.get_live_id_by_path(path)?.is_some())
+    }
+
+    /// Content of the node at `path`, `None` if there is no node at `path`
+    /// under `ROOT`, see [`Trie::path_exists`].
+    pub fn metadata_for_path(&self, path: &str) -> Result<Option<C>> {
+        self.get_live_id_by_path(path)?
+            .map(|id| Ok(self.store.get_ensure(id)?.content))
+            .transpose()
+    }
+
+    fn get_live_id_by_path(&self, path: &str) -> Result<Option<TrieId>> {
+        Ok(self
+            .store
+            .get_id_by_path(path)?
+            .filter(|id| *id != CONFLICT && *id != RECYCLE))
+    }
+
     fn dbg_itemization(
         &self,
         root: TrieId,
         prefix: &str,
         base: &mut Vec<(String, TrieId, TrieNode<C>)>,
-    ) {
-        let node = self.store.get_ensure(root).unwrap();
-        let children = self.store.get_children(root).unwrap();
-        let path = format!("{}/{}", prefix, node.key);
-        base.push((path.clone(), root, node.clone()));
+    ) -> Result<()> {
+        self.store.walk(
+            root,
+            &mut Itemization {
+                paths: vec![prefix.to_string()],
+                items: base,
+            },
+        )
+    }
+}
 
-        for (_, id) in children {
-            self.dbg_itemization(id, &path, base)
-        }
+/// Items of [`Trie::dbg_itemization`], the full path of each node in
+/// pre-order.
+struct Itemization<'a, C: TrieContent> {
+    /// Path of the entered nodes, starting with the prefix.
+    paths: Vec<String>,
+    items: &'a mut Vec<(String, TrieId, TrieNode<C>)>,
+}
+
+impl<C: TrieContent> TrieVisitor<C> for Itemization<'_, C> {
+    fn enter(&mut self, id: TrieId, node: &TrieNode<C>) -> WalkControl {
+        let path = format!("{}/{}", self.paths.last().unwrap(), node.key);
+        self.items.push((path.clone(), id, node.clone()));
+        self.paths.push(path);
+        WalkControl::Descend
+    }
+
+    fn leave(&mut self, _id: TrieId, _node: &TrieNode<C>) {
+        self.paths.pop();
     }
 }
+
 impl<M: TrieMarker, C: TrieContent, DBImpl: DB> Trie<M, C, DBImpl> {
+    /// Read-only trie over a snapshot of the database, see [`DB::snapshot`].
+    /// The writes committed after this call are not seen, so the nodes read
+    /// from it are always consistent.
+    pub fn snapshot(&self) -> Result<Trie<M, C, DBImpl::Snapshot<'_>>> {
+        Ok(Trie {
+            store: self.store.snapshot()?,
+            m: Default::default(),
+            c: Default::default(),
+        })
+    }
+
     pub fn init(db: DBImpl) -> Result<Self> {
         Ok(Trie {
             store: TrieStore::init(db)?,
@@ -626,11 +1091,78 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DB> Trie<M, C, DBImpl> {
     pub fn write(&mut self) -> Result<TrieTransaction<M, C, DBImpl::Transaction<'_>>> {
         Ok(TrieTransaction {
             transaction: self.store.start_transaction()?,
+            collision_policy: Default::default(),
+            external: false,
+            applied: None,
+            replayable: true,
+            #[cfg(feature = "tracing")]
+            conflicts: 0,
         })
     }
+
+    /// Run `f` in a new transaction and commit it, retrying when the commit
+    /// fails with [`db::Error::Conflict`], which optimistic transactions return
+    /// under contention.
+    ///
+    /// On a conflict the ops applied by `f` through [`TrieTransaction::apply`]
+    /// and [`TrieTransaction::apply_one`] are replayed on a fresh transaction,
+    /// with exponential backoff in between, until `max_attempts` commits have
+    /// failed. Ops are deterministic so the replay gives the same tree.
+    ///
+    /// The writes which are not ops, [`TrieTransaction::graft`],
+    /// [`TrieTransaction::undo_last`], [`TrieTransaction::gc_refs`] and
+    /// [`TrieTransaction::set_ref`], can't be replayed, the conflict is
+    /// returned instead if `f` did any of them. Writes done directly on the
+    /// store are not tracked and must not be used in `f`.
+    pub fn commit_with_retry<'a, T>(
+        &'a mut self,
+        max_attempts: usize,
+        f: impl FnOnce(&mut TrieTransaction<M, C, DBImpl::Transaction<'a>>) -> Result<T>,
+    ) -> Result<T> {
+        let store = &self.store;
+        let write = || -> Result<TrieTransaction<M, C, DBImpl::Transaction<'a>>> {
+            Ok(TrieTransaction {
+                transaction: store.start_transaction()?,
+                collision_policy: Default::default(),
+                external: false,
+                applied: None,
+                replayable: true,
+                #[cfg(feature = "tracing")]
+                conflicts: 0,
+            })
+        };
+
+        let mut transaction = write()?;
+        transaction.applied = Some(Vec::new());
+        let value = f(&mut transaction)?;
+        let collision_policy = transaction.collision_policy;
+        let external = transaction.external;
+        let applied = transaction.applied.take().unwrap_or_default();
+        let replayable = transaction.replayable;
+        let mut result = transaction.commit();
+
+        let mut backoff = Duration::from_millis(1);
+        for _ in 1..max_attempts {
+            if !replayable || !matches!(result, Err(Error::DBError(db::Error::Conflict))) {
+                break;
+            }
+            std::thread::sleep(backoff);
+            backoff *= 2;
+
+            let mut transaction = write()?
+                .with_collision_policy(collision_policy)
+                .with_external_ops(external);
+            for ops in applied.iter() {
+                transaction.apply(ops.clone())?;
+            }
+            result = transaction.commit();
+        }
+
+        result.map(|_| value)
+    }
 }
 
-impl<M: TrieMarker, C: TrieContent, DBImpl: DB> std::ops::Deref for Trie<M, C, DBImpl> {
+impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead> std::ops::Deref for Trie<M, C, DBImpl> {
     type Target = TrieStore<DBImpl, M, C>;
 
     fn deref(&self) -> &Self::Target {
@@ -638,8 +1170,39 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DB> std::ops::Deref for Trie<M, C, D
     }
 }
 
+/// What to do when two ops have the same marker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MarkerCollisionPolicy {
+    /// Reject the op with [`Error::InvalidOp`].
+    #[default]
+    Strict,
+    /// Order the colliding ops by their serialized bytes. This is a total
+    /// order which does not depend on the arrival order, so replicas which
+    /// applied the same set of ops still converge to the same tree. Ops
+    /// identical to an applied one are ignored.
+    Lenient,
+    /// Order the colliding ops by their child ref, see [`Op::cmp_tiebreak`].
+    /// Refs are globally unique, so every replica picks the same winner of a
+    /// conflict between the colliding ops, the op ordered last. Ops identical
+    /// to an applied one are ignored.
+    ChildRef,
+}
+
 pub struct TrieTransaction<M: TrieMarker, C: TrieContent, DBImpl: DBRead + DBWrite + DBLock> {
     transaction: TrieStoreTransaction<DBImpl, M, C>,
+    collision_policy: MarkerCollisionPolicy,
+    /// Reject the applied ops moving nodes into [`CONFLICT`] or [`RECYCLE`].
+    external: bool,
+    /// Ops applied so far, only recorded by [`Trie::commit_with_retry`] for
+    /// the replay.
+    applied: Option<Vec<Vec<Op<M, C>>>>,
+    /// Cleared by the writes which are not ops, e.g. [`TrieTransaction::graft`],
+    /// [`Trie::commit_with_retry`] can't replay the transaction then.
+    replayable: bool,
+    /// Conflicts resolved by [`TrieTransaction::do_op`], reported by the
+    /// `tracing` events.
+    #[cfg(feature = "tracing")]
+    conflicts: usize,
 }
 
 impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead + DBWrite + DBLock>
@@ -648,6 +1211,62 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead + DBWrite + DBLock>
     pub fn from_db(db: DBImpl) -> Self {
         TrieTransaction {
             transaction: TrieStoreTransaction::from_db(db),
+            collision_policy: Default::default(),
+            external: false,
+            applied: None,
+            replayable: true,
+            #[cfg(feature = "tracing")]
+            conflicts: 0,
+        }
+    }
+
+    pub fn with_collision_policy(mut self, policy: MarkerCollisionPolicy) -> Self {
+        self.collision_policy = policy;
+        self
+    }
+
+    /// Mark the applied ops as coming from the application, e.g. received
+    /// during sync. [`CONFLICT`] and [`RECYCLE`] are internal, ops moving a
+    /// node into them are rejected with [`Error::InvalidOp`]. Ops already in
+    /// the log are still redone.
+    pub fn with_external_ops(mut self, external: bool) -> Self {
+        self.external = external;
+        self
+    }
+
+    fn check_external_op(&self, op: &Op<M, C>) -> Result<()> {
+        if !self.external {
+            return Ok(());
+        }
+
+        let parent_id = match &op.parent_target {
+            OpTarget::Ref(parent_ref) => self.transaction.get_id(parent_ref.to_owned())?,
+            OpTarget::Id(id) => Some(*id),
+            OpTarget::NewId => None,
+            OpTarget::Path(path) => self.transaction.get_id_by_path(path)?,
+        };
+        if let Some(parent_id) = parent_id.filter(|id| *id == CONFLICT || *id == RECYCLE) {
+            return Err(Error::InvalidOp(format!(
+                "Could not move a node into the internal node {parent_id}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn compare_ops(&self, a: &Op<M, C>, b: &Op<M, C>) -> Result<Ordering> {
+        match a.marker.partial_cmp(&b.marker) {
+            Some(Ordering::Less) => Ok(Ordering::Less),
+            Some(Ordering::Greater) => Ok(Ordering::Greater),
+            None | Some(Ordering::Equal) => match self.collision_policy {
+                MarkerCollisionPolicy::Strict => Err(Error::InvalidOp(
+                    "The marker of the operation has duplicates. Every op must have a unique timestamp.".to_string(),
+                )),
+                MarkerCollisionPolicy::Lenient => {
+                    Ok(a.to_bytes().as_ref().cmp(b.to_bytes().as_ref()))
+                }
+                MarkerCollisionPolicy::ChildRef => Ok(a.cmp_tiebreak(b)),
+            },
         }
     }
 
@@ -660,6 +1279,12 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead + DBWrite + DBLock>
         Ok(old)
     }
 
+    fn get_id_by_path_ensure(&self, path: &str) -> Result<TrieId> {
+        self.transaction
+            .get_id_by_path(path)?
+            .ok_or_else(|| Error::InvalidOp(format!("Path {path} not found")))
+    }
+
     fn do_op(&mut self, op: Op<M, C>) -> Result<LogOp<M, C>> {
         let mut dos: Vec<Do<C>> = Vec::with_capacity(3);
         let child_id = match &op.child_target {
@@ -674,6 +1299,7 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead + DBWrite + DBLock>
             }
             OpTarget::Id(id) => *id,
             OpTarget::NewId => self.transaction.create_id()?,
+            OpTarget::Path(path) => self.get_id_by_path_ensure(path)?,
         };
         let parent_id = match &op.parent_target {
             OpTarget::Ref(parent_ref) => {
@@ -692,6 +1318,7 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead + DBWrite + DBLock>
                     "Parent target could not be new id".to_string(),
                 ));
             }
+            OpTarget::Path(path) => self.get_id_by_path_ensure(path)?,
         };
 
         // ensures no cycles are introduced.
@@ -699,12 +1326,26 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead + DBWrite + DBLock>
             if child_id != parent_id && !self.transaction.is_ancestor(parent_id, child_id)? {
                 if let Some(conflict_node_id) = self
                     .transaction
-                    .get_child(parent_id, op.child_key.to_owned())?
+                    .get_child_ref(parent_id, op.child_key.as_key_ref())?
                 {
                     if conflict_node_id != child_id {
                         let conflict_is_empty =
                             self.transaction.get_children(conflict_node_id)?.is_empty();
                         let new_is_empty = self.transaction.get_children(child_id)?.is_empty();
+                        #[cfg(feature = "tracing")]
+                        {
+                            self.conflicts += 1;
+                            tracing::debug!(
+                                parent = %parent_id,
+                                key = %op.child_key,
+                                kept = %if !conflict_is_empty && new_is_empty {
+                                    conflict_node_id
+                                } else {
+                                    child_id
+                                },
+                                "trie conflict"
+                            );
+                        }
                         if !conflict_is_empty && new_is_empty {
                             // new is empty, keep before
                             if let OpTarget::Ref(ref child_ref) = op.child_target {
@@ -799,46 +1440,276 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead + DBWrite + DBLock>
         Ok(log.op)
     }
 
+    /// Apply a single op. If the op is newer than the latest log, which is the
+    /// common case for a single writer, the op is done directly without undo
+    /// and redo. Otherwise fallback to [`TrieTransaction::apply`].
+    pub fn apply_one(&mut self, op: Op<M, C>) -> Result<&mut Self> {
+        self.check_external_op(&op)?;
+        if let Some(applied) = &mut self.applied {
+            applied.push(vec![op.clone()]);
+        }
+
+        self.apply_one_op(op)?;
+        Ok(self)
+    }
+
+    fn apply_one_op(&mut self, op: Op<M, C>) -> Result<()> {
+        if let Some(last) = self.transaction.last_log()? {
+            if self.compare_ops(&op, &last.op)? != Ordering::Greater {
+                return self.apply_ops(vec![op]);
+            }
+        }
+
+        let log_op = self.do_op(op)?;
+        self.transaction.push_log(log_op)?;
+        Ok(())
+    }
+
+    /// Attach `subtree` under `parent` with a single log, returns the id of
+    /// the subtree root.
+    ///
+    /// New ids are minted for every node and the descendants are attached to
+    /// the subtree root directly, only moving the root under `parent` is
+    /// logged with `marker`. Undo and redo of the log move the subtree as a
+    /// whole. Fails if the key of the root is taken under `parent`, sibling
+    /// keys collide in the subtree, or a ref of the subtree is already used.
+    pub fn graft(
+        &mut self,
+        marker: M,
+        parent: TrieId,
+        subtree: SerializedSubtree<C>,
+    ) -> Result<TrieId> {
+        if self.transaction.get(parent)?.is_none() {
+            return Err(Error::InvalidOp("Graft parent not found".to_string()));
+        }
+        if self
+            .transaction
+            .get_child_ref(parent, subtree.key.as_key_ref())?
+            .is_some()
+        {
+            return Err(Error::InvalidOp(format!(
+                "Graft key {} already exists",
+                subtree.key
+            )));
+        }
+        self.check_subtree(&subtree, &mut Default::default())?;
+
+        self.replayable = false;
+        let root = self.transaction.create_id()?;
+        for r in subtree.refs {
+            self.transaction.set_ref(r, Some(root))?;
+        }
+        self.graft_children(root, subtree.children)?;
+
+        self.apply_one_op(Op {
+            marker,
+            parent_target: OpTarget::Id(parent),
+            child_key: subtree.key,
+            child_target: OpTarget::Id(root),
+            child_content: Some(subtree.content),
+        })?;
+
+        Ok(root)
+    }
+
+    fn check_subtree(
+        &self,
+        subtree: &SerializedSubtree<C>,
+        refs: &mut BTreeSet<TrieRef>,
+    ) -> Result<()> {
+        for r in subtree.refs.iter() {
+            if !refs.insert(r.to_owned()) || self.transaction.get_id(r.to_owned())?.is_some() {
+                return Err(Error::InvalidOp(format!("Graft ref {} already exists", r)));
+            }
+        }
+
+        let mut keys = BTreeSet::new();
+        for child in subtree.children.iter() {
+            if !keys.insert(&child.key) {
+                return Err(Error::InvalidOp(format!(
+                    "Graft key {} collides in the subtree",
+                    child.key
+                )));
+            }
+            self.check_subtree(child, refs)?;
+        }
+
+        Ok(())
+    }
+
+    fn graft_children(
+        &mut self,
+        parent: TrieId,
+        children: Vec<SerializedSubtree<C>>,
+    ) -> Result<()> {
+        for child in children {
+            let id = self.transaction.create_id()?;
+            self.move_node(id, Some((parent, child.key, Some(child.content))))?;
+            for r in child.refs {
+                self.transaction.set_ref(r, Some(id))?;
+            }
+            self.graft_children(id, child.children)?;
+        }
+
+        Ok(())
+    }
+
+    /// Undo the latest op in the log and remove it from the log, e.g. to
+    /// restore a node moved to [`RECYCLE`] at its original location. Returns
+    /// the undone op, `None` if the log is empty.
+    ///
+    /// The undo can't be replayed by [`Trie::commit_with_retry`], and the op
+    /// is applied again if it is received later.
+    pub fn undo_last(&mut self) -> Result<Option<Op<M, C>>> {
+        self.replayable = false;
+        match self.transaction.pop_log()? {
+            Some(log) => Ok(Some(self.undo_op(log)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove the refs of ids whose node is gone, e.g. purged from `RECYCLE`.
+    /// The reserved refs are never removed. Returns the number of refs
+    /// removed.
+    pub fn gc_refs(&mut self) -> Result<usize> {
+        self.replayable = false;
+        self.transaction.gc_refs()
+    }
+
+    /// Point `r` at `id`, or remove it if `None`, returns the id it pointed
+    /// at. Can't be replayed by [`Trie::commit_with_retry`].
+    pub fn set_ref(&mut self, r: TrieRef, id: Option<TrieId>) -> Result<Option<TrieId>> {
+        self.replayable = false;
+        self.transaction.set_ref(r, id)
+    }
+
     pub fn apply(&mut self, ops: Vec<Op<M, C>>) -> Result<&mut Self> {
+        #[cfg(feature = "tracing")]
+        let (_span, start, conflicts) = (
+            tracing::debug_span!("trie_apply", ops = ops.len()).entered(),
+            std::time::Instant::now(),
+            self.conflicts,
+        );
+
+        for op in ops.iter() {
+            self.check_external_op(op)?;
+        }
+        if let Some(applied) = &mut self.applied {
+            applied.push(ops.clone());
+        }
+
+        self.apply_ops(ops)?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            conflicts = self.conflicts - conflicts,
+            elapsed_us = start.elapsed().as_micros() as u64,
+            "trie applied"
+        );
+        Ok(self)
+    }
+
+    /// Same as [`TrieTransaction::apply`], but the whole batch is checked
+    /// before any write, so a malformed op doesn't leave the batch partially
+    /// applied. The ops must be ordered by marker without duplicates, parent
+    /// refs must exist or be created by an earlier op of the batch, and no op
+    /// may move a node under itself.
+    ///
+    /// Paths are resolved when the ops are done, an op targeting a missing
+    /// path still fails midway.
+    pub fn bulk_apply(&mut self, ops: Vec<Op<M, C>>) -> Result<&mut Self> {
+        self.check_batch(&ops)?;
+        self.apply(ops)
+    }
+
+    fn check_batch(&self, ops: &[Op<M, C>]) -> Result<()> {
+        let mut created_refs = BTreeSet::new();
+        for (i, op) in ops.iter().enumerate() {
+            self.check_external_op(op)?;
+            if i > 0 && self.compare_ops(&ops[i - 1], op)? != Ordering::Less {
+                return Err(Error::InvalidOp(format!(
+                    "Op {i} of the batch is not ordered after the previous op"
+                )));
+            }
+
+            match &op.parent_target {
+                OpTarget::Ref(parent_ref) => {
+                    if !created_refs.contains(parent_ref)
+                        && self.transaction.get_id(parent_ref.to_owned())?.is_none()
+                    {
+                        return Err(Error::InvalidOp(format!(
+                            "Op {i} of the batch has parent ref {parent_ref} not found"
+                        )));
+                    }
+                }
+                OpTarget::NewId => {
+                    return Err(Error::InvalidOp(
+                        "Parent target could not be new id".to_string(),
+                    ));
+                }
+                OpTarget::Id(_) | OpTarget::Path(_) => {}
+            }
+            if op.parent_target == op.child_target {
+                return Err(Error::InvalidOp(format!(
+                    "Op {i} of the batch moves a node under itself"
+                )));
+            }
+
+            if let OpTarget::Ref(child_ref) = &op.child_target {
+                created_refs.insert(child_ref.to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_ops(&mut self, ops: Vec<Op<M, C>>) -> Result<()> {
         let mut redo_queue = Vec::new();
-        if let Some(first_op) = ops.first() {
+        let mut ops = ops.into_iter().peekable();
+        if let Some(first_op) = ops.peek() {
             while let Some(last) = self.transaction.pop_log()? {
-                match first_op.marker.partial_cmp(&last.op.marker) {
-                    None | Some(Ordering::Equal) => {
-                        return Err(Error::InvalidOp(
-                                "The marker of the operation has duplicates. Every op must have a unique timestamp.".to_string(),
-                            ));
-                    }
-                    Some(Ordering::Less) => {
+                match self.compare_ops(first_op, &last.op)? {
+                    Ordering::Less => {
                         redo_queue.push(self.undo_op(last)?);
                     }
-                    Some(Ordering::Greater) => {
+                    Ordering::Greater => {
+                        self.transaction.push_log(last)?;
+                        break;
+                    }
+                    Ordering::Equal => {
+                        // same op already applied
                         self.transaction.push_log(last)?;
+                        ops.next();
                         break;
                     }
                 }
             }
         }
 
+        #[cfg(feature = "tracing")]
+        if !redo_queue.is_empty() {
+            tracing::trace!(undone = redo_queue.len(), "trie undone for redo");
+        }
+
         for op in ops {
             loop {
                 if let Some(redo) = redo_queue.pop() {
-                    match op.marker.partial_cmp(&redo.marker) {
-                        None | Some(Ordering::Equal) => {
-                            return Err(Error::InvalidOp(
-                              "The marker of the operation has duplicates. Every op must have a unique timestamp.".to_string(),
-                          ));
-                        }
-                        Some(Ordering::Less) => {
+                    match self.compare_ops(&op, &redo)? {
+                        Ordering::Less => {
                             let log_op = self.do_op(op)?;
                             self.transaction.push_log(log_op)?;
                             redo_queue.push(redo);
                             break;
                         }
-                        Some(Ordering::Greater) => {
+                        Ordering::Greater => {
                             let redo_log_op: LogOp<M, C> = self.do_op(redo)?;
                             self.transaction.push_log(redo_log_op)?;
                         }
+                        Ordering::Equal => {
+                            // same op already applied
+                            redo_queue.push(redo);
+                            break;
+                        }
                     }
                 } else {
                     let log_op = self.do_op(op)?;
@@ -853,7 +1724,7 @@ impl<M: TrieMarker, C: TrieContent, DBImpl: DBRead + DBWrite + DBLock>
             self.transaction.push_log(redo_log_op)?;
         }
 
-        Ok(self)
+        Ok(())
     }
 }
 
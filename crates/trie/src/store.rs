@@ -1,20 +1,27 @@
 use std::{borrow::Borrow, marker::PhantomData};
 
 use db::{DBLock, DBRead, DBTransaction, DBWrite, DB};
-use utils::{Deserialize, PathTools, Serialize, Serializer};
+use utils::{
+    bytes_stringify_with_mode, Blake3, BytesStringifyMode, Deserialize, PathTools, Serialize,
+    Serializer,
+};
 
 use super::{
-    Error, LogOp, Result, TrieContent, TrieId, TrieKey, TrieMarker, TrieNode, TrieRef, CONFLICT,
-    CONFLICT_REF, RECYCLE, RECYCLE_REF, ROOT, ROOT_REF,
+    Error, LogOp, Result, SerializedSubtree, TrieContent, TrieHash, TrieId, TrieKey, TrieKeyRef,
+    TrieMarker, TrieNode, TrieRef, CONFLICT, CONFLICT_REF, RECYCLE, RECYCLE_REF, ROOT, ROOT_REF,
 };
 
+const NODE_CHILD_LABEL: &[u8] = b"c";
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum Keys {
     RefIdIndex(TrieRef),
     NodeInfo(TrieId),
+    NodeInfos,
     NodeChild(TrieId, TrieKey),
     NodeChildren(TrieId),
     IdRefsIndex(TrieId),
+    IdRefsIndexes,
     AutoIncrementId,
     LogTotalLength,
     Log(u64),
@@ -29,16 +36,16 @@ impl Serialize for Keys {
         match self {
             Keys::RefIdIndex(r) => serializer = r.serialize(serializer),
             Keys::NodeInfo(id) => serializer = id.serialize(serializer),
+            Keys::NodeInfos => {}
             Keys::NodeChild(id, k) => {
-                serializer = id.serialize(serializer);
-                serializer.push(b':');
-                serializer = k.serialize(serializer)
+                serializer = NodeChildKey(*id, k.as_key_ref()).serialize_args(serializer)
             }
             Keys::NodeChildren(id) => {
                 serializer = id.serialize(serializer);
                 serializer.push(b':')
             }
             Keys::IdRefsIndex(id) => serializer = id.serialize(serializer),
+            Keys::IdRefsIndexes => {}
             Keys::AutoIncrementId => {}
             Keys::LogTotalLength => {}
             Keys::Log(index) => serializer = index.serialize(serializer),
@@ -55,9 +62,11 @@ impl Serialize for Keys {
                 match self {
                     Keys::RefIdIndex(r) => r.byte_size()?,
                     Keys::NodeInfo(id) => id.byte_size()?,
-                    Keys::NodeChild(id, k) => id.byte_size()? + 1 + k.byte_size()?,
+                    Keys::NodeInfos => 0,
+                    Keys::NodeChild(id, k) => NodeChildKey(*id, k.as_key_ref()).args_byte_size()?,
                     Keys::NodeChildren(id) => id.byte_size()? + 1,
                     Keys::IdRefsIndex(id) => id.byte_size()?,
+                    Keys::IdRefsIndexes => 0,
                     Keys::AutoIncrementId => 0,
                     Keys::LogTotalLength => 0,
                     Keys::Log(index) => index.byte_size()?,
@@ -69,6 +78,34 @@ impl Serialize for Keys {
     }
 }
 
+/// [`Keys::NodeChild`] with a borrowed key, so child lookups encode the key
+/// without building an owned [`TrieKey`].
+struct NodeChildKey<'a>(TrieId, TrieKeyRef<'a>);
+
+impl NodeChildKey<'_> {
+    fn serialize_args(&self, mut serializer: Serializer) -> Serializer {
+        serializer = self.0.serialize(serializer);
+        serializer.push(b':');
+        self.1.serialize(serializer)
+    }
+
+    fn args_byte_size(&self) -> Option<usize> {
+        Some(self.0.byte_size()? + 1 + self.1.byte_size()?)
+    }
+}
+
+impl Serialize for NodeChildKey<'_> {
+    fn serialize(&self, mut serializer: Serializer) -> Serializer {
+        serializer.extend_from_slice(NODE_CHILD_LABEL);
+        serializer.push(b':');
+        self.serialize_args(serializer)
+    }
+
+    fn byte_size(&self) -> Option<usize> {
+        Some(NODE_CHILD_LABEL.len() + 1 + self.args_byte_size()?)
+    }
+}
+
 impl Deserialize for Keys {
     fn deserialize(bytes: &[u8]) -> std::result::Result<(Self, &[u8]), String> {
         let (label, args) = bytes.split_at(
@@ -109,14 +146,34 @@ impl Deserialize for Keys {
         }
     }
 }
+/// Decode a [`Keys::NodeChild`] entry to the child key and id.
+fn decode_child<M: TrieMarker, C: TrieContent>(
+    key: &[u8],
+    value: &[u8],
+) -> Result<(TrieKey, TrieId)> {
+    let key = Keys::from_bytes(key).map_err(|err| decode_error(err, key))?;
+    let value = Values::<M, C>::parse(&key, value)?.node_child()?;
+    Ok((key.node_child()?.1, value))
+}
+
+/// Decode error with a dump of the bytes failed to decode.
+fn decode_error(message: String, bytes: &[u8]) -> Error {
+    Error::DecodeError(format!(
+        "{message}\n{}",
+        bytes_stringify_with_mode(bytes, BytesStringifyMode::HexDump)
+    ))
+}
+
 impl Keys {
     fn bytes_label(&self) -> &'static [u8] {
         match self {
             Keys::RefIdIndex(_) => b"r",
             Keys::NodeInfo(_) => b"n",
-            Keys::NodeChild(_, _) => b"c",
+            Keys::NodeInfos => b"n",
+            Keys::NodeChild(_, _) => NODE_CHILD_LABEL,
             Keys::NodeChildren(_) => b"c",
             Keys::IdRefsIndex(_) => b"i",
+            Keys::IdRefsIndexes => b"i",
             Keys::AutoIncrementId => b"auto_increment_id",
             Keys::LogTotalLength => b"log_total_length",
             Keys::Log(_) => b"l",
@@ -206,6 +263,7 @@ impl<M: TrieMarker, C: TrieContent> Values<M, C> {
             Values::GlobalLock(_) => "GlobalLock",
         }
     }
+    #[cfg(test)]
     fn to_bytes(&self) -> impl AsRef<[u8]> {
         match self {
             Values::RefIdIndex(id) => id.to_bytes(),
@@ -219,44 +277,63 @@ impl<M: TrieMarker, C: TrieContent> Values<M, C> {
         }
     }
 
+    fn serialize_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            Values::RefIdIndex(id) => id.serialize_into(buf),
+            Values::NodeInfo(node) => node.serialize_into(buf),
+            Values::NodeChild(id) => id.serialize_into(buf),
+            Values::IdRefsIndex(refs) => refs.serialize_into(buf),
+            Values::AutoIncrementId(id) => id.serialize_into(buf),
+            Values::LogTotalLength(id) => id.serialize_into(buf),
+            Values::Log(log) => log.serialize_into(buf),
+            Values::GlobalLock(lock) => lock.serialize_into(buf),
+        }
+    }
+
     fn parse(key: &Keys, bytes: &[u8]) -> Result<Self> {
         Ok(match key {
             Keys::RefIdIndex(_) => Self::RefIdIndex(
                 Deserialize::deserialize(bytes)
-                    .map_err(Error::DecodeError)?
+                    .map_err(|err| decode_error(err, bytes))?
                     .0,
             ),
             Keys::NodeInfo(_) => Self::NodeInfo(
                 Deserialize::deserialize(bytes)
-                    .map_err(Error::DecodeError)?
+                    .map_err(|err| decode_error(err, bytes))?
                     .0,
             ),
             Keys::NodeChild(_, _) => Self::NodeChild(
                 Deserialize::deserialize(bytes)
-                    .map_err(Error::DecodeError)?
+                    .map_err(|err| decode_error(err, bytes))?
                     .0,
             ),
             Keys::IdRefsIndex(_) => Self::IdRefsIndex(
                 Deserialize::deserialize(bytes)
-                    .map_err(Error::DecodeError)?
+                    .map_err(|err| decode_error(err, bytes))?
                     .0,
             ),
             Keys::NodeChildren(_) => {
                 panic!("Keys::NodeChildren not have value format")
             }
+            Keys::NodeInfos => {
+                panic!("Keys::NodeInfos not have value format")
+            }
+            Keys::IdRefsIndexes => {
+                panic!("Keys::IdRefsIndexes not have value format")
+            }
             Keys::AutoIncrementId => Self::AutoIncrementId(
                 Deserialize::deserialize(bytes)
-                    .map_err(Error::DecodeError)?
+                    .map_err(|err| decode_error(err, bytes))?
                     .0,
             ),
             Keys::LogTotalLength => Self::LogTotalLength(
                 Deserialize::deserialize(bytes)
-                    .map_err(Error::DecodeError)?
+                    .map_err(|err| decode_error(err, bytes))?
                     .0,
             ),
             Keys::Log(_) => Self::Log(
                 Deserialize::deserialize(bytes)
-                    .map_err(Error::DecodeError)?
+                    .map_err(|err| decode_error(err, bytes))?
                     .0,
             ),
             Keys::Logs => {
@@ -264,7 +341,7 @@ impl<M: TrieMarker, C: TrieContent> Values<M, C> {
             }
             Keys::GlobalLock => Self::GlobalLock(
                 Deserialize::deserialize(bytes)
-                    .map_err(Error::DecodeError)?
+                    .map_err(|err| decode_error(err, bytes))?
                     .0,
             ),
         })
@@ -461,6 +538,67 @@ mod values_tests {
     }
 }
 
+/// What [`TrieStoreRead::walk`] does after [`TrieVisitor::enter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkControl {
+    /// Walk the children of the node.
+    Descend,
+    /// Prune the children of the node, the walk goes on with its siblings.
+    Skip,
+    /// End the walk.
+    Stop,
+}
+
+/// Visitor of [`TrieStoreRead::walk`].
+pub trait TrieVisitor<C: TrieContent> {
+    /// Called on each node before its children, in pre-order.
+    fn enter(&mut self, id: TrieId, node: &TrieNode<C>) -> WalkControl;
+
+    /// Called on each entered node after its children, in post-order. Not
+    /// called anymore once [`WalkControl::Stop`] is returned.
+    fn leave(&mut self, _id: TrieId, _node: &TrieNode<C>) {}
+}
+
+/// Hash of the subtree, see [`TrieStoreRead::get_subtree_hash`].
+struct SubtreeHasher {
+    /// The entered nodes, the children are added on leave.
+    stack: Vec<HashFrame>,
+    hash: Option<TrieHash>,
+}
+
+struct HashFrame {
+    content: [u8; 32],
+    children: Vec<(TrieKey, TrieHash)>,
+}
+
+impl<C: TrieContent> TrieVisitor<C> for SubtreeHasher {
+    fn enter(&mut self, _id: TrieId, node: &TrieNode<C>) -> WalkControl {
+        let mut content = Blake3::new();
+        node.content.digest(&mut content);
+        self.stack.push(HashFrame {
+            content: content.finish(),
+            children: vec![],
+        });
+        WalkControl::Descend
+    }
+
+    fn leave(&mut self, _id: TrieId, node: &TrieNode<C>) {
+        let frame = self.stack.pop().expect("left a node not entered");
+        let hash = TrieHash::combine(
+            &frame.content,
+            frame
+                .children
+                .iter()
+                .map(|(key, hash)| (key.as_key_ref(), hash.clone())),
+        );
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children.push((node.key.clone(), hash));
+        } else {
+            self.hash = Some(hash);
+        }
+    }
+}
+
 pub trait TrieStoreRead<M: TrieMarker, C: TrieContent> {
     type DBReadImpl<'a>: DBRead
     where
@@ -501,29 +639,148 @@ pub trait TrieStoreRead<M: TrieMarker, C: TrieContent> {
 
     fn get_children(&self, id: TrieId) -> Result<Vec<(TrieKey, TrieId)>> {
         let prefix = Keys::NodeChildren(id).to_bytes();
-        let mut upper_bound = prefix.clone();
-        *upper_bound.last_mut().unwrap() += 1;
         let db = self.db();
-        let iter = db.get_range(&prefix, &upper_bound);
+        let iter = db.prefix_scan(&prefix);
 
         let mut children = vec![];
 
         for item in iter {
             let item = item?;
-            let key = Keys::from_bytes(item.0.as_ref()).map_err(Error::DecodeError)?;
-            let value = Values::<M, C>::parse(&key, item.1.as_ref())?.node_child()?;
-            let key = key.node_child()?.1;
+            children.push(decode_child::<M, C>(item.0.as_ref(), item.1.as_ref())?)
+        }
+
+        Ok(children)
+    }
+
+    /// Children of `id` whose key starts with `key_prefix`, at most `limit`,
+    /// e.g. to complete a partial name. Shorter keys come first, then keys of
+    /// the same length in bytewise order.
+    ///
+    /// Child keys are stored after their length, so the children with the
+    /// prefix are not contiguous. Each key length is range scanned from the
+    /// prefix, and the lengths without a match are skipped with a seek instead
+    /// of listing all children. The prefix is matched bytewise, a `&str` ends
+    /// on a char boundary and UTF-8 never contains `0xFF`, so the upper bound
+    /// of the scan never cuts a multibyte char.
+    fn children_with_prefix(
+        &self,
+        id: TrieId,
+        key_prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(TrieKey, TrieId)>> {
+        let prefix = Keys::NodeChildren(id).to_bytes();
+        let db = self.db();
+        let mut children = vec![];
+
+        let mut key_len = u32::try_from(key_prefix.len()).ok();
+        while let Some(len) = key_len {
+            if children.len() >= limit {
+                break;
+            }
+
+            let from = [&prefix[..], &len.to_be_bytes(), key_prefix.as_bytes()].concat();
+            let first = match db.get_range_from(&from).next().transpose()? {
+                Some(item) if item.0.as_ref().starts_with(&prefix) => {
+                    decode_child::<M, C>(item.0.as_ref(), item.1.as_ref())?.0
+                }
+                // no more children
+                _ => break,
+            };
+            if first.as_bytes().len() as u32 != len {
+                // the keys of this length all sort before the prefix, seek
+                // to the prefix within the next length present.
+                key_len = Some(first.as_bytes().len() as u32);
+                continue;
+            }
+
+            if first.as_bytes().starts_with(key_prefix.as_bytes()) {
+                for item in db.prefix_scan(&from) {
+                    if children.len() >= limit {
+                        break;
+                    }
+                    let item = item?;
+                    children.push(decode_child::<M, C>(item.0.as_ref(), item.1.as_ref())?);
+                }
+            }
+            key_len = len.checked_add(1);
+        }
+
+        Ok(children)
+    }
+
+    /// Children of `id` whose node matches `pred`, e.g. to find a child by
+    /// its content without knowing its key.
+    fn find_children_where(
+        &self,
+        id: TrieId,
+        pred: impl Fn(&TrieNode<C>) -> bool,
+    ) -> Result<Vec<(TrieKey, TrieId)>> {
+        let mut children = vec![];
 
-            children.push((key, value))
+        for (key, child_id) in self.get_children(id)? {
+            if pred(&self.get_ensure(child_id)?) {
+                children.push((key, child_id))
+            }
         }
 
         Ok(children)
     }
 
+    /// Number of nodes in the tree, including the reserved root, conflict and
+    /// recycle nodes. Counts the keys without decoding them.
+    fn node_count(&self) -> Result<usize> {
+        let prefix = Keys::NodeInfos.to_bytes();
+        let db = self.db();
+        let mut count = 0;
+
+        for item in db.prefix_scan(&prefix) {
+            item?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Number of ops in the log, read from the stored length.
+    fn log_len(&self) -> Result<u64> {
+        self.db_get(Keys::LogTotalLength)?
+            .ok_or(Error::InvalidOp(
+                "Trie Database not initialized.".to_owned(),
+            ))?
+            .log_total_length()
+    }
+
+    /// Number of children of `id`, counts the child keys without decoding
+    /// them.
+    fn children_count(&self, id: TrieId) -> Result<usize> {
+        let prefix = Keys::NodeChildren(id).to_bytes();
+        let db = self.db();
+        let mut count = 0;
+
+        for item in db.prefix_scan(&prefix) {
+            item?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
     fn get_child(&self, id: TrieId, key: TrieKey) -> Result<Option<TrieId>> {
-        self.db_get(Keys::NodeChild(id, key))?
-            .map(|v| v.node_child())
-            .transpose()
+        self.get_child_ref(id, key.as_key_ref())
+    }
+
+    /// Same as [`TrieStoreRead::get_child`] with a borrowed key.
+    fn get_child_ref(&self, id: TrieId, key: TrieKeyRef<'_>) -> Result<Option<TrieId>> {
+        if let Some(value) = self.db().get(NodeChildKey(id, key).to_bytes())? {
+            let bytes = value.as_ref();
+            Ok(Some(
+                TrieId::deserialize(bytes)
+                    .map_err(|err| decode_error(err, bytes))?
+                    .0,
+            ))
+        } else {
+            Ok(None)
+        }
     }
 
     fn get_ensure(&self, id: TrieId) -> Result<TrieNode<C>> {
@@ -549,7 +806,7 @@ pub trait TrieStoreRead<M: TrieMarker, C: TrieContent> {
         let mut id = ROOT;
         if path != "/" {
             for part in PathTools::parts(path) {
-                if let Some(child_id) = self.get_child(id, TrieKey(part.to_string()))? {
+                if let Some(child_id) = self.get_child_ref(id, TrieKeyRef(part))? {
                     id = child_id
                 } else {
                     return Ok(None);
@@ -560,6 +817,122 @@ pub trait TrieStoreRead<M: TrieMarker, C: TrieContent> {
         Ok(Some(id))
     }
 
+    /// Full path of the node, `None` if the node is not under the root, e.g.
+    /// in the recycle bin.
+    fn get_path_by_id(&self, id: TrieId) -> Result<Option<String>> {
+        let mut parts = vec![];
+        let mut target_id = id;
+        while target_id != ROOT {
+            if target_id.id() < 10 {
+                return Ok(None);
+            }
+            if let Some(node) = self.get(target_id)? {
+                parts.push(node.key);
+                target_id = node.parent;
+            } else {
+                return Ok(None);
+            }
+        }
+
+        let mut path = String::new();
+        for part in parts.iter().rev() {
+            path.push('/');
+            path.push_str(part.as_str());
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        Ok(Some(path))
+    }
+
+    /// Copy of the subtree at `id` with the refs of every node, to be attached
+    /// to another trie with [`crate::TrieTransaction::graft`].
+    fn get_subtree(&self, id: TrieId) -> Result<SerializedSubtree<C>> {
+        let node = self.get_ensure(id)?;
+        let mut children = vec![];
+        for (_, child) in self.get_children(id)? {
+            // the special nodes are their own parent
+            if child != id {
+                children.push(self.get_subtree(child)?);
+            }
+        }
+
+        Ok(SerializedSubtree {
+            key: node.key,
+            content: node.content,
+            refs: self.get_refs(id)?.unwrap_or_default(),
+            children,
+        })
+    }
+
+    /// Hash of the subtree of `id`, covers the keys and contents but not the
+    /// ids and refs, which are different on each peer. Each node is hashed
+    /// with [`TrieHash::combine`].
+    fn get_subtree_hash(&self, id: TrieId) -> Result<TrieHash> {
+        let mut hasher = SubtreeHasher {
+            stack: vec![],
+            hash: None,
+        };
+        self.walk(id, &mut hasher)?;
+
+        Ok(hasher.hash.expect("the root is always left"))
+    }
+
+    /// Walk the subtree of `root` depth first, the children in key order,
+    /// calling `visitor` on each node. The visitor can prune a subtree or stop
+    /// the walk, see [`WalkControl`].
+    fn walk(&self, root: TrieId, visitor: &mut impl TrieVisitor<C>) -> Result<()> {
+        // walk with an explicit stack, deep trees would overflow the call stack
+        let mut stack = vec![];
+        let mut next = Some(root);
+        loop {
+            if let Some(id) = next.take() {
+                let node = self.get_ensure(id)?;
+                match visitor.enter(id, &node) {
+                    WalkControl::Descend => {
+                        let children = self.get_children(id)?.into_iter();
+                        stack.push((id, node, children));
+                    }
+                    WalkControl::Skip => visitor.leave(id, &node),
+                    WalkControl::Stop => return Ok(()),
+                }
+            }
+
+            let Some((id, _, children)) = stack.last_mut() else {
+                return Ok(());
+            };
+            let id = *id;
+            // the special nodes are their own parent
+            next = children.map(|(_, child)| child).find(|child| *child != id);
+            if next.is_none() {
+                let (id, node, _) = stack.pop().expect("stack is not empty");
+                visitor.leave(id, &node);
+            }
+        }
+    }
+
+    /// Find the log of the op with `marker`, the log is ordered by marker so
+    /// the scan stops at the first older op.
+    fn get_log_op(&self, marker: &M) -> Result<Option<LogOp<M, C>>> {
+        let prefix = Keys::Logs.to_bytes();
+        let db = self.db();
+
+        for item in db.prefix_scan(&prefix) {
+            let item = item?;
+            let key = Keys::from_bytes(item.0.as_ref())
+                .map_err(|err| decode_error(err, item.0.as_ref()))?;
+            let log = Values::<M, C>::parse(&key, item.1.as_ref())?.log()?;
+
+            match log.op.marker.partial_cmp(marker) {
+                Some(std::cmp::Ordering::Equal) => return Ok(Some(log)),
+                Some(std::cmp::Ordering::Less) => break,
+                _ => continue,
+            }
+        }
+
+        Ok(None)
+    }
+
     fn get_refs_by_path(&self, path: &str) -> Result<Option<Vec<TrieRef>>> {
         self.get_id_by_path(path).and_then(|id| {
             if let Some(id) = id {
@@ -597,15 +970,20 @@ impl<DBImpl: DBRead, M: TrieMarker, C: TrieContent> TrieStore<DBImpl, M, C> {
         }
     }
 
+    /// Store reading a consistent view of the database, see
+    /// [`DBRead::read_view`].
+    pub fn read_view(&self) -> Result<TrieStore<DBImpl::ReadView<'_>, M, C>> {
+        Ok(TrieStore::from_db(self.db.read_view()?))
+    }
+
     pub fn iter_log(&self) -> Result<impl Iterator<Item = Result<LogOp<M, C>>> + '_> {
         let prefix = Keys::Logs.to_bytes();
-        let mut upper_bound = prefix.clone();
-        *upper_bound.last_mut().unwrap() += 1;
-        let iter = self.db.get_range(&prefix, &upper_bound);
+        let iter = self.db.prefix_scan(&prefix);
 
         Ok(iter.map(|item| {
             item.map_err(Error::from).and_then(|item| {
-                let key = Keys::from_bytes(item.0.as_ref()).map_err(Error::DecodeError)?;
+                let key = Keys::from_bytes(item.0.as_ref())
+                    .map_err(|err| decode_error(err, item.0.as_ref()))?;
                 let value = Values::<M, C>::parse(&key, item.1.as_ref())?.log()?;
 
                 Ok(value)
@@ -627,7 +1005,7 @@ impl<DBImpl: DBRead, M: TrieMarker, C: TrieContent> TrieStoreRead<M, C>
 
 impl<DBImpl: DB, M: TrieMarker, C: TrieContent> TrieStore<DBImpl, M, C> {
     pub fn init(db: DBImpl) -> Result<Self> {
-        let mut this = Self::from_db(db);
+        let this = Self::from_db(db);
 
         let mut transaction = this.start_transaction()?;
         transaction.db_set(
@@ -678,8 +1056,13 @@ impl<DBImpl: DB, M: TrieMarker, C: TrieContent> TrieStore<DBImpl, M, C> {
         Ok(this)
     }
 
+    /// Store reading a snapshot of the database, see [`DB::snapshot`].
+    pub fn snapshot(&self) -> Result<TrieStore<DBImpl::Snapshot<'_>, M, C>> {
+        Ok(TrieStore::from_db(self.db.snapshot()?))
+    }
+
     pub fn start_transaction(
-        &'_ mut self,
+        &'_ self,
     ) -> Result<TrieStoreTransaction<DBImpl::Transaction<'_>, M, C>> {
         let transaction = TrieStoreTransaction::from_db(self.db.start_transaction()?);
 
@@ -692,7 +1075,13 @@ impl<DBImpl: DB, M: TrieMarker, C: TrieContent> TrieStore<DBImpl, M, C> {
 pub struct TrieStoreTransaction<DBImpl: DBRead + DBWrite + DBLock, M: TrieMarker, C: TrieContent> {
     transaction: DBImpl,
     cache_log_total_len: Option<u64>,
+    /// Last id handed out by [`Self::create_id`]. It is dropped with the
+    /// transaction, so a rollback never leaks an id to the next transaction.
     cache_inc_id: Option<TrieId>,
+    /// Scratch buffers for the keys and values written, reused by every write
+    /// of the transaction.
+    scratch_key: Vec<u8>,
+    scratch_value: Vec<u8>,
     m: PhantomData<M>,
     c: PhantomData<C>,
 }
@@ -716,6 +1105,8 @@ impl<DBImpl: DBRead + DBWrite + DBLock, M: TrieMarker, C: TrieContent>
             transaction: db,
             cache_log_total_len: None,
             cache_inc_id: None,
+            scratch_key: Vec::new(),
+            scratch_value: Vec::new(),
             m: Default::default(),
             c: Default::default(),
         }
@@ -744,12 +1135,16 @@ impl<DBImpl: DBRead + DBWrite + DBLock, M: TrieMarker, C: TrieContent>
     }
 
     fn db_set(&mut self, key: Keys, value: Values<M, C>) -> Result<()> {
-        self.transaction.set(key.to_bytes(), value.to_bytes())?;
+        key.serialize_into(&mut self.scratch_key);
+        value.serialize_into(&mut self.scratch_value);
+        self.transaction
+            .set(&self.scratch_key, &self.scratch_value)?;
         Ok(())
     }
 
     fn db_del(&mut self, key: Keys) -> Result<()> {
-        self.transaction.delete(key.to_bytes())?;
+        key.serialize_into(&mut self.scratch_key);
+        self.transaction.delete(&self.scratch_key)?;
         Ok(())
     }
 
@@ -773,13 +1168,12 @@ impl<DBImpl: DBRead + DBWrite + DBLock, M: TrieMarker, C: TrieContent>
 
     pub fn iter_log(&self) -> Result<impl Iterator<Item = Result<LogOp<M, C>>> + '_> {
         let prefix = Keys::Logs.to_bytes();
-        let mut upper_bound = prefix.clone();
-        *upper_bound.last_mut().unwrap() += 1;
-        let iter = self.transaction.get_range(&prefix, &upper_bound);
+        let iter = self.transaction.prefix_scan(&prefix);
 
         Ok(iter.map(|item| {
             item.map_err(Error::from).and_then(|item| {
-                let key = Keys::from_bytes(item.0.as_ref()).map_err(Error::DecodeError)?;
+                let key = Keys::from_bytes(item.0.as_ref())
+                    .map_err(|err| decode_error(err, item.0.as_ref()))?;
                 let value = Values::<M, C>::parse(&key, item.1.as_ref())?.log()?;
 
                 Ok(value)
@@ -832,6 +1226,45 @@ impl<DBImpl: DBRead + DBWrite + DBLock, M: TrieMarker, C: TrieContent>
         Ok(old_id)
     }
 
+    /// Remove the refs of ids without a node, the reserved refs are never
+    /// removed. Returns the number of refs removed.
+    pub fn gc_refs(&mut self) -> Result<usize> {
+        let prefix = Keys::IdRefsIndexes.to_bytes();
+        let mut garbage = vec![];
+
+        for item in self.transaction.prefix_scan(&prefix) {
+            let item = item?;
+            let key = Keys::from_bytes(item.0.as_ref())
+                .map_err(|err| decode_error(err, item.0.as_ref()))?;
+            if let Keys::IdRefsIndex(id) = key {
+                if self.db_get(Keys::NodeInfo(id))?.is_none() {
+                    let refs = Values::<M, C>::parse(&key, item.1.as_ref())?.id_refs_index()?;
+                    garbage.push((id, refs));
+                }
+            }
+        }
+
+        let mut count = 0;
+        for (id, refs) in garbage {
+            let mut kept = vec![];
+            for r in refs {
+                if [ROOT_REF, CONFLICT_REF, RECYCLE_REF].contains(&r) {
+                    kept.push(r);
+                } else if self.get_id(r.clone())? == Some(id) {
+                    self.db_del(Keys::RefIdIndex(r))?;
+                    count += 1;
+                }
+            }
+            if kept.is_empty() {
+                self.db_del(Keys::IdRefsIndex(id))?;
+            } else {
+                self.db_set(Keys::IdRefsIndex(id), Values::IdRefsIndex(kept))?;
+            }
+        }
+
+        Ok(count)
+    }
+
     pub fn create_id(&mut self) -> Result<TrieId> {
         let id = if let Some(cache_inc_id) = self.cache_inc_id {
             cache_inc_id
@@ -842,7 +1275,9 @@ impl<DBImpl: DBRead + DBWrite + DBLock, M: TrieMarker, C: TrieContent>
                 ))?
                 .auto_increment_id()?
         };
-        let new_id = id.inc();
+        let new_id = id
+            .inc()
+            .ok_or(Error::InvalidOp("id space exhausted".to_owned()))?;
 
         self.db_set(Keys::AutoIncrementId, Values::AutoIncrementId(new_id))?;
 
@@ -856,6 +1291,20 @@ impl<DBImpl: DBRead + DBWrite + DBLock, M: TrieMarker, C: TrieContent>
         id: TrieId,
         to: Option<(TrieId, TrieKey, Option<C>)>,
     ) -> Result<Option<(TrieId, TrieKey, Option<C>)>> {
+        // a second node at the same slot would orphan the mapping of the first
+        // one, the ops must move the occupant away first.
+        if cfg!(debug_assertions) {
+            if let Some((parent, key, _)) = &to {
+                if let Some(occupant) = self.get_child_ref(*parent, key.as_key_ref())? {
+                    if occupant != id {
+                        return Err(Error::TreeBroken(format!(
+                            "{id} can not take the place of {occupant} at {parent}/{key}"
+                        )));
+                    }
+                }
+            }
+        }
+
         let node = self
             .db_get(Keys::NodeInfo(id))?
             .map(|v| v.node_info())
@@ -902,6 +1351,29 @@ impl<DBImpl: DBRead + DBWrite + DBLock, M: TrieMarker, C: TrieContent>
         }
     }
 
+    /// Read the node `id`, and write it back if it is stored in an older
+    /// encoding of the content, e.g. before a field was added, so the old
+    /// records are upgraded in place the first time they are touched. The
+    /// nodes written by [`Self::set_tree_node`] are always in the latest
+    /// encoding.
+    ///
+    /// An older encoding is told apart by the bytes of the node encoded again,
+    /// which differ from the stored ones.
+    pub fn migrate(&mut self, id: TrieId) -> Result<Option<TrieNode<C>>> {
+        let key = Keys::NodeInfo(id);
+        let Some(value) = self.transaction.get(key.to_bytes())? else {
+            return Ok(None);
+        };
+        let node = Values::<M, C>::parse(&key, value.as_ref())?.node_info()?;
+        let outdated = node.to_bytes().as_slice() != value.as_ref();
+        drop(value);
+
+        if outdated {
+            self.db_set(key, Values::NodeInfo(node.clone()))?;
+        }
+        Ok(Some(node))
+    }
+
     pub fn pop_log(&mut self) -> Result<Option<LogOp<M, C>>> {
         let log_len = self.log_total_len()?;
 
@@ -920,6 +1392,21 @@ impl<DBImpl: DBRead + DBWrite + DBLock, M: TrieMarker, C: TrieContent>
         Ok(Some(log))
     }
 
+    pub fn last_log(&mut self) -> Result<Option<LogOp<M, C>>> {
+        let log_len = self.log_total_len()?;
+
+        if log_len == 0 {
+            return Ok(None);
+        }
+
+        let last_index = u64::MAX - (log_len - 1);
+        Ok(Some(
+            self.db_get(Keys::Log(last_index))?
+                .ok_or(Error::TreeBroken("log not found.".to_owned()))?
+                .log()?,
+        ))
+    }
+
     pub fn push_log(&mut self, log: LogOp<M, C>) -> Result<()> {
         let log_len = self.log_total_len()?;
 
@@ -942,3 +1429,29 @@ impl<DBImpl: DBTransaction, M: TrieMarker, C: TrieContent> TrieStoreTransaction<
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod transaction_tests {
+    use db::backend::memory::MemoryDB;
+
+    use super::super::{Error, TrieId};
+    use super::{Keys, TrieStore, Values};
+
+    #[test]
+    fn create_id_exhausted() {
+        let store = TrieStore::<_, u64, u64>::init(MemoryDB::default()).unwrap();
+        let mut transaction = store.start_transaction().unwrap();
+        transaction
+            .db_set(
+                Keys::AutoIncrementId,
+                Values::AutoIncrementId(TrieId::from(u64::MAX - 1)),
+            )
+            .unwrap();
+
+        assert_eq!(transaction.create_id().unwrap(), TrieId::from(u64::MAX));
+        assert!(matches!(
+            transaction.create_id(),
+            Err(Error::InvalidOp(message)) if message == "id space exhausted"
+        ));
+    }
+}
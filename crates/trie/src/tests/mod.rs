@@ -1,6 +1,35 @@
 #[macro_use]
 mod tools;
 
+use db::{backend::memory::MemoryDB, DBTransaction, DB};
+use utils::{Deserialize, FixedSize, Serialize};
+
+use super::{
+    compact_ops,
+    store::{TrieStoreRead, TrieVisitor, WalkControl},
+    Error, MarkerCollisionPolicy, Op, OpTarget, SerializedSubtree, Trie, TrieHash, TrieId, TrieKey,
+    TrieKeyRef, TrieNode, TrieRef, TrieTransaction, CONFLICT, CONFLICT_REF, RECYCLE, RECYCLE_REF,
+    ROOT, ROOT_REF,
+};
+
+/// Op moving the node of ref `child` to `key` below `parent`, e.g.
+/// `op(1, ROOT, "a", 100, Some("a"))`, `None` content keeps the content.
+fn op(
+    marker: u64,
+    parent: impl Into<OpTarget>,
+    key: &str,
+    child: u128,
+    content: Option<&str>,
+) -> Op<u64, String> {
+    Op {
+        marker,
+        parent_target: parent.into(),
+        child_key: TrieKey(key.to_string()),
+        child_target: TrieRef::from(child).into(),
+        child_content: content.map(|content| content.to_string()),
+    }
+}
+
 #[test]
 fn write_with_rename() {
     testing!(
@@ -25,6 +54,35 @@ fn write_with_rename() {
     );
 }
 
+#[test]
+fn rollback_test() {
+    testing!(
+        have { local(1) }
+        on local {
+            mkdir "/hello";
+            write "/hello/file" "world";
+        }
+        on local {
+            rollback_rename "/hello" "/dir";
+            rollback_write "/hello/file" "changed";
+            rollback_write "/hello/other" "new";
+        }
+        check local {
+            "
+                └ hello/file [world]
+                "
+        }
+        on local {
+            rename "/hello" "/dir";
+        }
+        check local {
+            "
+                └ dir/file [world]
+                "
+        }
+    );
+}
+
 #[test]
 fn clock_test() {
     testing!(
@@ -65,7 +123,8 @@ fn file_conflict_test() {
         }
         sync { local <=> remote }
         check local remote {
-            // remote id is larger, keep the remote
+            // same date, the markers are ordered by actor, the remote one is
+            // larger, keep the remote
             "
                 └ file [remote]
                 "
@@ -92,6 +151,33 @@ fn file_conflict_test() {
     );
 }
 
+#[test]
+fn dbg_full_test() {
+    testing!(
+        have { local(1) remote(2) }
+        on local {
+            write "/file" "local";
+        }
+        on remote {
+            write "/file" "remote";
+        }
+        sync { local <=> remote }
+        check local remote {
+            "
+                └ file [remote]
+                "
+        }
+        check_full local {
+            // the local version is kept under CONFLICT
+            "
+                ├ CONFLICT/11 [local] #11
+                ├ RECYCLE
+                └ ROOT/file [remote] #12
+                "
+        }
+    );
+}
+
 #[test]
 fn folder_conflict_test() {
     testing!(
@@ -174,3 +260,1227 @@ fn folder_conflict_test() {
         }
     );
 }
+
+#[test]
+fn get_log_op_test() {
+    let ops = vec![
+        op(1, ROOT, "a", 100, Some("a")),
+        op(3, ROOT, "b", 101, Some("b")),
+        op(5, ROOT, "a", 102, Some("a")),
+    ];
+
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer.apply(ops.clone()).unwrap();
+    assert_eq!(writer.get_log_op(&3).unwrap().unwrap().op, ops[1]);
+    writer.commit().unwrap();
+
+    let log = trie.get_log_op(&3).unwrap().unwrap();
+    assert_eq!(log.op, ops[1]);
+    // bind the new ref, create the node
+    assert_eq!(log.undos.len(), 2);
+
+    let conflict_log = trie.get_log_op(&5).unwrap().unwrap();
+    assert_eq!(conflict_log.op, ops[2]);
+    // also move the node of marker 1 to conflict
+    assert!(conflict_log.undos.len() > log.undos.len());
+
+    assert!(trie.get_log_op(&2).unwrap().is_none());
+    assert!(trie.get_log_op(&6).unwrap().is_none());
+    assert!(trie.get_log_op(&0).unwrap().is_none());
+}
+
+#[test]
+fn apply_one_test() {
+    let ops = vec![
+        op(1, ROOT, "dir", 100, Some("")),
+        op(2, TrieRef::from(100), "file", 101, Some("a")),
+        op(3, ROOT, "moved", 100, None),
+        op(4, TrieRef::from(100), "file", 101, Some("b")),
+        op(5, ROOT, "other", 102, Some("c")),
+    ];
+
+    let mut apply = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = apply.write().unwrap();
+    writer.apply(ops.clone()).unwrap();
+    writer.commit().unwrap();
+
+    let mut apply_one = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = apply_one.write().unwrap();
+    for op in ops {
+        writer.apply_one(op).unwrap();
+    }
+    writer.commit().unwrap();
+
+    assert_eq!(apply.to_string(), apply_one.to_string());
+    let markers = |trie: &Trie<u64, String, MemoryDB>| {
+        trie.iter_log()
+            .unwrap()
+            .map(|log| log.unwrap().op.marker)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(markers(&apply), vec![5, 4, 3, 2, 1]);
+    assert_eq!(markers(&apply), markers(&apply_one));
+
+    // older op falls back to apply
+    let late = op(0, ROOT, "late", 103, Some("d"));
+    let mut writer = apply.write().unwrap();
+    writer.apply(vec![late.clone()]).unwrap();
+    writer.commit().unwrap();
+    let mut writer = apply_one.write().unwrap();
+    writer.apply_one(late).unwrap();
+    writer.commit().unwrap();
+
+    assert_eq!(apply.to_string(), apply_one.to_string());
+    assert_eq!(markers(&apply), markers(&apply_one));
+}
+
+#[test]
+fn undo_last_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT_REF, "dir", 100, Some("")),
+            op(2, TrieRef::from(100), "file", 101, Some("a")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+    let before = trie.to_string();
+
+    let moved = op(3, ROOT_REF, "moved", 101, None);
+    let mut writer = trie.write().unwrap();
+    writer.apply_one(moved.clone()).unwrap();
+    assert!(writer.undo_last().unwrap() == Some(moved));
+    writer.commit().unwrap();
+
+    assert_eq!(trie.to_string(), before);
+    assert_eq!(trie.iter_log().unwrap().count(), 2);
+
+    // undo the creation of the file, then of the folder
+    let mut writer = trie.write().unwrap();
+    assert_eq!(writer.undo_last().unwrap().map(|op| op.marker), Some(2));
+    assert_eq!(writer.undo_last().unwrap().map(|op| op.marker), Some(1));
+    assert!(writer.undo_last().unwrap().is_none());
+    writer.commit().unwrap();
+    assert!(!trie.path_exists("/dir").unwrap());
+}
+
+#[test]
+fn bulk_apply_test() {
+    let db = MemoryDB::default();
+    let mut trie = Trie::<u64, String, _>::init(&db).unwrap();
+    let mut writer = trie.write().unwrap();
+    // the parent ref is created by the first op of the batch
+    writer
+        .bulk_apply(vec![
+            op(1, ROOT_REF, "dir", 100, Some("dir")),
+            op(2, TrieRef::from(100), "file", 101, Some("file")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+    let before = trie.to_string();
+
+    for ops in [
+        // parent ref of the last op not found
+        vec![
+            op(3, ROOT_REF, "a", 102, Some("a")),
+            op(4, TrieRef::from(100), "b", 103, Some("b")),
+            op(5, TrieRef::from(999), "c", 104, Some("c")),
+        ],
+        // not ordered
+        vec![
+            op(4, ROOT_REF, "a", 102, Some("a")),
+            op(3, ROOT_REF, "b", 103, Some("b")),
+        ],
+        // duplicated marker
+        vec![
+            op(3, ROOT_REF, "a", 102, Some("a")),
+            op(3, ROOT_REF, "b", 103, Some("b")),
+        ],
+        // moves a node under itself
+        vec![
+            op(3, ROOT_REF, "a", 102, Some("a")),
+            op(4, TrieRef::from(100), "dir", 100, Some("dir")),
+        ],
+    ] {
+        let mut transaction = db.start_transaction().unwrap();
+        let mut writer = TrieTransaction::<u64, String, _>::from_db(&mut transaction);
+        assert!(matches!(writer.bulk_apply(ops), Err(Error::InvalidOp(_))));
+        drop(writer);
+        assert!(transaction.is_empty());
+    }
+
+    assert_eq!(trie.to_string(), before);
+    assert_eq!(trie.iter_log().unwrap().count(), 2);
+}
+
+#[test]
+fn to_dot_test() {
+    let db = MemoryDB::default();
+    let mut trie = Trie::<u64, String, _>::init(&db).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT_REF, "dir", 100, Some("dir")),
+            op(2, TrieRef::from(100), "file", 101, Some("file")),
+            op(3, TrieRef::from(2), "old", 102, Some("old")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+
+    let dot = trie.to_dot();
+    assert!(dot.starts_with("digraph trie {\n"));
+    for line in [
+        "subgraph cluster_ROOT {",
+        "subgraph cluster_CONFLICT {",
+        "subgraph cluster_RECYCLE {",
+        r##""11" [label="#11 dir\n[dir]"];"##,
+        r##""12" [label="#12 file\n[file]"];"##,
+        r##""13" [label="#13 old\n[old]"];"##,
+        r##""ROOT" -> "11";"##,
+        r##""11" -> "12";"##,
+        r##""RECYCLE" -> "13";"##,
+        r##""00000000-0000-0000-0000-000000000064" [shape=box];"##,
+        r##""00000000-0000-0000-0000-000000000064" -> "11" [style=dashed];"##,
+        r##""00000000-0000-0000-0000-000000000001" -> "CONFLICT" [style=dashed];"##,
+    ] {
+        assert!(dot.contains(line), "{} not found in\n{}", line, dot);
+    }
+    // the recycled node is in the RECYCLE cluster
+    let recycle = &dot[dot.find("cluster_RECYCLE").unwrap()..];
+    assert!(recycle[..recycle.find('}').unwrap()].contains(r##""13" [label"##));
+}
+
+#[test]
+fn render_while_writing_test() {
+    let db = MemoryDB::default();
+    let mut trie = Trie::<u64, String, _>::init(&db).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT_REF, "a", 100, Some("a")),
+            op(2, ROOT_REF, "b", 101, Some("b")),
+            op(3, TrieRef::from(100), "file", 102, Some("file")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            // move the file back and forth between the folders
+            for marker in 4..500 {
+                let mut writer = trie.write().unwrap();
+                writer
+                    .apply(vec![op(
+                        marker,
+                        TrieRef::from(100 + marker as u128 % 2),
+                        "file",
+                        102,
+                        Some("file"),
+                    )])
+                    .unwrap();
+                writer.commit().unwrap();
+            }
+        });
+
+        let reader = Trie::<u64, String, _>::from_db(&db);
+        for _ in 0..500 {
+            // the file is always found once, never in both folders or none
+            assert_eq!(reader.to_string().matches("[file]").count(), 1);
+            assert_eq!(reader.dbg_full().matches("[file]").count(), 1);
+        }
+    });
+
+    // a trie over a read-only view renders from the view itself
+    let read_only = Trie::<u64, String, _>::from_db(db.read_only());
+    assert_eq!(read_only.to_string(), trie.to_string());
+    assert_eq!(format!("{read_only:?}"), format!("{trie:?}"));
+    assert_eq!(read_only.dbg_full(), trie.dbg_full());
+}
+
+#[test]
+fn render_read_error_test() {
+    /// Database failing every read.
+    struct Failing;
+
+    impl db::DBRead for Failing {
+        type KeyBytes<'a> = Vec<u8>;
+        type ValueBytes<'a> = Vec<u8>;
+
+        fn get(&self, _key: impl AsRef<[u8]>) -> db::Result<Option<Vec<u8>>> {
+            Err(db::Error::LockTimeout)
+        }
+
+        fn has(&self, _key: impl AsRef<[u8]>) -> db::Result<bool> {
+            Err(db::Error::LockTimeout)
+        }
+
+        type ReadView<'a> = &'a Self;
+
+        fn read_view(&self) -> db::Result<&Self> {
+            Ok(self)
+        }
+
+        type IterRange<'a> = std::iter::Once<db::Result<(Vec<u8>, Vec<u8>)>>;
+
+        fn get_range(&self, _from: impl AsRef<[u8]>, _to: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+            std::iter::once(Err(db::Error::LockTimeout))
+        }
+
+        fn get_range_from(&self, _from: impl AsRef<[u8]>) -> Self::IterRange<'_> {
+            std::iter::once(Err(db::Error::LockTimeout))
+        }
+    }
+
+    // the renderings show the error instead of panicking
+    let trie = Trie::<u64, String, _>::from_db(Failing);
+    assert!(trie.to_string().starts_with("<unavailable: "));
+    assert!(format!("{trie:?}").starts_with("<unavailable: "));
+    assert!(trie.dbg_full().starts_with("<unavailable: "));
+}
+
+#[test]
+fn children_with_prefix_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let names = [
+        "a",
+        "app",
+        "apple",
+        "application",
+        "banana",
+        "ápple",
+        "日",
+        "日本",
+        "日本語",
+        "ab",
+        "dir",
+    ];
+    let mut writer = trie.write().unwrap();
+    for (i, name) in names.iter().enumerate() {
+        writer
+            .apply(vec![Op {
+                marker: i as u64,
+                parent_target: ROOT.into(),
+                child_key: TrieKey(name.to_string()),
+                child_target: OpTarget::NewId,
+                child_content: Some(name.to_string()),
+            }])
+            .unwrap();
+    }
+    // children of another folder are never returned
+    writer
+        .apply(vec![Op {
+            marker: names.len() as u64,
+            parent_target: OpTarget::Path("/dir".to_string()),
+            child_key: TrieKey("apricot".to_string()),
+            child_target: OpTarget::NewId,
+            child_content: Some("apricot".to_string()),
+        }])
+        .unwrap();
+    writer.commit().unwrap();
+
+    let complete = |prefix: &str, limit: usize| {
+        trie.children_with_prefix(ROOT, prefix, limit)
+            .unwrap()
+            .into_iter()
+            .map(|(key, id)| {
+                assert_eq!(trie.get_child(ROOT, key.clone()).unwrap(), Some(id));
+                key.0
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // shorter keys first
+    assert_eq!(complete("ap", 10), vec!["app", "apple", "application"]);
+    assert_eq!(complete("app", 2), vec!["app", "apple"]);
+    assert_eq!(complete("a", 3), vec!["a", "ab", "app"]);
+    assert_eq!(complete("日", 10), vec!["日", "日本", "日本語"]);
+    assert_eq!(complete("日本", 10), vec!["日本", "日本語"]);
+    assert_eq!(complete("á", 10), vec!["ápple"]);
+    assert_eq!(complete("", 2), vec!["a", "ab"]);
+    assert_eq!(complete("", 100).len(), names.len());
+    assert!(complete("c", 10).is_empty());
+    assert!(complete("apples", 10).is_empty());
+    assert!(complete("app", 0).is_empty());
+    let dir = trie.get_id_by_path("/dir").unwrap().unwrap();
+    assert_eq!(trie.children_with_prefix(dir, "ap", 10).unwrap().len(), 1);
+}
+
+#[test]
+fn occupied_slot_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    let a = writer.create_id().unwrap();
+    let b = writer.create_id().unwrap();
+    writer
+        .set_tree_node(a, Some((ROOT, TrieKey("a".to_string()), None)))
+        .unwrap();
+    writer
+        .set_tree_node(b, Some((ROOT, TrieKey("b".to_string()), None)))
+        .unwrap();
+    // moving a node onto itself is fine
+    writer
+        .set_tree_node(a, Some((ROOT, TrieKey("a".to_string()), None)))
+        .unwrap();
+
+    if cfg!(debug_assertions) {
+        assert!(matches!(
+            writer.set_tree_node(b, Some((ROOT, TrieKey("a".to_string()), None))),
+            Err(super::Error::TreeBroken(_))
+        ));
+    }
+    assert_eq!(
+        writer.get_child(ROOT, TrieKey("a".to_string())).unwrap(),
+        Some(a)
+    );
+    assert_eq!(
+        writer.get_child(ROOT, TrieKey("b".to_string())).unwrap(),
+        Some(b)
+    );
+}
+
+#[test]
+fn marker_collision_test() {
+    let first = op(1, ROOT, "dir", 100, Some("first"));
+    let a = op(2, ROOT, "file", 101, Some("a"));
+    let b = op(2, ROOT, "file", 102, Some("b"));
+
+    let run = |ops: Vec<Op<u64, String>>, policy: MarkerCollisionPolicy| {
+        let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+        for op in ops {
+            let mut writer = trie.write().unwrap().with_collision_policy(policy);
+            writer.apply(vec![op])?;
+            writer.commit().unwrap();
+        }
+        Ok::<_, crate::Error>(trie.to_string())
+    };
+
+    assert!(run(
+        vec![first.clone(), a.clone(), b.clone()],
+        MarkerCollisionPolicy::Strict
+    )
+    .is_err());
+
+    let ab = run(
+        vec![first.clone(), a.clone(), b.clone()],
+        MarkerCollisionPolicy::Lenient,
+    )
+    .unwrap();
+    let ba = run(
+        vec![b.clone(), a.clone(), first.clone()],
+        MarkerCollisionPolicy::Lenient,
+    )
+    .unwrap();
+    assert_eq!(ab, ba);
+
+    // applying the same op twice is ignored
+    let twice = run(
+        vec![first.clone(), b.clone(), a.clone(), a, b],
+        MarkerCollisionPolicy::Lenient,
+    )
+    .unwrap();
+    assert_eq!(ab, twice);
+}
+
+#[test]
+fn child_ref_tiebreak_test() {
+    let dir = op(1, ROOT_REF, "dir", 100, Some(""));
+    // the same marker, the content orders the other way than the child ref
+    let local = op(2, TrieRef::from(100), "file", 102, Some("a"));
+    let remote = op(2, TrieRef::from(100), "file", 101, Some("b"));
+    assert_eq!(local.cmp_tiebreak(&remote), std::cmp::Ordering::Greater);
+
+    let peer = |ops: Vec<&Op<u64, String>>| {
+        let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+        for op in ops {
+            let mut writer = trie
+                .write()
+                .unwrap()
+                .with_collision_policy(MarkerCollisionPolicy::ChildRef);
+            writer.apply(vec![op.clone()]).unwrap();
+            writer.commit().unwrap();
+        }
+        trie
+    };
+
+    // each peer applies its own op first
+    let a = peer(vec![&dir, &local, &remote]);
+    let b = peer(vec![&dir, &remote, &local]);
+    assert_eq!(a.root_hash().unwrap(), b.root_hash().unwrap());
+    assert_eq!(a.to_string(), b.to_string());
+    // the larger child ref is ordered last and wins
+    assert_eq!(
+        a.metadata_for_path("/dir/file").unwrap(),
+        Some("a".to_string())
+    );
+    assert_eq!(
+        a.get_id_by_path("/dir/file").unwrap(),
+        a.get_id(TrieRef::from(102)).unwrap()
+    );
+}
+
+#[test]
+fn commit_with_retry_test() {
+    let db = MemoryDB::default();
+    let mut trie = Trie::<u64, String, _>::init(&db).unwrap();
+    let mut other = Trie::<u64, String, _>::from_db(&db);
+
+    // another writer commits while the transaction is open
+    let mut attempts = 0;
+    trie.commit_with_retry(3, |writer| {
+        attempts += 1;
+        writer.apply(vec![op(1, ROOT, "a", 100, Some("a"))])?;
+        let mut other = other.write()?;
+        other.apply(vec![op(2, ROOT, "b", 101, Some("b"))])?;
+        other.commit()
+    })
+    .unwrap();
+    assert_eq!(attempts, 1);
+    assert!(trie.get_id_by_path("/a").unwrap().is_some());
+    assert!(trie.get_id_by_path("/b").unwrap().is_some());
+
+    // no retry, the conflict is returned
+    let result = trie.commit_with_retry(1, |writer| {
+        writer.apply_one(op(3, ROOT, "c", 102, Some("c")))?;
+        let mut other = other.write()?;
+        other.apply(vec![op(4, ROOT, "d", 103, Some("d"))])?;
+        other.commit()
+    });
+    assert!(matches!(
+        result,
+        Err(crate::Error::DBError(db::Error::Conflict))
+    ));
+    assert!(trie.get_id_by_path("/c").unwrap().is_none());
+    assert!(trie.get_id_by_path("/d").unwrap().is_some());
+
+    // a graft can't be replayed, the conflict is returned without retrying
+    let mut attempts = 0;
+    let result = trie.commit_with_retry(3, |writer| {
+        attempts += 1;
+        writer.graft(
+            5,
+            ROOT,
+            SerializedSubtree {
+                key: TrieKey("e".to_string()),
+                content: "e".to_string(),
+                refs: vec![TrieRef::from(104)],
+                children: vec![],
+            },
+        )?;
+        let mut other = other.write()?;
+        other.apply(vec![op(6, ROOT, "f", 105, Some("f"))])?;
+        other.commit()
+    });
+    assert!(matches!(
+        result,
+        Err(crate::Error::DBError(db::Error::Conflict))
+    ));
+    assert_eq!(attempts, 1);
+    assert!(trie.get_id_by_path("/e").unwrap().is_none());
+    assert!(trie.get_id(TrieRef::from(104)).unwrap().is_none());
+    assert!(trie.get_id_by_path("/f").unwrap().is_some());
+}
+
+#[test]
+fn fixed_size_test() {
+    let id = TrieId::from(42);
+    assert_eq!(id.byte_size(), Some(TrieId::SIZE));
+    assert_eq!(id.to_fixed_bytes::<8>().as_ref(), id.to_bytes().as_ref());
+
+    let r = TrieRef::from(42);
+    assert_eq!(r.byte_size(), Some(TrieRef::SIZE));
+    assert_eq!(r.to_fixed_bytes::<16>().as_ref(), r.to_bytes().as_ref());
+
+    let hash = TrieHash([7; 32]);
+    assert_eq!(hash.byte_size(), Some(TrieHash::SIZE));
+    assert_eq!(
+        hash.to_fixed_bytes::<32>().as_ref(),
+        hash.to_bytes().as_ref()
+    );
+
+    assert_eq!((id, r).byte_size(), Some(<(TrieId, TrieRef)>::SIZE));
+}
+
+#[test]
+fn graft_test() {
+    let mut source = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = source.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT, "dir", 100, Some("")),
+            op(2, TrieRef::from(100), "a", 101, Some("a")),
+            op(3, TrieRef::from(100), "sub", 102, Some("")),
+            op(4, TrieRef::from(102), "b", 103, Some("b")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+
+    let dir = source.get_id_by_path("/dir").unwrap().unwrap();
+    let bytes = source.get_subtree(dir).unwrap().to_bytes();
+    let subtree = SerializedSubtree::<String>::from_bytes(&bytes).unwrap();
+
+    let mut target = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = target.write().unwrap();
+    writer.graft(1, ROOT, subtree.clone()).unwrap();
+    writer.commit().unwrap();
+
+    assert_eq!(target.to_string(), source.to_string());
+    assert_eq!(target.iter_log().unwrap().count(), 1);
+    assert_eq!(
+        target.get_id(TrieRef::from(103)).unwrap(),
+        target.get_id_by_path("/dir/sub/b").unwrap()
+    );
+
+    // an older op undoes and redoes the graft, the subtree moves as a whole
+    let mut writer = target.write().unwrap();
+    writer
+        .apply(vec![op(0, ROOT, "other", 200, Some("c"))])
+        .unwrap();
+    writer.commit().unwrap();
+    assert!(target.get_id_by_path("/dir/sub/b").unwrap().is_some());
+
+    let mut writer = target.write().unwrap();
+    // key taken
+    assert!(writer.graft(2, ROOT, subtree.clone()).is_err());
+    // refs in use
+    let dir = writer.get_id_by_path("/dir").unwrap().unwrap();
+    assert!(writer.graft(2, dir, subtree.clone()).is_err());
+    // sibling keys collide
+    let leaf = |key: &str| SerializedSubtree {
+        key: TrieKey(key.to_string()),
+        content: String::new(),
+        refs: vec![],
+        children: vec![],
+    };
+    let mut colliding = leaf("new");
+    colliding.children = vec![leaf("x"), leaf("x")];
+    assert!(writer.graft(2, ROOT, colliding).is_err());
+}
+
+#[test]
+fn children_count_test() {
+    let check = |trie: &Trie<u64, String, MemoryDB>| {
+        let dir = trie.get_id(TrieRef::from(100)).unwrap().unwrap();
+        for id in [ROOT, RECYCLE, dir] {
+            assert_eq!(
+                trie.children_count(id).unwrap(),
+                trie.get_children(id).unwrap().len()
+            );
+        }
+    };
+
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT, "dir", 100, Some("")),
+            op(2, TrieRef::from(100), "a", 101, Some("")),
+            op(3, TrieRef::from(100), "b", 102, Some("")),
+            op(4, ROOT, "c", 103, Some("")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+    check(&trie);
+    let dir = trie.get_id(TrieRef::from(100)).unwrap().unwrap();
+    assert_eq!(trie.children_count(dir).unwrap(), 2);
+    assert_eq!(trie.children_count(ROOT).unwrap(), 2);
+
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(5, RECYCLE, "a", 101, Some("")),
+            op(6, ROOT, "b", 102, Some("")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+    check(&trie);
+    assert_eq!(trie.children_count(dir).unwrap(), 0);
+    assert_eq!(trie.children_count(ROOT).unwrap(), 3);
+    assert_eq!(trie.children_count(RECYCLE).unwrap(), 1);
+}
+
+#[test]
+fn find_children_where_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT, "dir", 100, Some("")),
+            op(2, TrieRef::from(100), "a", 101, Some("x")),
+            op(3, TrieRef::from(100), "b", 102, Some("y")),
+            op(4, TrieRef::from(100), "c", 103, Some("x")),
+            op(5, ROOT, "d", 104, Some("x")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+
+    let dir = trie.get_id(TrieRef::from(100)).unwrap().unwrap();
+    let found = trie
+        .find_children_where(dir, |node| node.content == "x")
+        .unwrap();
+    assert_eq!(
+        found,
+        vec![
+            (
+                TrieKey("a".to_string()),
+                trie.get_id(TrieRef::from(101)).unwrap().unwrap()
+            ),
+            (
+                TrieKey("c".to_string()),
+                trie.get_id(TrieRef::from(103)).unwrap().unwrap()
+            ),
+        ]
+    );
+    assert!(trie
+        .find_children_where(dir, |node| node.content == "z")
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn get_child_ref_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(
+            (0..100u64)
+                .map(|i| Op {
+                    marker: i + 1,
+                    parent_target: ROOT.into(),
+                    child_key: TrieKey(format!("file-{i}")),
+                    child_target: TrieRef::from(100 + i as u128).into(),
+                    child_content: Some(i.to_string()),
+                })
+                .collect(),
+        )
+        .unwrap();
+    writer.commit().unwrap();
+
+    let names = (0..120).map(|i| format!("file-{i}")).collect::<Vec<_>>();
+    for _ in 0..10 {
+        for name in names.iter() {
+            let key_ref = TrieKeyRef(name);
+            // borrowed from the name, nothing allocated
+            assert_eq!(key_ref.as_str().as_ptr(), name.as_ptr());
+            assert_eq!(
+                key_ref.to_bytes().as_ref(),
+                TrieKey(name.clone()).to_bytes().as_ref()
+            );
+            assert_eq!(
+                trie.get_child_ref(ROOT, key_ref).unwrap(),
+                trie.get_child(ROOT, TrieKey(name.clone())).unwrap()
+            );
+        }
+    }
+
+    assert!(trie
+        .get_child_ref(ROOT, TrieKeyRef("file-99"))
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        trie.get_child_ref(ROOT, TrieKeyRef("file-100")).unwrap(),
+        None
+    );
+    assert_eq!(
+        trie.get_id_by_path("/file-42").unwrap(),
+        trie.get_id(TrieRef::from(142)).unwrap()
+    );
+}
+
+#[test]
+fn deep_tree_render_test() {
+    const DEPTH: u64 = 1000;
+
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(
+            (0..DEPTH)
+                .map(|i| Op {
+                    marker: i + 1,
+                    parent_target: if i == 0 {
+                        ROOT.into()
+                    } else {
+                        TrieRef::from(99 + i as u128).into()
+                    },
+                    child_key: TrieKey("d".to_string()),
+                    child_target: TrieRef::from(100 + i as u128).into(),
+                    child_content: Some(i.to_string()),
+                })
+                .collect(),
+        )
+        .unwrap();
+    writer.commit().unwrap();
+
+    let mut items = vec![];
+    trie.dbg_itemization(ROOT, "", &mut items).unwrap();
+    assert_eq!(items.len(), DEPTH as usize + 1);
+    assert_eq!(items.last().unwrap().2.content, (DEPTH - 1).to_string());
+
+    let rendered = trie.to_string();
+    assert_eq!(rendered.lines().count(), DEPTH as usize);
+    assert!(rendered.contains(&format!("d [{}]", DEPTH - 1)));
+}
+
+#[test]
+fn path_exists_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT, "a", 100, Some("folder")),
+            op(2, TrieRef::from(100), "b", 101, Some("file")),
+            op(3, TrieRef::from(100), "c", 102, Some("recycled")),
+            op(4, RECYCLE, "c", 102, Some("recycled")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+
+    assert!(trie.path_exists("/").unwrap());
+    assert!(trie.path_exists("/a").unwrap());
+    assert!(trie.path_exists("/a/b").unwrap());
+    assert_eq!(
+        trie.metadata_for_path("/a/b").unwrap(),
+        Some("file".to_string())
+    );
+
+    assert!(!trie.path_exists("/a/d").unwrap());
+    assert!(!trie.path_exists("/a/b/c").unwrap());
+    assert_eq!(trie.metadata_for_path("/d").unwrap(), None);
+
+    // moved to the recycle bin
+    assert!(!trie.path_exists("/a/c").unwrap());
+    assert!(!trie.path_exists("/c").unwrap());
+    assert_eq!(trie.metadata_for_path("/a/c").unwrap(), None);
+}
+
+#[test]
+fn rebuild_from_log_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT, "dir", 100, Some("")),
+            op(3, TrieRef::from(100), "a", 101, Some("a")),
+            op(4, TrieRef::from(100), "b", 102, Some("b")),
+            op(6, ROOT, "other", 103, Some("")),
+        ])
+        .unwrap();
+    // older ops arriving late are applied in between the logged ones
+    writer
+        .apply(vec![
+            op(2, ROOT, "dir", 104, Some("conflict")),
+            op(5, TrieRef::from(100), "c", 101, Some("renamed")),
+            op(7, RECYCLE, "b", 102, Some("b")),
+            op(8, TrieRef::from(103), "dir", 100, Some("")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+
+    let rebuilt = trie.rebuild_from_log(MemoryDB::default()).unwrap();
+    assert_eq!(rebuilt.root_hash().unwrap(), trie.root_hash().unwrap());
+    assert_eq!(rebuilt.to_string(), trie.to_string());
+    assert_eq!(
+        rebuilt.iter_log().unwrap().count(),
+        trie.iter_log().unwrap().count()
+    );
+
+    // the descendants of a graft are not in the log
+    let dir = trie.get_id_by_path("/other/dir").unwrap().unwrap();
+    let bytes = trie.get_subtree(dir).unwrap().to_bytes();
+    let subtree = SerializedSubtree::<String>::from_bytes(&bytes).unwrap();
+    let mut target = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = target.write().unwrap();
+    writer.graft(1, ROOT, subtree).unwrap();
+    writer.commit().unwrap();
+    assert!(matches!(
+        target.rebuild_from_log(MemoryDB::default()),
+        Err(Error::TreeBroken(_))
+    ));
+}
+
+#[test]
+fn external_ops_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap().with_external_ops(true);
+    writer.apply(vec![op(1, ROOT, "a", 100, Some(""))]).unwrap();
+    let parents: [OpTarget; 4] = [
+        RECYCLE.into(),
+        RECYCLE_REF.into(),
+        CONFLICT.into(),
+        CONFLICT_REF.into(),
+    ];
+    for parent in parents {
+        assert!(matches!(
+            writer.apply(vec![op(2, parent.clone(), "a", 100, Some(""))]),
+            Err(crate::Error::InvalidOp(_))
+        ));
+        assert!(matches!(
+            writer.apply_one(op(2, parent, "a", 100, Some(""))),
+            Err(crate::Error::InvalidOp(_))
+        ));
+    }
+    writer.commit().unwrap();
+    let id = trie.get_id(TrieRef::from(100)).unwrap().unwrap();
+    assert_eq!(trie.get_ensure(id).unwrap().parent, ROOT);
+
+    // internal ops still move into recycle
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![op(2, RECYCLE, "a", 100, Some(""))])
+        .unwrap();
+    writer.commit().unwrap();
+    assert_eq!(trie.get_ensure(id).unwrap().parent, RECYCLE);
+}
+
+#[test]
+fn compact_ops_test() {
+    let ops = vec![
+        op(1, ROOT, "dir", 100, Some("")),
+        op(2, TrieRef::from(100), "file", 101, Some("a")),
+        op(3, TrieRef::from(100), "file", 101, Some("b")),
+        op(4, ROOT, "moved", 101, None),
+        op(5, ROOT, "other", 102, Some("c")),
+        op(6, TrieRef::from(100), "file", 101, None),
+        op(7, ROOT, "renamed", 100, None),
+        op(8, ROOT, "renamed", 100, Some("d")),
+    ];
+
+    let compacted = compact_ops(ops.clone());
+    assert_eq!(
+        compacted,
+        vec![
+            op(1, ROOT, "dir", 100, Some("")),
+            op(4, ROOT, "moved", 101, Some("b")),
+            op(5, ROOT, "other", 102, Some("c")),
+            op(6, TrieRef::from(100), "file", 101, None),
+            op(8, ROOT, "renamed", 100, Some("d")),
+        ]
+    );
+
+    let mut original = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = original.write().unwrap();
+    writer.apply(ops).unwrap();
+    writer.commit().unwrap();
+
+    let mut target = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = target.write().unwrap();
+    writer.apply(compacted).unwrap();
+    writer.commit().unwrap();
+
+    assert_eq!(original.to_string(), target.to_string());
+}
+
+#[test]
+fn gc_refs_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT, "dir", 100, Some("")),
+            op(2, TrieRef::from(100), "file", 101, Some("")),
+            op(3, ROOT, "other", 102, Some("")),
+            op(4, RECYCLE, "dir", 100, Some("")),
+        ])
+        .unwrap();
+    let dir = writer.get_id(TrieRef::from(100)).unwrap().unwrap();
+    let file = writer.get_id(TrieRef::from(101)).unwrap().unwrap();
+    assert_eq!(writer.gc_refs().unwrap(), 0);
+
+    // purge the recycled nodes
+    writer.transaction.set_tree_node(file, None).unwrap();
+    writer.transaction.set_tree_node(dir, None).unwrap();
+    assert_eq!(writer.gc_refs().unwrap(), 2);
+    assert_eq!(writer.gc_refs().unwrap(), 0);
+    writer.commit().unwrap();
+
+    assert!(trie.get_id(TrieRef::from(100)).unwrap().is_none());
+    assert!(trie.get_id(TrieRef::from(101)).unwrap().is_none());
+    assert!(trie.get_refs(dir).unwrap().is_none());
+    assert!(trie.get_refs(file).unwrap().is_none());
+    assert!(trie.get_id(TrieRef::from(102)).unwrap().is_some());
+    for r in [ROOT_REF, CONFLICT_REF, RECYCLE_REF] {
+        assert!(trie.get_id(r).unwrap().is_some());
+    }
+}
+
+#[test]
+fn root_hash_test() {
+    let ops = vec![
+        op(1, ROOT, "dir", 100, Some("")),
+        op(2, TrieRef::from(100), "a", 101, Some("a")),
+        op(3, ROOT, "b", 102, Some("b")),
+    ];
+
+    let mut local = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = local.write().unwrap();
+    writer.apply(ops.clone()).unwrap();
+    writer.commit().unwrap();
+
+    // built in another order, with other refs
+    let mut remote = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = remote.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT, "b", 202, Some("b")),
+            op(2, ROOT, "dir", 200, Some("")),
+            op(3, RECYCLE, "removed", 203, Some("c")),
+            op(4, TrieRef::from(200), "a", 201, Some("a")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+
+    let hash = local.root_hash().unwrap();
+    assert!(remote.compare_roots(&hash));
+    assert_ne!(
+        local.root_hash_with_local().unwrap(),
+        remote.root_hash_with_local().unwrap()
+    );
+
+    let mut writer = remote.write().unwrap();
+    writer
+        .apply(vec![op(5, TrieRef::from(200), "a", 201, Some("changed"))])
+        .unwrap();
+    writer.commit().unwrap();
+    assert!(!remote.compare_roots(&hash));
+
+    let mut writer = local.write().unwrap();
+    writer
+        .apply(vec![op(4, TrieRef::from(100), "a", 101, Some("changed"))])
+        .unwrap();
+    writer.commit().unwrap();
+    assert_eq!(local.root_hash().unwrap(), remote.root_hash().unwrap());
+}
+
+#[test]
+fn path_target_test() {
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            Op {
+                marker: 1,
+                parent_target: ROOT.into(),
+                child_key: TrieKey("dir".to_string()),
+                child_target: TrieRef::from(100).into(),
+                child_content: Some(String::new()),
+            },
+            Op {
+                marker: 2,
+                parent_target: OpTarget::Path("/dir".to_string()),
+                child_key: TrieKey("file".to_string()),
+                child_target: TrieRef::from(101).into(),
+                child_content: Some("hello".to_string()),
+            },
+            Op {
+                marker: 3,
+                parent_target: OpTarget::Path("/".to_string()),
+                child_key: TrieKey("moved".to_string()),
+                child_target: OpTarget::Path("/dir/file".to_string()),
+                child_content: None,
+            },
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+    assert!(trie.path_exists("/dir").unwrap());
+    assert!(!trie.path_exists("/dir/file").unwrap());
+    assert_eq!(
+        trie.metadata_for_path("/moved").unwrap(),
+        Some("hello".to_string())
+    );
+
+    let missing = Op {
+        marker: 4,
+        parent_target: OpTarget::Path("/missing".to_string()),
+        child_key: TrieKey("file".to_string()),
+        child_target: OpTarget::NewId,
+        child_content: Some(String::new()),
+    };
+    let mut writer = trie.write().unwrap();
+    assert!(matches!(
+        writer.apply(vec![missing.clone()]),
+        Err(super::Error::InvalidOp(_))
+    ));
+
+    let bytes = missing.to_bytes();
+    let decoded = Op::<u64, String>::from_bytes(bytes.as_ref()).unwrap();
+    assert!(decoded == missing);
+}
+
+#[test]
+fn walk_test() {
+    /// Records the walk, `control` decides what to do with each key.
+    struct Recorder<F: Fn(&str) -> WalkControl> {
+        control: F,
+        walked: Vec<String>,
+    }
+
+    impl<F: Fn(&str) -> WalkControl> TrieVisitor<String> for Recorder<F> {
+        fn enter(&mut self, _id: TrieId, node: &TrieNode<String>) -> WalkControl {
+            self.walked.push(format!("+{}", node.key));
+            (self.control)(node.key.as_str())
+        }
+
+        fn leave(&mut self, _id: TrieId, node: &TrieNode<String>) {
+            self.walked.push(format!("-{}", node.key));
+        }
+    }
+
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            op(1, ROOT, "a", 100, Some("")),
+            op(2, TrieRef::from(100), "x", 101, Some("")),
+            op(3, TrieRef::from(100), "y", 102, Some("")),
+            op(4, ROOT, "b", 103, Some("")),
+            op(5, TrieRef::from(103), "z", 104, Some("")),
+            op(6, ROOT, "c", 105, Some("")),
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+
+    let walk = |control: fn(&str) -> WalkControl| {
+        let mut recorder = Recorder {
+            control,
+            walked: vec![],
+        };
+        trie.walk(ROOT, &mut recorder).unwrap();
+        recorder.walked.join(" ")
+    };
+
+    assert_eq!(
+        walk(|_| WalkControl::Descend),
+        "+ +a +x -x +y -y -a +b +z -z -b +c -c -"
+    );
+
+    // pruned, the skipped node is still left
+    assert_eq!(
+        walk(|key| if key == "a" {
+            WalkControl::Skip
+        } else {
+            WalkControl::Descend
+        }),
+        "+ +a -a +b +z -z -b +c -c -"
+    );
+    assert_eq!(walk(|_| WalkControl::Skip), "+ -");
+
+    // early stop, nothing is left after
+    assert_eq!(
+        walk(|key| if key == "z" {
+            WalkControl::Stop
+        } else {
+            WalkControl::Descend
+        }),
+        "+ +a +x -x +y -y -a +b +z"
+    );
+
+    // walk from a subtree
+    let mut recorder = Recorder {
+        control: |_: &str| WalkControl::Descend,
+        walked: vec![],
+    };
+    trie.walk(trie.get_id_by_path("/b").unwrap().unwrap(), &mut recorder)
+        .unwrap();
+    assert_eq!(recorder.walked.join(" "), "+b +z -z -b");
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_test() {
+    use tools::capture::Capture;
+
+    let capture = Capture::default();
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    tracing::subscriber::with_default(capture.clone(), || {
+        let mut writer = trie.write().unwrap();
+        writer
+            .apply(vec![
+                op(1, ROOT, "file", 100, Some("a")),
+                op(2, ROOT, "file", 101, Some("b")),
+            ])
+            .unwrap();
+        writer.commit().unwrap();
+    });
+
+    let spans = capture.find("trie_apply");
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0]["ops"], "2");
+
+    let conflicts = capture.find("trie conflict");
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0]["parent"], "ROOT");
+    assert_eq!(conflicts[0]["key"], "file");
+
+    let applied = capture.find("trie applied");
+    assert_eq!(applied.len(), 1);
+    assert_eq!(applied[0]["conflicts"], "1");
+    assert!(applied[0].contains_key("elapsed_us"));
+}
+
+#[test]
+fn hash_combine_test() {
+    let hex = |hash: &TrieHash| {
+        hash.as_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    };
+
+    // golden values, peers compare these hashes
+    let leaf = TrieHash::combine(&[1; 32], []);
+    assert_eq!(
+        hex(&leaf),
+        "9515049071ed913149a80d3bb7891fcd4c6c1e3d14ad878939a80f9b9a91e08c"
+    );
+    let node = TrieHash::combine(
+        &[2; 32],
+        [
+            (TrieKeyRef("b"), leaf.clone()),
+            (TrieKeyRef("ab"), TrieHash([3; 32])),
+        ],
+    );
+    assert_eq!(
+        hex(&node),
+        "85c832498c49b672c5ac6f98bfe6e5a9f8e7b9f786f478149e2d3ccfcc91f5c7"
+    );
+
+    // the children are sorted by key bytes, the order given does not matter
+    assert_eq!(
+        TrieHash::combine(
+            &[2; 32],
+            [
+                (TrieKeyRef("ab"), TrieHash([3; 32])),
+                (TrieKeyRef("b"), leaf),
+            ],
+        ),
+        node
+    );
+
+    let mut trie = Trie::<u64, String, _>::init(MemoryDB::default()).unwrap();
+    let mut writer = trie.write().unwrap();
+    writer
+        .apply(vec![
+            Op {
+                marker: 1,
+                parent_target: ROOT.into(),
+                child_key: TrieKey("dir".to_string()),
+                child_target: TrieRef::from(100).into(),
+                child_content: Some(String::new()),
+            },
+            Op {
+                marker: 2,
+                parent_target: TrieRef::from(100).into(),
+                child_key: TrieKey("file".to_string()),
+                child_target: TrieRef::from(101).into(),
+                child_content: Some("hello".to_string()),
+            },
+        ])
+        .unwrap();
+    writer.commit().unwrap();
+    assert_eq!(
+        hex(&trie.root_hash().unwrap()),
+        "da097cfcd94e06fb36c6e3616a6c84c5464d0e2fd668cfaf181fa37fb52e9fc9"
+    );
+}
@@ -1,7 +1,15 @@
-use std::{borrow::Borrow, collections::VecDeque};
+use std::{
+    borrow::Borrow,
+    collections::VecDeque,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use crdts::{CmRDT, VClock};
-use db::backend::memory::MemoryDB;
+use db::{
+    backend::{memory::MemoryDB, rocks::RocksDB},
+    prefix::Prefix,
+    DB,
+};
 use utils::{Deserialize, PathTools, Serialize, Serializer};
 
 use super::super::{Op, Trie, TrieKey, TrieRef, TrieStoreRead};
@@ -66,6 +74,9 @@ pub struct End {
     clock: VClock<u64>,
     time: u64,
     trie: Trie<Marker, String, MemoryDB>,
+    /// Batches of ops committed to the trie, replayed on the other stores by
+    /// [`check_parity`].
+    applied: Vec<Vec<Op<Marker, String>>>,
 }
 
 impl End {
@@ -75,6 +86,7 @@ impl End {
             clock: Default::default(),
             time: 0,
             trie: Trie::init(MemoryDB::default()).unwrap(),
+            applied: vec![],
         }
     }
 
@@ -103,24 +115,25 @@ impl End {
         for op in other_ops.iter() {
             self.clock.apply(op.marker.clock.dot(op.marker.actor))
         }
-        let mut writer = self.trie.write().unwrap();
-
-        writer.apply(other_ops).unwrap();
-        writer.commit().unwrap();
+        self.commit(other_ops);
 
         let self_ops = self.ops_after(&other.clock);
         for op in self_ops.iter() {
             other.clock.apply(op.marker.clock.dot(op.marker.actor))
         }
-        let mut writer = other.trie.write().unwrap();
+        other.commit(self_ops);
+    }
 
-        writer.apply(self_ops).unwrap();
+    fn commit(&mut self, ops: Vec<Op<Marker, String>>) {
+        let mut writer = self.trie.write().unwrap();
+        writer.apply(ops.clone()).unwrap();
         writer.commit().unwrap();
+        self.applied.push(ops);
     }
 
-    pub fn rename(&mut self, from: &str, to: &str) {
-        let mut writer = self.trie.write().unwrap();
-        let from = writer
+    fn rename_op(&mut self, from: &str, to: &str) -> Op<Marker, String> {
+        let from = self
+            .trie
             .get_refs_by_path(from)
             .unwrap()
             .unwrap()
@@ -128,7 +141,8 @@ impl End {
             .unwrap()
             .clone();
         let filename = PathTools::basename(to).to_owned();
-        let to = writer
+        let to = self
+            .trie
             .get_refs_by_path(PathTools::dirname(to))
             .unwrap()
             .unwrap()
@@ -138,26 +152,23 @@ impl End {
 
         self.clock.apply(self.clock.inc(self.actor));
 
-        writer
-            .apply(vec![Op {
-                marker: Marker {
-                    actor: self.actor,
-                    clock: self.clock.clone(),
-                    time: self.time,
-                },
-                parent_target: to.into(),
-                child_key: TrieKey(filename),
-                child_target: from.into(),
-                child_content: None,
-            }])
-            .unwrap();
-        writer.commit().unwrap();
+        Op {
+            marker: Marker {
+                actor: self.actor,
+                clock: self.clock.clone(),
+                time: self.time,
+            },
+            parent_target: to.into(),
+            child_key: TrieKey(filename),
+            child_target: from.into(),
+            child_content: None,
+        }
     }
 
-    pub fn write(&mut self, to: &str, data: &str) {
+    fn write_op(&mut self, to: &str, data: &str) -> Op<Marker, String> {
         let filename = PathTools::basename(to).to_owned();
-        let mut writer = self.trie.write().unwrap();
-        let to = writer
+        let to = self
+            .trie
             .get_refs_by_path(PathTools::dirname(to))
             .unwrap()
             .unwrap()
@@ -167,20 +178,49 @@ impl End {
 
         self.clock.apply(self.clock.inc(self.actor));
 
-        writer
-            .apply(vec![Op {
-                marker: Marker {
-                    actor: self.actor,
-                    clock: self.clock.clone(),
-                    time: self.time,
-                },
-                parent_target: to.into(),
-                child_key: TrieKey(filename),
-                child_target: TrieRef::new().into(),
-                child_content: Some(data.to_owned()),
-            }])
-            .unwrap();
-        writer.commit().unwrap();
+        Op {
+            marker: Marker {
+                actor: self.actor,
+                clock: self.clock.clone(),
+                time: self.time,
+            },
+            parent_target: to.into(),
+            child_key: TrieKey(filename),
+            child_target: TrieRef::new().into(),
+            child_content: Some(data.to_owned()),
+        }
+    }
+
+    fn apply_and_rollback(&mut self, op: Op<Marker, String>) {
+        let mut writer = self.trie.write().unwrap();
+        writer.apply(vec![op]).unwrap();
+        writer.rollback().unwrap();
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) {
+        let op = self.rename_op(from, to);
+        self.commit(vec![op]);
+    }
+
+    pub fn write(&mut self, to: &str, data: &str) {
+        let op = self.write_op(to, data);
+        self.commit(vec![op]);
+    }
+
+    /// Stage a rename and roll it back, the end should stay unchanged.
+    pub fn rollback_rename(&mut self, from: &str, to: &str) {
+        let clock = self.clock.clone();
+        let op = self.rename_op(from, to);
+        self.apply_and_rollback(op);
+        self.clock = clock;
+    }
+
+    /// Stage a write and roll it back, the end should stay unchanged.
+    pub fn rollback_write(&mut self, to: &str, data: &str) {
+        let clock = self.clock.clone();
+        let op = self.write_op(to, data);
+        self.apply_and_rollback(op);
+        self.clock = clock;
     }
 
     pub fn mkdir(&mut self, to: &str) {
@@ -200,6 +240,135 @@ pub fn check(ends: &[&End], expect: &str) {
     }
     for e in ends {
         assert_eq!(e.trie.to_string(), expect);
+        check_parity(e);
+    }
+}
+
+/// Replay the batches committed to `end` on a trie over each store, every
+/// store must give the same forest, ids and log, so the stores can't drift
+/// apart in conflict behavior.
+pub fn check_parity(end: &End) {
+    fn replay<T: DB>(end: &End, db: T) -> Trie<Marker, String, T> {
+        let mut trie = Trie::init(db).unwrap();
+        for ops in end.applied.iter() {
+            let mut writer = trie.write().unwrap();
+            writer.apply(ops.clone()).unwrap();
+            writer.commit().unwrap();
+        }
+        trie
+    }
+
+    fn check_same<T: DB>(end: &End, store: &str, trie: Trie<Marker, String, T>) {
+        assert_eq!(
+            trie.dbg_full(),
+            end.trie.dbg_full(),
+            "{store} store differs"
+        );
+        assert_eq!(trie.root_hash().unwrap(), end.trie.root_hash().unwrap());
+        assert_eq!(
+            trie.iter_log().unwrap().count(),
+            end.trie.iter_log().unwrap().count()
+        );
+    }
+
+    check_same(end, "memory", replay(end, MemoryDB::default()));
+    // the layout of the tracker, the trie under a prefix of a shared db
+    check_same(
+        end,
+        "prefixed memory",
+        replay(end, Prefix::new(MemoryDB::default(), b"trie:")),
+    );
+    // every call replays into an empty database of its own
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let dir =
+        test_results::save_dir!("parity").join(RUNS.fetch_add(1, Ordering::SeqCst).to_string());
+    let _ = std::fs::remove_dir_all(&dir);
+    let rocks = RocksDB::open_or_create_database(dir).unwrap();
+    check_same(end, "rocks", replay(end, rocks));
+}
+
+pub fn check_full(end: &End, expect: &str) {
+    assert_eq!(end.trie.dbg_full(), expect);
+}
+
+/// Subscriber recording the spans and events, for the tests of the `tracing`
+/// instrumentation.
+#[cfg(feature = "tracing")]
+pub mod capture {
+    use std::{
+        collections::BTreeMap,
+        fmt::Debug,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    /// A span or an event, named by the span name or the event message.
+    #[derive(Debug, Default)]
+    pub struct Captured {
+        pub name: String,
+        pub fields: BTreeMap<String, String>,
+    }
+
+    impl Visit for Captured {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            if field.name() == "message" {
+                self.name = format!("{value:?}");
+            } else {
+                self.fields
+                    .insert(field.name().to_string(), format!("{value:?}"));
+            }
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct Capture {
+        pub captured: Arc<Mutex<Vec<Captured>>>,
+    }
+
+    impl Capture {
+        pub fn find(&self, name: &str) -> Vec<BTreeMap<String, String>> {
+            self.captured
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|c| c.name == name)
+                .map(|c| c.fields.clone())
+                .collect()
+        }
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let mut captured = Captured {
+                name: span.metadata().name().to_string(),
+                ..Default::default()
+            };
+            span.record(&mut captured);
+            self.captured.lock().unwrap().push(captured);
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut captured = Captured::default();
+            event.record(&mut captured);
+            self.captured.lock().unwrap().push(captured);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
     }
 }
 
@@ -215,6 +384,9 @@ macro_rules! testing {
             &$x,
         )*], indoc::indoc! {$e})
     };
+    (check_full $x:ident { $e:expr }) => {
+        tools::check_full(&$x, indoc::indoc! {$e})
+    };
     (sync { $from:ident <=> $to:ident }) => {
         $from.sync_with(&mut $to);
     };
@@ -1,3 +1,12 @@
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BytesStringifyMode {
+    /// Printable characters as is, others escaped as `\uXX`.
+    #[default]
+    Escaped,
+    /// Aligned hex and ascii dump, like `hexdump -C`.
+    HexDump,
+}
+
 pub fn bytes_stringify(bytes: &[u8]) -> String {
     let mut data = String::new();
     for char in bytes {
@@ -14,3 +23,60 @@ pub fn bytes_stringify(bytes: &[u8]) -> String {
     }
     data
 }
+
+pub fn bytes_stringify_with_mode(bytes: &[u8], mode: BytesStringifyMode) -> String {
+    match mode {
+        BytesStringifyMode::Escaped => bytes_stringify(bytes),
+        BytesStringifyMode::HexDump => bytes_hexdump(bytes),
+    }
+}
+
+fn bytes_hexdump(bytes: &[u8]) -> String {
+    let mut lines = vec![];
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        let mut ascii = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            hex.push_str(&format!("{byte:02x} "));
+            if i == 7 {
+                hex.push(' ');
+            }
+            ascii.push(if (0x20..0x7f).contains(byte) {
+                *byte as char
+            } else {
+                '.'
+            });
+        }
+        lines.push(format!("{:08x}  {hex:<49} |{ascii}|", line * 16));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bytes_stringify_with_mode, BytesStringifyMode};
+
+    #[test]
+    fn hexdump_printable_test() {
+        assert_eq!(
+            bytes_stringify_with_mode(b"hello world", BytesStringifyMode::HexDump),
+            "00000000  68 65 6c 6c 6f 20 77 6f  72 6c 64                 |hello world|"
+        );
+    }
+
+    #[test]
+    fn hexdump_mixed_test() {
+        assert_eq!(
+            bytes_stringify_with_mode(
+                b"c:\x00\x00\x00\x00\x00\x00\x00\x01:filename",
+                BytesStringifyMode::HexDump
+            ),
+            "00000000  63 3a 00 00 00 00 00 00  00 01 3a 66 69 6c 65 6e  |c:........:filen|\n\
+             00000010  61 6d 65                                          |ame|"
+        );
+        assert_eq!(
+            bytes_stringify_with_mode(b"c:\x00\x01", BytesStringifyMode::Escaped),
+            "c:\\u00\\u01"
+        );
+    }
+}
@@ -38,6 +38,65 @@ impl Digest for Xxhash {
     }
 }
 
+pub struct Blake3 {
+    hasher: blake3::Hasher,
+}
+
+impl Default for Blake3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blake3 {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Blake3 {
+            hasher: blake3::Hasher::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn finish(&self) -> [u8; 32] {
+        *self.hasher.finalize().as_bytes()
+    }
+}
+
+impl Digest for Blake3 {
+    #[inline(always)]
+    fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.hasher.update(data.as_ref());
+    }
+}
+
+/// Hash algorithm used to digest a [`Digestible`].
+///
+/// xxhash is fast but not collision-resistant, good for change detection.
+/// blake3 should be used when the digest identifies the content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DigestAlgorithm {
+    #[default]
+    Xxhash,
+    Blake3,
+}
+
+impl DigestAlgorithm {
+    pub fn digest(&self, value: &impl Digestible) -> Vec<u8> {
+        match self {
+            DigestAlgorithm::Xxhash => {
+                let mut hash = Xxhash::new();
+                value.digest(&mut hash);
+                hash.finish().to_vec()
+            }
+            DigestAlgorithm::Blake3 => {
+                let mut hash = Blake3::new();
+                value.digest(&mut hash);
+                hash.finish().to_vec()
+            }
+        }
+    }
+}
+
 pub trait Digestible {
     fn digest(&self, data: &mut impl Digest);
 }
@@ -77,3 +136,26 @@ impl Digestible for Vec<u8> {
         d.update(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DigestAlgorithm;
+
+    #[test]
+    fn algorithm_test() {
+        let data = "hello world".to_string();
+
+        let xxhash = DigestAlgorithm::Xxhash.digest(&data);
+        let blake3 = DigestAlgorithm::Blake3.digest(&data);
+
+        assert_eq!(xxhash.len(), 8);
+        assert_eq!(blake3.len(), 32);
+        assert_eq!(xxhash, DigestAlgorithm::Xxhash.digest(&data));
+        assert_eq!(blake3, DigestAlgorithm::Blake3.digest(&data));
+        assert_ne!(xxhash[..], blake3[..8]);
+        assert_eq!(DigestAlgorithm::default(), DigestAlgorithm::Xxhash);
+
+        assert_eq!(blake3, blake3::hash(b"hello world").as_bytes().to_vec());
+        assert_ne!(blake3, DigestAlgorithm::Blake3.digest(&"hello".to_string()));
+    }
+}
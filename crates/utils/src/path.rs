@@ -36,17 +36,19 @@ impl PathTools {
             if i >= length {
                 break;
             }
-            let from_code = from.chars().nth(from_start + i);
-            if from_code != to.chars().nth(to_start + i) {
+            let from_code = from.as_bytes().get(from_start + i);
+            if from_code != to.as_bytes().get(to_start + i) {
                 break;
-            } else if from_code == Some(PathTools::DIRECTORY_SEPARATOR_CHAR) {
+            } else if from_code == Some(&(PathTools::DIRECTORY_SEPARATOR_CHAR as u8)) {
                 last_common_sep = Some(i);
             }
             i += 1;
         }
         if i == length {
             if to_len > length {
-                if to.chars().nth(to_start + i) == Some(PathTools::DIRECTORY_SEPARATOR_CHAR) {
+                if to.as_bytes().get(to_start + i)
+                    == Some(&(PathTools::DIRECTORY_SEPARATOR_CHAR as u8))
+                {
                     // We get here if `from` is the exact base path for `to`.
                     // For example: from='/foo/bar'; to='/foo/bar/baz'
                     return Cow::Owned(to[to_start + i + 1..].to_string());
@@ -57,7 +59,9 @@ impl PathTools {
                     return Cow::Owned(to[to_start + i..].to_string());
                 }
             } else if from_end > length {
-                if from.chars().nth(from_start + i) == Some(PathTools::DIRECTORY_SEPARATOR_CHAR) {
+                if from.as_bytes().get(from_start + i)
+                    == Some(&(PathTools::DIRECTORY_SEPARATOR_CHAR as u8))
+                {
                     // We get here if `to` is the exact base path for `from`.
                     // For example: from='/foo/bar/baz'; to='/foo/bar'
                     last_common_sep = Some(i);
@@ -78,7 +82,9 @@ impl PathTools {
             if i > from_end {
                 break;
             }
-            if i == from_end || from.chars().nth(i) == Some(PathTools::DIRECTORY_SEPARATOR_CHAR) {
+            if i == from_end
+                || from.as_bytes().get(i) == Some(&(PathTools::DIRECTORY_SEPARATOR_CHAR as u8))
+            {
                 out.push_str(if out.is_empty() { ".." } else { "/.." });
             }
 
@@ -193,9 +199,9 @@ impl PathTools {
         let mut start = 0;
         let mut end = -1;
         let mut matched_slash = true;
-        let mut i = path.len() as i32 - 1;
 
-        for code in path.chars().rev() {
+        for (i, code) in path.char_indices().rev() {
+            let i = i as i32;
             if code == Self::DIRECTORY_SEPARATOR_CHAR {
                 if !matched_slash {
                     start = i + 1;
@@ -203,10 +209,8 @@ impl PathTools {
                 }
             } else if end == -1 {
                 matched_slash = false;
-                end = i + 1;
+                end = i + code.len_utf8() as i32;
             }
-
-            i -= 1;
         }
 
         if end == -1 {
@@ -231,10 +235,8 @@ impl PathTools {
         // after any path separator we find
         let mut pre_dot_state = 0;
 
-        let mut i = path.len() as i32;
-
-        for code in path.chars().rev() {
-            i -= 1;
+        for (i, code) in path.char_indices().rev() {
+            let i = i as i32;
 
             if code == Self::DIRECTORY_SEPARATOR_CHAR {
                 // If we reached a path separator that was not part of a set of path
@@ -251,7 +253,7 @@ impl PathTools {
                 // We saw the first non-path separator, mark this as the end of our
                 // extension
                 matched_slash = false;
-                end = i + 1;
+                end = i + code.len_utf8() as i32;
             }
 
             if code == '.' {
@@ -291,22 +293,16 @@ impl PathTools {
         let mut end = -1;
         let mut matched_slash = true;
 
-        let mut i = path.len() as i32 - 1;
-
-        for code in path.chars().rev() {
+        // the first char is the root, if any
+        for (i, code) in path.char_indices().rev().take_while(|(i, _)| *i > 0) {
             if code == Self::DIRECTORY_SEPARATOR_CHAR {
                 if !matched_slash {
-                    end = i;
+                    end = i as i32;
                     break;
                 }
             } else {
                 matched_slash = false
             }
-
-            i -= 1;
-            if i < 1 {
-                break;
-            }
         }
 
         if end == -1 {
@@ -327,17 +323,18 @@ impl PathTools {
         let mut last_segment_length = 0;
         let mut last_slash = -1;
         let mut dots = 0;
-        let mut chars = path.chars();
-        let mut i: i32 = -1;
+        let mut chars = path.char_indices();
+        // byte index of `code`, the segments are sliced by it
+        let mut i: i32;
 
         loop {
-            i += 1;
-
-            if let Some(next_code) = chars.next() {
+            if let Some((index, next_code)) = chars.next() {
+                i = index as i32;
                 code = next_code
             } else if is_path_separator(&code) {
                 break;
             } else {
+                i = path.len() as i32;
                 code = Self::DIRECTORY_SEPARATOR_CHAR
             }
 
@@ -409,7 +406,7 @@ impl PathTools {
     pub fn dive(path: &str) -> impl Iterator<Item = &str> {
         let mut positions = vec![];
 
-        for (i, char) in path.chars().enumerate() {
+        for (i, char) in path.char_indices() {
             if char == Self::DIRECTORY_SEPARATOR_CHAR {
                 if positions.last() == Some(&i) || positions.is_empty() {
                     positions.push(i + 1)
@@ -551,6 +548,8 @@ mod tests {
             PathTools::normalize("../.../../foobar/../../../bar/../../baz")
         );
         assert_eq!("foo/bar\\baz", PathTools::normalize("foo/bar\\baz"));
+        assert_eq!("/ü/é", PathTools::normalize("/ü/./a/../é"));
+        assert_eq!("/é", PathTools::resolve("/é/ü", ".."));
     }
 
     #[test]
@@ -581,6 +580,8 @@ mod tests {
         assert_eq!("basename.ext\\", PathTools::basename("basename.ext\\"));
         assert_eq!("basename.ext\\\\", PathTools::basename("basename.ext\\\\"));
         assert_eq!("foo", PathTools::basename("foo"));
+        assert_eq!("café", PathTools::basename("/ü/café"));
+        assert_eq!("é", PathTools::basename("é/"));
     }
 
     #[test]
@@ -636,6 +637,7 @@ mod tests {
         assert_eq!(PathTools::extname("file\\"), "");
         assert_eq!(PathTools::extname("file.\\"), ".\\");
         assert_eq!(PathTools::extname("file.\\\\"), ".\\\\");
+        assert_eq!(PathTools::extname("/ü/café.é"), ".é");
     }
 
     #[test]
@@ -648,6 +650,8 @@ mod tests {
         assert_eq!("/", PathTools::dirname("////"));
         assert_eq!("//", PathTools::dirname("//a"));
         assert_eq!(".", PathTools::dirname("foo"));
+        assert_eq!("/ü", PathTools::dirname("/ü/café"));
+        assert_eq!("é", PathTools::dirname("é/ü"));
     }
 
     #[test]
@@ -677,11 +681,17 @@ mod tests {
         assert_eq!("../baz", PathTools::relative("/baz-quux", "/baz"));
         assert_eq!("../baz-quux", PathTools::relative("/baz", "/baz-quux"));
         assert_eq!("../../..", PathTools::relative("/page1/page2/foo", "/"));
+        assert_eq!("../ü", PathTools::relative("/é/ä", "/é/ü"));
+        assert_eq!("ü", PathTools::relative("/é", "/é/ü"));
     }
 
     #[test]
     fn dive_test() {
         assert_eq!(vec!["/"], PathTools::dive("/").collect::<Vec<_>>());
+        assert_eq!(
+            vec!["/", "/é", "/é/ü"],
+            PathTools::dive("/é/ü").collect::<Vec<_>>()
+        );
         assert_eq!(
             vec!["/", "/foo"],
             PathTools::dive("/foo").collect::<Vec<_>>()
@@ -33,6 +33,11 @@ impl Serializer {
     pub fn finish(self) -> SmallVec<[u8; 16]> {
         self.bytes
     }
+
+    /// Clear the written bytes, keeping the allocation for the next value.
+    pub fn reset(&mut self) {
+        self.bytes.clear()
+    }
 }
 
 impl From<Vec<u8>> for Serializer {
@@ -62,9 +67,96 @@ pub trait Serialize {
         self.serialize(Serializer::new()).finish()
     }
 
+    /// Replace the content of `buf` with the serialized bytes. The allocation
+    /// of `buf` is reused, so serializing many values into one scratch buffer
+    /// does not allocate for each value.
+    fn serialize_into(&self, buf: &mut Vec<u8>) {
+        buf.clear();
+        if let Some(size) = self.byte_size() {
+            buf.reserve(size)
+        }
+        *buf = self
+            .serialize(Serializer::from(std::mem::take(buf)))
+            .finish()
+            .into_vec();
+    }
+
     fn byte_size(&self) -> Option<usize>;
 }
 
+/// Types which always serialize to exactly `SIZE` bytes.
+pub trait FixedSize: Serialize {
+    const SIZE: usize;
+
+    /// Write the serialized bytes into `buf`, which is `SIZE` bytes long.
+    fn serialize_fixed(&self, buf: &mut [u8]);
+
+    /// Serialize into a stack buffer, `N` is checked against `SIZE` at compile
+    /// time.
+    fn to_fixed_bytes<const N: usize>(&self) -> [u8; N] {
+        const { assert!(N == Self::SIZE, "buffer size does not match the fixed size") };
+        let mut buf = [0; N];
+        self.serialize_fixed(&mut buf);
+        buf
+    }
+}
+
+macro_rules! impl_fixed_size_int {
+    ($($ty:ty)*) => {
+        $(
+            impl FixedSize for $ty {
+                const SIZE: usize = size_of::<$ty>();
+
+                fn serialize_fixed(&self, buf: &mut [u8]) {
+                    buf.copy_from_slice(&self.to_be_bytes())
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_size_int!(u8 u32 u64 i64 u128);
+
+impl FixedSize for bool {
+    const SIZE: usize = 1;
+
+    fn serialize_fixed(&self, buf: &mut [u8]) {
+        buf[0] = u8::from(*self)
+    }
+}
+
+impl<T: FixedSize, const N: usize> FixedSize for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn serialize_fixed(&self, buf: &mut [u8]) {
+        for (elem, buf) in self.iter().zip(buf.chunks_exact_mut(T::SIZE)) {
+            elem.serialize_fixed(buf)
+        }
+    }
+}
+
+impl<A: FixedSize, B: FixedSize> FixedSize for (A, B) {
+    const SIZE: usize = A::SIZE + B::SIZE;
+
+    fn serialize_fixed(&self, buf: &mut [u8]) {
+        let (a, b) = buf.split_at_mut(A::SIZE);
+        self.0.serialize_fixed(a);
+        self.1.serialize_fixed(b);
+    }
+}
+
+impl<A: FixedSize, B: FixedSize, C: FixedSize> FixedSize for (A, B, C) {
+    const SIZE: usize = A::SIZE + B::SIZE + C::SIZE;
+
+    fn serialize_fixed(&self, buf: &mut [u8]) {
+        let (a, rest) = buf.split_at_mut(A::SIZE);
+        let (b, c) = rest.split_at_mut(B::SIZE);
+        self.0.serialize_fixed(a);
+        self.1.serialize_fixed(b);
+        self.2.serialize_fixed(c);
+    }
+}
+
 pub trait Deserialize: Sized {
     fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String>;
 
@@ -88,9 +180,10 @@ impl Serialize for String {
 impl Deserialize for String {
     fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
         let size = u32::from_be_bytes(
-            bytes[0..4]
-                .try_into()
-                .map_err(|_| format!("Failed to decode string: {bytes:?}"))?,
+            bytes
+                .get(0..4)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| format!("Failed to decode string: {bytes:?}"))?,
         ) as usize;
 
         let bytes = &bytes[4..];
@@ -121,9 +214,10 @@ impl Deserialize for u8 {
     fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
         Ok((
             Self::from_be_bytes(
-                bytes[0..1]
-                    .try_into()
-                    .map_err(|_| format!("Failed to decode u8: {bytes:?}"))?,
+                bytes
+                    .get(0..1)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or_else(|| format!("Failed to decode u8: {bytes:?}"))?,
             ),
             &bytes[1..],
         ))
@@ -145,9 +239,10 @@ impl Deserialize for u32 {
     fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
         Ok((
             Self::from_be_bytes(
-                bytes[0..4]
-                    .try_into()
-                    .map_err(|_| format!("Failed to decode u32: {bytes:?}"))?,
+                bytes
+                    .get(0..4)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or_else(|| format!("Failed to decode u32: {bytes:?}"))?,
             ),
             &bytes[4..],
         ))
@@ -169,9 +264,10 @@ impl Deserialize for u64 {
     fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
         Ok((
             Self::from_be_bytes(
-                bytes[0..8]
-                    .try_into()
-                    .map_err(|_| format!("Failed to decode u64: {bytes:?}"))?,
+                bytes
+                    .get(0..8)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or_else(|| format!("Failed to decode u64: {bytes:?}"))?,
             ),
             &bytes[8..],
         ))
@@ -193,9 +289,10 @@ impl Deserialize for i64 {
     fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
         Ok((
             Self::from_be_bytes(
-                bytes[0..8]
-                    .try_into()
-                    .map_err(|_| format!("Failed to decode u64: {bytes:?}"))?,
+                bytes
+                    .get(0..8)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or_else(|| format!("Failed to decode u64: {bytes:?}"))?,
             ),
             &bytes[8..],
         ))
@@ -217,9 +314,10 @@ impl Deserialize for u128 {
     fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
         Ok((
             Self::from_be_bytes(
-                bytes[0..16]
-                    .try_into()
-                    .map_err(|_| format!("Failed to decode u128: {bytes:?}"))?,
+                bytes
+                    .get(0..16)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or_else(|| format!("Failed to decode u128: {bytes:?}"))?,
             ),
             &bytes[16..],
         ))
@@ -340,10 +438,13 @@ impl<T: Serialize> Serialize for Option<T> {
 
 impl<T: Deserialize> Deserialize for Option<T> {
     fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
-        if bytes[0] == 0 {
-            Ok((None, &bytes[1..]))
+        let (tag, rest) = bytes
+            .split_first()
+            .ok_or_else(|| format!("Failed to decode option: {bytes:?}"))?;
+        if *tag == 0 {
+            Ok((None, rest))
         } else {
-            let (elem, bytes) = T::deserialize(&bytes[1..])?;
+            let (elem, bytes) = T::deserialize(rest)?;
             Ok((Some(elem), bytes))
         }
     }
@@ -437,6 +538,72 @@ impl Serialize for bool {
 
 impl Deserialize for bool {
     fn deserialize(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
-        Ok((bytes[0] != 0, &bytes[1..]))
+        let (value, rest) = bytes
+            .split_first()
+            .ok_or_else(|| format!("Failed to decode bool: {bytes:?}"))?;
+        Ok((*value != 0, rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Deserialize, FixedSize, Serialize, Serializer};
+
+    fn assert_fixed<T: FixedSize>(value: T) {
+        assert_eq!(value.byte_size(), Some(T::SIZE));
+        let mut buf = vec![0; T::SIZE];
+        value.serialize_fixed(&mut buf);
+        assert_eq!(buf, value.to_bytes().to_vec());
+    }
+
+    #[test]
+    fn fixed_size_test() {
+        assert_fixed(1u8);
+        assert_fixed(true);
+        assert_fixed(0x0102_0304u32);
+        assert_fixed(u64::MAX - 1);
+        assert_fixed(-2i64);
+        assert_fixed(u128::MAX / 3);
+        assert_fixed([1u32, 2, 3]);
+        assert_fixed((1u8, 2u64));
+        assert_fixed((1u8, [2u32; 2], 3u128));
+
+        assert_eq!((1u8, 2u32).to_fixed_bytes::<5>(), [1, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn serialize_into_test() {
+        let values: Vec<(String, Vec<u64>, Option<bool>)> = vec![
+            ("a".repeat(100), vec![1, 2, 3], Some(true)),
+            (String::new(), vec![], None),
+            ("b".to_string(), vec![u64::MAX; 50], Some(false)),
+        ];
+
+        let mut buf = vec![];
+        for value in values.iter() {
+            value.serialize_into(&mut buf);
+            assert_eq!(buf, value.to_bytes().to_vec());
+        }
+        // the allocation of the largest value is kept
+        assert!(buf.capacity() >= values[2].to_bytes().len());
+
+        let mut serializer = Serializer::from(vec![]);
+        for value in values.iter() {
+            serializer.reset();
+            serializer = value.serialize(serializer);
+            assert_eq!(serializer.to_vec(), value.to_bytes().to_vec());
+        }
+    }
+
+    #[test]
+    fn truncated_test() {
+        type Value = ((String, u8), (u32, u64), (i64, u128), Option<bool>);
+
+        let value: Value = (("a".to_string(), 1), (2, 3), (4, 5), Some(true));
+        let bytes = value.to_bytes();
+        for len in 0..bytes.len() {
+            assert!(Value::from_bytes(&bytes[..len]).is_err());
+        }
+        assert_eq!(Value::from_bytes(&bytes).unwrap(), value);
     }
 }
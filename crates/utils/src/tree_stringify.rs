@@ -5,6 +5,17 @@ struct TreeNode {
     content: String,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct TreeStringifyOptions {
+    /// Max levels to render, deeper children are replaced with an ellipsis node.
+    pub max_depth: Option<usize>,
+    /// Use `+`, `` ` `` and `|` instead of the box drawing characters.
+    pub ascii: bool,
+    /// Max children to render for each node, the rest are replaced with an
+    /// ellipsis node.
+    pub max_entries: Option<usize>,
+}
+
 pub fn tree_stringify<
     'a,
     Entities: Iterator<Item = (&'a str, Content)>,
@@ -12,6 +23,18 @@ pub fn tree_stringify<
 >(
     entities: Entities,
     pat: &str,
+) -> String {
+    tree_stringify_with_options(entities, pat, &Default::default())
+}
+
+pub fn tree_stringify_with_options<
+    'a,
+    Entities: Iterator<Item = (&'a str, Content)>,
+    Content: std::fmt::Display,
+>(
+    entities: Entities,
+    pat: &str,
+    options: &TreeStringifyOptions,
 ) -> String {
     let mut tree: TreeNode = TreeNode::default();
 
@@ -22,20 +45,11 @@ pub fn tree_stringify<
         let (path, content) = entity;
         let parts: Vec<_> = path.split(pat).filter(|part| !part.is_empty()).collect();
 
-        let mut current_stack: Vec<usize> = vec![];
+        let mut current = &mut tree;
         for (i, part) in parts.iter().enumerate() {
             let is_end = i == parts.len() - 1;
-            let mut current = &mut tree;
-            for i in current_stack.iter() {
-                current = &mut current.children[*i]
-            }
-            if let Some((i, _)) = current
-                .children
-                .iter_mut()
-                .enumerate()
-                .find(|(_, c)| c.key == *part)
-            {
-                current_stack.push(i);
+            let index = if let Some(index) = current.children.iter().position(|c| c.key == *part) {
+                index
             } else {
                 current.children.push(TreeNode {
                     key: part.to_string(),
@@ -46,53 +60,119 @@ pub fn tree_stringify<
                     },
                     ..Default::default()
                 });
-                current_stack.push(current.children.len() - 1);
-            }
+                current.children.len() - 1
+            };
+            current = &mut current.children[index];
         }
     }
 
-    fn output_tree_children(mut str: String, tree: TreeNode, prefix: &str, pat: &str) -> String {
-        let count = tree.children.len();
-        for (i, mut entry) in tree.children.into_iter().enumerate() {
-            let mut key = entry.key.clone();
-            loop {
-                if entry.children.len() == 1 && entry.content.is_empty() {
-                    entry = entry.children.remove(0);
-                    key = format!("{}{}{}", key, pat, entry.key);
-                    continue;
-                }
-                break;
+    let (last, middle, line, ellipsis) = if options.ascii {
+        ("`", "+", '|', "...")
+    } else {
+        ("└", "├", '│', "…")
+    };
+
+    /// Children of a node left to output, the tree is walked with an explicit
+    /// stack of frames so deep trees don't overflow the call stack.
+    struct Frame {
+        children: std::iter::Enumerate<std::vec::IntoIter<TreeNode>>,
+        count: usize,
+        truncated: bool,
+        prefix: String,
+        depth: usize,
+    }
+
+    let push_frame = |str: &mut String,
+                      stack: &mut Vec<Frame>,
+                      mut tree: TreeNode,
+                      prefix: String,
+                      depth: usize| {
+        if options.max_depth.map(|max| depth >= max).unwrap_or(false) {
+            str.push_str(&format!("{}{} {}\n", prefix, last, ellipsis));
+            return;
+        }
+
+        let truncated = match options.max_entries {
+            Some(max) if tree.children.len() > max => {
+                tree.children.truncate(max);
+                true
             }
-            let mut new_prefix = prefix.to_string();
+            _ => false,
+        };
 
-            let output_content = if entry.content.is_empty() {
-                Default::default()
-            } else {
-                format!(" {}", entry.content)
-            };
+        stack.push(Frame {
+            count: tree.children.len(),
+            children: tree.children.into_iter().enumerate(),
+            truncated,
+            prefix,
+            depth,
+        })
+    };
 
-            if count == i + 1 {
-                str.push_str(&format!("{}└ {}{}\n", new_prefix, key, output_content));
-                new_prefix.push(' ');
-            } else {
-                str.push_str(&format!("{}├ {}{}\n", new_prefix, key, output_content));
-                new_prefix.push('│');
+    let mut str = String::new();
+    let mut stack = vec![];
+    push_frame(&mut str, &mut stack, tree, String::new(), 0);
+
+    while let Some(frame) = stack.last_mut() {
+        let Some((i, mut entry)) = frame.children.next() else {
+            let frame = stack.pop().unwrap();
+            if frame.truncated {
+                str.push_str(&format!("{}{} {}\n", frame.prefix, last, ellipsis));
             }
+            continue;
+        };
 
-            if !entry.children.is_empty() {
-                str = output_tree_children(str, entry, &new_prefix, pat);
+        let mut key = entry.key.clone();
+        loop {
+            if entry.children.len() == 1 && entry.content.is_empty() {
+                entry = entry.children.remove(0);
+                key = format!("{}{}{}", key, pat, entry.key);
+                continue;
             }
+            break;
+        }
+        let mut new_prefix = frame.prefix.clone();
+
+        let output_content = if entry.content.is_empty() {
+            Default::default()
+        } else {
+            format!(" {}", entry.content)
+        };
+
+        if frame.count == i + 1 && !frame.truncated {
+            str.push_str(&format!(
+                "{}{} {}{}\n",
+                new_prefix, last, key, output_content
+            ));
+            new_prefix.push(' ');
+        } else {
+            str.push_str(&format!(
+                "{}{} {}{}\n",
+                new_prefix, middle, key, output_content
+            ));
+            new_prefix.push(line);
         }
 
-        str
+        if !entry.children.is_empty() {
+            let depth = frame.depth + 1;
+            push_frame(&mut str, &mut stack, entry, new_prefix, depth);
+        }
     }
 
-    output_tree_children(Default::default(), tree, "", pat)
+    str
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::tree_stringify;
+    use crate::{tree_stringify, tree_stringify_with_options, TreeStringifyOptions};
+
+    const ENTITIES: [(&str, &str); 5] = [
+        ("/ccc/ddd", "hello"),
+        ("/ccc/eee", "hello1"),
+        ("/aaa/ddd", "hello2"),
+        ("/aaa/ddd/eee/fff/ttt/kkk", "hello4"),
+        ("/i/e", "hello5"),
+    ];
 
     #[test]
     fn test() {
@@ -111,4 +191,66 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn ascii_test() {
+        assert_eq!(
+            tree_stringify(ENTITIES.into_iter(), "/"),
+            "├ aaa/ddd hello2\n\
+             │└ eee/fff/ttt/kkk hello4\n\
+             ├ ccc\n\
+             │├ ddd hello\n\
+             │└ eee hello1\n\
+             └ i/e hello5\n"
+        );
+        assert_eq!(
+            tree_stringify_with_options(
+                ENTITIES.into_iter(),
+                "/",
+                &TreeStringifyOptions {
+                    ascii: true,
+                    ..Default::default()
+                }
+            ),
+            "+ aaa/ddd hello2\n\
+             |` eee/fff/ttt/kkk hello4\n\
+             + ccc\n\
+             |+ ddd hello\n\
+             |` eee hello1\n\
+             ` i/e hello5\n"
+        );
+    }
+
+    #[test]
+    fn limit_test() {
+        assert_eq!(
+            tree_stringify_with_options(
+                ENTITIES.into_iter(),
+                "/",
+                &TreeStringifyOptions {
+                    max_depth: Some(1),
+                    ..Default::default()
+                }
+            ),
+            "├ aaa/ddd hello2\n\
+             │└ …\n\
+             ├ ccc\n\
+             │└ …\n\
+             └ i/e hello5\n"
+        );
+        assert_eq!(
+            tree_stringify_with_options(
+                ENTITIES.into_iter(),
+                "/",
+                &TreeStringifyOptions {
+                    max_entries: Some(1),
+                    ascii: true,
+                    ..Default::default()
+                }
+            ),
+            "+ aaa/ddd hello2\n\
+             |` eee/fff/ttt/kkk hello4\n\
+             ` ...\n"
+        );
+    }
 }